@@ -2,6 +2,11 @@ extern crate glsl_to_spirv;
 
 use std::error::Error;
 
+// Shared with the engine, which uses the same constants
+#[allow(dead_code)]
+#[path = "src/render/shader_constants.rs"]
+mod shader_constants;
+
 const SRC_LOC: &'static str = "src/shaders";
 const DEST_LOC: &'static str = "assets/shaders";
 
@@ -10,6 +15,7 @@ fn main() -> Result<(), Box<Error>> {
 
     // Tell the build script to only run again if we change our source shaders
     println!("cargo:rerun-if-changed={}", SRC_LOC);
+    println!("cargo:rerun-if-changed=src/render/shader_constants.rs");
 
     // Create destination directory
     std::fs::create_dir_all(DEST_LOC)?;
@@ -36,7 +42,7 @@ fn main() -> Result<(), Box<Error>> {
             if let Some(shader_type) = shader_type {
                 use std::io::Read;
 
-                let source = std::fs::read_to_string(&in_path)?;
+                let source = shader_constants::inject(&std::fs::read_to_string(&in_path)?);
                 let mut compiled_file = glsl_to_spirv::compile(&source, shader_type)?;
 
                 let mut compiled_bytes = Vec::new();
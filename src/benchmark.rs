@@ -0,0 +1,203 @@
+//! Benchmark mode.
+//!
+//! When the game is started with `--benchmark`, the camera
+//! follows a scripted path over the maze instead of the usual
+//! view. Frame times and profiler scopes are collected along
+//! the way, and once the path finishes a JSON report is
+//! written and the game exits.
+//!
+//! There are no GPU timestamp queries yet, so pass timings
+//! are the CPU-side profiler scopes. The gfx backend waits on
+//! its fence in `submit`, so that scope includes GPU time.
+use glm::*;
+use profile::FrameProfiler;
+use render::{Camera, CameraPath};
+use std::fmt::Write;
+
+/// The command line argument which enables benchmark mode
+pub const ARG: &str = "--benchmark";
+/// The file the report is written to
+pub const REPORT_PATH: &str = "benchmark-report.json";
+
+/// Drives the camera along a path and collects timings.
+pub struct Benchmark {
+    path: CameraPath,
+    /// Game time in milliseconds at which the path started
+    start: Option<f64>,
+    /// Frame times in milliseconds
+    frame_times: Vec<f64>,
+    /// Profiler scope names and their total milliseconds
+    scopes: Vec<(&'static str, f64)>,
+}
+
+impl Benchmark {
+    pub fn new(path: CameraPath) -> Benchmark {
+        Benchmark {
+            path,
+            start: None,
+            frame_times: Vec::new(),
+            scopes: Vec::new(),
+        }
+    }
+
+    /// Returns whether benchmark mode was requested on the command line.
+    pub fn requested() -> bool {
+        ::std::env::args().any(|arg| arg == ARG)
+    }
+
+    /// Moves the camera to its position at `now`, in milliseconds
+    /// of game time. The path starts on the first call.
+    pub fn update_camera(&mut self, camera: &mut Camera, now: f64) {
+        let start = *self.start.get_or_insert(now);
+        let (position, target) = self.path.sample(now - start);
+        camera.position = position;
+        camera.target = target;
+        camera.up = up_for(position, target);
+    }
+
+    /// Records a finished frame.
+    pub fn record_frame(&mut self, frame_time: f64, profiler: &FrameProfiler) {
+        self.frame_times.push(frame_time);
+        for &(name, millis) in profiler.scopes() {
+            match self.scopes.iter_mut().find(|scope| scope.0 == name) {
+                Some(scope) => scope.1 += millis,
+                None => self.scopes.push((name, millis)),
+            }
+        }
+    }
+
+    /// Returns whether the path has finished at `now`, in milliseconds of game time.
+    pub fn finished(&self, now: f64) -> bool {
+        match self.start {
+            Some(start) => now - start >= self.path.duration(),
+            None => false,
+        }
+    }
+
+    /// Formats the collected statistics as JSON.
+    pub fn report(&self) -> String {
+        let mut sorted = self.frame_times.clone();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let frames = sorted.len();
+        let total = sorted.iter().sum::<f64>();
+        let average = if frames == 0 {
+            0.0
+        } else {
+            total / frames as f64
+        };
+
+        let mut report = String::new();
+        writeln!(report, "{{").unwrap();
+        writeln!(report, "  \"version\": \"{}\",", env!("CARGO_PKG_VERSION")).unwrap();
+        writeln!(report, "  \"frames\": {},", frames).unwrap();
+        writeln!(report, "  \"duration_ms\": {:.3},", total).unwrap();
+        writeln!(
+            report,
+            "  \"average_fps\": {:.3},",
+            if average == 0.0 {
+                0.0
+            } else {
+                1000.0 / average
+            }
+        )
+        .unwrap();
+        writeln!(report, "  \"frame_time_ms\": {{").unwrap();
+        writeln!(report, "    \"average\": {:.3},", average).unwrap();
+        writeln!(
+            report,
+            "    \"min\": {:.3},",
+            sorted.first().cloned().unwrap_or(0.0)
+        )
+        .unwrap();
+        for &(name, percentile) in &[("p50", 0.5), ("p95", 0.95), ("p99", 0.99)] {
+            writeln!(
+                report,
+                "    \"{}\": {:.3},",
+                name,
+                percentile_of(&sorted, percentile)
+            )
+            .unwrap();
+        }
+        writeln!(
+            report,
+            "    \"max\": {:.3}",
+            sorted.last().cloned().unwrap_or(0.0)
+        )
+        .unwrap();
+        writeln!(report, "  }},").unwrap();
+
+        writeln!(report, "  \"scopes_ms\": {{").unwrap();
+        for (i, &(name, millis)) in self.scopes.iter().enumerate() {
+            let separator = if i + 1 == self.scopes.len() { "" } else { "," };
+            writeln!(
+                report,
+                "    \"{}\": {:.3}{}",
+                name,
+                millis / frames.max(1) as f64,
+                separator
+            )
+            .unwrap();
+        }
+        writeln!(report, "  }}").unwrap();
+        writeln!(report, "}}").unwrap();
+        report
+    }
+
+    /// Writes the report to `REPORT_PATH`.
+    pub fn write_report(&self) -> ::std::io::Result<()> {
+        ::std::fs::write(REPORT_PATH, self.report())
+    }
+}
+
+/// Returns an up vector for a camera looking from `position` to `target`.
+/// Y is up unless the camera looks nearly vertically, where
+/// `look_at` would get a zero cross product.
+fn up_for(position: Vec3, target: Vec3) -> Vec3 {
+    let forward = normalize(target - position);
+    if forward.y.abs() > 0.99 {
+        vec3(0.0, 0.0, -1.0)
+    } else {
+        vec3(0.0, 1.0, 0.0)
+    }
+}
+
+/// Returns the value at `percentile`, between 0 and 1,
+/// of a sorted list using the nearest-rank method.
+fn percentile_of(sorted: &[f64], percentile: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (percentile * sorted.len() as f64).ceil() as usize;
+    sorted[rank.max(1) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles() {
+        let sorted = (1..101).map(|i| i as f64).collect::<Vec<_>>();
+        assert_eq!(percentile_of(&sorted, 0.5), 50.0);
+        assert_eq!(percentile_of(&sorted, 0.99), 99.0);
+        assert_eq!(percentile_of(&[], 0.5), 0.0);
+    }
+
+    #[test]
+    fn flyover_views_are_finite() {
+        let path = CameraPath::maze_flyover();
+        let mut camera = Camera::default();
+        let mut benchmark = Benchmark::new(CameraPath::maze_flyover());
+        let mut time = 0.0;
+        while time <= path.duration() {
+            benchmark.update_camera(&mut camera, time);
+            let view = camera.view();
+            for column in 0..4 {
+                for row in 0..4 {
+                    assert!(view[column][row].is_finite(), "NaN view at {}ms", time);
+                }
+            }
+            time += 100.0;
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! Logging and crash reporting.
+//!
+//! The logger keeps the most recent lines in memory so
+//! that, if the game panics, they can be written to a crash
+//! report along with the panic message, a backtrace, and
+//! information about the graphics adapter.
+use log::{Level, Log, Metadata, Record};
+use std::backtrace::Backtrace;
+use std::collections::VecDeque;
+use std::fmt::Write as FmtWrite;
+use std::panic::{self, PanicHookInfo};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of log lines kept for crash reports
+const RECENT_LINES: usize = 64;
+
+static RECENT_LOG: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+static ADAPTER_INFO: Mutex<Option<String>> = Mutex::new(None);
+static LOGGER: RecentLogger = RecentLogger;
+
+/// Logger which prints to standard output and remembers recent lines.
+struct RecentLogger;
+
+impl Log for RecentLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Trace
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = format!(
+            "{:<5} [{}] {}",
+            record.level(),
+            record.module_path().unwrap_or_default(),
+            record.args()
+        );
+        println!("{}", line);
+
+        if let Ok(mut recent) = RECENT_LOG.lock() {
+            if recent.len() == RECENT_LINES {
+                recent.pop_front();
+            }
+            recent.push_back(line);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the logger and the panic handler.
+/// This should be called once at startup.
+pub fn init() {
+    log::set_logger(&LOGGER).expect("logger already set");
+    log::set_max_level(log::LevelFilter::Trace);
+
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let report = crash_report(info);
+        match write_report(&report) {
+            Ok(path) => eprintln!("Crash report written to {}", path),
+            Err(err) => eprintln!("Failed to write crash report: {}", err),
+        }
+        default_hook(info);
+    }));
+}
+
+/// Records a description of the graphics adapter
+/// for inclusion in crash reports.
+pub fn set_adapter_info(info: String) {
+    if let Ok(mut adapter) = ADAPTER_INFO.lock() {
+        *adapter = Some(info);
+    }
+}
+
+/// Builds the text of a crash report for the given panic.
+fn crash_report(info: &PanicHookInfo) -> String {
+    let mut report = String::new();
+    writeln!(report, "Luminite {} crashed", env!("CARGO_PKG_VERSION")).unwrap();
+
+    let message = match info.payload().downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => match info.payload().downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => "<unknown>".to_string(),
+        },
+    };
+    writeln!(report, "Message: {}", message).unwrap();
+
+    if let Some(location) = info.location() {
+        writeln!(report, "Location: {}:{}", location.file(), location.line()).unwrap();
+    }
+    writeln!(
+        report,
+        "Thread: {}",
+        std::thread::current().name().unwrap_or("<unnamed>")
+    )
+    .unwrap();
+
+    // `try_lock` since the panic may have happened while holding the lock
+    let adapter = match ADAPTER_INFO.try_lock() {
+        Ok(adapter) => adapter.clone(),
+        Err(_) => None,
+    };
+    writeln!(
+        report,
+        "Adapter: {}",
+        adapter.unwrap_or_else(|| "<unknown>".to_string())
+    )
+    .unwrap();
+
+    writeln!(report, "\nBacktrace:\n{}", Backtrace::force_capture()).unwrap();
+
+    writeln!(report, "Recent log:").unwrap();
+    if let Ok(recent) = RECENT_LOG.try_lock() {
+        for line in recent.iter() {
+            writeln!(report, "{}", line).unwrap();
+        }
+    }
+
+    report
+}
+
+/// Writes the report to a timestamped file in the
+/// working directory, returning the file's path.
+fn write_report(report: &str) -> std::io::Result<String> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0);
+    let path = format!("crash-{}.txt", timestamp);
+    std::fs::write(&path, report)?;
+    Ok(path)
+}
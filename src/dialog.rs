@@ -0,0 +1,155 @@
+//! Native file open and save dialogs.
+//!
+//! Dialogs block the thread which shows them, so each one
+//! runs on its own thread and its result is delivered
+//! through `Dialogs::poll()` once the user closes it. This
+//! module is only built with the `dialogs` feature.
+//!
+//! macOS requires dialogs to be shown from the main thread,
+//! so there they will fail until the event loop can run them.
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use tinyfiledialogs;
+
+/// Whether a dialog chooses a file to open or to save to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DialogKind {
+    Open,
+    Save,
+}
+
+/// A file dialog which has been closed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DialogEvent {
+    /// The ID returned when the dialog was shown
+    pub id: u64,
+    pub kind: DialogKind,
+    /// The chosen file, or `None` if the dialog was cancelled
+    pub path: Option<PathBuf>,
+}
+
+/// Describes a file dialog.
+///
+/// ```ignore
+/// let id = FileDialog::new("Open model")
+///     .with_filter("OBJ models", &["*.obj"])
+///     .open(&mut dialogs);
+/// ```
+#[derive(Clone, Debug)]
+pub struct FileDialog {
+    title: String,
+    default_path: String,
+    /// The description and patterns of the file filter
+    filter: Option<(String, Vec<String>)>,
+}
+
+impl FileDialog {
+    pub fn new(title: &str) -> FileDialog {
+        FileDialog {
+            title: title.to_string(),
+            default_path: String::new(),
+            filter: None,
+        }
+    }
+
+    /// Sets the directory or file the dialog starts at.
+    pub fn with_default_path(mut self, path: &str) -> Self {
+        self.default_path = path.to_string();
+        self
+    }
+
+    /// Only shows files matching one of `patterns`, such as `*.obj`.
+    pub fn with_filter(mut self, description: &str, patterns: &[&str]) -> Self {
+        let patterns = patterns.iter().map(|pattern| pattern.to_string()).collect();
+        self.filter = Some((description.to_string(), patterns));
+        self
+    }
+
+    /// Shows a dialog for choosing a file to open,
+    /// returning the ID its `DialogEvent` will have.
+    pub fn open(self, dialogs: &mut Dialogs) -> u64 {
+        dialogs.show(self, DialogKind::Open)
+    }
+
+    /// Shows a dialog for choosing a file to save to,
+    /// returning the ID its `DialogEvent` will have.
+    pub fn save(self, dialogs: &mut Dialogs) -> u64 {
+        dialogs.show(self, DialogKind::Save)
+    }
+
+    /// Shows the dialog, blocking until it is closed.
+    fn run(&self, kind: DialogKind) -> Option<PathBuf> {
+        let (description, patterns) = match self.filter {
+            Some((ref description, ref patterns)) => (&description[..], patterns.clone()),
+            None => ("", Vec::new()),
+        };
+        let patterns = patterns
+            .iter()
+            .map(|pattern| &pattern[..])
+            .collect::<Vec<_>>();
+
+        let path = match kind {
+            DialogKind::Open => {
+                let filter = if patterns.is_empty() {
+                    None
+                } else {
+                    Some((&patterns[..], description))
+                };
+                tinyfiledialogs::open_file_dialog(&self.title, &self.default_path, filter)
+            }
+            DialogKind::Save => tinyfiledialogs::save_file_dialog_with_filter(
+                &self.title,
+                &self.default_path,
+                &patterns,
+                description,
+            ),
+        };
+        path.map(PathBuf::from)
+    }
+}
+
+/// Runs file dialogs without blocking the game loop.
+pub struct Dialogs {
+    next_id: u64,
+    sender: Sender<DialogEvent>,
+    receiver: Receiver<DialogEvent>,
+}
+
+impl Dialogs {
+    pub fn new() -> Dialogs {
+        let (sender, receiver) = channel();
+        Dialogs {
+            next_id: 0,
+            sender,
+            receiver,
+        }
+    }
+
+    fn show(&mut self, dialog: FileDialog, kind: DialogKind) -> u64 {
+        self.next_id += 1;
+        let id = self.next_id;
+        let sender = self.sender.clone();
+        let spawned = thread::Builder::new()
+            .name(format!("dialog {}", id))
+            .spawn(move || {
+                let path = dialog.run(kind);
+                // The receiver is gone if the game is shutting down
+                let _ = sender.send(DialogEvent { id, kind, path });
+            });
+        if let Err(err) = spawned {
+            warn!("Failed to show file dialog: {}", err);
+            let _ = self.sender.send(DialogEvent {
+                id,
+                kind,
+                path: None,
+            });
+        }
+        id
+    }
+
+    /// Returns the dialogs closed since the last poll.
+    pub fn poll(&self) -> Vec<DialogEvent> {
+        self.receiver.try_iter().collect()
+    }
+}
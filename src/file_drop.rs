@@ -0,0 +1,55 @@
+//! Files dragged onto the window.
+//!
+//! Drag and drop events are collected each frame into
+//! `Game::file_drops`. When `Game::auto_import` is set,
+//! dropped models are uploaded and placed in front of
+//! the camera, which is handy for viewing assets.
+use std::path::{Path, PathBuf};
+
+/// Command line argument which enables `Game::auto_import`
+pub const AUTO_IMPORT_ARG: &str = "--auto-import";
+
+/// A drag and drop event.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FileDropEvent {
+    /// A file is being dragged over the window
+    Hovered(PathBuf),
+    /// A file was dropped on the window
+    Dropped(PathBuf),
+    /// The file being dragged left the window
+    Cancelled,
+}
+
+/// The kind of asset a file contains, judged by its extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AssetKind {
+    Model,
+    Texture,
+    Unknown,
+}
+
+impl AssetKind {
+    pub fn of(path: &Path) -> AssetKind {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        match extension.as_ref().map(|extension| &extension[..]) {
+            Some("obj") => AssetKind::Model,
+            Some("png") | Some("jpg") | Some("jpeg") => AssetKind::Texture,
+            _ => AssetKind::Unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn asset_kinds() {
+        assert_eq!(AssetKind::of(Path::new("a/cube.obj")), AssetKind::Model);
+        assert_eq!(AssetKind::of(Path::new("sword.PNG")), AssetKind::Texture);
+        assert_eq!(AssetKind::of(Path::new("notes.txt")), AssetKind::Unknown);
+        assert_eq!(AssetKind::of(Path::new("README")), AssetKind::Unknown);
+    }
+}
@@ -1,9 +1,9 @@
 extern crate glm;
+#[macro_use]
 extern crate log;
 extern crate num;
 extern crate petgraph;
 extern crate rand;
-extern crate simple_logger;
 extern crate tobj;
 extern crate winit;
 
@@ -19,35 +19,151 @@ extern crate glium;
 
 #[cfg(not(feature = "gl"))]
 extern crate gfx_hal;
+#[cfg(feature = "glsl")]
+extern crate glsl_to_spirv;
+#[cfg(feature = "dialogs")]
+extern crate tinyfiledialogs;
+
+#[cfg(not(any(
+    feature = "vulkan",
+    feature = "dx12",
+    feature = "metal",
+    feature = "gl"
+)))]
+compile_error!(
+    "no graphics backend selected, build with one of --features=vulkan, dx12, metal or gl"
+);
+#[cfg(any(
+    all(
+        feature = "vulkan",
+        any(feature = "dx12", feature = "metal", feature = "gl")
+    ),
+    all(feature = "dx12", any(feature = "metal", feature = "gl")),
+    all(feature = "metal", feature = "gl")
+))]
+compile_error!(
+    "more than one graphics backend selected, build with only one of vulkan, dx12, metal or gl"
+);
 
+use benchmark::Benchmark;
+use file_drop::{AssetKind, FileDropEvent};
+#[cfg(feature = "gl")]
+use glium::glutin::VirtualKeyCode;
 pub use glm::*;
-use std::time::SystemTime;
-use world::{Object, World};
+use mods::ModLoader;
+use photo::{PhotoAction, PhotoMode};
+use profile::{FrameProfiler, HitchDetector};
+use render::{
+    Camera, CameraEffects, CameraPath, GraphicsQuality, GraphicsSettings, RenderWorld, TimeOfDay,
+    WindowMode,
+};
+use stats::StatsOverlay;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use timeline::{Timeline, TimelinePlayer};
+use touch::{Gesture, GestureRecognizer, TouchEvent};
+#[cfg(not(feature = "gl"))]
+use winit::VirtualKeyCode;
+use world::{Location, Object, Schedule, World};
 // Trait
 use render::RenderBackend;
 // Type
 use render::_RenderBackend;
 
+pub mod benchmark;
+pub mod crash;
+#[cfg(feature = "dialogs")]
+pub mod dialog;
+pub mod file_drop;
 pub mod maze;
+pub mod mods;
+pub mod photo;
+pub mod profile;
 pub mod render;
+pub mod stats;
+pub mod timeline;
+pub mod touch;
 pub mod world;
 
 const MS_PER_UPDATE: f64 = 1000.0 / 60.0;
+const TITLE: &str = "Luminite";
+/// Frames taking longer than this many milliseconds are logged as hitches
+const HITCH_BUDGET: f64 = 1000.0 / 30.0;
 
 pub struct Game<B: RenderBackend> {
     pub render: B::RenderContext,
     pub world: World<B>,
+    /// Render data extracted from the world each frame
+    pub render_world: RenderWorld,
+    pub camera: Camera,
+    /// Shake, recoil and other effects applied over `camera`
+    pub camera_effects: CameraEffects,
+    /// Pauses the game and frees the camera while active
+    pub photo: PhotoMode,
+    /// The in-game time, which sets the lighting
+    pub time_of_day: TimeOfDay,
+    /// The current graphics settings. View distance
+    /// changes take effect on the next frame, while MSAA,
+    /// vsync and depth mode changes need a restart.
+    pub graphics: GraphicsSettings,
+    /// Systems run against the world each tick
+    pub schedule: Schedule<World<B>>,
     pub running: bool,
     pub start_time: SystemTime,
+    /// Engine statistics shown in the window title
+    pub stats: StatsOverlay,
+    /// Timings for the phases of the current frame
+    pub profiler: FrameProfiler,
+    pub hitches: HitchDetector,
+    /// Set when running in benchmark mode
+    pub benchmark: Option<Benchmark>,
+    /// The cutscene playing, which moves the camera
+    pub cutscene: Option<TimelinePlayer>,
+    /// Mods mounted at startup
+    pub mods: ModLoader,
+    /// Drag and drop events from the last poll
+    pub file_drops: Vec<FileDropEvent>,
+    /// Whether dropped models are loaded into the world
+    pub auto_import: bool,
+    /// Turns touch input into gestures
+    pub gestures: GestureRecognizer,
+    pub window_mode: WindowMode,
 }
 
 fn main() {
-    simple_logger::init().unwrap();
+    crash::init();
+    let backend = render::backend::from_env()
+        .unwrap_or_else(|err| panic!("failed to select backend: {}", err));
+    info!("Rendering with {}", backend);
+    let mods = ModLoader::discover(mods::MODS_DIR);
+    let graphics = GraphicsQuality::default().settings();
+    let (render, graphics) =
+        render::create_context::<_RenderBackend>(TITLE, (720, 480), &graphics, &mods);
     let mut game: Game<_RenderBackend> = Game {
-        render: render::create_context::<_RenderBackend>("Luminite", (720, 480)),
+        render,
         world: World::new(),
+        render_world: RenderWorld::new(),
+        camera: Camera::default(),
+        camera_effects: CameraEffects::new(),
+        photo: PhotoMode::new(),
+        time_of_day: TimeOfDay::default(),
+        graphics,
+        schedule: Schedule::new(),
         running: true,
         start_time: SystemTime::now(),
+        stats: StatsOverlay::new(),
+        profiler: FrameProfiler::new(),
+        hitches: HitchDetector::new(HITCH_BUDGET),
+        benchmark: if Benchmark::requested() {
+            Some(Benchmark::new(CameraPath::maze_flyover()))
+        } else {
+            None
+        },
+        cutscene: timeline::requested().and_then(|name| load_cutscene(&mods, &name)),
+        mods,
+        file_drops: Vec::new(),
+        auto_import: std::env::args().any(|arg| arg == file_drop::AUTO_IMPORT_ARG),
+        gestures: GestureRecognizer::new(),
+        window_mode: WindowMode::Windowed,
     };
 
     /*let cube = Object::new(
@@ -70,6 +186,7 @@ fn main() {
         .add_objs(maze::gen::gen_maze(64, 64, &mut game.render));
 
     main_loop(&mut game);
+    _RenderBackend::destroy(game.render, game.world);
 }
 
 fn main_loop(game: &mut Game<_RenderBackend>) {
@@ -81,46 +198,412 @@ fn main_loop(game: &mut Game<_RenderBackend>) {
         previous = current;
         lag += elapsed;
 
+        let frame_start = Instant::now();
+        game.profiler.begin_frame();
+
+        let start = Instant::now();
+        _RenderBackend::begin_input(&mut game.render, &game.graphics);
+        game.profiler
+            .record("input wait", profile::millis_since(start));
+
+        let start = Instant::now();
         poll_events(game);
+        game.profiler.record("events", profile::millis_since(start));
 
+        // The simulation is paused in photo mode
+        if game.photo.is_active() {
+            lag = 0.0;
+        }
         while lag >= MS_PER_UPDATE {
+            {
+                let profiler = &mut game.profiler;
+                game.schedule.run_with(&mut game.world, |label, millis| {
+                    profiler.record(label, millis)
+                });
+            }
+            game.time_of_day.update((MS_PER_UPDATE / 1000.0) as f32);
+            let start = Instant::now();
             game.world.tick();
+            game.profiler.record("tick", profile::millis_since(start));
             lag -= MS_PER_UPDATE;
         }
 
+        if let Some(ref mut benchmark) = game.benchmark {
+            benchmark.update_camera(&mut game.camera, current);
+        }
+        play_cutscene(game, elapsed);
+
+        game.camera_effects
+            .update((elapsed / 1000.0) as f32, &game.camera);
+        let camera = if game.photo.is_active() {
+            game.photo
+                .capture_camera()
+                .unwrap_or_else(|| game.photo.camera())
+        } else {
+            game.camera_effects.apply(&game.camera)
+        };
+
         // TODO extrapolation for smoothness
-        render::render(&mut game.render, &mut game.world);
+        let start = Instant::now();
+        render::extract(
+            &game.world,
+            &mut game.render_world,
+            &camera,
+            &game.time_of_day.lighting(),
+            &game.graphics,
+        );
+        game.profiler
+            .record("extract", profile::millis_since(start));
+
+        let start = Instant::now();
+        _RenderBackend::prepare(&mut game.render, &mut game.world, &game.render_world);
+        game.profiler
+            .record("prepare", profile::millis_since(start));
+
+        if game.photo.is_capturing() {
+            _RenderBackend::capture_next_frame(&mut game.render);
+        }
+        let start = Instant::now();
+        _RenderBackend::submit(&mut game.render, &game.world, &game.render_world);
+        game.profiler.record("submit", profile::millis_since(start));
+        capture_photo_tile(game);
+
+        game.stats.record_frame(elapsed);
+        update_stats_overlay(game, current);
+
+        let frame_time = profile::millis_since(frame_start);
+        game.hitches.end_frame(frame_time, &game.profiler);
+
+        if let Some(ref mut benchmark) = game.benchmark {
+            benchmark.record_frame(frame_time, &game.profiler);
+            if benchmark.finished(current) {
+                match benchmark.write_report() {
+                    Ok(()) => info!("Benchmark report written to {}", benchmark::REPORT_PATH),
+                    Err(err) => error!("Failed to write benchmark report: {}", err),
+                }
+                game.running = false;
+            }
+        }
+    }
+}
+
+/// Loads a cutscene and starts playing it.
+fn load_cutscene(mods: &ModLoader, name: &str) -> Option<TimelinePlayer> {
+    match Timeline::load(mods, name) {
+        Ok(timeline) => {
+            let mut player = TimelinePlayer::new(timeline);
+            player.play();
+            Some(player)
+        }
+        Err(err) => {
+            warn!("Failed to load cutscene {}: {}", name, err);
+            None
+        }
+    }
+}
+
+/// Advances the cutscene by `elapsed` milliseconds, moving
+/// the camera. The cutscene is dropped once it finishes.
+fn play_cutscene(game: &mut Game<_RenderBackend>, elapsed: f64) {
+    let finished = match game.cutscene {
+        Some(ref mut cutscene) => {
+            for cue in cutscene.update(elapsed) {
+                info!("Cutscene cue: {}", cue.name);
+            }
+            cutscene.update_camera(&mut game.camera);
+            cutscene.finished()
+        }
+        None => return,
+    };
+    if finished {
+        game.cutscene = None;
+    }
+}
+
+/// Shows engine statistics in the window title
+/// while the overlay is enabled.
+fn update_stats_overlay(game: &mut Game<_RenderBackend>, now: f64) {
+    if !game.stats.should_update(now) {
+        return;
+    }
+    let summary = game.stats.summary(
+        _RenderBackend::stats(&game.render),
+        _RenderBackend::telemetry(&game.render).last(),
+        game.world.get_objs().len(),
+        game.schedule.len(),
+    );
+    _RenderBackend::set_title(&mut game.render, &format!("{} | {}", TITLE, summary));
+}
+
+/// Toggles the statistics overlay,
+/// restoring the title when it is hidden.
+fn toggle_stats_overlay(game: &mut Game<_RenderBackend>) {
+    game.stats.toggle();
+    if !game.stats.enabled {
+        _RenderBackend::set_title(&mut game.render, TITLE);
+    }
+}
+
+/// Adds the frame just submitted to the photo being
+/// captured, saving the photo once every tile is added.
+fn capture_photo_tile(game: &mut Game<_RenderBackend>) {
+    if !game.photo.is_capturing() {
+        return;
+    }
+    let tile = match _RenderBackend::take_captured_frame(&mut game.render) {
+        Some(tile) => tile,
+        None => {
+            warn!("The frame could not be captured, so the photo was cancelled");
+            game.photo.cancel_capture();
+            return;
+        }
+    };
+    if let Some(photo) = game.photo.add_tile(&tile) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or(0);
+        let path = format!("photo-{}.tga", timestamp);
+        match photo.save_tga(&path) {
+            Ok(()) => info!("Saved a {}x{} photo to {}", photo.width, photo.height, path),
+            Err(err) => error!("Failed to save photo: {}", err),
+        }
+    }
+}
+
+/// Switches to the next renderer debug view.
+fn cycle_debug_view(game: &mut Game<_RenderBackend>) {
+    let view = game.render_world.debug_view.next();
+    game.render_world.debug_view = view;
+    info!("Debug view: {}", view.name());
+}
+
+/// Switches to the next window mode.
+fn cycle_window_mode(game: &mut Game<_RenderBackend>) {
+    game.window_mode = game.window_mode.next();
+    _RenderBackend::set_window_mode(&mut game.render, game.window_mode);
+}
+
+/// Handles a resize of the window.
+fn window_resized(game: &mut Game<_RenderBackend>, width: f64, height: f64) {
+    _RenderBackend::window_resized(&mut game.render);
+    // Minimized windows have no size
+    if height > 0.0 {
+        game.camera.aspect = (width / height) as f32;
+    }
+}
+
+/// Handles a key press.
+fn key_pressed(game: &mut Game<_RenderBackend>, key: VirtualKeyCode) {
+    match key {
+        VirtualKeyCode::F2 => game.photo.toggle(&game.camera),
+        VirtualKeyCode::F3 => toggle_stats_overlay(game),
+        VirtualKeyCode::F4 => cycle_debug_view(game),
+        VirtualKeyCode::F11 => cycle_window_mode(game),
+        VirtualKeyCode::F12 if game.photo.is_active() => game.photo.start_capture(),
+        _ if game.photo.is_active() => {
+            if let Some(action) = photo_action(key) {
+                game.photo.apply(action);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Handles a touch gesture. Pinching zooms in photo mode.
+fn gesture_recognized(game: &mut Game<_RenderBackend>, gesture: Gesture) {
+    match gesture {
+        Gesture::Pinch { scale, .. } if game.photo.is_active() => {
+            if scale > 1.0 {
+                game.photo.apply(PhotoAction::ZoomIn);
+            } else if scale < 1.0 {
+                game.photo.apply(PhotoAction::ZoomOut);
+            }
+        }
+        Gesture::LongPress { .. } => game.photo.toggle(&game.camera),
+        _ => (),
+    }
+}
+
+/// Returns the photo mode control bound to a key.
+fn photo_action(key: VirtualKeyCode) -> Option<PhotoAction> {
+    match key {
+        VirtualKeyCode::W => Some(PhotoAction::Forward),
+        VirtualKeyCode::S => Some(PhotoAction::Back),
+        VirtualKeyCode::A => Some(PhotoAction::Left),
+        VirtualKeyCode::D => Some(PhotoAction::Right),
+        VirtualKeyCode::Space => Some(PhotoAction::Up),
+        VirtualKeyCode::LShift => Some(PhotoAction::Down),
+        VirtualKeyCode::Q => Some(PhotoAction::RollLeft),
+        VirtualKeyCode::E => Some(PhotoAction::RollRight),
+        VirtualKeyCode::Equals => Some(PhotoAction::ZoomIn),
+        VirtualKeyCode::Minus => Some(PhotoAction::ZoomOut),
+        VirtualKeyCode::F => Some(PhotoAction::NextFilter),
+        _ => None,
+    }
+}
+
+/// Uploads models dropped on the window and places them in
+/// front of the camera, if `Game::auto_import` is enabled.
+fn import_dropped_files(game: &mut Game<_RenderBackend>) {
+    if !game.auto_import {
+        return;
+    }
+    let dropped = game
+        .file_drops
+        .iter()
+        .filter_map(|event| match event {
+            FileDropEvent::Dropped(path) => Some(path.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    for path in dropped {
+        match AssetKind::of(&path) {
+            AssetKind::Model => {
+                match render::import_model::<_RenderBackend>(&mut game.render, &path) {
+                    Ok(model_index) => {
+                        let target = game.camera.world_target();
+                        let render =
+                            _RenderBackend::create_obj_render(model_index, 0, &mut game.render);
+                        game.world.add_obj(Object::new(
+                            render,
+                            Location::new(target.x, target.y, target.z),
+                        ));
+                        info!("Imported {}", path.display());
+                    }
+                    Err(err) => warn!("Failed to import {}: {}", path.display(), err),
+                }
+            }
+            AssetKind::Texture => warn!(
+                "Cannot import {}: textures are not drawn yet",
+                path.display()
+            ),
+            AssetKind::Unknown => warn!("Cannot import {}: unknown file type", path.display()),
+        }
     }
 }
 
 /// Polls events
 #[cfg(not(feature = "gl"))]
 fn poll_events(game: &mut Game<_RenderBackend>) {
+    use winit::{ElementState, KeyboardInput};
     let mut running = true;
-    let events_loop = &mut game.render.events_loop;
+    let mut keys = Vec::new();
+    let mut drops = Vec::new();
+    let mut touches = Vec::new();
+    let mut resized = None;
+    let start_time = game.start_time;
+    let events_loop = match game.render.events_loop {
+        Some(ref mut events_loop) => events_loop,
+        // Headless contexts have no window to send events
+        None => return,
+    };
     events_loop.poll_events(|event| match event {
         winit::Event::WindowEvent { event, .. } => match event {
             winit::WindowEvent::CloseRequested => running = false,
+            winit::WindowEvent::Resized(size) => resized = Some(size),
+            winit::WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => keys.push(key),
+            winit::WindowEvent::HoveredFile(path) => drops.push(FileDropEvent::Hovered(path)),
+            winit::WindowEvent::DroppedFile(path) => drops.push(FileDropEvent::Dropped(path)),
+            winit::WindowEvent::HoveredFileCancelled => drops.push(FileDropEvent::Cancelled),
+            winit::WindowEvent::Touch(touch) => touches.push(TouchEvent {
+                id: touch.id,
+                phase: touch.phase.into(),
+                position: (touch.location.x, touch.location.y),
+                time: get_time(&start_time),
+            }),
             _ => (),
         },
         _ => (),
     });
     game.running = running;
+    game.file_drops = drops;
+    if let Some(size) = resized {
+        window_resized(game, size.width, size.height);
+    }
+    for key in keys {
+        key_pressed(game, key);
+    }
+    import_dropped_files(game);
+
+    let mut gestures = game.gestures.update(get_time(&start_time));
+    for touch in touches {
+        gestures.extend(game.gestures.handle(touch));
+    }
+    for gesture in gestures {
+        gesture_recognized(game, gesture);
+    }
 }
 
 /// Polls events
 #[cfg(feature = "gl")]
 fn poll_events(game: &mut Game<_RenderBackend>) {
+    use glium::glutin::{ElementState, KeyboardInput};
     let mut running = true;
+    let mut keys = Vec::new();
+    let mut drops = Vec::new();
+    let mut touches = Vec::new();
+    let mut resized = None;
+    let start_time = game.start_time;
     let events_loop = &mut game.render.events_loop;
     events_loop.poll_events(|event| match event {
         glium::glutin::Event::WindowEvent { event, .. } => match event {
             glium::glutin::WindowEvent::CloseRequested => running = false,
+            glium::glutin::WindowEvent::Resized(size) => resized = Some(size),
+            glium::glutin::WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => keys.push(key),
+            glium::glutin::WindowEvent::HoveredFile(path) => {
+                drops.push(FileDropEvent::Hovered(path))
+            }
+            glium::glutin::WindowEvent::DroppedFile(path) => {
+                drops.push(FileDropEvent::Dropped(path))
+            }
+            glium::glutin::WindowEvent::HoveredFileCancelled => {
+                drops.push(FileDropEvent::Cancelled)
+            }
+            glium::glutin::WindowEvent::Touch(touch) => touches.push(TouchEvent {
+                id: touch.id,
+                phase: touch.phase.into(),
+                position: (touch.location.x, touch.location.y),
+                time: get_time(&start_time),
+            }),
             _ => (),
         },
         _ => (),
     });
     game.running = running;
+    game.file_drops = drops;
+    if let Some(size) = resized {
+        window_resized(game, size.width, size.height);
+    }
+    for key in keys {
+        key_pressed(game, key);
+    }
+    import_dropped_files(game);
+
+    let mut gestures = game.gestures.update(get_time(&start_time));
+    for touch in touches {
+        gestures.extend(game.gestures.handle(touch));
+    }
+    for gesture in gestures {
+        gesture_recognized(game, gesture);
+    }
 }
 
 /// Returns the current time in milleseconds,
@@ -0,0 +1,196 @@
+//! Loading of mods.
+//!
+//! A mod is a directory inside `mods/` with the same layout
+//! as `assets/`. When an asset is loaded, each mod is checked
+//! in priority order and the first one containing the file
+//! overrides the base asset.
+//!
+//! A mod may describe itself with a `mod.txt` file of
+//! `key = value` lines:
+//!
+//! ```text
+//! name = Better Swords
+//! version = 1.2
+//! priority = 10
+//! ```
+//!
+//! Mods with a higher priority override those with a lower one.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The directory mods are discovered in
+pub const MODS_DIR: &str = "mods";
+/// The directory containing the base assets
+pub const ASSETS_DIR: &str = "assets";
+/// The name of a mod's metadata file
+const MANIFEST: &str = "mod.txt";
+
+/// Metadata for a single mod.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModInfo {
+    /// Defaults to the name of the mod's directory
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    /// Mods with a higher priority override those with a lower one
+    pub priority: i32,
+    /// The mod's directory
+    pub path: PathBuf,
+}
+
+impl ModInfo {
+    /// Parses a mod's metadata from the contents of its `mod.txt`.
+    /// Unknown keys are ignored.
+    pub fn parse(path: PathBuf, manifest: &str) -> ModInfo {
+        let mut info = ModInfo {
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            version: String::new(),
+            description: String::new(),
+            priority: 0,
+            path,
+        };
+
+        for line in manifest.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.find('=') {
+                Some(index) => (line[..index].trim(), line[index + 1..].trim()),
+                None => {
+                    warn!("Ignoring line in {}: {}", info.name, line);
+                    continue;
+                }
+            };
+            match key {
+                "name" => info.name = value.to_string(),
+                "version" => info.version = value.to_string(),
+                "description" => info.description = value.to_string(),
+                "priority" => match value.parse() {
+                    Ok(priority) => info.priority = priority,
+                    Err(_) => warn!("Invalid priority in {}: {}", info.name, value),
+                },
+                _ => (),
+            }
+        }
+        info
+    }
+}
+
+/// The set of mounted mods.
+pub struct ModLoader {
+    /// Mounted mods, highest priority first
+    mods: Vec<ModInfo>,
+}
+
+impl ModLoader {
+    /// Creates a loader with no mods, which
+    /// always resolves to the base assets.
+    pub fn empty() -> ModLoader {
+        ModLoader { mods: Vec::new() }
+    }
+
+    /// Mounts every mod directory inside `dir`. A
+    /// missing directory is treated as having no mods.
+    pub fn discover<P: AsRef<Path>>(dir: P) -> ModLoader {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return ModLoader::empty(),
+        };
+
+        let mut loader = ModLoader::empty();
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            if !path.is_dir() {
+                continue;
+            }
+            let manifest = fs::read_to_string(path.join(MANIFEST)).unwrap_or_default();
+            loader.mount(ModInfo::parse(path, &manifest));
+        }
+        loader
+    }
+
+    /// Mounts a mod, keeping mods sorted by priority.
+    /// Mods of equal priority are ordered by name.
+    pub fn mount(&mut self, info: ModInfo) {
+        info!(
+            "Mounted mod {} {} (priority {})",
+            info.name, info.version, info.priority
+        );
+        let position = self
+            .mods
+            .iter()
+            .position(|other| {
+                other.priority < info.priority
+                    || (other.priority == info.priority && other.name > info.name)
+            })
+            .unwrap_or(self.mods.len());
+        self.mods.insert(position, info);
+    }
+
+    /// Returns the mounted mods, highest priority first.
+    pub fn mods(&self) -> &[ModInfo] {
+        &self.mods
+    }
+
+    /// Returns the path to load an asset from. `path` is relative to
+    /// the assets directory, such as `models/cube.obj`.
+    pub fn resolve(&self, path: &str) -> PathBuf {
+        self.resolve_with(path, |path| path.is_file())
+    }
+
+    /// Like `resolve`, but uses `exists` to check for files.
+    fn resolve_with<F: Fn(&Path) -> bool>(&self, path: &str, exists: F) -> PathBuf {
+        self.mods
+            .iter()
+            .map(|info| info.path.join(path))
+            .find(|path| exists(path))
+            .unwrap_or_else(|| Path::new(ASSETS_DIR).join(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_manifest() {
+        let info = ModInfo::parse(
+            PathBuf::from("mods/swords"),
+            "name = Better Swords\n# comment\nversion = 1.2\npriority = 10\n",
+        );
+        assert_eq!(info.name, "Better Swords");
+        assert_eq!(info.version, "1.2");
+        assert_eq!(info.priority, 10);
+
+        let info = ModInfo::parse(PathBuf::from("mods/swords"), "");
+        assert_eq!(info.name, "swords");
+        assert_eq!(info.priority, 0);
+    }
+
+    #[test]
+    fn priority_override() {
+        let mut loader = ModLoader::empty();
+        loader.mount(ModInfo::parse(PathBuf::from("mods/low"), "priority = 1"));
+        loader.mount(ModInfo::parse(PathBuf::from("mods/high"), "priority = 5"));
+
+        let names = loader.mods().iter().map(|info| &info.name[..]);
+        assert_eq!(names.collect::<Vec<_>>(), vec!["high", "low"]);
+
+        let exists = |path: &Path| path.starts_with("mods/low") || path.ends_with("sword.obj");
+        assert_eq!(
+            loader.resolve_with("models/sword.obj", exists),
+            PathBuf::from("mods/high/models/sword.obj")
+        );
+        assert_eq!(
+            loader.resolve_with("models/cube.obj", exists),
+            PathBuf::from("mods/low/models/cube.obj")
+        );
+
+        let base = ModLoader::empty().resolve_with("models/cube.obj", |_| false);
+        assert_eq!(base, PathBuf::from("assets/models/cube.obj"));
+    }
+}
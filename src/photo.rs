@@ -0,0 +1,505 @@
+//! Photo mode.
+//!
+//! Toggled with F2. While active the simulation is paused and
+//! the view switches to a free camera, starting from where the
+//! game camera was, which can be moved, rolled and zoomed.
+//! Camera effects such as shake are not applied to it.
+//!
+//! Photos are captured at a multiple of the window's size by
+//! rendering the view in tiles. Each tile is drawn with an
+//! off-center projection covering its part of the view, copied
+//! back, and stitched into the photo. The selected filter is
+//! then applied and the photo saved as a TGA file.
+use glm::*;
+use render::{Camera, CapturedFrame, Projection};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// World units moved per key press
+const MOVE_STEP: f32 = 4.0;
+/// Degrees rolled per key press
+const ROLL_STEP: f32 = 2.0;
+/// Radians of field of view changed per key press
+const ZOOM_STEP: f32 = 0.05;
+/// Narrowest and widest field of view, in radians
+const FOV_RANGE: (f32, f32) = (0.1, 2.5);
+/// Tiles along each side of a captured photo, so
+/// photos have this many times the window's resolution
+pub const CAPTURE_SCALE: u32 = 4;
+
+/// A control for the free camera.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PhotoAction {
+    Forward,
+    Back,
+    Left,
+    Right,
+    Up,
+    Down,
+    RollLeft,
+    RollRight,
+    ZoomIn,
+    ZoomOut,
+    /// Selects the next filter applied to captured photos
+    NextFilter,
+}
+
+/// A filter applied to a photo before it is saved.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PhotoFilter {
+    None,
+    Grayscale,
+    Sepia,
+    /// Darkens the corners
+    Vignette,
+}
+
+impl PhotoFilter {
+    /// Returns the filter after this one, wrapping around.
+    pub fn next(self) -> PhotoFilter {
+        match self {
+            PhotoFilter::None => PhotoFilter::Grayscale,
+            PhotoFilter::Grayscale => PhotoFilter::Sepia,
+            PhotoFilter::Sepia => PhotoFilter::Vignette,
+            PhotoFilter::Vignette => PhotoFilter::None,
+        }
+    }
+
+    /// Applies the filter to RGBA pixels in rows from the top.
+    pub fn apply(self, width: u32, height: u32, rgba: &mut [u8]) {
+        let center = (width as f32 / 2.0, height as f32 / 2.0);
+        let max_distance = (center.0 * center.0 + center.1 * center.1).sqrt();
+        for (index, pixel) in rgba.chunks_mut(4).enumerate() {
+            let (r, g, b) = (
+                f32::from(pixel[0]),
+                f32::from(pixel[1]),
+                f32::from(pixel[2]),
+            );
+            let (r, g, b) = match self {
+                PhotoFilter::None => return,
+                PhotoFilter::Grayscale => {
+                    let luma = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+                    (luma, luma, luma)
+                }
+                PhotoFilter::Sepia => (
+                    0.393 * r + 0.769 * g + 0.189 * b,
+                    0.349 * r + 0.686 * g + 0.168 * b,
+                    0.272 * r + 0.534 * g + 0.131 * b,
+                ),
+                PhotoFilter::Vignette => {
+                    let x = (index as u32 % width) as f32 + 0.5 - center.0;
+                    let y = (index as u32 / width) as f32 + 0.5 - center.1;
+                    let distance = (x * x + y * y).sqrt() / max_distance;
+                    let factor = 1.0 - 0.6 * distance * distance;
+                    (r * factor, g * factor, b * factor)
+                }
+            };
+            pixel[0] = r.min(255.0) as u8;
+            pixel[1] = g.min(255.0) as u8;
+            pixel[2] = b.min(255.0) as u8;
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PhotoFilter::None => "none",
+            PhotoFilter::Grayscale => "grayscale",
+            PhotoFilter::Sepia => "sepia",
+            PhotoFilter::Vignette => "vignette",
+        }
+    }
+}
+
+/// A captured photo.
+pub struct Photo {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA pixels in rows from the top
+    pub rgba: Vec<u8>,
+}
+
+impl Photo {
+    /// Saves the photo as an uncompressed 32-bit TGA file.
+    pub fn save_tga<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        if self.width > u32::from(u16::max_value()) || self.height > u32::from(u16::max_value()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "photo is too large for a TGA file",
+            ));
+        }
+        let mut file = io::BufWriter::new(File::create(path)?);
+        file.write_all(&tga_header(self.width as u16, self.height as u16))?;
+        for pixel in self.rgba.chunks(4) {
+            file.write_all(&[pixel[2], pixel[1], pixel[0], pixel[3]])?;
+        }
+        file.flush()
+    }
+}
+
+/// Returns the header of an uncompressed true color
+/// TGA image with 8 bits of alpha, stored from the top.
+fn tga_header(width: u16, height: u16) -> [u8; 18] {
+    let mut header = [0; 18];
+    // Uncompressed true color
+    header[2] = 2;
+    header[12..14].copy_from_slice(&[width as u8, (width >> 8) as u8]);
+    header[14..16].copy_from_slice(&[height as u8, (height >> 8) as u8]);
+    header[16] = 32;
+    // 8 alpha bits, with rows stored from the top
+    header[17] = 0x28;
+    header
+}
+
+/// A photo being captured in tiles.
+struct Capture {
+    /// The camera the whole photo is taken with
+    camera: Camera,
+    /// The next tile to render, counted in rows from the top
+    next: u32,
+    /// Dimensions of each tile, known once the first is rendered
+    tile_size: Option<(u32, u32)>,
+    rgba: Vec<u8>,
+}
+
+impl Capture {
+    /// Returns the camera which renders the next
+    /// tile of a photo taken with `camera`.
+    fn tile_camera(&self) -> Camera {
+        let mut camera = self.camera.clone();
+        let (column, row) = (self.next % CAPTURE_SCALE, self.next / CAPTURE_SCALE);
+        let scale = CAPTURE_SCALE as f32;
+        camera.projection_mode = match camera.projection_mode {
+            Projection::Custom(matrix) => {
+                // Scales the tile's part of clip space to fill it
+                let x = scale - 1.0 - 2.0 * column as f32;
+                let y = -(scale - 1.0 - 2.0 * row as f32);
+                let crop = Mat4::new(
+                    vec4(scale, 0.0, 0.0, 0.0),
+                    vec4(0.0, scale, 0.0, 0.0),
+                    vec4(0.0, 0.0, 1.0, 0.0),
+                    vec4(x, y, 0.0, 1.0),
+                );
+                Projection::Custom(crop * matrix)
+            }
+            mode => {
+                let (left, right, bottom, top) = match mode {
+                    Projection::OffCenter {
+                        left,
+                        right,
+                        bottom,
+                        top,
+                    } => (left, right, bottom, top),
+                    _ => {
+                        let top = camera.near * (camera.fov / 2.0).tan();
+                        let right = top * camera.aspect;
+                        (-right, right, -top, top)
+                    }
+                };
+                let width = (right - left) / scale;
+                let height = (top - bottom) / scale;
+                Projection::OffCenter {
+                    left: left + width * column as f32,
+                    right: left + width * (column + 1) as f32,
+                    bottom: top - height * (row + 1) as f32,
+                    top: top - height * row as f32,
+                }
+            }
+        };
+        camera
+    }
+
+    /// Copies a rendered tile into the photo. Returns an
+    /// error if the tile's size differs from the others.
+    fn add_tile(&mut self, tile: &CapturedFrame) -> Result<(), ()> {
+        let (width, height) = *self.tile_size.get_or_insert((tile.width, tile.height));
+        if (width, height) != (tile.width, tile.height) {
+            return Err(());
+        }
+        if self.rgba.is_empty() {
+            let size = (width * CAPTURE_SCALE) as usize * (height * CAPTURE_SCALE) as usize * 4;
+            self.rgba = vec![0; size];
+        }
+
+        let (column, row) = (self.next % CAPTURE_SCALE, self.next / CAPTURE_SCALE);
+        let photo_row = (width * CAPTURE_SCALE * 4) as usize;
+        let tile_row = (width * 4) as usize;
+        for (y, pixels) in tile.rgba.chunks(tile_row).enumerate() {
+            let start =
+                (row * height) as usize * photo_row + y * photo_row + column as usize * tile_row;
+            self.rgba[start..start + tile_row].copy_from_slice(pixels);
+        }
+        self.next += 1;
+        Ok(())
+    }
+
+    fn finished(&self) -> bool {
+        self.next == CAPTURE_SCALE * CAPTURE_SCALE
+    }
+}
+
+/// The photo mode state.
+pub struct PhotoMode {
+    active: bool,
+    camera: Camera,
+    /// Roll around the view direction in degrees
+    roll: f32,
+    /// Applied to photos before they are saved
+    filter: PhotoFilter,
+    /// The photo being captured, if any
+    capture: Option<Capture>,
+}
+
+impl PhotoMode {
+    pub fn new() -> PhotoMode {
+        PhotoMode {
+            active: false,
+            camera: Camera::default(),
+            roll: 0.0,
+            filter: PhotoFilter::None,
+            capture: None,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Enters or leaves photo mode. On entering, the
+    /// free camera starts at `camera`.
+    pub fn toggle(&mut self, camera: &Camera) {
+        self.active = !self.active;
+        self.capture = None;
+        if self.active {
+            self.camera = camera.clone();
+            self.roll = 0.0;
+        }
+    }
+
+    pub fn filter(&self) -> PhotoFilter {
+        self.filter
+    }
+
+    /// Starts capturing a photo from the free camera.
+    /// The view is rendered one tile per frame, using
+    /// the camera returned by `capture_camera()`.
+    pub fn start_capture(&mut self) {
+        if self.active && self.capture.is_none() {
+            self.capture = Some(Capture {
+                camera: self.camera(),
+                next: 0,
+                tile_size: None,
+                rgba: Vec::new(),
+            });
+        }
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    /// Returns the camera to render the next tile of
+    /// the photo being captured with, if any.
+    pub fn capture_camera(&self) -> Option<Camera> {
+        self.capture.as_ref().map(|capture| capture.tile_camera())
+    }
+
+    /// Adds the tile rendered with `capture_camera()` to the
+    /// photo, returning the filtered photo once every tile has
+    /// been added. The capture is cancelled if the tile's size
+    /// differs from the others, such as after a resize.
+    pub fn add_tile(&mut self, tile: &CapturedFrame) -> Option<Photo> {
+        let added = match self.capture {
+            Some(ref mut capture) => capture.add_tile(tile),
+            None => return None,
+        };
+        if added.is_err() {
+            warn!("The window was resized while capturing, so the photo was cancelled");
+            self.capture = None;
+            return None;
+        }
+        if !self
+            .capture
+            .as_ref()
+            .map_or(false, |capture| capture.finished())
+        {
+            return None;
+        }
+
+        let capture = self.capture.take().unwrap();
+        let (width, height) = capture.tile_size.unwrap();
+        let mut photo = Photo {
+            width: width * CAPTURE_SCALE,
+            height: height * CAPTURE_SCALE,
+            rgba: capture.rgba,
+        };
+        self.filter
+            .apply(photo.width, photo.height, &mut photo.rgba);
+        Some(photo)
+    }
+
+    /// Stops capturing without finishing the photo.
+    pub fn cancel_capture(&mut self) {
+        self.capture = None;
+    }
+
+    /// Moves, rolls or zooms the free camera.
+    pub fn apply(&mut self, action: PhotoAction) {
+        let forward = normalize(self.camera.target - self.camera.position);
+        let right = normalize(cross(forward, self.camera.up));
+        let up = cross(right, forward);
+        let offset = match action {
+            PhotoAction::Forward => forward * MOVE_STEP,
+            PhotoAction::Back => forward * -MOVE_STEP,
+            PhotoAction::Right => right * MOVE_STEP,
+            PhotoAction::Left => right * -MOVE_STEP,
+            PhotoAction::Up => up * MOVE_STEP,
+            PhotoAction::Down => up * -MOVE_STEP,
+            PhotoAction::RollLeft => {
+                self.roll -= ROLL_STEP;
+                return;
+            }
+            PhotoAction::RollRight => {
+                self.roll += ROLL_STEP;
+                return;
+            }
+            PhotoAction::ZoomIn => {
+                self.zoom(-ZOOM_STEP);
+                return;
+            }
+            PhotoAction::ZoomOut => {
+                self.zoom(ZOOM_STEP);
+                return;
+            }
+            PhotoAction::NextFilter => {
+                self.filter = self.filter.next();
+                info!("Photo filter: {}", self.filter.name());
+                return;
+            }
+        };
+        self.camera.position = self.camera.position + offset;
+        self.camera.target = self.camera.target + offset;
+    }
+
+    fn zoom(&mut self, amount: f32) {
+        self.camera.fov = (self.camera.fov + amount).max(FOV_RANGE.0).min(FOV_RANGE.1);
+    }
+
+    /// Returns the free camera with its roll applied.
+    pub fn camera(&self) -> Camera {
+        let mut camera = self.camera.clone();
+        let axis = normalize(camera.target - camera.position);
+        let (sin, cos) = radians(self.roll).sin_cos();
+        let up = camera.up;
+        // Rodrigues' rotation of the up vector around the view direction
+        camera.up = up * cos + cross(axis, up) * sin + axis * (dot(axis, up) * (1.0 - cos));
+        camera
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn free_camera_starts_at_game_camera() {
+        let mut photo = PhotoMode::new();
+        let camera = Camera::new(
+            vec3(0.0, 0.0, 0.0),
+            vec3(0.0, 0.0, -1.0),
+            vec3(0.0, 1.0, 0.0),
+        );
+        photo.toggle(&camera);
+        assert!(photo.is_active());
+
+        photo.apply(PhotoAction::Forward);
+        assert_eq!(photo.camera().position.z, -MOVE_STEP);
+
+        photo.apply(PhotoAction::ZoomIn);
+        assert!(photo.camera().fov < camera.fov);
+    }
+
+    #[test]
+    fn roll_rotates_up() {
+        let mut photo = PhotoMode::new();
+        photo.toggle(&Camera::new(
+            vec3(0.0, 0.0, 0.0),
+            vec3(0.0, 0.0, -1.0),
+            vec3(0.0, 1.0, 0.0),
+        ));
+        for _ in 0..45 {
+            photo.apply(PhotoAction::RollRight);
+        }
+
+        let up = photo.camera().up;
+        assert!(up.y.abs() < 0.001);
+        assert!((up.x.abs() - 1.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn tiles_are_stitched() {
+        let mut photo = PhotoMode::new();
+        photo.toggle(&Camera::default());
+        photo.start_capture();
+
+        let mut result = None;
+        for index in 0..CAPTURE_SCALE * CAPTURE_SCALE {
+            let tile = CapturedFrame {
+                width: 2,
+                height: 1,
+                rgba: vec![index as u8; 8],
+            };
+            assert!(result.is_none());
+            result = photo.add_tile(&tile);
+        }
+        let result = result.unwrap();
+        assert!(!photo.is_capturing());
+        assert_eq!(
+            (result.width, result.height),
+            (2 * CAPTURE_SCALE, CAPTURE_SCALE)
+        );
+        // The second row of tiles starts on the second row of pixels
+        let row = (result.width * 4) as usize;
+        assert_eq!(result.rgba[row], CAPTURE_SCALE as u8);
+        assert_eq!(result.rgba[row - 1], CAPTURE_SCALE as u8 - 1);
+    }
+
+    #[test]
+    fn tiles_cover_the_view() {
+        let mut photo = PhotoMode::new();
+        let camera = Camera::default();
+        photo.toggle(&camera);
+        photo.start_capture();
+
+        let top = camera.near * (camera.fov / 2.0).tan();
+        let right = top * camera.aspect;
+        match photo.capture_camera().unwrap().projection_mode {
+            Projection::OffCenter {
+                left,
+                right: tile_right,
+                top: tile_top,
+                bottom,
+            } => {
+                assert_eq!(left, -right);
+                assert_eq!(tile_top, top);
+                assert!((tile_right - (-right + 2.0 * right / CAPTURE_SCALE as f32)).abs() < 1e-6);
+                assert!((bottom - (top - 2.0 * top / CAPTURE_SCALE as f32)).abs() < 1e-6);
+            }
+            mode => panic!("unexpected projection {:?}", mode),
+        }
+    }
+
+    #[test]
+    fn filters() {
+        let mut pixels = vec![255, 0, 0, 255];
+        PhotoFilter::Grayscale.apply(1, 1, &mut pixels);
+        assert_eq!(pixels[0], pixels[1]);
+        assert_eq!(pixels[3], 255);
+
+        // The center of a vignette is left alone
+        let mut pixels = vec![200; 3 * 3 * 4];
+        PhotoFilter::Vignette.apply(3, 3, &mut pixels);
+        assert_eq!(pixels[4 * 4], 200);
+        assert!(pixels[0] < 200);
+    }
+}
@@ -0,0 +1,201 @@
+//! Per-frame timing scopes and hitch detection.
+//!
+//! The main loop times each phase of a frame (and each
+//! system run by the schedule) into a `FrameProfiler`.
+//! At the end of the frame the `HitchDetector` checks the
+//! total against a budget and, if it was exceeded, logs
+//! the scopes which took the longest.
+use std::time::Instant;
+
+/// Number of hitches kept by the detector
+const WORST_HITCHES: usize = 8;
+/// Number of scopes included in hitch log messages
+const LOGGED_SCOPES: usize = 3;
+
+/// Collects named durations for a single frame.
+pub struct FrameProfiler {
+    /// The index of the current frame
+    frame: u64,
+    /// Scope names and their durations in milliseconds
+    scopes: Vec<(&'static str, f64)>,
+}
+
+impl FrameProfiler {
+    pub fn new() -> FrameProfiler {
+        FrameProfiler {
+            frame: 0,
+            scopes: Vec::new(),
+        }
+    }
+
+    /// Clears the scopes recorded in the previous frame.
+    pub fn begin_frame(&mut self) {
+        self.frame += 1;
+        self.scopes.clear();
+    }
+
+    /// Records a duration in milliseconds for the named scope.
+    /// Durations recorded for the same scope within a frame are summed.
+    pub fn record(&mut self, name: &'static str, millis: f64) {
+        match self.scopes.iter_mut().find(|scope| scope.0 == name) {
+            Some(scope) => scope.1 += millis,
+            None => self.scopes.push((name, millis)),
+        }
+    }
+
+    /// Runs `f`, recording how long it took under the named scope.
+    pub fn time<R, F: FnOnce() -> R>(&mut self, name: &'static str, f: F) -> R {
+        let start = Instant::now();
+        let result = f();
+        self.record(name, millis_since(start));
+        result
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn scopes(&self) -> &[(&'static str, f64)] {
+        &self.scopes
+    }
+}
+
+/// Returns the milliseconds elapsed since `start`.
+pub fn millis_since(start: Instant) -> f64 {
+    let elapsed = start.elapsed();
+    (elapsed.as_secs() as f64) * 1000.0 + (elapsed.subsec_nanos() as f64) / 1_000_000.0
+}
+
+/// A frame which exceeded the budget.
+#[derive(Clone, Debug)]
+pub struct Hitch {
+    pub frame: u64,
+    /// Total frame time in milliseconds
+    pub frame_time: f64,
+    /// Recorded scopes, longest first
+    pub scopes: Vec<(&'static str, f64)>,
+}
+
+/// Flags frames which take longer than a budget.
+pub struct HitchDetector {
+    /// The frame time budget in milliseconds
+    pub budget: f64,
+    /// The worst hitches so far, longest first
+    worst: Vec<Hitch>,
+    /// Total number of hitches detected
+    count: u64,
+}
+
+impl HitchDetector {
+    /// Creates a detector with the specified budget in milliseconds.
+    pub fn new(budget: f64) -> HitchDetector {
+        HitchDetector {
+            budget,
+            worst: Vec::with_capacity(WORST_HITCHES + 1),
+            count: 0,
+        }
+    }
+
+    /// Checks a finished frame against the budget, logging
+    /// and storing it if it was over. Returns whether the
+    /// frame was a hitch.
+    pub fn end_frame(&mut self, frame_time: f64, profiler: &FrameProfiler) -> bool {
+        if frame_time <= self.budget {
+            return false;
+        }
+        self.count += 1;
+
+        let mut scopes = profiler.scopes().to_vec();
+        scopes.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+
+        let offenders = scopes
+            .iter()
+            .take(LOGGED_SCOPES)
+            .map(|&(name, millis)| format!("{} {:.1} ms", name, millis))
+            .collect::<Vec<_>>()
+            .join(", ");
+        warn!(
+            "Frame {} took {:.1} ms (budget {:.1} ms): {}",
+            profiler.frame(),
+            frame_time,
+            self.budget,
+            offenders
+        );
+
+        let hitch = Hitch {
+            frame: profiler.frame(),
+            frame_time,
+            scopes,
+        };
+        let position = self
+            .worst
+            .iter()
+            .position(|worst| worst.frame_time < frame_time)
+            .unwrap_or(self.worst.len());
+        self.worst.insert(position, hitch);
+        self.worst.truncate(WORST_HITCHES);
+        true
+    }
+
+    /// Returns the worst hitches detected so far, longest first.
+    pub fn worst(&self) -> &[Hitch] {
+        &self.worst
+    }
+
+    /// Returns the total number of hitches detected.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes_are_summed() {
+        let mut profiler = FrameProfiler::new();
+        profiler.begin_frame();
+        profiler.record("tick", 1.0);
+        profiler.record("render", 4.0);
+        profiler.record("tick", 2.0);
+
+        assert_eq!(profiler.scopes(), &[("tick", 3.0), ("render", 4.0)]);
+
+        profiler.begin_frame();
+        assert!(profiler.scopes().is_empty());
+    }
+
+    #[test]
+    fn hitches_over_budget() {
+        let mut profiler = FrameProfiler::new();
+        let mut detector = HitchDetector::new(16.0);
+
+        profiler.begin_frame();
+        profiler.record("tick", 2.0);
+        assert!(!detector.end_frame(15.0, &profiler));
+
+        profiler.begin_frame();
+        profiler.record("tick", 2.0);
+        profiler.record("render", 30.0);
+        assert!(detector.end_frame(32.0, &profiler));
+
+        assert_eq!(detector.count(), 1);
+        let hitch = &detector.worst()[0];
+        assert_eq!(hitch.frame, 2);
+        assert_eq!(hitch.scopes[0], ("render", 30.0));
+    }
+
+    #[test]
+    fn keeps_worst_hitches() {
+        let profiler = FrameProfiler::new();
+        let mut detector = HitchDetector::new(0.0);
+        for i in 0..(WORST_HITCHES * 2) {
+            detector.end_frame(i as f64 + 1.0, &profiler);
+        }
+
+        let worst = detector.worst();
+        assert_eq!(worst.len(), WORST_HITCHES);
+        assert_eq!(worst[0].frame_time, (WORST_HITCHES * 2) as f64);
+    }
+}
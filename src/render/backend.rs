@@ -0,0 +1,169 @@
+//! Which graphics backend renders.
+//!
+//! A backend is chosen when building, with exactly one of the
+//! `vulkan`, `dx12`, `metal` or `gl` cargo features. Game code
+//! only uses the `RenderBackend` trait, and `_RenderBackend` is
+//! whichever backend was built, so the code is the same for
+//! all of them.
+//!
+//! `LUMINITE_BACKEND` names the backend a run expects, such as
+//! when comparing backends while debugging. Only one backend is
+//! compiled in, so it can't switch backends at runtime; instead
+//! startup fails if a different backend was built, rather than
+//! quietly debugging the wrong one.
+use std::fmt;
+use std::str::FromStr;
+
+/// Environment variable naming the expected backend
+pub const BACKEND_ENV: &str = "LUMINITE_BACKEND";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackendKind {
+    Vulkan,
+    Dx12,
+    Metal,
+    Gl,
+}
+
+impl BackendKind {
+    /// The backend this build renders with
+    #[cfg(feature = "gl")]
+    pub const COMPILED: BackendKind = BackendKind::Gl;
+    #[cfg(feature = "dx12")]
+    pub const COMPILED: BackendKind = BackendKind::Dx12;
+    #[cfg(feature = "metal")]
+    pub const COMPILED: BackendKind = BackendKind::Metal;
+    #[cfg(all(
+        feature = "vulkan",
+        not(any(feature = "dx12", feature = "metal", feature = "gl"))
+    ))]
+    pub const COMPILED: BackendKind = BackendKind::Vulkan;
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BackendKind::Vulkan => "Vulkan",
+            BackendKind::Dx12 => "DirectX 12",
+            BackendKind::Metal => "Metal",
+            BackendKind::Gl => "OpenGL",
+        }
+    }
+
+    /// Returns the cargo feature which builds this backend.
+    pub fn feature(self) -> &'static str {
+        match self {
+            BackendKind::Vulkan => "vulkan",
+            BackendKind::Dx12 => "dx12",
+            BackendKind::Metal => "metal",
+            BackendKind::Gl => "gl",
+        }
+    }
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for BackendKind {
+    type Err = BackendError;
+
+    /// Parses a backend's feature name or a common alias, ignoring case.
+    fn from_str(name: &str) -> Result<BackendKind, BackendError> {
+        match name.trim().to_lowercase().as_str() {
+            "vulkan" | "vk" => Ok(BackendKind::Vulkan),
+            "dx12" | "d3d12" => Ok(BackendKind::Dx12),
+            "metal" | "mtl" => Ok(BackendKind::Metal),
+            "gl" | "opengl" => Ok(BackendKind::Gl),
+            _ => Err(BackendError::Unknown(name.to_string())),
+        }
+    }
+}
+
+/// An error produced when selecting a backend.
+#[derive(Debug, PartialEq)]
+pub enum BackendError {
+    /// No backend has this name
+    Unknown(String),
+    /// The backend isn't the one this build renders with
+    NotCompiled {
+        requested: BackendKind,
+        compiled: BackendKind,
+    },
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackendError::Unknown(name) => write!(
+                f,
+                "unknown backend {:?}, expected vulkan, dx12, metal or gl",
+                name
+            ),
+            BackendError::NotCompiled {
+                requested,
+                compiled,
+            } => write!(
+                f,
+                "{} was requested but this build renders with {}; \
+                 rebuild with --features={}",
+                requested,
+                compiled,
+                requested.feature()
+            ),
+        }
+    }
+}
+
+/// Returns `compiled`, checking that it is the backend
+/// `requested`, if one was.
+pub fn select(requested: Option<&str>, compiled: BackendKind) -> Result<BackendKind, BackendError> {
+    match requested {
+        None => Ok(compiled),
+        Some(name) if name.trim().is_empty() => Ok(compiled),
+        Some(name) => {
+            let requested = name.parse::<BackendKind>()?;
+            if requested == compiled {
+                Ok(compiled)
+            } else {
+                Err(BackendError::NotCompiled {
+                    requested,
+                    compiled,
+                })
+            }
+        }
+    }
+}
+
+/// Returns the backend this build renders with, checking
+/// it against `LUMINITE_BACKEND` if that is set.
+pub fn from_env() -> Result<BackendKind, BackendError> {
+    let requested = std::env::var(BACKEND_ENV).ok();
+    select(
+        requested.as_ref().map(|name| name.as_str()),
+        BackendKind::COMPILED,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selection() {
+        assert_eq!(select(None, BackendKind::Vulkan), Ok(BackendKind::Vulkan));
+        assert_eq!(select(Some(""), BackendKind::Gl), Ok(BackendKind::Gl));
+        assert_eq!(select(Some("OpenGL"), BackendKind::Gl), Ok(BackendKind::Gl));
+        assert_eq!(
+            select(Some("metal"), BackendKind::Vulkan),
+            Err(BackendError::NotCompiled {
+                requested: BackendKind::Metal,
+                compiled: BackendKind::Vulkan,
+            })
+        );
+        assert_eq!(
+            select(Some("glide"), BackendKind::Vulkan),
+            Err(BackendError::Unknown("glide".to_string()))
+        );
+    }
+}
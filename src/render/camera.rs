@@ -0,0 +1,344 @@
+//! The camera the world is viewed from.
+//!
+//! Object locations are stored in double precision, but
+//! matrices are single precision, which jitters once values
+//! get large. Objects are therefore drawn relative to the
+//! camera: their offset from the camera is computed in double
+//! precision before being converted. The camera itself is
+//! kept precise by an `origin` which its position is relative
+//! to; `rebase()` moves the origin to the camera.
+use super::*;
+
+/// The shape of a camera's view frustum.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    /// A symmetric frustum from the camera's field of view and aspect ratio
+    Perspective,
+    /// An asymmetric frustum, with the edges given
+    /// as distances on the near plane
+    OffCenter {
+        left: f32,
+        right: f32,
+        bottom: f32,
+        top: f32,
+    },
+    /// A matrix used as is, which must follow the context's
+    /// `DepthMode`. Useful for oblique clipping planes,
+    /// such as for portals.
+    Custom(Mat4),
+}
+
+/// A perspective camera looking from `position` towards `target`.
+#[derive(Clone, Debug)]
+pub struct Camera {
+    /// The world position `position` and `target` are relative to
+    pub origin: DVec3,
+    pub position: Vec3,
+    pub target: Vec3,
+    pub up: Vec3,
+    /// Vertical field of view in radians
+    pub fov: f32,
+    /// Width divided by height
+    pub aspect: f32,
+    /// The near clipping distance
+    pub near: f32,
+    pub projection_mode: Projection,
+    /// Whether to ignore the far clipping distance
+    /// and project all the way to infinity
+    pub infinite_far: bool,
+    /// Offset of the projection in normalized device
+    /// coordinates, for sub-pixel jittering
+    pub jitter: Vec2,
+    /// Layers of the objects the camera draws
+    pub culling_mask: LayerMask,
+}
+
+impl Camera {
+    /// Creates a camera with the default field of view and aspect ratio.
+    pub fn new(position: Vec3, target: Vec3, up: Vec3) -> Camera {
+        Camera {
+            origin: dvec3(0.0, 0.0, 0.0),
+            position,
+            target,
+            up,
+            fov: glm::radians(45.0),
+            aspect: 4.0 / 3.0,
+            near: 0.1,
+            projection_mode: Projection::Perspective,
+            infinite_far: false,
+            jitter: vec2(0.0, 0.0),
+            culling_mask: LayerMask::ALL,
+        }
+    }
+
+    /// Returns the view matrix.
+    pub fn view(&self) -> Mat4 {
+        glm::ext::look_at(self.position, self.target, self.up)
+    }
+
+    /// Returns the view matrix with the camera at the
+    /// origin, for drawing objects relative to the camera.
+    pub fn relative_view(&self) -> Mat4 {
+        glm::ext::look_at(vec3(0.0, 0.0, 0.0), self.target - self.position, self.up)
+    }
+
+    /// Returns the camera's position in world space.
+    pub fn world_position(&self) -> DVec3 {
+        self.origin
+            + dvec3(
+                self.position.x as f64,
+                self.position.y as f64,
+                self.position.z as f64,
+            )
+    }
+
+    /// Returns the point the camera looks at in world space.
+    pub fn world_target(&self) -> DVec3 {
+        self.origin
+            + dvec3(
+                self.target.x as f64,
+                self.target.y as f64,
+                self.target.z as f64,
+            )
+    }
+
+    /// Returns the offset of `location` from the camera.
+    pub fn relative(&self, location: &world::Location) -> Vec3 {
+        let camera = self.world_position();
+        vec3(
+            (location.x - camera.x) as f32,
+            (location.y - camera.y) as f32,
+            (location.z - camera.z) as f32,
+        )
+    }
+
+    /// Moves the origin to the camera's position, so that
+    /// `position` is zero. The view does not change.
+    pub fn rebase(&mut self) {
+        let position = self.position;
+        self.origin = self.world_position();
+        self.position = vec3(0.0, 0.0, 0.0);
+        self.target = self.target - position;
+    }
+
+    /// Returns the projection matrix for depth laid out as
+    /// `mode`, clipping at `far` unless `infinite_far` is set.
+    pub fn projection(&self, far: f32, mode: DepthMode) -> Mat4 {
+        let far = if self.infinite_far { None } else { Some(far) };
+        let projection = match self.projection_mode {
+            Projection::Perspective => perspective(self.fov, self.aspect, self.near, far, mode),
+            Projection::OffCenter {
+                left,
+                right,
+                bottom,
+                top,
+            } => frustum(left, right, bottom, top, self.near, far, mode),
+            Projection::Custom(matrix) => matrix,
+        };
+        self.jittered(projection)
+    }
+
+    /// Offsets `projection` by the camera's jitter.
+    pub fn jittered(&self, projection: Mat4) -> Mat4 {
+        if self.jitter == vec2(0.0, 0.0) {
+            return projection;
+        }
+        let offset = vec3(self.jitter.x, self.jitter.y, 0.0);
+        glm::ext::translate(&num::one(), offset) * projection
+    }
+
+    /// Sets the jitter to an offset of `pixels` in a
+    /// viewport of the given dimensions.
+    pub fn set_jitter(&mut self, pixels: Vec2, dimensions: (u32, u32)) {
+        self.jitter = vec2(
+            2.0 * pixels.x / dimensions.0 as f32,
+            2.0 * pixels.y / dimensions.1 as f32,
+        );
+    }
+}
+
+/// Returns a perspective projection with a vertical field of
+/// view of `fov` radians. A `far` of `None` places the far
+/// plane at infinity.
+pub fn perspective(fov: f32, aspect: f32, near: f32, far: Option<f32>, mode: DepthMode) -> Mat4 {
+    let top = near * (fov / 2.0).tan();
+    let right = top * aspect;
+    frustum(-right, right, -top, top, near, far, mode)
+}
+
+/// Returns the projection of a frustum whose edges are `left`,
+/// `right`, `bottom` and `top` on the near plane. A `far` of
+/// `None` places the far plane at infinity.
+pub fn frustum(
+    left: f32,
+    right: f32,
+    bottom: f32,
+    top: f32,
+    near: f32,
+    far: Option<f32>,
+    mode: DepthMode,
+) -> Mat4 {
+    // Depth is (scale * z + offset) / -z, which is 0 or 1 at
+    // the near plane and reaches the other at the far plane
+    let (depth_scale, depth_offset) = match (mode, far) {
+        (DepthMode::Standard, Some(far)) => (-far / (far - near), -near * far / (far - near)),
+        (DepthMode::Standard, None) => (-1.0, -near),
+        (DepthMode::Reversed, Some(far)) => (near / (far - near), near * far / (far - near)),
+        (DepthMode::Reversed, None) => (0.0, near),
+    };
+    Mat4::new(
+        vec4(2.0 * near / (right - left), 0.0, 0.0, 0.0),
+        vec4(0.0, 2.0 * near / (top - bottom), 0.0, 0.0),
+        vec4(
+            (right + left) / (right - left),
+            (top + bottom) / (top - bottom),
+            depth_scale,
+            -1.0,
+        ),
+        vec4(0.0, 0.0, depth_offset, 0.0),
+    )
+}
+
+impl Default for Camera {
+    /// A camera looking straight down at the maze.
+    fn default() -> Self {
+        Camera::new(
+            vec3(512.0, 512.0, 512.0),
+            vec3(512.0, 0.0, 512.0),
+            vec3(1.0, 0.0, 0.0),
+        )
+    }
+}
+
+/// A point on a camera path.
+#[derive(Clone, Debug)]
+pub struct Keyframe {
+    /// Time since the start of the path in milliseconds
+    pub time: f64,
+    pub position: Vec3,
+    pub target: Vec3,
+}
+
+/// A camera path interpolated linearly between keyframes.
+pub struct CameraPath {
+    /// Keyframes in order of time
+    keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    /// Creates a path from keyframes, which must be in order of time.
+    pub fn new(keyframes: Vec<Keyframe>) -> CameraPath {
+        assert!(!keyframes.is_empty(), "camera path has no keyframes");
+        CameraPath { keyframes }
+    }
+
+    /// A path which descends towards the maze,
+    /// sweeps across it, and pulls back out.
+    pub fn maze_flyover() -> CameraPath {
+        let key = |time, position, target| Keyframe {
+            time,
+            position,
+            target,
+        };
+        CameraPath::new(vec![
+            key(0.0, vec3(512.0, 512.0, 512.0), vec3(512.0, 0.0, 512.0)),
+            key(5000.0, vec3(64.0, 96.0, 64.0), vec3(256.0, 0.0, 256.0)),
+            key(10000.0, vec3(512.0, 48.0, 256.0), vec3(768.0, 0.0, 512.0)),
+            key(15000.0, vec3(960.0, 96.0, 960.0), vec3(512.0, 0.0, 512.0)),
+            key(20000.0, vec3(512.0, 1024.0, 512.0), vec3(512.0, 0.0, 512.0)),
+        ])
+    }
+
+    /// Returns the length of the path in milliseconds.
+    pub fn duration(&self) -> f64 {
+        self.keyframes.last().unwrap().time
+    }
+
+    /// Returns the camera position and target at `time`.
+    /// Times outside the path are clamped to its ends.
+    pub fn sample(&self, time: f64) -> (Vec3, Vec3) {
+        let next = self
+            .keyframes
+            .iter()
+            .position(|key| key.time > time)
+            .unwrap_or(self.keyframes.len());
+        if next == 0 {
+            let first = &self.keyframes[0];
+            return (first.position, first.target);
+        }
+        if next == self.keyframes.len() {
+            let last = self.keyframes.last().unwrap();
+            return (last.position, last.target);
+        }
+
+        let (a, b) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        let t = ((time - a.time) / (b.time - a.time)) as f32;
+        (
+            a.position + (b.position - a.position) * t,
+            a.target + (b.target - a.target) * t,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_to_distant_camera() {
+        let mut camera = Camera::new(
+            vec3(0.5, 0.0, 0.0),
+            vec3(0.5, 0.0, -1.0),
+            vec3(0.0, 1.0, 0.0),
+        );
+        camera.origin = dvec3(1.0e9, 0.0, 0.0);
+        camera.rebase();
+        assert_eq!(camera.position.x, 0.0);
+        assert_eq!(camera.target.z, -1.0);
+
+        let location = world::Location::new(1.0e9 + 2.5, 0.0, 0.0);
+        assert_eq!(camera.relative(&location).x, 2.0);
+    }
+
+    #[test]
+    fn depth_range() {
+        let depth = |projection: Mat4, z: f32| {
+            let clip = projection * vec4(0.0, 0.0, z, 1.0);
+            clip.z / clip.w
+        };
+        let close = |a: f32, b: f32| (a - b).abs() < 1.0e-5;
+        for &(mode, near, far) in &[
+            (DepthMode::Standard, 0.0, 1.0),
+            (DepthMode::Reversed, 1.0, 0.0),
+        ] {
+            let finite = perspective(glm::radians(90.0), 1.0, 0.5, Some(100.0), mode);
+            assert!(close(depth(finite, -0.5), near));
+            assert!(close(depth(finite, -100.0), far));
+
+            let infinite = perspective(glm::radians(90.0), 1.0, 0.5, None, mode);
+            assert!(close(depth(infinite, -0.5), near));
+            assert!(close(depth(infinite, -1.0e6), far));
+        }
+    }
+
+    #[test]
+    fn path_is_interpolated_and_clamped() {
+        let path = CameraPath::new(vec![
+            Keyframe {
+                time: 0.0,
+                position: vec3(0.0, 0.0, 0.0),
+                target: vec3(0.0, 0.0, 1.0),
+            },
+            Keyframe {
+                time: 100.0,
+                position: vec3(10.0, 0.0, 0.0),
+                target: vec3(0.0, 0.0, 1.0),
+            },
+        ]);
+
+        assert_eq!(path.sample(50.0).0.x, 5.0);
+        assert_eq!(path.sample(-10.0).0.x, 0.0);
+        assert_eq!(path.sample(500.0).0.x, 10.0);
+        assert_eq!(path.duration(), 100.0);
+    }
+}
@@ -0,0 +1,187 @@
+//! Procedural camera effects.
+//!
+//! Effects are applied on top of the base camera each frame,
+//! so gameplay code can keep moving the camera itself while
+//! shake, recoil and FOV punches are layered over it.
+//!
+//! Shake follows the "trauma" model: events add trauma in the
+//! range 0 to 1, which decays over time, and the strength of
+//! the shake is the square of the current trauma.
+use super::*;
+
+/// Layered effects applied to a `Camera`.
+#[derive(Clone, Debug)]
+pub struct CameraEffects {
+    /// Current shake trauma, from 0 to 1
+    trauma: f32,
+    /// Trauma lost per second
+    pub trauma_decay: f32,
+    /// Largest shake rotation in degrees, at full trauma
+    pub max_shake_angle: f32,
+    /// Largest shake offset in world units, at full trauma
+    pub max_shake_offset: f32,
+    /// Current upwards recoil in degrees
+    recoil: f32,
+    /// Current field of view change in radians
+    fov_punch: f32,
+    /// Fraction of recoil and FOV punch which
+    /// remains after one second
+    pub recovery: f32,
+    /// Seconds for the followed position to close most of the
+    /// distance to the base camera; 0 disables smoothing
+    pub follow_smoothing: f32,
+    /// The smoothed camera position
+    followed: Option<Vec3>,
+    /// Multiplier applied to elapsed time
+    pub time_scale: f32,
+    /// Disables shake for players sensitive to motion
+    pub reduce_motion: bool,
+    /// Scaled time in seconds, used to sample shake noise
+    time: f32,
+}
+
+impl CameraEffects {
+    pub fn new() -> CameraEffects {
+        CameraEffects {
+            trauma: 0.0,
+            trauma_decay: 1.0,
+            max_shake_angle: 4.0,
+            max_shake_offset: 0.5,
+            recoil: 0.0,
+            fov_punch: 0.0,
+            recovery: 0.001,
+            follow_smoothing: 0.0,
+            followed: None,
+            time_scale: 1.0,
+            reduce_motion: false,
+            time: 0.0,
+        }
+    }
+
+    /// Adds shake trauma, clamped to 1.
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).min(1.0).max(0.0);
+    }
+
+    pub fn trauma(&self) -> f32 {
+        self.trauma
+    }
+
+    /// Kicks the view upwards by `degrees`.
+    pub fn add_recoil(&mut self, degrees: f32) {
+        self.recoil += degrees;
+    }
+
+    /// Widens the field of view by `radians`;
+    /// negative values narrow it.
+    pub fn add_fov_punch(&mut self, radians: f32) {
+        self.fov_punch += radians;
+    }
+
+    /// Advances the effects by `delta` seconds,
+    /// following the position of `base`.
+    pub fn update(&mut self, delta: f32, base: &Camera) {
+        let delta = delta * self.time_scale;
+        self.time += delta;
+        self.trauma = (self.trauma - self.trauma_decay * delta).max(0.0);
+
+        let remaining = self.recovery.powf(delta);
+        self.recoil *= remaining;
+        self.fov_punch *= remaining;
+
+        self.followed = Some(match self.followed {
+            Some(followed) if self.follow_smoothing > 0.0 => {
+                let t = 1.0 - (-delta / self.follow_smoothing).exp();
+                followed + (base.position - followed) * t
+            }
+            _ => base.position,
+        });
+    }
+
+    /// Returns `base` with the effects applied.
+    pub fn apply(&self, base: &Camera) -> Camera {
+        let mut camera = base.clone();
+        let position = self.followed.unwrap_or(base.position);
+        camera.target = base.target + (position - base.position);
+        camera.position = position;
+        camera.fov = base.fov + self.fov_punch;
+
+        let shake = if self.reduce_motion {
+            0.0
+        } else {
+            self.trauma * self.trauma
+        };
+        let yaw = self.max_shake_angle * shake * noise(self.time, 0.0);
+        let pitch = self.recoil + self.max_shake_angle * shake * noise(self.time, 1.0);
+
+        let forward = camera.target - camera.position;
+        let distance = length(forward);
+        if distance == 0.0 {
+            return camera;
+        }
+        let right = normalize(cross(forward, camera.up));
+        let up = normalize(cross(right, forward));
+        let offset = (right * noise(self.time, 2.0) + up * noise(self.time, 3.0))
+            * (self.max_shake_offset * shake);
+
+        camera.position = camera.position + offset;
+        camera.target = camera.position
+            + forward
+            + right * (radians(yaw).tan() * distance)
+            + up * (radians(pitch).tan() * distance);
+        camera
+    }
+}
+
+/// Smooth noise in the range -1 to 1. Each `seed`
+/// gives an independent channel.
+fn noise(time: f32, seed: f32) -> f32 {
+    let offset = seed * 17.3;
+    (time * 7.1 + offset).sin() * 0.5
+        + (time * 13.7 + offset * 1.9).sin() * 0.3
+        + (time * 23.3 + offset * 2.7).sin() * 0.2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_effects_keeps_camera() {
+        let base = Camera::default();
+        let mut effects = CameraEffects::new();
+        effects.update(0.1, &base);
+
+        let camera = effects.apply(&base);
+        assert_eq!(camera.position.x, base.position.x);
+        assert_eq!(camera.target.y, base.target.y);
+        assert_eq!(camera.fov, base.fov);
+    }
+
+    #[test]
+    fn effects_recover() {
+        let base = Camera::default();
+        let mut effects = CameraEffects::new();
+        effects.add_trauma(2.0);
+        effects.add_fov_punch(0.2);
+        assert_eq!(effects.trauma(), 1.0);
+
+        effects.update(0.5, &base);
+        assert_eq!(effects.trauma(), 0.5);
+        effects.update(1.0, &base);
+        assert_eq!(effects.trauma(), 0.0);
+        assert!(effects.apply(&base).fov - base.fov < 0.001);
+    }
+
+    #[test]
+    fn reduce_motion_disables_shake() {
+        let base = Camera::default();
+        let mut effects = CameraEffects::new();
+        effects.reduce_motion = true;
+        effects.add_trauma(1.0);
+        effects.update(0.1, &base);
+
+        let camera = effects.apply(&base);
+        assert_eq!(camera.position.z, base.position.z);
+    }
+}
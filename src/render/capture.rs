@@ -0,0 +1,44 @@
+//! Copies of rendered frames.
+//!
+//! `RenderBackend::capture_next_frame()` asks the backend to
+//! copy the next frame it submits back to the CPU, before it
+//! is presented. The copy is taken with `take_captured_frame()`
+//! once the frame has been submitted.
+
+/// A frame copied back to the CPU.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CapturedFrame {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA pixels with 8 bits per channel, in rows from the top
+    pub rgba: Vec<u8>,
+}
+
+impl CapturedFrame {
+    /// Creates a frame from rows starting at the bottom,
+    /// as OpenGL reads them.
+    pub fn from_bottom_rows(width: u32, height: u32, rgba: &[u8]) -> CapturedFrame {
+        let row = width as usize * 4;
+        CapturedFrame {
+            width,
+            height,
+            rgba: rgba
+                .chunks(row)
+                .rev()
+                .flat_map(|row| row.iter().cloned())
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bottom_rows_are_flipped() {
+        let rows = [1, 1, 1, 1, 2, 2, 2, 2];
+        let frame = CapturedFrame::from_bottom_rows(1, 2, &rows);
+        assert_eq!(frame.rgba, vec![2, 2, 2, 2, 1, 1, 1, 1]);
+    }
+}
@@ -0,0 +1,25 @@
+//! How passes treat what their attachments held before.
+//!
+//! The built-in frame clears its color attachment to the clear
+//! color, which is the sky color of the time of day unless one
+//! is set. A pass redrawing only part of what was drawn before,
+//! such as a UI-only redraw, can load the previous contents
+//! instead, and one covering every pixel can leave them
+//! undefined to skip the clear.
+
+/// What a pass does with an attachment's contents when it begins.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LoadOp {
+    /// Cleared to the clear value
+    Clear,
+    /// Kept as they were
+    Load,
+    /// Undefined, for passes which draw every pixel
+    DontCare,
+}
+
+impl Default for LoadOp {
+    fn default() -> Self {
+        LoadOp::Clear
+    }
+}
@@ -0,0 +1,57 @@
+//! The mouse cursor.
+//!
+//! Backends show one of the platform's cursors over the
+//! window, or hide it. winit can't give the cursor an image
+//! of our own, so custom crosshairs are drawn by the game
+//! over a hidden cursor.
+
+/// One of the cursors every platform provides.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SystemCursor {
+    Arrow,
+    Crosshair,
+    Hand,
+    Text,
+    Wait,
+    Move,
+    NotAllowed,
+    ResizeHorizontal,
+    ResizeVertical,
+}
+
+/// What is shown at the mouse position.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cursor {
+    System(SystemCursor),
+    Hidden,
+}
+
+impl Cursor {
+    /// Returns the system cursor to show, or
+    /// `None` if it should be hidden.
+    pub fn system(&self) -> Option<SystemCursor> {
+        match self {
+            Cursor::System(cursor) => Some(*cursor),
+            Cursor::Hidden => None,
+        }
+    }
+}
+
+impl Default for Cursor {
+    fn default() -> Self {
+        Cursor::System(SystemCursor::Arrow)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn system_cursors() {
+        assert_eq!(Cursor::default().system(), Some(SystemCursor::Arrow));
+        let crosshair = Cursor::System(SystemCursor::Crosshair);
+        assert_eq!(crosshair.system(), Some(SystemCursor::Crosshair));
+        assert_eq!(Cursor::Hidden.system(), None);
+    }
+}
@@ -0,0 +1,209 @@
+//! Debug views for diagnosing shading problems.
+//!
+//! While a debug view is selected, every object is drawn
+//! with a debug fragment shader in place of its own, which
+//! outputs one property of the surface as a color.
+//!
+//! The overdraw view draws every fragment additively over
+//! black without depth testing. Each layer adds `1 / 4` to
+//! red, `1 / 16` to green and `1 / 64` to blue, so pixels
+//! go from red through yellow to white as they are shaded
+//! more often. `OverdrawStats` decodes the counts back from
+//! the image.
+use super::shader_constants;
+
+/// What the renderer outputs instead of shaded color.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebugView {
+    /// Normal shading
+    Off,
+    /// View space normals, mapped from -1..1 to 0..1
+    Normals,
+    /// The depth buffer, brighter for nearer surfaces
+    /// with reversed depth and for further ones without
+    Depth,
+    /// How many times each pixel is shaded
+    Overdraw,
+}
+
+/// Every view, in the order they are cycled through
+pub const DEBUG_VIEWS: [DebugView; 4] = [
+    DebugView::Off,
+    DebugView::Normals,
+    DebugView::Depth,
+    DebugView::Overdraw,
+];
+
+/// Layers of overdraw which saturate the green and
+/// blue channels of the overdraw view
+const GREEN_LAYERS: f32 = shader_constants::OVERDRAW_GREEN_LAYERS as f32;
+const BLUE_LAYERS: f32 = shader_constants::OVERDRAW_BLUE_LAYERS as f32;
+
+impl DebugView {
+    /// Returns the view after this one, wrapping
+    /// back around to `Off` after the last.
+    pub fn next(self) -> DebugView {
+        let index = DEBUG_VIEWS.iter().position(|&view| view == self).unwrap();
+        DEBUG_VIEWS[(index + 1) % DEBUG_VIEWS.len()]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DebugView::Off => "off",
+            DebugView::Normals => "normals",
+            DebugView::Depth => "depth",
+            DebugView::Overdraw => "overdraw",
+        }
+    }
+
+    /// Returns whether the view is drawn by adding up
+    /// fragments over black, without depth testing.
+    pub fn is_additive(self) -> bool {
+        self == DebugView::Overdraw
+    }
+}
+
+impl Default for DebugView {
+    fn default() -> Self {
+        DebugView::Off
+    }
+}
+
+/// Fill rate statistics of a frame drawn with the overdraw view.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct OverdrawStats {
+    /// Pixels shaded at least once
+    pub covered_pixels: u32,
+    /// Fragments shaded in total
+    pub fragments: u64,
+    /// Most times a single pixel was shaded
+    pub max: u32,
+    /// Estimated fraction of fragment shader invocations
+    /// which shaded a covered pixel rather than filling
+    /// out a 2x2 quad
+    pub quad_occupancy: f32,
+}
+
+impl OverdrawStats {
+    /// Measures a frame drawn with the overdraw view,
+    /// given as sRGB encoded RGBA rows. Frames read back
+    /// from BGRA images must be swizzled first.
+    ///
+    /// Quad occupancy is estimated per 2x2 block of pixels,
+    /// assuming every layer covering part of the block is
+    /// shaded as a full quad. It can't see quads shared by
+    /// triangles of the same draw, so it errs low.
+    pub fn from_rgba(width: u32, height: u32, pixels: &[u8]) -> OverdrawStats {
+        let counts = pixels.chunks(4).map(overdraw_count).collect::<Vec<_>>();
+        let count = |x: u32, y: u32| {
+            if x < width && y < height {
+                counts[(y * width + x) as usize]
+            } else {
+                0
+            }
+        };
+
+        let mut stats = OverdrawStats::default();
+        let mut lanes = 0;
+        for y in (0..height).step_by(2) {
+            for x in (0..width).step_by(2) {
+                let block = [
+                    count(x, y),
+                    count(x + 1, y),
+                    count(x, y + 1),
+                    count(x + 1, y + 1),
+                ];
+                for &count in &block {
+                    if count > 0 {
+                        stats.covered_pixels += 1;
+                    }
+                    stats.fragments += count as u64;
+                    stats.max = stats.max.max(count);
+                }
+                lanes += 4 * *block.iter().max().unwrap() as u64;
+            }
+        }
+        if lanes > 0 {
+            stats.quad_occupancy = stats.fragments as f32 / lanes as f32;
+        }
+        stats
+    }
+
+    /// Average times each covered pixel was shaded.
+    pub fn average(&self) -> f32 {
+        if self.covered_pixels == 0 {
+            0.0
+        } else {
+            self.fragments as f32 / self.covered_pixels as f32
+        }
+    }
+}
+
+/// Decodes the number of layers drawn over an RGBA pixel.
+fn overdraw_count(pixel: &[u8]) -> u32 {
+    let green = srgb_to_linear(pixel[1]);
+    if green < 1.0 {
+        (green * GREEN_LAYERS).round() as u32
+    } else {
+        (srgb_to_linear(pixel[2]) * BLUE_LAYERS).round() as u32
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes a pixel the way the overdraw view draws it.
+    fn layers(count: u32) -> [u8; 4] {
+        let encode = |linear: f32| {
+            let linear = linear.min(1.0);
+            let srgb = if linear <= 0.0031308 {
+                linear * 12.92
+            } else {
+                1.055 * linear.powf(1.0 / 2.4) - 0.055
+            };
+            (srgb * 255.0).round() as u8
+        };
+        let count = count as f32;
+        [
+            encode(count / shader_constants::OVERDRAW_RED_LAYERS as f32),
+            encode(count / GREEN_LAYERS),
+            encode(count / BLUE_LAYERS),
+            255,
+        ]
+    }
+
+    #[test]
+    fn decodes_counts() {
+        for count in 0..BLUE_LAYERS as u32 {
+            assert_eq!(overdraw_count(&layers(count)), count);
+        }
+    }
+
+    #[test]
+    fn overdraw_stats() {
+        // A 4x2 frame: the left block is fully covered twice,
+        // the right block has a single pixel covered once
+        let counts = [2, 2, 1, 0, 2, 2, 0, 0];
+        let pixels = counts
+            .iter()
+            .flat_map(|&count| layers(count).to_vec())
+            .collect::<Vec<_>>();
+        let stats = OverdrawStats::from_rgba(4, 2, &pixels);
+
+        assert_eq!(stats.covered_pixels, 5);
+        assert_eq!(stats.fragments, 9);
+        assert_eq!(stats.max, 2);
+        assert_eq!(stats.average(), 1.8);
+        assert_eq!(stats.quad_occupancy, 9.0 / 12.0);
+    }
+}
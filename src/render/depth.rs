@@ -0,0 +1,51 @@
+//! Depth buffer conventions.
+//!
+//! With reversed depth the near plane is at depth 1 and the
+//! far plane at 0. Floating point values are most precise
+//! near 0, which with a 32-bit float depth buffer cancels out
+//! the way perspective crowds distant depths together, so
+//! large scenes don't z-fight in the distance.
+//!
+//! The built-in projections, depth tests and clear values all
+//! follow the context's `DepthMode`. Custom shaders and
+//! projections need to follow it too when it is reversed:
+//!
+//! - `Projection::Custom` matrices must map the near plane to
+//!   1 and the far plane to 0, like `camera::perspective()`
+//! - Depth comparisons in pipeline descriptions are written
+//!   for standard depth (`Less` passes nearer fragments) and
+//!   are flipped automatically, so they need no change
+//! - Shaders which read depth, such as to linearize it, see
+//!   `1 - d` compared to standard depth, and shaders writing
+//!   `gl_FragDepth` must write reversed values
+
+/// How depth values are laid out in the depth buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DepthMode {
+    /// The near plane is at depth 0 and the far plane at 1
+    Standard,
+    /// The near plane is at depth 1 and the far plane at 0,
+    /// with a 32-bit float depth buffer
+    Reversed,
+}
+
+impl DepthMode {
+    /// Returns the depth of the far plane,
+    /// which depth buffers are cleared to.
+    pub fn clear_depth(self) -> f32 {
+        match self {
+            DepthMode::Standard => 1.0,
+            DepthMode::Reversed => 0.0,
+        }
+    }
+
+    pub fn is_reversed(self) -> bool {
+        self == DepthMode::Reversed
+    }
+}
+
+impl Default for DepthMode {
+    fn default() -> Self {
+        DepthMode::Reversed
+    }
+}
@@ -0,0 +1,104 @@
+//! Capabilities of the graphics device.
+//!
+//! Each backend fills in a `DeviceFeatures` when its context is
+//! created. Subsystems check it before enabling an optional
+//! feature so that it can be turned off on devices which lack
+//! support, rather than failing when a pipeline is created.
+use super::*;
+
+/// Limits and optional features supported by the device.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceFeatures {
+    /// Largest width or height of a 2D texture
+    pub max_texture_size: u32,
+    /// Largest sampler anisotropy; 1 when anisotropic filtering is unsupported
+    pub max_anisotropy: f32,
+    /// Largest number of samples per pixel for color attachments
+    pub max_samples: u8,
+    /// Mask with a bit set for each number of samples per pixel
+    /// supported by both color and depth attachments, so bit 2
+    /// is set if 4 samples are supported
+    pub sample_counts: u8,
+    /// Whether shaders can write to storage buffers
+    pub storage_buffers: bool,
+    pub tessellation: bool,
+    pub geometry_shaders: bool,
+    /// Whether an indirect draw can read more than one command
+    pub multi_draw_indirect: bool,
+}
+
+impl DeviceFeatures {
+    /// Returns `settings` with any features the
+    /// device does not support turned down.
+    pub fn supported_settings(&self, settings: &GraphicsSettings) -> GraphicsSettings {
+        GraphicsSettings {
+            msaa_samples: self.supported_samples(settings.msaa_samples),
+            ..*settings
+        }
+    }
+
+    /// Lowers `samples` to the largest sample count no greater
+    /// than it which color and depth attachments both support.
+    pub fn supported_samples(&self, samples: u8) -> u8 {
+        // A single sample is always supported
+        let counts = self.sample_counts | 1;
+        let supported = (0..8)
+            .map(|bit| 1u8 << bit)
+            .filter(|&count| count <= samples && counts & count != 0)
+            .last()
+            .unwrap_or(1);
+        if supported != samples {
+            warn!("{}x MSAA is not supported, using {}x", samples, supported);
+        }
+        supported
+    }
+}
+
+impl Default for DeviceFeatures {
+    /// The minimum every supported device provides.
+    fn default() -> Self {
+        DeviceFeatures {
+            max_texture_size: 4096,
+            max_anisotropy: 1.0,
+            max_samples: 1,
+            sample_counts: 1,
+            storage_buffers: false,
+            tessellation: false,
+            geometry_shaders: false,
+            multi_draw_indirect: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsupported_msaa_is_reduced() {
+        let features = DeviceFeatures {
+            max_samples: 4,
+            sample_counts: 0b111,
+            ..Default::default()
+        };
+        let ultra = GraphicsQuality::Ultra.settings();
+
+        let supported = features.supported_settings(&ultra);
+        assert_eq!(supported.msaa_samples, 4);
+        assert_eq!(supported.view_distance, ultra.view_distance);
+    }
+
+    #[test]
+    fn sample_count_gaps() {
+        // Color supports 1, 2 and 8 samples, and depth 1, 2 and 4
+        let features = DeviceFeatures {
+            max_samples: 8,
+            sample_counts: 0b1011 & 0b0111,
+            ..Default::default()
+        };
+        assert_eq!(features.supported_samples(8), 2);
+        assert_eq!(features.supported_samples(4), 2);
+        assert_eq!(features.supported_samples(1), 1);
+        assert_eq!(features.supported_samples(0), 1);
+    }
+}
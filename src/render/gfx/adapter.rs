@@ -0,0 +1,65 @@
+//! Describing and choosing graphics adapters.
+use super::*;
+use gfx_hal::adapter::DeviceType;
+use gfx_hal::{Adapter, Instance, PhysicalDevice};
+
+/// A summary of an adapter, for choosing between them.
+#[derive(Clone, Debug)]
+pub struct AdapterDesc {
+    /// Position of the adapter in the list returned by the instance
+    pub index: usize,
+    pub name: String,
+    pub vendor: usize,
+    pub device: usize,
+    pub device_type: DeviceType,
+    /// Total size of the device's memory heaps in bytes
+    pub memory: u64,
+}
+
+impl AdapterDesc {
+    pub fn new<B: Backend>(index: usize, adapter: &Adapter<B>) -> AdapterDesc {
+        AdapterDesc {
+            index,
+            name: adapter.info.name.clone(),
+            vendor: adapter.info.vendor,
+            device: adapter.info.device,
+            device_type: adapter.info.device_type.clone(),
+            memory: adapter
+                .physical_device
+                .memory_properties()
+                .memory_heaps
+                .iter()
+                .sum(),
+        }
+    }
+}
+
+/// Chooses an adapter from those available, returning its index.
+pub type AdapterSelector<'a> = Box<dyn Fn(&[AdapterDesc]) -> Option<usize> + 'a>;
+
+/// Lists the adapters available to the backend, so that
+/// applications can show them in their settings.
+pub fn enumerate_adapters() -> Vec<AdapterDesc> {
+    let instance = back::Instance::create("Luminite", 1);
+    describe_adapters(&instance.enumerate_adapters())
+}
+
+/// Describes each of `adapters`.
+pub fn describe_adapters<B: Backend>(adapters: &[Adapter<B>]) -> Vec<AdapterDesc> {
+    adapters
+        .iter()
+        .enumerate()
+        .map(|(index, adapter)| AdapterDesc::new(index, adapter))
+        .collect()
+}
+
+/// Picks the discrete GPU with the most memory, falling
+/// back to the first adapter if there is none.
+pub fn prefer_discrete_gpu(adapters: &[AdapterDesc]) -> Option<usize> {
+    adapters
+        .iter()
+        .filter(|adapter| adapter.device_type == DeviceType::DiscreteGpu)
+        .max_by_key(|adapter| adapter.memory)
+        .or_else(|| adapters.first())
+        .map(|adapter| adapter.index)
+}
@@ -0,0 +1,371 @@
+//! Sub-allocation of device memory.
+//!
+//! Drivers limit how many memory objects can exist at once,
+//! sometimes to as few as 4096, so buffers and images don't get
+//! a memory object each. The `MemoryAllocator` allocates large
+//! blocks and hands out ranges of them from a free list.
+//!
+//! Blocks hold either buffers or images, never both, so linear
+//! and optimally tiled resources are never close enough to
+//! need `buffer_image_granularity` padding. Resources too large
+//! to share a block get a dedicated block of their own size.
+//!
+//! Freeing an `Allocation` only returns its range to the block.
+//! Blocks nothing is allocated from are freed by `trim()`,
+//! except for one spare block per memory type and kind.
+//!
+//! Reads and writes map ranges aligned to the device's
+//! `non_coherent_atom_size`, and invalidate or flush them,
+//! so they also work with memory which isn't host coherent.
+use super::*;
+use gfx_hal::memory::Requirements;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::ptr;
+use std::sync::{Arc, Mutex};
+
+/// Size of the blocks shared by allocations
+const BLOCK_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Free ranges of a block, sorted by offset.
+/// Adjacent ranges are always merged.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FreeList {
+    ranges: Vec<Range<u64>>,
+}
+
+impl FreeList {
+    /// Creates a list of `size` free bytes.
+    pub fn new(size: u64) -> FreeList {
+        FreeList {
+            ranges: vec![0..size],
+        }
+    }
+
+    /// Takes the first `size` free bytes starting at
+    /// a multiple of `alignment`, returning their offset.
+    pub fn allocate(&mut self, size: u64, alignment: u64) -> Option<u64> {
+        let alignment = alignment.max(1);
+        let index = self.ranges.iter().position(|range| {
+            let start = align(range.start, alignment);
+            start + size <= range.end
+        })?;
+        let range = self.ranges.remove(index);
+        let start = align(range.start, alignment);
+
+        // Keep what is left on either side
+        let mut insert = index;
+        if range.start < start {
+            self.ranges.insert(insert, range.start..start);
+            insert += 1;
+        }
+        if start + size < range.end {
+            self.ranges.insert(insert, start + size..range.end);
+        }
+        Some(start)
+    }
+
+    /// Returns `range` to the list.
+    pub fn free(&mut self, range: Range<u64>) {
+        let index = self
+            .ranges
+            .iter()
+            .position(|free| free.start > range.start)
+            .unwrap_or_else(|| self.ranges.len());
+        self.ranges.insert(index, range);
+
+        // Merge with the following range, then the preceding one
+        if index + 1 < self.ranges.len() && self.ranges[index].end == self.ranges[index + 1].start {
+            let next = self.ranges.remove(index + 1);
+            self.ranges[index].end = next.end;
+        }
+        if index > 0 && self.ranges[index - 1].end == self.ranges[index].start {
+            let current = self.ranges.remove(index);
+            self.ranges[index - 1].end = current.end;
+        }
+    }
+
+    /// Returns the number of free bytes.
+    pub fn free_bytes(&self) -> u64 {
+        self.ranges
+            .iter()
+            .map(|range| range.end - range.start)
+            .sum()
+    }
+}
+
+fn align(value: u64, alignment: u64) -> u64 {
+    (value + alignment - 1) / alignment * alignment
+}
+
+/// Widens `range` to multiples of `atom_size`,
+/// without going past the end of a block of `size`.
+fn atom_range(range: Range<u64>, atom_size: u64, size: u64) -> Range<u64> {
+    let atom_size = atom_size.max(1);
+    let start = range.start / atom_size * atom_size;
+    let end = align(range.end, atom_size).min(size);
+    start..end
+}
+
+/// A memory object allocations are taken from.
+struct Block<B: Backend> {
+    memory: B::Memory,
+    size: u64,
+    /// Whether the block belongs to a single large resource
+    dedicated: bool,
+    free: Mutex<FreeList>,
+    /// Held while the block is mapped, since a memory
+    /// object can't be mapped twice at once
+    mapping: Mutex<()>,
+    /// Alignment of mapped ranges which are flushed or invalidated
+    atom_size: u64,
+}
+
+/// A range of a block of device memory.
+pub struct Allocation<B: Backend> {
+    block: Arc<Block<B>>,
+    offset: u64,
+    size: u64,
+}
+
+impl<B: Backend> Allocation<B> {
+    /// Returns the memory object the allocation is part of.
+    pub fn memory(&self) -> &B::Memory {
+        &self.block.memory
+    }
+
+    /// Returns the offset of the allocation in `memory()`.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Copies `items` to the start of the allocation,
+    /// which must be CPU visible.
+    pub fn write<T: Copy>(&self, device: &B::Device, items: &[T]) {
+        let len = (items.len() * std::mem::size_of::<T>()) as u64;
+        assert!(len <= self.size, "write past the end of an allocation");
+
+        let _mapped = self.block.mapping.lock().unwrap();
+        let range = self.mapped_range(len);
+        let memory = &self.block.memory;
+        unsafe {
+            let mapping = device.map_memory(memory, range.clone()).unwrap();
+            let dest = mapping.offset((self.offset - range.start) as isize);
+            ptr::copy_nonoverlapping(items.as_ptr() as *const u8, dest, len as usize);
+            device.flush_mapped_memory_ranges(std::iter::once((memory, range)));
+            device.unmap_memory(memory);
+        }
+    }
+
+    /// Copies `count` items from the start of the
+    /// allocation, which must be CPU visible.
+    pub fn read<T: Copy>(&self, device: &B::Device, count: usize) -> Vec<T> {
+        let len = (count * std::mem::size_of::<T>()) as u64;
+        assert!(len <= self.size, "read past the end of an allocation");
+
+        let _mapped = self.block.mapping.lock().unwrap();
+        let range = self.mapped_range(len);
+        let memory = &self.block.memory;
+        let mut items = Vec::<T>::with_capacity(count);
+        unsafe {
+            let mapping = device.map_memory(memory, range.clone()).unwrap();
+            device.invalidate_mapped_memory_ranges(std::iter::once((memory, range.clone())));
+            let src = mapping.offset((self.offset - range.start) as isize);
+            ptr::copy_nonoverlapping(src, items.as_mut_ptr() as *mut u8, len as usize);
+            items.set_len(count);
+            device.unmap_memory(memory);
+        }
+        items
+    }
+
+    /// Returns the range of the block to map for the first `len`
+    /// bytes of the allocation, widened to whole atoms.
+    fn mapped_range(&self, len: u64) -> Range<u64> {
+        atom_range(
+            self.offset..self.offset + len,
+            self.block.atom_size,
+            self.block.size,
+        )
+    }
+
+    /// Returns the range to its block. Resources
+    /// bound to it must already be destroyed.
+    pub fn free(self) {
+        self.block
+            .free
+            .lock()
+            .unwrap()
+            .free(self.offset..self.offset + self.size);
+    }
+}
+
+/// Allocates device memory for buffers and images.
+/// It can be shared between threads.
+pub struct MemoryAllocator<B: Backend> {
+    memory_types: Vec<MemoryType>,
+    /// The device's `non_coherent_atom_size`
+    atom_size: u64,
+    /// Blocks by memory type and whether they hold images
+    blocks: Mutex<HashMap<(usize, bool), Vec<Arc<Block<B>>>>>,
+}
+
+impl<B: Backend> MemoryAllocator<B> {
+    /// Creates an allocator for memory of `memory_types`, on
+    /// a device with a `non_coherent_atom_size` of `atom_size`.
+    pub fn new(memory_types: Vec<MemoryType>, atom_size: u64) -> MemoryAllocator<B> {
+        MemoryAllocator {
+            memory_types,
+            atom_size,
+            blocks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Allocates memory meeting `requirements` with
+    /// `properties`, for an image if `image` is set.
+    pub fn allocate(
+        &self,
+        device: &B::Device,
+        requirements: Requirements,
+        properties: Properties,
+        image: bool,
+    ) -> Result<Allocation<B>, String> {
+        let memory_type = self
+            .memory_types
+            .iter()
+            .enumerate()
+            .position(|(id, ty)| {
+                requirements.type_mask & (1 << id) != 0 && ty.properties.contains(properties)
+            })
+            .ok_or_else(|| format!("no memory type with {:?}", properties))?;
+
+        let mut blocks = self.blocks.lock().unwrap();
+        let blocks = blocks.entry((memory_type, image)).or_insert_with(Vec::new);
+        let dedicated = requirements.size > BLOCK_SIZE / 2;
+        if !dedicated {
+            for block in blocks.iter().filter(|block| !block.dedicated) {
+                let offset = block
+                    .free
+                    .lock()
+                    .unwrap()
+                    .allocate(requirements.size, requirements.alignment);
+                if let Some(offset) = offset {
+                    return Ok(Allocation {
+                        block: Arc::clone(block),
+                        offset,
+                        size: requirements.size,
+                    });
+                }
+            }
+        }
+
+        let size = if dedicated {
+            requirements.size
+        } else {
+            BLOCK_SIZE
+        };
+        let memory = device
+            .allocate_memory(memory_type.into(), size)
+            .map_err(|err| format!("{:?}", err))?;
+        let mut free = FreeList::new(size);
+        free.allocate(requirements.size, requirements.alignment);
+        let block = Arc::new(Block {
+            memory,
+            size,
+            dedicated,
+            free: Mutex::new(free),
+            mapping: Mutex::new(()),
+            atom_size: self.atom_size,
+        });
+        blocks.push(Arc::clone(&block));
+        Ok(Allocation {
+            block,
+            offset: 0,
+            size: requirements.size,
+        })
+    }
+
+    /// Returns the number of blocks and their total size in bytes.
+    pub fn usage(&self) -> (usize, u64) {
+        let blocks = self.blocks.lock().unwrap();
+        let blocks = blocks.values().flat_map(|blocks| blocks.iter());
+        blocks.fold((0, 0), |(count, size), block| {
+            (count + 1, size + block.size)
+        })
+    }
+
+    /// Frees blocks nothing is allocated from, keeping
+    /// one shared block of each type and kind.
+    pub fn trim(&self, device: &B::Device) {
+        let mut blocks = self.blocks.lock().unwrap();
+        for blocks in blocks.values_mut() {
+            let mut spare = false;
+            let mut index = 0;
+            while index < blocks.len() {
+                let unused = Arc::strong_count(&blocks[index]) == 1;
+                if unused && !blocks[index].dedicated && !spare {
+                    spare = true;
+                } else if unused {
+                    let block = blocks.remove(index);
+                    if let Ok(block) = Arc::try_unwrap(block) {
+                        device.free_memory(block.memory);
+                    }
+                    continue;
+                }
+                index += 1;
+            }
+        }
+    }
+
+    /// Frees every block. Blocks which still have
+    /// allocations are left to the device's destruction.
+    pub fn destroy(&self, device: &B::Device) {
+        let mut leaked = 0;
+        for (_, blocks) in self.blocks.lock().unwrap().drain() {
+            for block in blocks {
+                match Arc::try_unwrap(block) {
+                    Ok(block) => device.free_memory(block.memory),
+                    Err(_) => leaked += 1,
+                }
+            }
+        }
+        if leaked > 0 {
+            debug!("{} memory blocks still had allocations", leaked);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn free_list() {
+        let mut list = FreeList::new(256);
+        assert_eq!(list.allocate(10, 1), Some(0));
+        assert_eq!(list.allocate(16, 16), Some(16));
+        // The gap left by alignment is too small
+        assert_eq!(list.allocate(8, 1), Some(32));
+        assert_eq!(list.allocate(6, 1), Some(10));
+        assert_eq!(list.allocate(512, 1), None);
+        assert_eq!(list.free_bytes(), 256 - 10 - 16 - 8 - 6);
+
+        list.free(32..40);
+        list.free(0..10);
+        list.free(16..32);
+        list.free(10..16);
+        assert_eq!(list, FreeList::new(256));
+    }
+
+    #[test]
+    fn atom_ranges() {
+        assert_eq!(atom_range(70..90, 64, 1024), 64..128);
+        assert_eq!(atom_range(64..128, 64, 1024), 64..128);
+        // Ranges at the end of a block stop at its end
+        assert_eq!(atom_range(1000..1010, 64, 1010), 960..1010);
+        assert_eq!(atom_range(3..5, 0, 16), 3..5);
+    }
+}
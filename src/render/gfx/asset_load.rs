@@ -1,37 +1,33 @@
 //! Module for loading assets from files.
 use super::*;
+use profile::millis_since;
+use std::time::Instant;
 
-pub fn upload_model(ctx: &mut RenderContext<back::Backend>, models: Vec<tobj::Model>) {
+/// Uploads a model, returning its index in `ctx.models`.
+pub fn upload_model(ctx: &mut RenderContext<back::Backend>, models: Vec<tobj::Model>) -> usize {
+    let start = Instant::now();
     let (vertices, indices) = combine_models(models);
-    let vertices_count = vertices.len();
-    let indices_count = indices.len();
+    let device = ctx.device_handle();
+    let model = match compact_indices(&indices, vertices.len()) {
+        Some(compact) => IndexedMesh::new(&device, &vertices, Indices::U16(&compact)),
+        None => IndexedMesh::new(&device, &vertices, Indices::U32(&indices)),
+    };
 
-    let (v_buffer, v_mem) = buffer_util::create_buffer::<back::Backend, _>(
-        &ctx.device,
-        &ctx.memory_types,
-        Properties::CPU_VISIBLE,
-        Usage::VERTEX,
-        &vertices,
-    );
+    let index_size = match model.index_type {
+        IndexType::U16 => std::mem::size_of::<u16>(),
+        IndexType::U32 => std::mem::size_of::<u32>(),
+    };
+    let bytes =
+        (vertices.len() * std::mem::size_of::<Vertex>() + indices.len() * index_size) as u64;
+    ctx.stats.model_bytes += bytes;
 
-    let (i_buffer, i_mem) = buffer_util::create_buffer::<back::Backend, _>(
-        &ctx.device,
-        &ctx.memory_types,
-        Properties::CPU_VISIBLE,
-        Usage::INDEX,
-        &indices,
-    );
-
-    ctx.models.push(context::ModelBuffer {
-        vertices: context::BufferMem {
-            buffer: v_buffer,
-            memory: v_mem,
-            element_count: vertices_count,
-        },
-        indices: context::BufferMem {
-            buffer: i_buffer,
-            memory: i_mem,
-            element_count: indices_count,
-        },
+    let mesh = ctx.meshes.insert(model);
+    ctx.models.push(mesh);
+    ctx.telemetry.record(RenderEvent {
+        kind: EventKind::Upload,
+        reason: format!("model {}", ctx.models.len() - 1),
+        millis: millis_since(start),
+        bytes: Some(bytes),
     });
+    ctx.models.len() - 1
 }
@@ -0,0 +1,164 @@
+//! A context for running compute shaders without a window.
+//!
+//! Tools which only need the GPU for computation, such as
+//! bakers and image processors, can create a `ComputeContext`
+//! instead of a `RenderContext`. It opens a device with a
+//! compute queue and nothing else: there is no window,
+//! surface, swapchain or graphics pipeline.
+use super::*;
+use gfx_hal::pso::ComputePipelineDesc;
+use gfx_hal::{Compute, Instance, PhysicalDevice};
+use std::sync::Arc;
+
+/// Holds everything needed to dispatch compute work.
+pub struct ComputeContext<B: Backend> {
+    /// The gfx-rs instance
+    pub instance: back::Instance,
+    pub device: Arc<B::Device>,
+    pub queue_group: QueueGroup<B, Compute>,
+    pub command_pool: CommandPool<B, Compute>,
+    /// Memory types the physical device supports
+    pub memory_types: Vec<MemoryType>,
+    /// Allocates the memory of buffers created through `device_handle()`
+    pub allocator: Arc<MemoryAllocator<B>>,
+    pub features: DeviceFeatures,
+    /// Fence to wait for dispatches to finish
+    fence: B::Fence,
+}
+
+/// A compute shader and the layout of its descriptors.
+pub struct ComputePipeline<B: Backend> {
+    pub pipeline: B::ComputePipeline,
+    pub layout: B::PipelineLayout,
+    pub set_layout: B::DescriptorSetLayout,
+}
+
+impl ComputeContext<back::Backend> {
+    /// Opens the first adapter with a compute queue.
+    /// `name` identifies the application to the driver.
+    pub fn new(name: &str) -> Result<ComputeContext<back::Backend>, RenderError> {
+        let instance = back::Instance::create(name, 1);
+        let mut adapters = instance.enumerate_adapters();
+        if adapters.is_empty() {
+            return Err(RenderError::NoSuitableAdapter);
+        }
+        let mut adapter = adapters.remove(0);
+        crash::set_adapter_info(format!("{:?}", adapter.info));
+
+        let (device, queue_group) = adapter
+            .open_with::<_, Compute>(1, |_| true)
+            .map_err(|err| RenderError::DeviceCreation(format!("{:?}", err)))?;
+        let memory_types = adapter.physical_device.memory_properties().memory_types;
+        let features = factory::device_features(&adapter.physical_device);
+
+        let max_buffers = 16;
+        let command_pool = device.create_command_pool_typed(
+            &queue_group,
+            CommandPoolCreateFlags::empty(),
+            max_buffers,
+        );
+        let fence = device.create_fence(false);
+        let atom_size = adapter.physical_device.limits().non_coherent_atom_size as u64;
+        let allocator = Arc::new(MemoryAllocator::new(memory_types.clone(), atom_size));
+
+        Ok(ComputeContext {
+            instance,
+            device: Arc::new(device),
+            queue_group,
+            command_pool,
+            memory_types,
+            allocator,
+            features,
+            fence,
+        })
+    }
+
+    /// Returns a handle to the device which can
+    /// be used to create buffers and shader modules.
+    pub fn device_handle(&self) -> RenderDevice<back::Backend> {
+        RenderDevice::new(Arc::clone(&self.device), Arc::clone(&self.allocator))
+    }
+
+    /// Creates a pipeline from a SPIR-V compute shader
+    /// with a single descriptor set of `bindings`.
+    pub fn create_pipeline(
+        &self,
+        spirv: &[u8],
+        bindings: &[DescriptorSetLayoutBinding],
+    ) -> Result<ComputePipeline<back::Backend>, RenderError> {
+        let module = self
+            .device
+            .create_shader_module(spirv)
+            .map_err(|err| RenderError::ShaderCompilation(format!("{:?}", err)))?;
+        let set_layout = self.device.create_descriptor_set_layout(bindings, &[]);
+        let layout = self.device.create_pipeline_layout(vec![&set_layout], &[]);
+
+        let pipeline = {
+            let entry = EntryPoint::<back::Backend> {
+                entry: "main",
+                module: &module,
+                specialization: Specialization {
+                    constants: &[],
+                    data: &[],
+                },
+            };
+            self.device
+                .create_compute_pipeline(&ComputePipelineDesc::new(entry, &layout), None)
+        };
+        self.device.destroy_shader_module(module);
+
+        match pipeline {
+            Ok(pipeline) => Ok(ComputePipeline {
+                pipeline,
+                layout,
+                set_layout,
+            }),
+            Err(err) => {
+                self.device.destroy_pipeline_layout(layout);
+                self.device.destroy_descriptor_set_layout(set_layout);
+                Err(RenderError::PipelineCreation(format!("{:?}", err)))
+            }
+        }
+    }
+
+    /// Runs `pipeline` over `groups` work groups and
+    /// waits for it to finish.
+    pub fn dispatch(
+        &mut self,
+        pipeline: &ComputePipeline<back::Backend>,
+        desc_set: &<back::Backend as Backend>::DescriptorSet,
+        groups: [u32; 3],
+    ) {
+        self.device.reset_fence(&self.fence);
+        self.command_pool.reset();
+
+        let finished_command_buffer = {
+            let mut command_buffer = self.command_pool.acquire_command_buffer(false);
+            command_buffer.bind_compute_pipeline(&pipeline.pipeline);
+            command_buffer.bind_compute_descriptor_sets(&pipeline.layout, 0, vec![desc_set], &[]);
+            command_buffer.dispatch(groups);
+            command_buffer.finish()
+        };
+
+        let submission = Submission::new().submit(vec![finished_command_buffer]);
+        self.queue_group.queues[0].submit(submission, Some(&self.fence));
+        self.device.wait_for_fence(&self.fence, !0);
+    }
+
+    /// Destroys a pipeline created by this context.
+    pub fn destroy_pipeline(&self, pipeline: ComputePipeline<back::Backend>) {
+        self.device.destroy_compute_pipeline(pipeline.pipeline);
+        self.device.destroy_pipeline_layout(pipeline.layout);
+        self.device
+            .destroy_descriptor_set_layout(pipeline.set_layout);
+    }
+
+    /// Destroys the context. Pipelines must be destroyed
+    /// first, and buffers created through `device_handle()`
+    /// freed.
+    pub fn destroy(self) {
+        self.device
+            .destroy_command_pool(self.command_pool.into_raw());
+        self.device.destroy_fence(self.fence);
+    }
+}
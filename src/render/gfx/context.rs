@@ -1,19 +1,23 @@
 //! Contains structs for storing
 //! values relevant to rendering.
 use super::*;
+use render::handle::{BufferResource, MeshResource, TargetResource, TextureResource};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
 
 /// Contains a GPU buffer, memory, and
 /// an optionally used element count.
 pub struct BufferMem<B: Backend> {
     pub buffer: B::Buffer,
-    pub memory: B::Memory,
+    pub memory: Allocation<B>,
     pub element_count: usize,
 }
 
 impl<B: Backend> BufferMem<B> {
     /// Creates a BufferMem with element count 1
     /// and the specified buffer and memory.
-    pub fn new(buffer: B::Buffer, memory: B::Memory) -> BufferMem<B> {
+    pub fn new(buffer: B::Buffer, memory: Allocation<B>) -> BufferMem<B> {
         BufferMem {
             buffer,
             memory,
@@ -22,30 +26,119 @@ impl<B: Backend> BufferMem<B> {
     }
 }
 
+/// A CPU visible buffer which is refilled in place, holding
+/// up to as many elements as it was created with.
+pub struct DynamicBuffer<B: Backend> {
+    pub buffer: B::Buffer,
+    pub memory: Allocation<B>,
+    /// Number of elements in use
+    len: usize,
+    /// Number of elements the buffer has room for
+    capacity: usize,
+}
+
+impl<B: Backend> DynamicBuffer<B> {
+    /// Creates a buffer filled with `elements`.
+    pub fn new<T: Copy>(
+        device: &RenderDevice<B>,
+        usage: Usage,
+        elements: &[T],
+    ) -> DynamicBuffer<B> {
+        let (buffer, memory) = device.create_buffer(Properties::CPU_VISIBLE, usage, elements);
+        DynamicBuffer {
+            buffer,
+            memory,
+            len: elements.len(),
+            capacity: elements.len(),
+        }
+    }
+
+    /// Replaces the elements, keeping the buffer. There can't
+    /// be more elements than the buffer was created with, and
+    /// no frame using the buffer may be in flight. `what`
+    /// names the elements in the panic message.
+    pub fn update<T: Copy>(&mut self, device: &B::Device, elements: &[T], what: &str) {
+        assert!(
+            elements.len() <= self.capacity,
+            "{} {} don't fit in a buffer of {}",
+            elements.len(),
+            what,
+            self.capacity
+        );
+        buffer_util::fill_buffer::<B, T>(device, &self.memory, elements);
+        self.len = elements.len();
+    }
+
+    /// Returns the number of elements in use.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of elements the buffer has room for.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Frees the buffer and its memory.
+    pub fn destroy(self, device: &B::Device) {
+        device.destroy_buffer(self.buffer);
+        self.memory.free();
+    }
+}
+
+/// Contains an image, its memory, and a view of it.
+pub struct ImageMem<B: Backend> {
+    pub image: B::Image,
+    pub memory: Allocation<B>,
+    pub view: B::ImageView,
+}
+
+impl<B: Backend> ImageMem<B> {
+    /// Frees the image, its view and its memory.
+    pub fn destroy(self, device: &B::Device) {
+        device.destroy_image_view(self.view);
+        device.destroy_image(self.image);
+        self.memory.free();
+    }
+}
+
 /// Holds all values necessary to render to the screen.
 pub struct RenderContext<B: Backend> {
     /// The gfx-rs instance
     pub instance: back::Instance,
-    /// The logical device selected for rendering
-    pub device: B::Device,
-    /// The events loop associated with the window
-    pub events_loop: winit::EventsLoop,
+    /// The adapter the device was opened on
+    pub adapter: gfx_hal::Adapter<B>,
+    /// The logical device selected for rendering.
+    /// Use `device_handle()` to share it with other threads.
+    pub device: Arc<B::Device>,
+    /// The events loop associated with the window,
+    /// or `None` if the context is headless
+    pub events_loop: Option<winit::EventsLoop>,
     /// The window the game is open in
-    pub window: winit::Window,
+    pub window: Option<winit::Window>,
     /// The surface for rendering to
-    pub surface: B::Surface,
+    pub surface: Option<B::Surface>,
 
     pub queue_group: QueueGroup<B, Graphics>,
     /// The command pool for submitting commands to the GPU
     pub command_pool: CommandPool<B, Graphics>,
     /// The current render pass (changed upon window resize)
     pub render_pass: B::RenderPass,
-    /// The default graphics pipeline, which includes vertex and fragment shaders
-    pub pipeline: B::GraphicsPipeline,
+    /// The graphics pipelines, starting with the default
+    /// pipeline built from the factory's shaders
+    pub pipelines: PipelineRegistry<B>,
     /// The layout of the pipeline
     pub pipeline_layout: B::PipelineLayout,
-    /// The swapchain
-    pub swapchain: B::Swapchain,
+    /// The swapchain, or `None` if the context is headless
+    pub swapchain: Option<B::Swapchain>,
+    /// The image headless contexts render into
+    pub offscreen: Option<ImageMem<B>>,
+    /// Images of the swapchain, which are owned by it
+    pub swapchain_images: Vec<B::Image>,
     /// Image views
     pub image_views: Vec<B::ImageView>,
     /// Frame buffers
@@ -56,25 +149,126 @@ pub struct RenderContext<B: Backend> {
     pub frame_fence: B::Fence,
     /// The extent
     pub extent: Extent,
-    /// A vector containing all models uploaded to the GPU.
-    /// The `model_index` property of objects is an index
-    /// into this vector.
-    pub models: Vec<ModelBuffer<B>>,
+    /// The meshes of all models uploaded to the GPU.
+    /// The `model_index` objects are created with is an
+    /// index into this vector.
+    pub models: Vec<MeshHandle>,
     /// Memory types the physical device supports
     pub memory_types: Vec<MemoryType>,
+    /// Allocates the memory of buffers and images
+    pub allocator: Arc<MemoryAllocator<B>>,
     /// The descriptor set layout
     pub set_layout: B::DescriptorSetLayout,
+    /// The bindings of `set_layout`
+    pub set_bindings: Vec<DescriptorSetLayoutBinding>,
+    /// Format of the swapchain images
+    pub color_format: Format,
+    /// Format of the depth buffer
+    pub depth_format: Format,
     pub depth_image: B::Image,
     pub depth_image_view: B::ImageView,
-    pub depth_image_memory: B::Memory,
+    pub depth_image_memory: Allocation<B>,
+    /// The multisampled color target, if MSAA is enabled
+    pub msaa_image: Option<ImageMem<B>>,
+    /// Samples per pixel of the color and depth attachments
+    pub samples: u8,
+    /// How depth is laid out in the depth buffer
+    pub depth_mode: DepthMode,
+    /// The present mode chosen when the context
+    /// was built, or `Fifo` if it is headless
+    pub present_mode: PresentMode,
+    /// Counters for the stats overlay
+    pub stats: RenderStats,
+    pub features: DeviceFeatures,
+    /// Limits of the physical device
+    pub limits: Limits,
+    /// Samplers created by `sampler()`
+    pub samplers: HashMap<SamplerDesc, B::Sampler>,
+    /// Compiled pipelines which are reused between runs
+    pub pipeline_cache: B::PipelineCache,
+    /// File the pipeline cache is saved to on destruction
+    pub pipeline_cache_path: Option<PathBuf>,
+    /// Vertex layout shared by every pipeline
+    pub vertex_desc: Option<VertexBufferDesc>,
+    pub attr_descs: Vec<AttributeDesc>,
+    /// Reloads pipelines when their shaders change
+    pub shader_watcher: ShaderWatcher,
+    /// Pipelines which replace every object's pipeline
+    /// while their debug view is selected
+    pub debug_pipelines: Vec<(DebugView, PipelineHandle)>,
+    /// Images behind the transient attachments of render graphs
+    pub graph_images: GraphImages<B>,
+    /// Passes injected into the built-in frame
+    pub custom_passes: Vec<AddedPass<B>>,
+    /// Timings of expensive events
+    pub telemetry: Telemetry,
+    /// Copies data into device local buffers and images
+    pub uploader: Uploader<B>,
+    /// Resources created through handles
+    pub meshes: HandleMap<MeshResource, IndexedMesh<B>>,
+    pub textures: HandleMap<TextureResource, Texture<B>>,
+    pub targets: HandleMap<TargetResource, ImageMem<B>>,
+    /// Buffers of bytes, so their `element_count` is their size
+    pub buffers: HandleMap<BufferResource, BufferMem<B>>,
+    /// Timestamps of the current frame and recent latencies
+    pub latency: LatencyTracker,
+    /// The window's mode, and where it was while windowed
+    pub window_mode: WindowModeState,
+    /// What is shown at the mouse position
+    pub cursor: Cursor,
+    /// Color frames are cleared to, or `None` for the sky color
+    pub clear_color: Option<[f32; 4]>,
+    /// What frames do with the color attachment's previous contents
+    pub color_load: LoadOp,
+    /// Variants of `render_pass` which load or discard the
+    /// color attachment, created when first used
+    pub load_passes: Vec<(LoadOp, B::RenderPass)>,
+    /// Whether the color attachment of each frame buffer
+    /// hasn't been rendered to since it was created, so its
+    /// contents are undefined and can't be loaded
+    pub fresh_images: Vec<bool>,
+    /// Whether frames can be copied back to the CPU, which
+    /// the surface may not allow for swapchain images
+    pub frames_readable: bool,
+    /// Whether to copy the next frame back to the CPU
+    pub capture_requested: bool,
+    /// The copy of the last captured frame, until it is taken
+    pub captured_frame: Option<CapturedFrame>,
 }
 
-/// Contains a `BufferMem` for both
-/// vertices and indices to represent
-/// a model.
-pub struct ModelBuffer<B: Backend> {
-    pub vertices: BufferMem<B>,
-    pub indices: BufferMem<B>,
+impl<B: Backend> RenderContext<B> {
+    /// Returns a handle to the device which can
+    /// be sent to other threads to create resources.
+    pub fn device_handle(&self) -> RenderDevice<B> {
+        RenderDevice::new(Arc::clone(&self.device), Arc::clone(&self.allocator))
+    }
+
+    /// Sets the color frames are cleared to, or
+    /// with `None`, the sky color.
+    pub fn set_clear_color(&mut self, color: Option<[f32; 4]>) {
+        self.clear_color = color;
+    }
+
+    /// Sets what frames do with the previous contents of the
+    /// color attachment. Without MSAA, `LoadOp::Load` loads what
+    /// was last presented from the same swapchain image, which
+    /// may be older than the last frame. Images are cleared the
+    /// first time they are rendered to instead.
+    pub fn set_color_load(&mut self, load: LoadOp) {
+        let created = load == LoadOp::Clear || self.load_passes.iter().any(|&(op, _)| op == load);
+        if !created {
+            let render_pass = factory::create_frame_render_pass::<B>(
+                &self.device,
+                self.color_format,
+                self.depth_format,
+                self.samples,
+                self.frame_layout(),
+                load,
+            );
+            self.load_passes.push((load, render_pass));
+        }
+        self.color_load = load;
+    }
 }
 
 /// Contains a `BufferMem`, a descriptor
@@ -0,0 +1,117 @@
+//! Recording passes injected into the built-in frame.
+//!
+//! The built-in frame is a single render pass, so injected
+//! passes are recorded into it at their injection point. They
+//! draw with the frame's render pass into its color and depth
+//! attachments, and can't sample either of them.
+use super::*;
+use render::injection::{self, FrameGraph, InjectedPass, InjectionPoint};
+
+/// A pass users add to the built-in frame.
+pub trait CustomPass<B: Backend> {
+    /// Describes where the pass runs and the resources it uses.
+    fn desc(&self) -> &InjectedPass;
+
+    /// Records the pass into the frame's render pass.
+    fn record(
+        &mut self,
+        encoder: &mut RenderPassInlineEncoder<B, Primary>,
+        resources: &PassResources<B>,
+    );
+
+    /// Frees the pass's resources before the context is destroyed.
+    fn destroy(&mut self, _device: &B::Device) {}
+}
+
+/// Engine resources available to injected passes.
+pub struct PassResources<'a, B: Backend> {
+    pub camera: CameraUniforms,
+    pub extent: Extent,
+    /// The render pass pipelines of injected passes are created for
+    pub render_pass: &'a B::RenderPass,
+    /// The frame's color attachment, if the backend exposes it
+    pub color: Option<&'a B::ImageView>,
+    pub depth: &'a B::ImageView,
+    /// Draws of the objects the scene camera sees, in order
+    pub draws: &'a [DrawItem],
+    /// Draws of the objects the camera's culling mask leaves
+    /// out, in order
+    pub culled_draws: &'a [DrawItem],
+}
+
+impl<'a, B: Backend> PassResources<'a, B> {
+    /// Returns the view of the frame attachment called `name`,
+    /// or `None` if there is no such attachment or the backend
+    /// doesn't expose it.
+    pub fn attachment(&self, name: &str) -> Option<&'a B::ImageView> {
+        match name {
+            injection::COLOR => self.color,
+            injection::DEPTH => Some(self.depth),
+            _ => None,
+        }
+    }
+
+    /// Returns the draws of objects on `layers`, such as
+    /// the layers in a pass's `InjectedPass::layers`, in
+    /// order. This includes objects the camera doesn't see.
+    pub fn draws_in(&self, layers: LayerMask) -> Vec<&'a DrawItem> {
+        let mut draws: Vec<_> = self
+            .draws
+            .iter()
+            .chain(self.culled_draws)
+            .filter(|draw| layers.contains(draw.layer()))
+            .collect();
+        draws.sort_by_key(|draw| draw.sort_key);
+        draws
+    }
+}
+
+/// A pass added with `add_custom_pass()`,
+/// and whether it survived culling.
+pub struct AddedPass<B: Backend> {
+    pub pass: Box<dyn CustomPass<B>>,
+    pub live: bool,
+}
+
+impl<B: Backend> RenderContext<B> {
+    /// Adds a pass to the built-in frame. Fails if the pass
+    /// names a resource the engine doesn't provide.
+    pub fn add_custom_pass(&mut self, pass: Box<dyn CustomPass<B>>) -> Result<(), RenderError> {
+        self.custom_passes.push(AddedPass { pass, live: false });
+        match self.frame_graph() {
+            Ok(frame) => {
+                for (index, added) in self.custom_passes.iter_mut().enumerate() {
+                    added.live = frame.is_live(index);
+                }
+                Ok(())
+            }
+            Err(err) => {
+                self.custom_passes.pop();
+                Err(RenderError::Graph(err))
+            }
+        }
+    }
+
+    /// Describes the built-in frame with the added passes.
+    pub fn frame_graph(&self) -> Result<FrameGraph, GraphError> {
+        injection::frame_graph(
+            self.extent.width,
+            self.extent.height,
+            self.custom_passes.iter().map(|added| added.pass.desc()),
+        )
+    }
+}
+
+/// Records the live passes at `point`, in the order they were added.
+pub fn record_custom_passes<B: Backend>(
+    passes: &mut [AddedPass<B>],
+    point: InjectionPoint,
+    encoder: &mut RenderPassInlineEncoder<B, Primary>,
+    resources: &PassResources<B>,
+) {
+    for added in passes {
+        if added.live && added.pass.desc().point == point {
+            added.pass.record(encoder, resources);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! Descriptor set layouts and uniform buffers.
+use super::*;
+
+/// Builds the bindings of a descriptor set layout.
+///
+/// ```ignore
+/// let bindings = DescriptorLayoutBuilder::new()
+///     .with_uniform_buffer(0, ShaderStageFlags::VERTEX)
+///     .build();
+/// ```
+pub struct DescriptorLayoutBuilder {
+    bindings: Vec<DescriptorSetLayoutBinding>,
+}
+
+impl DescriptorLayoutBuilder {
+    pub fn new() -> DescriptorLayoutBuilder {
+        DescriptorLayoutBuilder {
+            bindings: Vec::new(),
+        }
+    }
+
+    /// Adds a single descriptor of type `ty` at `binding`,
+    /// visible to the shader stages in `stages`.
+    pub fn with_binding(
+        mut self,
+        binding: u32,
+        ty: DescriptorType,
+        stages: ShaderStageFlags,
+    ) -> Self {
+        self.bindings.push(DescriptorSetLayoutBinding {
+            binding,
+            ty,
+            count: 1,
+            stage_flags: stages,
+            immutable_samplers: false,
+        });
+        self
+    }
+
+    /// Adds a uniform buffer at `binding`.
+    pub fn with_uniform_buffer(self, binding: u32, stages: ShaderStageFlags) -> Self {
+        self.with_binding(binding, DescriptorType::UniformBuffer, stages)
+    }
+
+    /// Adds a combined image sampler at `binding`,
+    /// for sampling a `Texture`.
+    pub fn with_texture(self, binding: u32, stages: ShaderStageFlags) -> Self {
+        self.with_binding(binding, DescriptorType::CombinedImageSampler, stages)
+    }
+
+    /// Adds a combined image sampler at `binding`, for
+    /// sampling a `TextureArray` as a `sampler2DArray`.
+    pub fn with_texture_array(self, binding: u32, stages: ShaderStageFlags) -> Self {
+        self.with_binding(binding, DescriptorType::CombinedImageSampler, stages)
+    }
+
+    pub fn build(self) -> Vec<DescriptorSetLayoutBinding> {
+        self.bindings
+    }
+}
+
+impl<B: Backend> UniformBuffer<B> {
+    /// Creates a buffer holding one `T` and a descriptor set
+    /// of `set_layout` with the buffer written to `binding`.
+    /// The set's pool also has room for the other descriptors
+    /// in `bindings`, the bindings of `set_layout`, which are
+    /// left for the caller to write.
+    pub fn new<T>(
+        device: &B::Device,
+        allocator: &MemoryAllocator<B>,
+        set_layout: &B::DescriptorSetLayout,
+        bindings: &[DescriptorSetLayoutBinding],
+        binding: u32,
+    ) -> UniformBuffer<B> {
+        let ranges = std::iter::once(DescriptorRangeDesc {
+            ty: DescriptorType::UniformBuffer,
+            count: 1,
+        })
+        .chain(
+            bindings
+                .iter()
+                .filter(|desc| desc.binding != binding)
+                .map(|desc| DescriptorRangeDesc {
+                    ty: desc.ty,
+                    count: desc.count,
+                }),
+        )
+        .collect::<Vec<_>>();
+        let mut desc_pool = device.create_descriptor_pool(1, &ranges);
+        let desc_set = desc_pool.allocate_set(set_layout).unwrap();
+
+        let (buffer, memory) = buffer_util::empty_buffer::<B, T>(
+            device,
+            allocator,
+            Properties::CPU_VISIBLE,
+            Usage::UNIFORM,
+            1,
+        );
+
+        device.write_descriptor_sets(vec![DescriptorSetWrite {
+            set: &desc_set,
+            binding,
+            array_offset: 0,
+            descriptors: Some(Descriptor::Buffer(&buffer, None..None)),
+        }]);
+
+        UniformBuffer {
+            buffer: BufferMem::new(buffer, memory),
+            desc_set,
+            desc_pool,
+        }
+    }
+
+    /// Writes `value` into the buffer. `T` must be the
+    /// type the buffer was created with.
+    pub fn update<T: Copy>(&mut self, device: &B::Device, value: T) {
+        buffer_util::fill_buffer::<B, T>(device, &self.buffer.memory, &[value]);
+    }
+
+    /// Frees the buffer and its descriptor pool.
+    pub fn destroy(self, device: &B::Device) {
+        device.destroy_descriptor_pool(self.desc_pool);
+        device.destroy_buffer(self.buffer.buffer);
+        self.buffer.memory.free();
+    }
+}
@@ -0,0 +1,70 @@
+//! A handle to the logical device which can be
+//! shared with other threads.
+use super::*;
+use std::sync::Arc;
+
+/// A cloneable, thread-safe handle to the device of a
+/// `RenderContext`. Worker threads can use it to create
+/// buffers and shader modules while frame submission
+/// stays on the thread owning the `RenderContext`.
+///
+/// gfx-hal devices are `Send + Sync` and synchronize
+/// resource creation internally, and the memory allocator
+/// locks itself, so no extra locking is needed here.
+pub struct RenderDevice<B: Backend> {
+    device: Arc<B::Device>,
+    allocator: Arc<MemoryAllocator<B>>,
+}
+
+impl<B: Backend> Clone for RenderDevice<B> {
+    fn clone(&self) -> Self {
+        RenderDevice {
+            device: Arc::clone(&self.device),
+            allocator: Arc::clone(&self.allocator),
+        }
+    }
+}
+
+impl<B: Backend> RenderDevice<B> {
+    pub fn new(device: Arc<B::Device>, allocator: Arc<MemoryAllocator<B>>) -> RenderDevice<B> {
+        RenderDevice { device, allocator }
+    }
+
+    /// Returns the underlying device.
+    pub fn device(&self) -> &B::Device {
+        &self.device
+    }
+
+    /// Creates an empty buffer able to hold `item_count` items.
+    /// See `buffer_util::empty_buffer()`.
+    pub fn empty_buffer<I>(
+        &self,
+        properties: Properties,
+        usage: Usage,
+        item_count: usize,
+    ) -> (B::Buffer, Allocation<B>) {
+        buffer_util::empty_buffer::<B, I>(
+            &self.device,
+            &self.allocator,
+            properties,
+            usage,
+            item_count,
+        )
+    }
+
+    /// Creates a buffer filled with `items`.
+    /// See `buffer_util::create_buffer()`.
+    pub fn create_buffer<I: Copy>(
+        &self,
+        properties: Properties,
+        usage: Usage,
+        items: &[I],
+    ) -> (B::Buffer, Allocation<B>) {
+        buffer_util::create_buffer::<B, I>(&self.device, &self.allocator, properties, usage, items)
+    }
+
+    /// Creates a shader module from SPIR-V.
+    pub fn create_shader_module(&self, spirv: &[u8]) -> B::ShaderModule {
+        self.device.create_shader_module(spirv).unwrap()
+    }
+}
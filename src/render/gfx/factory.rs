@@ -1,6 +1,71 @@
 //! Includes factory functions for building RenderContexts.
+use super::pipeline::{self, PipelineTarget};
 use super::*;
-use gfx_hal::{Instance, PhysicalDevice, Surface};
+use gfx_hal::{Features, Instance, PhysicalDevice, Surface};
+use profile::millis_since;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// An error produced when building a `RenderContext`.
+#[derive(Debug)]
+pub enum RenderError {
+    /// The window could not be created
+    WindowCreation(String),
+    /// No adapter supports rendering to the window
+    NoSuitableAdapter,
+    /// The logical device could not be opened
+    DeviceCreation(String),
+    /// The surface supports no sRGB color format
+    NoSurfaceFormat,
+    /// A shader module could not be created from SPIR-V
+    ShaderCompilation(String),
+    /// The graphics pipeline could not be created
+    PipelineCreation(String),
+    /// The swapchain images, views or framebuffers could not be created
+    SwapchainCreation(String),
+    /// The depth buffer could not be created
+    DepthBufferCreation(String),
+    /// A render graph could not be compiled
+    Graph(GraphError),
+    /// An image for a render graph attachment could not be created
+    AttachmentCreation(String),
+    /// The shaders could not be reflected
+    Reflection(ReflectError),
+    /// A GLSL shader could not be compiled
+    #[cfg(feature = "glsl")]
+    Glsl(glsl::ShaderError),
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RenderError::WindowCreation(err) => write!(f, "failed to create window: {}", err),
+            RenderError::NoSuitableAdapter => write!(f, "no suitable graphics adapter found"),
+            RenderError::DeviceCreation(err) => write!(f, "failed to open device: {}", err),
+            RenderError::NoSurfaceFormat => write!(f, "surface supports no sRGB format"),
+            RenderError::ShaderCompilation(err) => write!(f, "failed to load shader: {}", err),
+            RenderError::PipelineCreation(err) => write!(f, "failed to create pipeline: {}", err),
+            RenderError::SwapchainCreation(err) => {
+                write!(f, "failed to create swapchain: {}", err)
+            }
+            RenderError::DepthBufferCreation(err) => {
+                write!(f, "failed to create depth buffer: {}", err)
+            }
+            RenderError::Graph(err) => write!(f, "invalid render graph: {}", err),
+            RenderError::AttachmentCreation(err) => {
+                write!(f, "failed to create attachment: {}", err)
+            }
+            RenderError::Reflection(err) => write!(f, "failed to reflect shaders: {}", err),
+            #[cfg(feature = "glsl")]
+            RenderError::Glsl(err) => write!(f, "failed to compile shader:\n{}", err),
+        }
+    }
+}
 
 /// Struct used to build RenderContexts
 /// in a clean manner
@@ -23,22 +88,51 @@ pub struct RenderBuilder<'a, B: Backend> {
     render_pass: Option<B::RenderPass>,
     /// Raw vertex shader
     // TODO multiple pipelines/shaders
-    vertex_shader: &'a [u8],
+    vertex_shader: Cow<'a, [u8]>,
     /// Raw fragment shader
-    fragment_shader: &'a [u8],
+    fragment_shader: Cow<'a, [u8]>,
+    /// GLSL files compiled into the shaders when building
+    #[cfg(feature = "glsl")]
+    glsl_shaders: (Option<PathBuf>, Option<PathBuf>),
     /// Title of window
     title: &'a str,
     /// Dimensions of window
     dimensions: (u32, u32),
-    pipeline_layout: &'a [DescriptorSetLayoutBinding],
+    /// RGBA pixels, width and height of the window's icon
+    icon: Option<(&'a [u8], u32, u32)>,
+    /// Color frames are cleared to, or `None` for the sky color
+    clear_color: Option<[f32; 4]>,
+    pipeline_layout: Cow<'a, [DescriptorSetLayoutBinding]>,
+    /// Bindings of texture arrays added to the layout
+    texture_arrays: Vec<(u32, ShaderStageFlags)>,
+    /// Push constant ranges of the pipeline layout, in 32-bit words
+    push_constants: Vec<(ShaderStageFlags, Range<u32>)>,
     /// Surface's color format
     surface_color_format: Option<Format>,
     adapter: Option<gfx_hal::Adapter<B>>,
     caps: Option<gfx_hal::SurfaceCapabilities>,
+    /// Present modes the surface supports
+    supported_present_modes: Vec<PresentMode>,
+    /// Present modes to use, most preferred first
+    present_modes: Vec<PresentMode>,
     vertex_desc: Option<VertexBufferDesc>,
     attr_descs: Vec<AttributeDesc>,
     memory_types: Vec<MemoryType>,
-    depth_format: Format,
+    /// Format of the depth buffer, or `None` to choose by `depth_mode`
+    depth_format: Option<Format>,
+    depth_mode: DepthMode,
+    features: Option<DeviceFeatures>,
+    limits: Limits,
+    /// Samples per pixel of the color and depth attachments
+    samples: u8,
+    /// Chooses the adapter to render with; defaults to the first
+    adapter_selector: Option<AdapterSelector<'a>>,
+    /// File the pipeline cache is loaded from and saved to
+    pipeline_cache_path: Option<PathBuf>,
+    /// Whether to derive the layout from the shaders
+    reflect: bool,
+    /// Whether to render offscreen without a window
+    headless: bool,
 }
 
 impl<'a, B: Backend> Default for RenderBuilder<'a, B> {
@@ -53,18 +147,34 @@ impl<'a, B: Backend> Default for RenderBuilder<'a, B> {
             command_pool: None,
             render_pass: None,
             // TODO allow for more shaders
-            vertex_shader: &[],
-            fragment_shader: &[],
+            vertex_shader: Cow::Borrowed(&[]),
+            fragment_shader: Cow::Borrowed(&[]),
+            #[cfg(feature = "glsl")]
+            glsl_shaders: (None, None),
             title: "",
             dimensions: (720, 480),
+            icon: None,
+            clear_color: None,
             surface_color_format: None,
             adapter: None,
             caps: None,
-            pipeline_layout: &[],
+            supported_present_modes: vec![],
+            present_modes: vec![PresentMode::Fifo],
+            pipeline_layout: Cow::Borrowed(&[]),
+            texture_arrays: vec![],
+            push_constants: vec![],
             vertex_desc: None,
             attr_descs: vec![],
             memory_types: vec![],
-            depth_format: Format::D32FloatS8Uint,
+            depth_format: None,
+            depth_mode: DepthMode::default(),
+            features: None,
+            limits: Limits::default(),
+            samples: 1,
+            adapter_selector: None,
+            pipeline_cache_path: None,
+            reflect: false,
+            headless: false,
         }
     }
 }
@@ -87,18 +197,91 @@ impl<'a> RenderBuilder<'a, back::Backend> {
         self
     }
 
+    /// Sets the window's icon from RGBA pixel data with
+    /// 8 bits per channel, in rows from top to bottom.
+    pub fn with_icon(mut self, rgba: &'a [u8], width: u32, height: u32) -> Self {
+        self.icon = Some((rgba, width, height));
+        self
+    }
+
+    /// Clears frames to `color` instead of the sky color.
+    pub fn with_clear_color(mut self, color: [f32; 4]) -> Self {
+        self.clear_color = Some(color);
+        self
+    }
+
+    /// Renders into an offscreen image of the given size
+    /// instead of a window, such as for automated image
+    /// tests. Frames are read back with `read_frame()`.
+    pub fn headless(mut self, width: u32, height: u32) -> Self {
+        self.headless = true;
+        self.with_dimensions(width, height)
+    }
+
     pub fn with_vertex_shader(mut self, vertex_shader: &'a [u8]) -> Self {
-        self.vertex_shader = vertex_shader;
+        self.vertex_shader = Cow::Borrowed(vertex_shader);
         self
     }
 
     pub fn with_fragment_shader(mut self, fragment_shader: &'a [u8]) -> Self {
-        self.fragment_shader = fragment_shader;
+        self.fragment_shader = Cow::Borrowed(fragment_shader);
+        self
+    }
+
+    /// Compiles the vertex shader from the GLSL file
+    /// at `path` when the context is built.
+    #[cfg(feature = "glsl")]
+    pub fn with_vertex_shader_glsl<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.glsl_shaders.0 = Some(path.into());
+        self
+    }
+
+    /// Compiles the fragment shader from the GLSL file
+    /// at `path` when the context is built.
+    #[cfg(feature = "glsl")]
+    pub fn with_fragment_shader_glsl<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.glsl_shaders.1 = Some(path.into());
         self
     }
 
     pub fn with_pipeline(mut self, desc: &'a [DescriptorSetLayoutBinding]) -> Self {
-        self.pipeline_layout = desc;
+        self.pipeline_layout = Cow::Borrowed(desc);
+        self
+    }
+
+    /// Adds a combined image sampler at `binding` to the
+    /// layout, for a `TextureArray` shaders sample as a
+    /// `sampler2DArray`. Unlike `with_pipeline()`, this
+    /// adds to a layout derived with `with_reflection()`.
+    pub fn with_texture_array(mut self, binding: u32, stages: ShaderStageFlags) -> Self {
+        self.texture_arrays.push((binding, stages));
+        self
+    }
+
+    /// Adds a push constant range visible to `stages`. Offsets
+    /// and sizes are in 32-bit words, so a `Mat4` takes 16.
+    pub fn with_push_constants(mut self, stages: ShaderStageFlags, words: Range<u32>) -> Self {
+        self.push_constants.push((stages, words));
+        self
+    }
+
+    /// Derives the descriptor set layout, push constant ranges
+    /// and vertex attributes from the shaders when building.
+    /// Anything set with `with_pipeline()`, `with_push_constants()`
+    /// or `with_vertex_attr()` is used instead of its reflection.
+    ///
+    /// Reflected vertex attributes are packed one after another
+    /// in order of location, so the vertex struct must be too.
+    pub fn with_reflection(mut self) -> Self {
+        self.reflect = true;
+        self
+    }
+
+    /// Loads the pipeline cache from `path` if it exists, so that
+    /// drivers can skip compiling shaders they have seen before.
+    /// The cache is written back to `path` when the context is destroyed.
+    pub fn with_pipeline_cache<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.pipeline_cache_path = Some(path.into());
         self
     }
 
@@ -112,52 +295,170 @@ impl<'a> RenderBuilder<'a, back::Backend> {
         self
     }
 
+    /// Sets the format of the depth buffer. Defaults to `D32Float`
+    /// with reversed depth and `D32FloatS8Uint` otherwise.
+    pub fn with_depth_format(mut self, format: Format) -> Self {
+        self.depth_format = Some(format);
+        self
+    }
+
+    /// Sets how depth is laid out, which flips the depth tests
+    /// of every pipeline when reversed. Defaults to reversed.
+    pub fn with_depth_mode(mut self, mode: DepthMode) -> Self {
+        self.depth_mode = mode;
+        self
+    }
+
+    fn depth_format(&self) -> Format {
+        self.depth_format.unwrap_or(match self.depth_mode {
+            DepthMode::Standard => Format::D32FloatS8Uint,
+            DepthMode::Reversed => Format::D32Float,
+        })
+    }
+
+    /// Sets the number of samples per pixel for multisample
+    /// anti-aliasing. Counts the device does not support are
+    /// lowered to the nearest supported count. Defaults to 1.
+    pub fn with_samples(mut self, samples: u8) -> Self {
+        self.samples = samples.max(1);
+        self
+    }
+
+    /// Presents with `mode` if the surface supports it,
+    /// otherwise falls back to `Fifo`, which is always supported.
+    pub fn with_present_mode(mut self, mode: PresentMode) -> Self {
+        self.present_modes = vec![mode];
+        self
+    }
+
+    /// Chooses whether presenting waits for vertical sync.
+    /// Without vsync, `Mailbox` is preferred over `Immediate`
+    /// since it does not tear.
+    pub fn vsync(mut self, vsync: bool) -> Self {
+        self.present_modes = if vsync {
+            vec![PresentMode::Fifo]
+        } else {
+            vec![PresentMode::Mailbox, PresentMode::Immediate]
+        };
+        self
+    }
+
+    /// Uses `selector` to choose the adapter to render with.
+    /// Returning `None` fails the build with `NoSuitableAdapter`.
+    pub fn with_adapter_selector<F>(mut self, selector: F) -> Self
+    where
+        F: Fn(&[AdapterDesc]) -> Option<usize> + 'a,
+    {
+        self.adapter_selector = Some(Box::new(selector));
+        self
+    }
+
+    /// Renders with the discrete GPU if there is one.
+    /// See `adapter::prefer_discrete_gpu()`.
+    pub fn prefer_discrete_gpu(self) -> Self {
+        self.with_adapter_selector(adapter::prefer_discrete_gpu)
+    }
+
     /// Builds a RenderContext, initializing all values and
     /// consuming the RenderBuilder in the process.
-    pub fn build(mut self) -> RenderContext<back::Backend> {
+    pub fn build(mut self) -> Result<RenderContext<back::Backend>, RenderError> {
+        #[cfg(feature = "glsl")]
+        self.compile_glsl()?;
+        if self.reflect {
+            self.reflect_layout()?;
+        }
         self.build_instance();
-        self.build_window_and_events_loop();
-        self.build_device_and_queue_group_and_surface();
+        if !self.headless {
+            self.build_window_and_events_loop()?;
+        }
+        self.build_device_and_queue_group_and_surface()?;
         self.build_command_pool();
         self.build_render_pass();
         self.finish()
     }
 
+    /// Compiles the GLSL shaders, before a window
+    /// is opened in case they have errors.
+    #[cfg(feature = "glsl")]
+    fn compile_glsl(&mut self) -> Result<(), RenderError> {
+        if let Some(path) = self.glsl_shaders.0.take() {
+            self.vertex_shader = Cow::Owned(glsl::compile(&path).map_err(RenderError::Glsl)?);
+        }
+        if let Some(path) = self.glsl_shaders.1.take() {
+            self.fragment_shader = Cow::Owned(glsl::compile(&path).map_err(RenderError::Glsl)?);
+        }
+        Ok(())
+    }
+
+    /// Fills in the parts of the layout which
+    /// weren't set from the shaders' reflection.
+    fn reflect_layout(&mut self) -> Result<(), RenderError> {
+        let shaders = [
+            reflect::reflect(&self.vertex_shader).map_err(RenderError::Reflection)?,
+            reflect::reflect(&self.fragment_shader).map_err(RenderError::Reflection)?,
+        ];
+        if self.pipeline_layout.is_empty() {
+            self.pipeline_layout = Cow::Owned(ShaderReflection::layout_bindings(&shaders));
+        }
+        if self.push_constants.is_empty() {
+            self.push_constants
+                .extend(ShaderReflection::push_constant_range(&shaders));
+        }
+        if self.vertex_desc.is_none() && !shaders[0].vertex_inputs.is_empty() {
+            let (vertex_desc, attr_descs) = shaders[0].vertex_layout();
+            self.vertex_desc = Some(vertex_desc);
+            self.attr_descs = attr_descs;
+        }
+        Ok(())
+    }
+
     fn build_instance(&mut self) {
         self.instance = Some(back::Instance::create(self.title, 1));
     }
 
-    fn build_device_and_queue_group_and_surface(&mut self) {
-        self.surface = Some(
-            self.instance
-                .as_ref()
-                .unwrap()
-                .create_surface(self.window.as_ref().unwrap()),
-        );
+    fn build_device_and_queue_group_and_surface(&mut self) -> Result<(), RenderError> {
+        if let Some(ref window) = self.window {
+            self.surface = Some(self.instance.as_ref().unwrap().create_surface(window));
+        }
 
         let (device, queue_group) = {
-            let mut adapter = self
-                .instance
-                .as_mut()
-                .unwrap()
-                .enumerate_adapters()
-                .remove(0);
-            let surface = self.surface.as_mut().unwrap();
-            let (device, queue_group) = adapter
-                .open_with::<_, Graphics>(1, |family| surface.supports_queue_family(family))
-                .unwrap();
+            let mut adapters = self.instance.as_mut().unwrap().enumerate_adapters();
+            let index = match self.adapter_selector {
+                Some(ref selector) => selector(&adapter::describe_adapters(&adapters)),
+                None => Some(0),
+            };
+            let mut adapter = match index {
+                Some(index) if index < adapters.len() => adapters.remove(index),
+                _ => return Err(RenderError::NoSuitableAdapter),
+            };
+            crash::set_adapter_info(format!("{:?}", adapter.info));
+            let (device, queue_group) = match self.surface {
+                Some(ref surface) => adapter
+                    .open_with::<_, Graphics>(1, |family| surface.supports_queue_family(family)),
+                // Without a surface any graphics queue will do
+                None => adapter.open_with::<_, Graphics>(1, |_| true),
+            }
+            .map_err(|err| RenderError::DeviceCreation(format!("{:?}", err)))?;
             self.adapter = Some(adapter);
             (device, queue_group)
         };
         let physical_device = &self.adapter.as_mut().unwrap().physical_device;
-        let (caps, formats, _) = self
-            .surface
-            .as_mut()
-            .unwrap()
-            .compatibility(physical_device);
-        self.caps = Some(caps);
+        let formats = match self.surface {
+            Some(ref mut surface) => {
+                let (caps, formats, present_modes) = surface.compatibility(physical_device);
+                self.caps = Some(caps);
+                self.supported_present_modes = present_modes;
+                formats
+            }
+            // Offscreen images can have any format
+            None => None,
+        };
 
         self.memory_types = physical_device.memory_properties().memory_types;
+        self.limits = physical_device.limits();
+        let features = device_features(physical_device);
+        self.samples = features.supported_samples(self.samples);
+        self.features = Some(features);
 
         self.surface_color_format = {
             // Pick color format
@@ -166,7 +467,7 @@ impl<'a> RenderBuilder<'a, back::Backend> {
                     choices
                         .into_iter()
                         .find(|format| format.base_format().1 == ChannelType::Srgb)
-                        .unwrap(),
+                        .ok_or(RenderError::NoSurfaceFormat)?,
                 ),
                 None => Some(Format::Rgba8Srgb),
             }
@@ -174,17 +475,28 @@ impl<'a> RenderBuilder<'a, back::Backend> {
 
         self.device = Some(device);
         self.queue_group = Some(queue_group);
+        Ok(())
     }
 
-    fn build_window_and_events_loop(&mut self) {
+    fn build_window_and_events_loop(&mut self) -> Result<(), RenderError> {
+        let icon = match self.icon {
+            Some((rgba, width, height)) => Some(
+                winit::Icon::from_rgba(rgba.to_vec(), width, height).map_err(|err| {
+                    RenderError::WindowCreation(format!("invalid icon: {:?}", err))
+                })?,
+            ),
+            None => None,
+        };
         self.events_loop = Some(winit::EventsLoop::new());
         self.window = Some(
             winit::WindowBuilder::new()
                 .with_title(self.title)
                 .with_dimensions(self.dimensions.into())
+                .with_window_icon(icon)
                 .build(self.events_loop.as_ref().unwrap())
-                .unwrap(),
+                .map_err(|err| RenderError::WindowCreation(err.to_string()))?,
         );
+        Ok(())
     }
 
     fn build_command_pool(&mut self) {
@@ -197,309 +509,476 @@ impl<'a> RenderBuilder<'a, back::Backend> {
     }
 
     fn build_render_pass(&mut self) {
-        let render_pass = {
-            let color_attachment = Attachment {
-                format: Some(self.surface_color_format.unwrap().clone()),
-                samples: 1,
-                ops: AttachmentOps::new(AttachmentLoadOp::Clear, AttachmentStoreOp::Store),
-                stencil_ops: AttachmentOps::DONT_CARE,
-                layouts: Layout::Undefined..Layout::Present,
-            };
-
-            let depth_attachment = Attachment {
-                format: Some(self.depth_format),
-                samples: 1,
-                ops: AttachmentOps::new(AttachmentLoadOp::Clear, AttachmentStoreOp::DontCare),
-                stencil_ops: AttachmentOps::DONT_CARE,
-                layouts: Layout::Undefined..Layout::DepthStencilAttachmentOptimal,
-            };
-
-            // Single subpass for now
-            let subpass = SubpassDesc {
-                colors: &[(0, Layout::ColorAttachmentOptimal)],
-                depth_stencil: Some(&(1, Layout::DepthStencilAttachmentOptimal)),
-                inputs: &[],
-                resolves: &[],
-                preserves: &[],
-            };
-
-            let dependency = SubpassDependency {
-                passes: SubpassRef::External..SubpassRef::Pass(0),
-                stages: PipelineStage::COLOR_ATTACHMENT_OUTPUT
-                    ..PipelineStage::COLOR_ATTACHMENT_OUTPUT,
-                accesses: Access::empty()
-                    ..(Access::COLOR_ATTACHMENT_READ | Access::COLOR_ATTACHMENT_WRITE),
-            };
-
-            self.device.as_mut().unwrap().create_render_pass(
-                &[color_attachment, depth_attachment],
-                &[subpass],
-                &[dependency],
-            )
+        // Offscreen images are left ready to be read back
+        let final_layout = if self.headless {
+            Layout::TransferSrcOptimal
+        } else {
+            Layout::Present
         };
+        let render_pass = create_frame_render_pass::<back::Backend>(
+            self.device.as_ref().unwrap(),
+            self.surface_color_format.unwrap(),
+            self.depth_format(),
+            self.samples,
+            final_layout,
+            LoadOp::Clear,
+        );
         self.render_pass = Some(render_pass);
     }
 
-    fn finish(mut self) -> RenderContext<back::Backend> {
+    /// Returns the most preferred present mode the surface supports.
+    fn choose_present_mode(&self) -> PresentMode {
+        let supported = &self.supported_present_modes;
+        match self
+            .present_modes
+            .iter()
+            .find(|mode| supported.contains(mode))
+        {
+            Some(&mode) => mode,
+            None => {
+                warn!(
+                    "None of the present modes {:?} are supported, using Fifo",
+                    self.present_modes
+                );
+                PresentMode::Fifo
+            }
+        }
+    }
+
+    fn finish(mut self) -> Result<RenderContext<back::Backend>, RenderError> {
+        let pipeline_cache = {
+            let data = self
+                .pipeline_cache_path
+                .as_ref()
+                .and_then(|path| std::fs::read(path).ok());
+            let device = self.device.as_ref().unwrap();
+            // Drivers validate the data and ignore caches from
+            // other devices, so a stale cache is only a slow start
+            match device.create_pipeline_cache(data.as_ref().map(|data| &data[..])) {
+                Ok(cache) => cache,
+                Err(err) => {
+                    warn!("Failed to load pipeline cache: {:?}", err);
+                    device
+                        .create_pipeline_cache(None)
+                        .map_err(|err| RenderError::PipelineCreation(format!("{:?}", err)))?
+                }
+            }
+        };
+
+        for &(binding, stages) in &self.texture_arrays {
+            self.pipeline_layout
+                .to_mut()
+                .push(DescriptorSetLayoutBinding {
+                    binding,
+                    ty: DescriptorType::CombinedImageSampler,
+                    count: 1,
+                    stage_flags: stages,
+                    immutable_samplers: false,
+                });
+        }
         let set_layout = self
             .device
             .as_ref()
             .unwrap()
-            .create_descriptor_set_layout(self.pipeline_layout, &[]);
+            .create_descriptor_set_layout(&*self.pipeline_layout, &[]);
 
         let pipeline_layout = self
             .device
             .as_ref()
             .unwrap()
-            .create_pipeline_layout(vec![&set_layout], &[]);
-
-        let vertex_shader_mod =
-            create_shader::<back::Backend>(self.vertex_shader, self.device.as_ref().unwrap());
-        let fragment_shader_mod =
-            create_shader::<back::Backend>(self.fragment_shader, self.device.as_ref().unwrap());
-
-        let pipeline = {
-            let vs_entry = EntryPoint::<back::Backend> {
-                entry: "main",
-                module: &vertex_shader_mod,
-                specialization: Specialization {
-                    constants: &[],
-                    data: &[],
-                },
-            };
-
-            let fs_entry = EntryPoint::<back::Backend> {
-                entry: "main",
-                module: &fragment_shader_mod,
-                specialization: Specialization {
-                    constants: &[],
-                    data: &[],
-                },
-            };
-
-            let shader_entries = GraphicsShaderSet {
-                vertex: vs_entry,
-                hull: None,
-                domain: None,
-                geometry: None,
-                fragment: Some(fs_entry),
-            };
-
-            let subpass = Subpass {
-                index: 0,
-                main_pass: self.render_pass.as_ref().unwrap(),
-            };
-
-            let mut pipeline_desc = GraphicsPipelineDesc::new(
-                shader_entries,
-                Primitive::TriangleList,
-                Rasterizer::FILL,
-                &pipeline_layout,
-                subpass,
-            );
-
-            pipeline_desc
-                .blender
-                .targets
-                .push(ColorBlendDesc(ColorMask::ALL, BlendState::ALPHA));
-
-            // Vertex buffers
-            if let Some(vertex_desc) = self.vertex_desc {
-                pipeline_desc.vertex_buffers.push(vertex_desc);
-
-                for attr_desc in self.attr_descs.clone() {
-                    pipeline_desc.attributes.push(attr_desc);
+            .create_pipeline_layout(vec![&set_layout], self.push_constants.clone());
+
+        let mut telemetry = Telemetry::new();
+        let start = Instant::now();
+        let pipeline = pipeline::create_pipeline::<back::Backend>(
+            self.device.as_ref().unwrap(),
+            &PipelineTarget {
+                render_pass: self.render_pass.as_ref().unwrap(),
+                layout: &pipeline_layout,
+                vertex_desc: self.vertex_desc.clone(),
+                attr_descs: &self.attr_descs,
+                samples: self.samples,
+                depth_mode: self.depth_mode,
+                cache: Some(&pipeline_cache),
+            },
+            &PipelineDesc::new(&self.vertex_shader, &self.fragment_shader),
+        )?;
+        telemetry.record(RenderEvent {
+            kind: EventKind::PipelineCompilation,
+            reason: "default pipeline".to_string(),
+            millis: millis_since(start),
+            bytes: None,
+        });
+
+        // Swapchain, unless rendering offscreen
+        let surface_color_format = self.surface_color_format.unwrap();
+        let present_mode = if self.headless {
+            PresentMode::Fifo
+        } else {
+            self.choose_present_mode()
+        };
+        let (extent, swapchain, backbuffer, frames_readable) = match self.surface {
+            Some(ref mut surface) => {
+                let caps = self.caps.as_ref().unwrap();
+                let mut swapchain_config = SwapchainConfig::from_caps(caps, surface_color_format);
+                let extent = swapchain_config.extent.to_extent();
+                swapchain_config.present_mode = present_mode;
+                // Frames are copied out for the overdraw statistics
+                // and captures, if the surface allows it
+                let readable = caps.usage.contains(image::Usage::TRANSFER_SRC);
+                if readable {
+                    swapchain_config.image_usage |= image::Usage::TRANSFER_SRC;
                 }
-            }
-
-            pipeline_desc.depth_stencil = DepthStencilDesc {
-                depth: DepthTest::On {
-                    fun: Comparison::Less,
-                    write: true,
-                },
-                depth_bounds: false,
-                stencil: StencilTest::default(),
-            };
 
-            self.device
-                .as_ref()
-                .unwrap()
-                .create_graphics_pipeline(&pipeline_desc, None)
-                .unwrap()
+                let device = self.device.as_ref().unwrap();
+                let (swapchain, backbuffer) =
+                    telemetry.time(EventKind::SwapchainCreation, "context creation", || {
+                        device.create_swapchain(surface, swapchain_config, None)
+                    });
+                (extent, Some(swapchain), Some(backbuffer), readable)
+            }
+            None => {
+                let extent = Extent {
+                    width: self.dimensions.0,
+                    height: self.dimensions.1,
+                    depth: 1,
+                };
+                (extent, None, None, true)
+            }
         };
+        let depth_format = self.depth_format();
+        let allocator = Arc::new(MemoryAllocator::new(
+            self.memory_types.clone(),
+            self.limits.non_coherent_atom_size as u64,
+        ));
 
-        self.device
-            .as_ref()
-            .unwrap()
-            .destroy_shader_module(vertex_shader_mod);
-        self.device
-            .as_ref()
-            .unwrap()
-            .destroy_shader_module(fragment_shader_mod);
-
-        // Swapchain
-        let swapchain_config = SwapchainConfig::from_caps(
-            self.caps.as_ref().unwrap(),
-            self.surface_color_format.unwrap(),
-        );
-        let extent = swapchain_config.extent.to_extent();
-
-        let surface_color_format = self.surface_color_format.unwrap();
-        let (swapchain, backbuffer) = self.device.as_ref().unwrap().create_swapchain(
-            self.surface.as_mut().unwrap(),
-            swapchain_config,
-            None,
-        );
-        let depth_format = self.depth_format;
-
-        // Depth testing
-        let (depth_image, depth_image_memory, depth_image_view) = {
-            let ty = image::Kind::D2(extent.width as Size, extent.height as Size, 1, 1);
-
-            let unbound_depth_image = self
-                .device
-                .as_ref()
-                .unwrap()
-                .create_image(
-                    ty,
+        // Depth testing, and the multisampled color
+        // target resolved into the swapchain image
+        let (depth, msaa_image) = swapchain::create_attachments::<back::Backend>(
+            self.device.as_ref().unwrap(),
+            &allocator,
+            extent,
+            self.samples,
+            surface_color_format,
+            depth_format,
+        )?;
+        let (depth_image, depth_image_memory, depth_image_view) =
+            (depth.image, depth.memory, depth.view);
+
+        // Color target of headless contexts, read back by `read_frame()`
+        let offscreen = if self.headless {
+            Some(
+                create_image::<back::Backend>(
+                    self.device.as_ref().unwrap(),
+                    &allocator,
+                    extent,
                     1,
-                    depth_format,
-                    image::Tiling::Optimal,
-                    image::Usage::DEPTH_STENCIL_ATTACHMENT,
-                    image::ViewCapabilities::empty(),
-                )
-                .unwrap();
-
-            let image_reqs = self
-                .device
-                .as_ref()
-                .unwrap()
-                .get_image_requirements(&unbound_depth_image);
-
-            let device_type = self
-                .memory_types
-                .iter()
-                .enumerate()
-                .position(|(id, memory_type)| {
-                    image_reqs.type_mask & (1 << id) != 0
-                        && memory_type.properties.contains(Properties::DEVICE_LOCAL)
-                })
-                .unwrap()
-                .into();
-
-            let depth_image_memory = self
-                .device
-                .as_ref()
-                .unwrap()
-                .allocate_memory(device_type, image_reqs.size)
-                .unwrap();
-
-            let depth_image = self
-                .device
-                .as_ref()
-                .unwrap()
-                .bind_image_memory(&depth_image_memory, 0, unbound_depth_image)
-                .unwrap();
-
-            let depth_image_view = self
-                .device
-                .as_ref()
-                .unwrap()
-                .create_image_view(
-                    &depth_image,
-                    image::ViewKind::D2,
-                    depth_format,
-                    Swizzle::NO,
-                    image::SubresourceRange {
-                        aspects: Aspects::DEPTH | Aspects::STENCIL,
-                        levels: 0..1,
-                        layers: 0..1,
-                    },
+                    1,
+                    surface_color_format,
+                    image::Usage::COLOR_ATTACHMENT | image::Usage::TRANSFER_SRC,
+                    Aspects::COLOR,
                 )
-                .unwrap();
-
-            (depth_image, depth_image_memory, depth_image_view)
+                .map_err(RenderError::SwapchainCreation)?,
+            )
+        } else {
+            None
         };
 
         // Create image views and frame buffers
-        let (image_views, frame_buffers) = match backbuffer {
-            Backbuffer::Images(images) => {
-                let color_range = SubresourceRange {
-                    aspects: Aspects::COLOR,
-                    levels: 0..1,
-                    layers: 0..1,
-                };
-
-                let image_views = images
-                    .iter()
-                    .map(|image| {
-                        self.device
-                            .as_ref()
-                            .unwrap()
-                            .create_image_view(
-                                image,
-                                ViewKind::D2,
-                                surface_color_format,
-                                Swizzle::NO,
-                                color_range.clone(),
-                            )
-                            .unwrap()
-                    })
-                    .collect::<Vec<_>>();
-
-                let _frame_buffers = image_views
-                    .iter()
-                    .map(|image_view| {
-                        self.device
-                            .as_ref()
-                            .unwrap()
-                            .create_framebuffer(
-                                self.render_pass.as_ref().unwrap(),
-                                vec![image_view, &depth_image_view],
-                                extent,
-                            )
-                            .unwrap()
-                    })
-                    .collect();
-
-                (image_views, _frame_buffers)
-            }
-
-            // For OpenGL backend
-            Backbuffer::Framebuffer(fbo) => (vec![], vec![fbo]),
+        let (swapchain_images, image_views, frame_buffers) = match backbuffer {
+            Some(backbuffer) => swapchain::create_frame_buffers::<back::Backend>(
+                self.device.as_ref().unwrap(),
+                self.render_pass.as_ref().unwrap(),
+                backbuffer,
+                surface_color_format,
+                &depth_image_view,
+                msaa_image.as_ref(),
+                extent,
+            )?,
+            None => (
+                vec![],
+                vec![],
+                vec![swapchain::create_frame_buffer::<back::Backend>(
+                    self.device.as_ref().unwrap(),
+                    self.render_pass.as_ref().unwrap(),
+                    &offscreen.as_ref().unwrap().view,
+                    &depth_image_view,
+                    msaa_image.as_ref(),
+                    extent,
+                )?],
+            ),
         };
 
         let frame_semaphore = self.device.as_ref().unwrap().create_semaphore();
         let frame_fence = self.device.as_ref().unwrap().create_fence(false);
+        let uploader = Uploader::new(
+            self.device.as_ref().unwrap(),
+            self.queue_group.as_ref().unwrap(),
+        );
 
-        RenderContext {
+        Ok(RenderContext {
             instance: self.instance.unwrap(),
-            device: self.device.unwrap(),
-            events_loop: self.events_loop.unwrap(),
-            window: self.window.unwrap(),
-            surface: self.surface.unwrap(),
+            adapter: self.adapter.unwrap(),
+            device: Arc::new(self.device.unwrap()),
+            events_loop: self.events_loop,
+            window: self.window,
+            surface: self.surface,
             queue_group: self.queue_group.unwrap(),
             command_pool: self.command_pool.unwrap(),
             render_pass: self.render_pass.unwrap(),
-            pipeline,
+            pipelines: PipelineRegistry::new(pipeline),
             pipeline_layout,
             swapchain,
+            offscreen,
+            swapchain_images,
             image_views,
+            fresh_images: vec![true; frame_buffers.len()],
             frame_buffers,
             frame_semaphore,
             frame_fence,
             extent,
             models: Vec::new(),
             memory_types: self.memory_types,
+            allocator,
             set_layout,
+            set_bindings: self.pipeline_layout.into_owned(),
+            color_format: surface_color_format,
+            depth_format,
             depth_image,
             depth_image_view,
             depth_image_memory,
-        }
+            msaa_image,
+            samples: self.samples,
+            depth_mode: self.depth_mode,
+            present_mode,
+            stats: RenderStats::default(),
+            features: self.features.unwrap(),
+            limits: self.limits,
+            samplers: HashMap::new(),
+            pipeline_cache,
+            pipeline_cache_path: self.pipeline_cache_path,
+            vertex_desc: self.vertex_desc,
+            attr_descs: self.attr_descs,
+            shader_watcher: ShaderWatcher::new(),
+            debug_pipelines: Vec::new(),
+            graph_images: GraphImages::new(),
+            custom_passes: Vec::new(),
+            telemetry,
+            uploader,
+            meshes: HandleMap::new(),
+            textures: HandleMap::new(),
+            targets: HandleMap::new(),
+            buffers: HandleMap::new(),
+            latency: LatencyTracker::new(),
+            window_mode: WindowModeState::new(),
+            cursor: Cursor::default(),
+            clear_color: self.clear_color,
+            color_load: LoadOp::Clear,
+            load_passes: Vec::new(),
+            frames_readable,
+            capture_requested: false,
+            captured_frame: None,
+        })
+    }
+}
+
+/// Queries the limits and features of the physical device.
+pub fn device_features<P: PhysicalDevice<back::Backend>>(physical_device: &P) -> DeviceFeatures {
+    let limits = physical_device.limits();
+    let features = physical_device.features();
+
+    // Sample counts are a mask with one bit set for each supported count
+    let color_counts = limits.framebuffer_color_samples_count;
+    let max_samples = if color_counts == 0 {
+        1
+    } else {
+        1 << (7 - color_counts.leading_zeros())
+    };
+
+    DeviceFeatures {
+        max_texture_size: limits.max_texture_size as u32,
+        max_anisotropy: if features.contains(Features::SAMPLER_ANISOTROPY) {
+            limits.max_sampler_anisotropy
+        } else {
+            1.0
+        },
+        max_samples,
+        // The depth buffer has as many samples as the color attachment
+        sample_counts: color_counts & limits.framebuffer_depth_samples_count,
+        storage_buffers: features.contains(Features::VERTEX_STORES_AND_ATOMICS)
+            || features.contains(Features::FRAGMENT_STORES_AND_ATOMICS),
+        tessellation: features.contains(Features::TESSELLATION_SHADER),
+        geometry_shaders: features.contains(Features::GEOMETRY_SHADER),
+        multi_draw_indirect: features.contains(Features::MULTI_DRAW_INDIRECT),
     }
 }
 
-#[inline(always)]
-fn create_shader<B: Backend>(raw: &[u8], device: &B::Device) -> B::ShaderModule {
-    device.create_shader_module(raw).unwrap()
+/// Creates the render pass of the built-in frame, which draws
+/// into a color and depth attachment and, if `samples` is more
+/// than one, resolves the color into a single sampled image
+/// left in `final_layout`. `load` is what it does with the color
+/// attachment's previous contents; depth is always cleared.
+///
+/// Passes created with different `load`s are compatible, so
+/// they share pipelines and frame buffers.
+pub fn create_frame_render_pass<B: Backend>(
+    device: &B::Device,
+    color_format: Format,
+    depth_format: Format,
+    samples: u8,
+    final_layout: Layout,
+    load: LoadOp,
+) -> B::RenderPass {
+    let multisampled = samples > 1;
+    let load_op = match load {
+        LoadOp::Clear => AttachmentLoadOp::Clear,
+        LoadOp::Load => AttachmentLoadOp::Load,
+        LoadOp::DontCare => AttachmentLoadOp::DontCare,
+    };
+    // Loaded contents start in the layout the last frame left
+    let color_attachment = Attachment {
+        format: Some(color_format),
+        samples,
+        ops: match (multisampled, load) {
+            // Only the resolved image is kept, unless
+            // the next frame loads the multisampled one
+            (true, LoadOp::Load) => AttachmentOps::new(load_op, AttachmentStoreOp::Store),
+            (true, _) => AttachmentOps::new(load_op, AttachmentStoreOp::DontCare),
+            (false, _) => AttachmentOps::new(load_op, AttachmentStoreOp::Store),
+        },
+        stencil_ops: AttachmentOps::DONT_CARE,
+        layouts: match (multisampled, load) {
+            (true, LoadOp::Load) => Layout::ColorAttachmentOptimal..Layout::ColorAttachmentOptimal,
+            (true, _) => Layout::Undefined..Layout::ColorAttachmentOptimal,
+            (false, LoadOp::Load) => final_layout..final_layout,
+            (false, _) => Layout::Undefined..final_layout,
+        },
+    };
+
+    let depth_attachment = Attachment {
+        format: Some(depth_format),
+        samples,
+        ops: AttachmentOps::new(AttachmentLoadOp::Clear, AttachmentStoreOp::DontCare),
+        stencil_ops: AttachmentOps::DONT_CARE,
+        layouts: Layout::Undefined..Layout::DepthStencilAttachmentOptimal,
+    };
+
+    let mut attachments = vec![color_attachment, depth_attachment];
+    if multisampled {
+        // The swapchain image the color attachment resolves into
+        attachments.push(Attachment {
+            format: Some(color_format),
+            samples: 1,
+            ops: AttachmentOps::new(AttachmentLoadOp::DontCare, AttachmentStoreOp::Store),
+            stencil_ops: AttachmentOps::DONT_CARE,
+            layouts: Layout::Undefined..final_layout,
+        });
+    }
+    let resolves: &[_] = if multisampled {
+        &[(2, Layout::ColorAttachmentOptimal)]
+    } else {
+        &[]
+    };
+
+    // Single subpass for now
+    let subpass = SubpassDesc {
+        colors: &[(0, Layout::ColorAttachmentOptimal)],
+        depth_stencil: Some(&(1, Layout::DepthStencilAttachmentOptimal)),
+        inputs: &[],
+        resolves,
+        preserves: &[],
+    };
+
+    let dependency = SubpassDependency {
+        passes: SubpassRef::External..SubpassRef::Pass(0),
+        stages: PipelineStage::COLOR_ATTACHMENT_OUTPUT..PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+        accesses: Access::empty()..(Access::COLOR_ATTACHMENT_READ | Access::COLOR_ATTACHMENT_WRITE),
+    };
+
+    device.create_render_pass(&attachments, &[subpass], &[dependency])
+}
+
+/// Creates a 2D image with device local memory and a view
+/// of the whole image, for use as an attachment or texture.
+pub fn create_image<B: Backend>(
+    device: &B::Device,
+    allocator: &MemoryAllocator<B>,
+    extent: Extent,
+    levels: image::Level,
+    samples: u8,
+    format: Format,
+    usage: image::Usage,
+    aspects: Aspects,
+) -> Result<ImageMem<B>, String> {
+    create_image_of_kind(
+        device,
+        allocator,
+        image::Kind::D2(extent.width as Size, extent.height as Size, 1, samples),
+        ViewKind::D2,
+        levels,
+        format,
+        usage,
+        aspects,
+    )
+}
+
+/// Creates an image of any kind with device local memory,
+/// and a view of every level and layer of it.
+pub fn create_image_of_kind<B: Backend>(
+    device: &B::Device,
+    allocator: &MemoryAllocator<B>,
+    kind: image::Kind,
+    view_kind: ViewKind,
+    levels: image::Level,
+    format: Format,
+    usage: image::Usage,
+    aspects: Aspects,
+) -> Result<ImageMem<B>, String> {
+    let layers = kind.num_layers();
+    let unbound_image = device
+        .create_image(
+            kind,
+            levels,
+            format,
+            image::Tiling::Optimal,
+            usage,
+            image::ViewCapabilities::empty(),
+        )
+        .map_err(|err| format!("{:?}", err))?;
+
+    let image_reqs = device.get_image_requirements(&unbound_image);
+    let memory = allocator.allocate(device, image_reqs, Properties::DEVICE_LOCAL, true)?;
+    let image = device
+        .bind_image_memory(memory.memory(), memory.offset(), unbound_image)
+        .map_err(|err| format!("{:?}", err))?;
+    let view = device
+        .create_image_view(
+            &image,
+            view_kind,
+            format,
+            Swizzle::NO,
+            image::SubresourceRange {
+                aspects,
+                levels: 0..levels,
+                layers: 0..layers,
+            },
+        )
+        .map_err(|err| format!("{:?}", err))?;
+
+    Ok(ImageMem {
+        image,
+        memory,
+        view,
+    })
+}
+
+/// Returns the aspects of a depth format,
+/// which include stencil if it has a stencil component.
+pub fn depth_aspects(format: Format) -> Aspects {
+    match format {
+        Format::D16UnormS8Uint | Format::D24UnormS8Uint | Format::D32FloatS8Uint => {
+            Aspects::DEPTH | Aspects::STENCIL
+        }
+        _ => Aspects::DEPTH,
+    }
 }
@@ -0,0 +1,338 @@
+//! Runtime GLSL to SPIR-V compilation.
+//!
+//! Shaders are normally compiled by the build script, but with
+//! the `glsl` feature the `RenderBuilder` can also take GLSL
+//! files and compile them when the context is built.
+//!
+//! Sources may contain `#include "file"` lines, which are
+//! replaced with the contents of `file` relative to the
+//! including file. Compile errors are reported with the file
+//! and line they came from, before includes were expanded.
+//! The constants in `shader_constants` are defined like they
+//! are for shaders compiled by the build script.
+use glsl_to_spirv::{self, ShaderType};
+use render::shader_constants;
+use std::fmt;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// A problem at a location in a shader source file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub path: PathBuf,
+    /// Line number starting at 1, if known
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.path.display(), line, self.message),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+/// An error produced when compiling a GLSL shader.
+#[derive(Debug)]
+pub enum ShaderError {
+    /// A source file could not be read
+    Io { path: PathBuf, error: String },
+    /// The file extension is not `vert` or `frag`
+    UnknownStage(PathBuf),
+    /// An `#include` line is malformed or recursive
+    Include(Diagnostic),
+    /// The compiler rejected the shader
+    Compile(Vec<Diagnostic>),
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShaderError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            ShaderError::UnknownStage(path) => {
+                write!(f, "{}: unknown shader stage", path.display())
+            }
+            ShaderError::Include(diagnostic) => write!(f, "{}", diagnostic),
+            ShaderError::Compile(diagnostics) => {
+                for (i, diagnostic) in diagnostics.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", diagnostic)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// GLSL with its includes expanded.
+pub struct Preprocessed {
+    pub source: String,
+    /// The file which was preprocessed
+    path: PathBuf,
+    /// The file and line each line of `source` came from
+    lines: Vec<(PathBuf, usize)>,
+}
+
+impl Preprocessed {
+    fn new(path: &Path) -> Preprocessed {
+        Preprocessed {
+            source: String::new(),
+            path: path.to_path_buf(),
+            lines: Vec::new(),
+        }
+    }
+
+    /// Maps compiler output back to the original files.
+    fn diagnostics(&self, output: &str) -> Vec<Diagnostic> {
+        let diagnostics = output
+            .lines()
+            .filter_map(parse_error)
+            .map(|(line, message)| self.locate(line, message))
+            .collect::<Vec<_>>();
+
+        if diagnostics.is_empty() {
+            // Output we don't understand is still worth showing
+            vec![Diagnostic {
+                path: self.path.clone(),
+                line: None,
+                message: output.trim().to_string(),
+            }]
+        } else {
+            diagnostics
+        }
+    }
+
+    /// Creates a diagnostic for `line` of the expanded source.
+    fn locate(&self, line: usize, message: &str) -> Diagnostic {
+        let (path, line) = match self.lines.get(line.wrapping_sub(1)) {
+            Some(&(ref path, line)) => (path.clone(), Some(line)),
+            None => (self.path.clone(), None),
+        };
+        Diagnostic {
+            path,
+            line,
+            message: message.to_string(),
+        }
+    }
+
+    /// Defines the engine's shader constants after the `#version`
+    /// line, attributing errors in them to that line.
+    fn define_constants(&mut self) {
+        let insertion = shader_constants::insertion_line(&self.source);
+        let offset = self
+            .source
+            .lines()
+            .take(insertion)
+            .map(|line| line.len() + 1)
+            .sum::<usize>();
+        let location = self
+            .lines
+            .get(insertion.wrapping_sub(1))
+            .cloned()
+            .unwrap_or_else(|| (self.path.clone(), 1));
+
+        let defines = shader_constants::defines();
+        let mut block = String::new();
+        for define in &defines {
+            block.push_str(define);
+            block.push('\n');
+        }
+        self.source.insert_str(offset, &block);
+        let tail = self.lines.split_off(insertion);
+        self.lines
+            .extend(defines.iter().map(|_| location.clone()).chain(tail));
+    }
+}
+
+/// Parses an `ERROR: <source>:<line>: <message>` line of
+/// glslang output into the line number and message.
+fn parse_error(line: &str) -> Option<(usize, &str)> {
+    let line = line.trim();
+    if !line.starts_with("ERROR:") {
+        return None;
+    }
+    let rest = &line["ERROR:".len()..];
+    // The source name may contain colons, so the line
+    // number is the first numeric field after it
+    let mut end = 0;
+    for (i, field) in rest.split(':').enumerate() {
+        end += field.len() + 1;
+        if i > 0 {
+            if let Ok(number) = field.trim().parse() {
+                return Some((number, rest.get(end..).unwrap_or("").trim()));
+            }
+        }
+    }
+    None
+}
+
+/// Reads the GLSL file at `path`, expanding its
+/// includes and defining the shader constants.
+pub fn preprocess(path: &Path) -> Result<Preprocessed, ShaderError> {
+    let mut preprocessed = Preprocessed::new(path);
+    expand(
+        path,
+        &mut |path| std::fs::read_to_string(path),
+        &mut Vec::new(),
+        &mut preprocessed,
+    )?;
+    preprocessed.define_constants();
+    Ok(preprocessed)
+}
+
+/// Appends the lines of `path` to `out`, recursing into
+/// includes. `stack` holds the files being expanded.
+fn expand<F>(
+    path: &Path,
+    read: &mut F,
+    stack: &mut Vec<PathBuf>,
+    out: &mut Preprocessed,
+) -> Result<(), ShaderError>
+where
+    F: FnMut(&Path) -> io::Result<String>,
+{
+    let source = read(path).map_err(|err| ShaderError::Io {
+        path: path.to_path_buf(),
+        error: err.to_string(),
+    })?;
+    stack.push(path.to_path_buf());
+
+    for (i, line) in source.lines().enumerate() {
+        let include_error = |message: &str| {
+            ShaderError::Include(Diagnostic {
+                path: path.to_path_buf(),
+                line: Some(i + 1),
+                message: message.to_string(),
+            })
+        };
+        let directive = line.trim_start();
+        if !directive.starts_with("#include") {
+            out.source.push_str(line);
+            out.source.push('\n');
+            out.lines.push((path.to_path_buf(), i + 1));
+            continue;
+        }
+
+        let name = directive["#include".len()..].trim();
+        if name.len() < 2 || !name.starts_with('"') || !name.ends_with('"') {
+            return Err(include_error("expected #include \"file\""));
+        }
+        let included = path
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(&name[1..name.len() - 1]);
+        if stack.contains(&included) {
+            return Err(include_error("file includes itself"));
+        }
+        expand(&included, read, stack, out)?;
+    }
+
+    stack.pop();
+    Ok(())
+}
+
+/// Compiles the GLSL file at `path` to SPIR-V. The stage
+/// is chosen by the extension, `vert` or `frag`.
+pub fn compile(path: &Path) -> Result<Vec<u8>, ShaderError> {
+    let ty = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("vert") => ShaderType::Vertex,
+        Some("frag") => ShaderType::Fragment,
+        _ => return Err(ShaderError::UnknownStage(path.to_path_buf())),
+    };
+    let preprocessed = preprocess(path)?;
+    let mut file = glsl_to_spirv::compile(&preprocessed.source, ty)
+        .map_err(|output| ShaderError::Compile(preprocessed.diagnostics(&output)))?;
+
+    let mut spirv = Vec::new();
+    file.read_to_end(&mut spirv)
+        .map_err(|err| ShaderError::Io {
+            path: path.to_path_buf(),
+            error: err.to_string(),
+        })?;
+    Ok(spirv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn expand_files(files: &[(&str, &str)]) -> Result<Preprocessed, ShaderError> {
+        let files = files
+            .iter()
+            .map(|&(path, source)| (PathBuf::from(path), source.to_string()))
+            .collect::<HashMap<_, _>>();
+        let path = Path::new("shaders/main.frag");
+        let mut out = Preprocessed::new(path);
+        expand(
+            path,
+            &mut |path| {
+                files
+                    .get(path)
+                    .cloned()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "not found"))
+            },
+            &mut Vec::new(),
+            &mut out,
+        )?;
+        Ok(out)
+    }
+
+    #[test]
+    fn includes_map_back_to_their_lines() {
+        let preprocessed = expand_files(&[
+            (
+                "shaders/main.frag",
+                "#version 450\n#include \"lib/light.glsl\"\nvoid main() {}",
+            ),
+            ("shaders/lib/light.glsl", "float a;\nfloat b;"),
+        ])
+        .unwrap();
+        assert_eq!(
+            preprocessed.source,
+            "#version 450\nfloat a;\nfloat b;\nvoid main() {}\n"
+        );
+
+        let diagnostics = preprocessed.diagnostics(
+            "ERROR: 0:3: 'b' : redefinition\nERROR: 1 compilation errors.  No code generated.",
+        );
+        assert_eq!(
+            diagnostics,
+            vec![Diagnostic {
+                path: PathBuf::from("shaders/lib/light.glsl"),
+                line: Some(2),
+                message: "'b' : redefinition".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn constants_after_version() {
+        let mut preprocessed =
+            expand_files(&[("shaders/main.frag", "#version 450\nvoid main() {}")]).unwrap();
+        preprocessed.define_constants();
+        let count = shader_constants::SHADER_CONSTANTS.len();
+        assert_eq!(
+            preprocessed.source.lines().nth(1),
+            Some("#define OVERDRAW_RED_LAYERS 4")
+        );
+
+        // Errors in the defines point at #version, and
+        // lines after them keep their numbers
+        let location = |line| preprocessed.locate(line, "").line;
+        assert_eq!(location(2), Some(1));
+        assert_eq!(location(count + 2), Some(2));
+    }
+
+    #[test]
+    fn recursive_include() {
+        match expand_files(&[("shaders/main.frag", "#include \"main.frag\"")]) {
+            Err(ShaderError::Include(diagnostic)) => assert_eq!(diagnostic.line, Some(1)),
+            _ => panic!("expected an include error"),
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Shader hot reloading for development.
+//!
+//! When the game is started with `--hot-reload-shaders`, the
+//! context polls the compiled shaders of its pipelines and
+//! recreates a pipeline between frames whenever one of its
+//! shaders changes. Running `cargo build` in another terminal
+//! recompiles `src/shaders` into `assets/shaders`, so edits
+//! show up without restarting the game.
+//!
+//! A shader which fails to load keeps the old pipeline, and
+//! the error is logged.
+use super::*;
+use profile::millis_since;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Command line flag which enables hot reloading
+pub const HOT_RELOAD_ARG: &str = "--hot-reload-shaders";
+/// Directory the build script writes compiled shaders to
+pub const SHADER_DIR: &str = "assets/shaders";
+
+/// Minimum milliseconds between checks of the shader files
+const POLL_INTERVAL_MS: u64 = 500;
+
+/// A set of files and the latest time any of them changed.
+struct WatchedFiles {
+    paths: Vec<PathBuf>,
+    modified: Option<SystemTime>,
+}
+
+impl WatchedFiles {
+    fn new(paths: Vec<PathBuf>) -> WatchedFiles {
+        let modified = last_modified(&paths);
+        WatchedFiles { paths, modified }
+    }
+
+    /// Returns whether the files changed since the last poll.
+    /// Missing files are ignored until they all exist, so a
+    /// shader being rewritten is not loaded half way.
+    fn poll(&mut self) -> bool {
+        match last_modified(&self.paths) {
+            Some(modified) if Some(modified) != self.modified => {
+                self.modified = Some(modified);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Returns the latest modification time of `paths`,
+/// or `None` if any of them can't be read.
+fn last_modified(paths: &[PathBuf]) -> Option<SystemTime> {
+    paths
+        .iter()
+        .map(|path| path.metadata().and_then(|meta| meta.modified()).ok())
+        .fold(Some(SystemTime::UNIX_EPOCH), |latest, modified| {
+            match (latest, modified) {
+                (Some(latest), Some(modified)) => Some(latest.max(modified)),
+                _ => None,
+            }
+        })
+}
+
+/// A pipeline recreated when its shaders change.
+struct WatchedPipeline {
+    handle: PipelineHandle,
+    files: WatchedFiles,
+    primitive: Primitive,
+    rasterizer: Rasterizer,
+    depth_test: DepthTest,
+    blend: BlendState,
+    instances: Option<InstanceLayout>,
+}
+
+/// Watches the shaders of a context's pipelines.
+pub struct ShaderWatcher {
+    pipelines: Vec<WatchedPipeline>,
+    last_poll: Instant,
+}
+
+impl ShaderWatcher {
+    pub fn new() -> ShaderWatcher {
+        ShaderWatcher {
+            pipelines: Vec::new(),
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// Returns whether any pipelines are watched.
+    pub fn is_enabled(&self) -> bool {
+        !self.pipelines.is_empty()
+    }
+
+    /// Stops watching the shaders of the pipeline `handle`.
+    pub fn unwatch(&mut self, handle: PipelineHandle) {
+        self.pipelines.retain(|watched| watched.handle != handle);
+    }
+}
+
+impl<B: Backend> RenderContext<B> {
+    /// Recreates the pipeline `handle` from the SPIR-V files at
+    /// `vertex_path` and `fragment_path` whenever they change.
+    /// The other pipeline state is taken from `desc`, which
+    /// should describe the pipeline as it was created.
+    pub fn watch_shaders<P: AsRef<Path>>(
+        &mut self,
+        handle: PipelineHandle,
+        vertex_path: P,
+        fragment_path: P,
+        desc: &PipelineDesc,
+    ) {
+        let files = WatchedFiles::new(vec![
+            vertex_path.as_ref().to_path_buf(),
+            fragment_path.as_ref().to_path_buf(),
+        ]);
+        self.shader_watcher.pipelines.push(WatchedPipeline {
+            handle,
+            files,
+            primitive: desc.primitive,
+            rasterizer: desc.rasterizer.clone(),
+            depth_test: desc.depth_test,
+            blend: desc.blend,
+            instances: desc.instances.clone(),
+        });
+    }
+
+    /// Recreates the pipelines whose shaders changed. This must
+    /// only be called between frames, while no command buffers
+    /// using the pipelines are in flight.
+    pub fn reload_shaders(&mut self) {
+        if !self.shader_watcher.is_enabled()
+            || self.shader_watcher.last_poll.elapsed() < Duration::from_millis(POLL_INTERVAL_MS)
+        {
+            return;
+        }
+        self.shader_watcher.last_poll = Instant::now();
+
+        let mut watcher = std::mem::replace(&mut self.shader_watcher, ShaderWatcher::new());
+        for watched in &mut watcher.pipelines {
+            if watched.files.poll() {
+                self.reload_pipeline(watched);
+            }
+        }
+        self.shader_watcher = watcher;
+    }
+
+    fn reload_pipeline(&mut self, watched: &WatchedPipeline) {
+        let read = |path: &PathBuf| {
+            std::fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))
+        };
+        let shaders = read(&watched.files.paths[0])
+            .and_then(|vertex| read(&watched.files.paths[1]).map(|fragment| (vertex, fragment)));
+        let (vertex_shader, fragment_shader) = match shaders {
+            Ok(shaders) => shaders,
+            Err(err) => {
+                warn!("Failed to read shader {}", err);
+                return;
+            }
+        };
+
+        let mut desc = PipelineDesc::new(&vertex_shader, &fragment_shader)
+            .with_primitive(watched.primitive)
+            .with_rasterizer(watched.rasterizer.clone())
+            .with_depth_test(watched.depth_test)
+            .with_blend(watched.blend);
+        desc.instances = watched.instances.clone();
+        let start = Instant::now();
+        match pipeline::create_pipeline::<B>(&self.device, &self.pipeline_target(), &desc) {
+            Ok(pipeline) => {
+                self.telemetry.record(RenderEvent {
+                    kind: EventKind::PipelineCompilation,
+                    reason: format!("shaders of pipeline {} changed", watched.handle),
+                    millis: millis_since(start),
+                    bytes: None,
+                });
+                let old = self.pipelines.replace(watched.handle, pipeline);
+                self.device.destroy_graphics_pipeline(old);
+                info!("Reloaded shaders of pipeline {}", watched.handle);
+            }
+            Err(err) => warn!("Failed to reload shaders: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poll_waits_for_every_file() {
+        let dir = std::env::temp_dir().join("luminite-hot-reload-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (vertex, fragment) = (dir.join("test.vert.spv"), dir.join("test.frag.spv"));
+        let _ = std::fs::remove_file(&fragment);
+        std::fs::write(&vertex, b"vertex").unwrap();
+
+        let mut files = WatchedFiles::new(vec![vertex, fragment.clone()]);
+        assert!(!files.poll());
+        std::fs::write(&fragment, b"fragment").unwrap();
+        assert!(files.poll());
+        assert!(!files.poll());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
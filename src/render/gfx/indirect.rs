@@ -0,0 +1,154 @@
+//! Draws whose parameters are read from GPU buffers.
+//!
+//! A `DrawIndirectBuffer` holds draw commands laid out the way
+//! the GPU reads them. It is also a storage buffer, so a compute
+//! pass, such as GPU culling, can write the commands. Recording
+//! `draw_indirect()` or `draw_indexed_indirect()` then draws
+//! whatever the buffer holds when the draw executes, without
+//! reading anything back to the CPU.
+//!
+//! Drawing more than one command per call needs
+//! `DeviceFeatures::multi_draw_indirect`. Without it, each
+//! command is recorded as a draw call of its own.
+use super::*;
+use std::marker::PhantomData;
+
+/// Parameters of a non-indexed draw.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DrawCommand {
+    pub vertex_count: u32,
+    pub instance_count: u32,
+    pub first_vertex: u32,
+    pub first_instance: u32,
+}
+
+/// Parameters of an indexed draw.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DrawIndexedCommand {
+    pub index_count: u32,
+    pub instance_count: u32,
+    pub first_index: u32,
+    /// Added to each index before reading the vertex
+    pub vertex_offset: i32,
+    pub first_instance: u32,
+}
+
+/// A buffer of draw commands of type `C`.
+pub struct DrawIndirectBuffer<B: Backend, C: Copy> {
+    pub commands: DynamicBuffer<B>,
+    marker: PhantomData<C>,
+}
+
+impl<B: Backend, C: Copy> DrawIndirectBuffer<B, C> {
+    /// Uploads `commands` to a new buffer.
+    pub fn new(device: &RenderDevice<B>, commands: &[C]) -> DrawIndirectBuffer<B, C> {
+        DrawIndirectBuffer {
+            commands: DynamicBuffer::new(
+                device,
+                Usage::INDIRECT | Usage::STORAGE | Usage::TRANSFER_DST,
+                commands,
+            ),
+            marker: PhantomData,
+        }
+    }
+
+    /// Replaces the commands from the CPU. There can't be
+    /// more commands than the buffer was created with, and
+    /// no frame drawing the buffer may be in flight.
+    pub fn update(&mut self, device: &B::Device, commands: &[C]) {
+        self.commands.update(device, commands, "draw commands");
+    }
+
+    /// Returns the number of commands in the buffer.
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Returns the number of commands the buffer has room for.
+    pub fn capacity(&self) -> usize {
+        self.commands.capacity()
+    }
+
+    /// Frees the buffer and its memory.
+    pub fn destroy(self, device: &B::Device) {
+        self.commands.destroy(device);
+    }
+}
+
+/// Records a draw of `mesh` for each command in `commands`.
+/// The pipeline and descriptor sets must already be bound.
+/// Without `multi_draw_indirect`, each command is drawn
+/// with a call of its own.
+pub fn draw_indirect<B: Backend>(
+    encoder: &mut RenderPassInlineEncoder<B, Primary>,
+    features: &DeviceFeatures,
+    mesh: &Mesh<B>,
+    commands: &DrawIndirectBuffer<B, DrawCommand>,
+) {
+    encoder.bind_vertex_buffers(0, vec![(&mesh.vertices.buffer, 0)]);
+    let stride = std::mem::size_of::<DrawCommand>() as u32;
+    for (offset, count) in draw_calls(features, commands.len(), stride) {
+        encoder.draw_indirect(&commands.commands.buffer, offset, count, stride);
+    }
+}
+
+/// Records an indexed draw of `mesh` for each command in
+/// `commands`. The pipeline and descriptor sets must
+/// already be bound. Without `multi_draw_indirect`, each
+/// command is drawn with a call of its own.
+pub fn draw_indexed_indirect<B: Backend>(
+    encoder: &mut RenderPassInlineEncoder<B, Primary>,
+    features: &DeviceFeatures,
+    mesh: &IndexedMesh<B>,
+    commands: &DrawIndirectBuffer<B, DrawIndexedCommand>,
+) {
+    encoder.bind_vertex_buffers(0, vec![(&mesh.vertices.buffer, 0)]);
+    encoder.bind_index_buffer(IndexBufferView {
+        buffer: &mesh.indices.buffer,
+        offset: 0,
+        index_type: mesh.index_type,
+    });
+    let stride = std::mem::size_of::<DrawIndexedCommand>() as u32;
+    for (offset, count) in draw_calls(features, commands.len(), stride) {
+        encoder.draw_indexed_indirect(&commands.commands.buffer, offset, count, stride);
+    }
+}
+
+/// Splits `count` commands `stride` bytes apart into the
+/// offsets and counts of the indirect draw calls to record.
+fn draw_calls(features: &DeviceFeatures, count: usize, stride: u32) -> Vec<(u64, u32)> {
+    if features.multi_draw_indirect || count <= 1 {
+        vec![(0, count as u32)]
+    } else {
+        (0..count)
+            .map(|index| (index as u64 * u64::from(stride), 1))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_sizes() {
+        // Laid out as the GPU reads them
+        assert_eq!(std::mem::size_of::<DrawCommand>(), 16);
+        assert_eq!(std::mem::size_of::<DrawIndexedCommand>(), 20);
+    }
+
+    #[test]
+    fn single_draw_fallback() {
+        let mut features = DeviceFeatures::default();
+        features.multi_draw_indirect = true;
+        assert_eq!(draw_calls(&features, 3, 20), vec![(0, 3)]);
+        features.multi_draw_indirect = false;
+        assert_eq!(draw_calls(&features, 3, 20), vec![(0, 1), (20, 1), (40, 1)]);
+    }
+}
@@ -0,0 +1,298 @@
+//! Meshes of vertices uploaded to the GPU.
+//!
+//! A `Mesh` draws its vertices in order, while an
+//! `IndexedMesh` draws them through an index buffer, so
+//! vertices shared between triangles are only stored once.
+//!
+//! Many copies of an indexed mesh can be drawn in one call
+//! with `draw_instanced()`. Each copy reads its own element
+//! of an `InstanceBuffer` through vertex attributes which
+//! advance once per instance, described by the pipeline's
+//! `InstanceLayout`.
+use super::*;
+
+/// A vertex buffer which can be drawn
+/// with the default pipeline.
+pub struct Mesh<B: Backend> {
+    pub vertices: BufferMem<B>,
+}
+
+impl<B: Backend> Mesh<B> {
+    /// Uploads `vertices` to a new vertex buffer.
+    pub fn new(device: &RenderDevice<B>, vertices: &[Vertex]) -> Mesh<B> {
+        let (buffer, memory) =
+            device.create_buffer(Properties::CPU_VISIBLE, Usage::VERTEX, vertices);
+        Mesh {
+            vertices: BufferMem {
+                buffer,
+                memory,
+                element_count: vertices.len(),
+            },
+        }
+    }
+
+    /// Returns the number of vertices in the mesh.
+    pub fn len(&self) -> usize {
+        self.vertices.element_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.element_count == 0
+    }
+
+    /// Frees the mesh's buffer and memory.
+    pub fn destroy(self, device: &B::Device) {
+        device.destroy_buffer(self.vertices.buffer);
+        self.vertices.memory.free();
+    }
+}
+
+/// Records a draw of every vertex in `mesh` as a triangle
+/// list, returning the number of vertices drawn. The pipeline
+/// and descriptor sets must already be bound.
+///
+/// This takes the encoder rather than the `RenderContext`, since
+/// the encoder borrows the context's command pool while recording.
+pub fn draw_mesh<B: Backend>(
+    encoder: &mut RenderPassInlineEncoder<B, Primary>,
+    mesh: &Mesh<B>,
+) -> usize {
+    encoder.bind_vertex_buffers(0, vec![(&mesh.vertices.buffer, 0)]);
+    encoder.draw(0..(mesh.len() as u32), 0..1);
+    mesh.len()
+}
+
+/// Indices into a mesh's vertices. 16-bit indices
+/// use half the memory but address at most 65536 vertices.
+pub enum Indices<'a> {
+    U16(&'a [u16]),
+    U32(&'a [u32]),
+}
+
+impl<'a> Indices<'a> {
+    pub fn len(&self) -> usize {
+        match *self {
+            Indices::U16(indices) => indices.len(),
+            Indices::U32(indices) => indices.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn index_type(&self) -> IndexType {
+        match *self {
+            Indices::U16(_) => IndexType::U16,
+            Indices::U32(_) => IndexType::U32,
+        }
+    }
+}
+
+/// Converts indices to 16 bits if `vertex_count`
+/// vertices can be addressed with them.
+pub fn compact_indices(indices: &[u32], vertex_count: usize) -> Option<Vec<u16>> {
+    if vertex_count > u16::max_value() as usize + 1 {
+        return None;
+    }
+    Some(indices.iter().map(|&index| index as u16).collect())
+}
+
+/// A vertex buffer drawn through an index buffer.
+pub struct IndexedMesh<B: Backend> {
+    pub vertices: BufferMem<B>,
+    pub indices: BufferMem<B>,
+    pub index_type: IndexType,
+}
+
+impl<B: Backend> IndexedMesh<B> {
+    /// Uploads `vertices` and `indices` to new buffers.
+    pub fn new(device: &RenderDevice<B>, vertices: &[Vertex], indices: Indices) -> IndexedMesh<B> {
+        let (v_buffer, v_mem) =
+            device.create_buffer(Properties::CPU_VISIBLE, Usage::VERTEX, vertices);
+        let (i_buffer, i_mem) = match indices {
+            Indices::U16(indices) => {
+                device.create_buffer(Properties::CPU_VISIBLE, Usage::INDEX, indices)
+            }
+            Indices::U32(indices) => {
+                device.create_buffer(Properties::CPU_VISIBLE, Usage::INDEX, indices)
+            }
+        };
+
+        IndexedMesh {
+            vertices: BufferMem {
+                buffer: v_buffer,
+                memory: v_mem,
+                element_count: vertices.len(),
+            },
+            indices: BufferMem {
+                buffer: i_buffer,
+                memory: i_mem,
+                element_count: indices.len(),
+            },
+            index_type: indices.index_type(),
+        }
+    }
+
+    /// Frees the mesh's buffers and memory.
+    pub fn destroy(self, device: &B::Device) {
+        device.destroy_buffer(self.vertices.buffer);
+        self.vertices.memory.free();
+        device.destroy_buffer(self.indices.buffer);
+        self.indices.memory.free();
+    }
+}
+
+/// Records an indexed draw of `mesh` as a triangle list,
+/// returning the number of indices drawn. The pipeline and
+/// descriptor sets must already be bound.
+pub fn draw_indexed_mesh<B: Backend>(
+    encoder: &mut RenderPassInlineEncoder<B, Primary>,
+    mesh: &IndexedMesh<B>,
+) -> usize {
+    encoder.bind_vertex_buffers(0, vec![(&mesh.vertices.buffer, 0)]);
+    encoder.bind_index_buffer(IndexBufferView {
+        buffer: &mesh.indices.buffer,
+        offset: 0,
+        index_type: mesh.index_type,
+    });
+    encoder.draw_indexed(0..(mesh.indices.element_count as u32), 0, 0..1);
+    mesh.indices.element_count
+}
+
+/// Binding of per-instance vertex buffers
+pub const INSTANCE_BINDING: u32 = 1;
+
+/// Describes per-instance vertex attributes.
+#[derive(Clone)]
+pub struct InstanceLayout {
+    pub desc: VertexBufferDesc,
+    pub attributes: Vec<AttributeDesc>,
+}
+
+impl InstanceLayout {
+    /// Packs attributes of `formats` one after another, at
+    /// consecutive locations from `first_location`. The
+    /// instance struct must be laid out the same.
+    pub fn new(first_location: u32, formats: &[Format]) -> InstanceLayout {
+        let mut offset = 0;
+        let attributes = formats
+            .iter()
+            .enumerate()
+            .map(|(index, &format)| {
+                let attribute = AttributeDesc {
+                    location: first_location + index as u32,
+                    binding: INSTANCE_BINDING,
+                    element: Element { format, offset },
+                };
+                offset += u32::from(format.surface_desc().bits) / 8;
+                attribute
+            })
+            .collect();
+        InstanceLayout {
+            desc: VertexBufferDesc {
+                binding: INSTANCE_BINDING,
+                stride: offset,
+                rate: 1,
+            },
+            attributes,
+        }
+    }
+
+    /// A 4x4 matrix per instance, such as a model matrix,
+    /// read as four `vec4` columns from `location`.
+    pub fn matrix(location: u32) -> InstanceLayout {
+        InstanceLayout::new(location, &[Format::Rgba32Float; 4])
+    }
+}
+
+/// A vertex buffer of per-instance data.
+pub struct InstanceBuffer<B: Backend> {
+    pub instances: DynamicBuffer<B>,
+}
+
+impl<B: Backend> InstanceBuffer<B> {
+    /// Uploads `instances` to a new buffer.
+    pub fn new<I: Copy>(device: &RenderDevice<B>, instances: &[I]) -> InstanceBuffer<B> {
+        InstanceBuffer {
+            instances: DynamicBuffer::new(device, Usage::VERTEX, instances),
+        }
+    }
+
+    /// Replaces the instances, keeping the buffer. There can't
+    /// be more instances than the buffer was created with, and
+    /// no frame drawing the buffer may be in flight.
+    pub fn update<I: Copy>(&mut self, device: &B::Device, instances: &[I]) {
+        self.instances.update(device, instances, "instances");
+    }
+
+    /// Returns the number of instances in the buffer.
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    /// Returns the number of instances the buffer has room for.
+    pub fn capacity(&self) -> usize {
+        self.instances.capacity()
+    }
+
+    /// Frees the buffer and its memory.
+    pub fn destroy(self, device: &B::Device) {
+        self.instances.destroy(device);
+    }
+}
+
+/// Records an indexed draw of `mesh` once for every element of
+/// `instances`, returning the number of indices drawn across all
+/// instances. The pipeline must have been created with an
+/// `InstanceLayout` matching the instances.
+pub fn draw_instanced<B: Backend>(
+    encoder: &mut RenderPassInlineEncoder<B, Primary>,
+    mesh: &IndexedMesh<B>,
+    instances: &InstanceBuffer<B>,
+) -> usize {
+    encoder.bind_vertex_buffers(
+        0,
+        vec![(&mesh.vertices.buffer, 0), (&instances.instances.buffer, 0)],
+    );
+    encoder.bind_index_buffer(IndexBufferView {
+        buffer: &mesh.indices.buffer,
+        offset: 0,
+        index_type: mesh.index_type,
+    });
+    encoder.draw_indexed(
+        0..(mesh.indices.element_count as u32),
+        0,
+        0..(instances.len() as u32),
+    );
+    mesh.indices.element_count * instances.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instance_layout_offsets() {
+        let layout = InstanceLayout::new(
+            3,
+            &[Format::Rgba32Float, Format::Rg32Float, Format::R32Uint],
+        );
+        let attributes = layout
+            .attributes
+            .iter()
+            .map(|attribute| (attribute.location, attribute.element.offset))
+            .collect::<Vec<_>>();
+        assert_eq!(attributes, vec![(3, 0), (4, 16), (5, 24)]);
+        assert_eq!(layout.desc.stride, 28);
+        assert_eq!(layout.desc.rate, 1);
+        assert!(layout
+            .attributes
+            .iter()
+            .all(|attribute| attribute.binding == INSTANCE_BINDING));
+    }
+}
@@ -6,50 +6,107 @@ use super::winit;
 use super::*;
 use gfx_hal::{
     buffer::{IndexBufferView, Usage},
-    command::{ClearColor, ClearDepthStencil, ClearValue, Primary, RenderPassInlineEncoder},
+    command::{
+        AttachmentClear, ClearColor, ClearDepthStencil, ClearValue, Primary,
+        RenderPassInlineEncoder,
+    },
     format::{Aspects, ChannelType, Format, Swizzle},
     image,
     image::{Access, Extent, Layout, Size, SubresourceRange, ViewKind},
-    memory::Properties,
+    memory::{Dependencies, Properties},
     pass::{
         Attachment, AttachmentLoadOp, AttachmentOps, AttachmentStoreOp, Subpass, SubpassDependency,
         SubpassDesc, SubpassRef,
     },
     pool::{CommandPool, CommandPoolCreateFlags},
     pso::{
-        AttributeDesc, Comparison, DepthStencilDesc, DepthTest, Descriptor, DescriptorRangeDesc,
-        DescriptorSetLayoutBinding, DescriptorSetWrite, DescriptorType, Element, ShaderStageFlags,
-        StencilTest, VertexBufferDesc,
+        AttributeDesc, ClearRect, Comparison, DepthStencilDesc, DepthTest, Descriptor,
+        DescriptorRangeDesc, DescriptorSetLayoutBinding, DescriptorSetWrite, DescriptorType,
+        Element, ShaderStageFlags, StencilTest, VertexBufferDesc,
     },
     pso::{
         BlendState, ColorBlendDesc, ColorMask, EntryPoint, GraphicsPipelineDesc, GraphicsShaderSet,
         PipelineStage, Rasterizer, Rect, Specialization, Viewport,
     },
-    Backbuffer, Backend, DescriptorPool, Device, FrameSync, Graphics, MemoryType, Primitive,
-    QueueGroup, Submission, SwapImageIndex, Swapchain, SwapchainConfig,
+    Backbuffer, Backend, DescriptorPool, Device, FrameSync, Graphics, Limits, MemoryType,
+    PresentMode, Primitive, QueueGroup, Submission, SwapImageIndex, Swapchain, SwapchainConfig,
 };
 
+pub use self::adapter::{AdapterDesc, AdapterSelector};
+pub use self::allocator::{Allocation, FreeList, MemoryAllocator};
 use self::asset_load::upload_model;
-pub use self::context::{BufferMem, RenderContext, UniformBuffer};
+pub use self::compute::{ComputeContext, ComputePipeline};
+pub use self::context::{BufferMem, DynamicBuffer, ImageMem, RenderContext, UniformBuffer};
+pub use self::custom_pass::{record_custom_passes, AddedPass, CustomPass, PassResources};
+pub use self::descriptor::DescriptorLayoutBuilder;
+pub use self::device::RenderDevice;
+pub use self::factory::RenderError;
+pub use self::hot_reload::{ShaderWatcher, HOT_RELOAD_ARG, SHADER_DIR};
+pub use self::indirect::{
+    draw_indexed_indirect, draw_indirect, DrawCommand, DrawIndexedCommand, DrawIndirectBuffer,
+};
+pub use self::mesh::{
+    compact_indices, draw_indexed_mesh, draw_instanced, draw_mesh, IndexedMesh, Indices,
+    InstanceBuffer, InstanceLayout, Mesh, INSTANCE_BINDING,
+};
+pub use self::pipeline::{PipelineDesc, PipelineRegistry, DEFAULT_PIPELINE};
+pub use self::push_constants::{push_constant_words, push_constants};
+pub use self::readback::{BufferReadback, FrameReadback, ReadbackError};
+pub use self::reflect::{ReflectError, ShaderReflection};
+pub use self::render_graph::GraphImages;
+pub use self::sampler::{AddressMode, FilterMode, SamplerDesc};
+pub use self::secondary_window::SecondaryWindow;
+pub use self::texture::{
+    Texture, Texture3D, TextureArray, TextureError, MAX_TEXTURE_3D_SIZE, MAX_TEXTURE_LAYERS,
+};
+pub use self::uploader::{UploadId, Uploader};
 use gfx_hal::IndexType;
-use std::borrow::Borrow;
+use render::handle::MeshResource;
+use std::time::Instant;
 
+pub mod adapter;
+pub mod allocator;
 pub mod asset_load;
 pub mod buffer_util;
+pub mod compute;
 pub mod context;
+pub mod custom_pass;
+pub mod descriptor;
+pub mod device;
 pub mod factory;
+#[cfg(feature = "glsl")]
+pub mod glsl;
+pub mod hot_reload;
+pub mod indirect;
+pub mod mesh;
+pub mod pipeline;
+pub mod push_constants;
+pub mod readback;
+pub mod reflect;
+pub mod render_graph;
+pub mod resources;
+pub mod sampler;
+pub mod secondary_window;
+pub mod swapchain;
+pub mod texture;
+pub mod uploader;
+pub mod window;
 
 pub enum _RenderBackend {}
 impl RenderBackend for _RenderBackend {
     type ObjectRender = ObjectRender<back::Backend>;
     type RenderContext = RenderContext<back::Backend>;
 
-    fn upload_model(ctx: &mut Self::RenderContext, models: Vec<tobj::Model>) {
-        upload_model(ctx, models);
+    fn upload_model(ctx: &mut Self::RenderContext, models: Vec<tobj::Model>) -> usize {
+        upload_model(ctx, models)
     }
 
-    fn create_context(title: &str, dimensions: (u32, u32)) -> Self::RenderContext {
-        _create_context(title, dimensions)
+    fn create_context(
+        title: &str,
+        dimensions: (u32, u32),
+        settings: &GraphicsSettings,
+    ) -> Self::RenderContext {
+        _create_context(title, dimensions, settings)
     }
 
     fn create_obj_render(
@@ -59,6 +116,81 @@ impl RenderBackend for _RenderBackend {
     ) -> Self::ObjectRender {
         _create_obj_render(model_index, shader_index, render)
     }
+
+    fn model_index(render: &Self::ObjectRender) -> usize {
+        render.model_index
+    }
+
+    fn shader_index(render: &Self::ObjectRender) -> usize {
+        render.shader_index
+    }
+
+    fn stats(ctx: &Self::RenderContext) -> &RenderStats {
+        &ctx.stats
+    }
+
+    fn telemetry(ctx: &Self::RenderContext) -> &Telemetry {
+        &ctx.telemetry
+    }
+
+    fn features(ctx: &Self::RenderContext) -> &DeviceFeatures {
+        &ctx.features
+    }
+
+    fn set_title(ctx: &mut Self::RenderContext, title: &str) {
+        if let Some(ref window) = ctx.window {
+            window.set_title(title);
+        }
+    }
+
+    /// `submit()` waits for each frame to finish on the GPU
+    /// before presenting it, so the previous frame has always
+    /// finished here, in low latency mode or not.
+    fn begin_input(ctx: &mut Self::RenderContext, _settings: &GraphicsSettings) {
+        ctx.latency.input_sampled(Instant::now());
+    }
+
+    fn set_window_mode(ctx: &mut Self::RenderContext, mode: WindowMode) {
+        if let Err(err) = ctx.set_window_mode(mode) {
+            error!("Failed to switch to {}: {}", mode, err);
+        }
+    }
+
+    fn set_cursor(ctx: &mut Self::RenderContext, cursor: Cursor) {
+        ctx.set_cursor(cursor);
+    }
+
+    fn set_clear_color(ctx: &mut Self::RenderContext, color: Option<[f32; 4]>) {
+        ctx.set_clear_color(color);
+    }
+
+    fn set_color_load(ctx: &mut Self::RenderContext, load: LoadOp) {
+        ctx.set_color_load(load);
+    }
+
+    fn capture_next_frame(ctx: &mut Self::RenderContext) {
+        ctx.capture_requested = true;
+    }
+
+    fn take_captured_frame(ctx: &mut Self::RenderContext) -> Option<CapturedFrame> {
+        ctx.captured_frame.take()
+    }
+
+    fn window_resized(ctx: &mut Self::RenderContext) {
+        recreate_swapchain(ctx, "window resized");
+    }
+
+    fn prepare(ctx: &mut Self::RenderContext, world: &mut World<Self>, frame: &RenderWorld) {
+        prepare(ctx, world, frame);
+    }
+
+    fn submit(ctx: &mut Self::RenderContext, world: &World<Self>, frame: &RenderWorld) {
+        submit(ctx, world, frame);
+    }
+
+    fn destroy(ctx: Self::RenderContext, world: World<Self>) {
+        destroy(ctx, world);
+    }
 }
 
 /// Uniform
@@ -71,23 +203,36 @@ struct MatrixBlock {
     /// The full MVP matrix
     matrix: Mat4,
     modelview: Mat4,
+    /// Direction towards the light in view space
+    light_direction: Vec4,
+    light_color: Vec4,
+    ambient_color: Vec4,
 }
 
+/// File the pipeline cache is kept in between runs
+const PIPELINE_CACHE_PATH: &str = "pipeline-cache.bin";
+
 /// Render data associated with an object
 pub struct ObjectRender<B: Backend> {
-    pub model_index: usize,
+    /// The mesh of the object's model
+    pub mesh: MeshHandle,
     pub uniform: UniformBuffer<B>,
-    pub shader_index: usize,
+    /// The pipeline in slot `shader_index` when the object was created
+    pub pipeline: PipelineHandle,
+    /// Indices the object was created with, only
+    /// kept for sorting draws across backends
+    model_index: usize,
+    shader_index: usize,
 }
 
-pub fn _create_context(title: &str, dimensions: (u32, u32)) -> RenderContext<back::Backend> {
-    let pipeline_layout = [DescriptorSetLayoutBinding {
-        binding: 0,
-        ty: DescriptorType::UniformBuffer,
-        count: 1,
-        stage_flags: ShaderStageFlags::VERTEX,
-        immutable_samplers: false,
-    }];
+pub fn _create_context(
+    title: &str,
+    dimensions: (u32, u32),
+    settings: &GraphicsSettings,
+) -> RenderContext<back::Backend> {
+    let pipeline_layout = DescriptorLayoutBuilder::new()
+        .with_uniform_buffer(0, ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT)
+        .build();
 
     let vertex_desc = VertexBufferDesc {
         binding: 0,
@@ -113,31 +258,236 @@ pub fn _create_context(title: &str, dimensions: (u32, u32)) -> RenderContext<bac
         },
     };
 
+    let uv_attr = AttributeDesc {
+        location: 2,
+        binding: 0,
+        element: Element {
+            format: Format::Rg32Float,
+            offset: 24,
+        },
+    };
+
+    let vertex_shader = include_bytes!("../../../assets/shaders/model.vert.spv");
+    let fragment_shader = include_bytes!("../../../assets/shaders/model.frag.spv");
+
     let builder = RenderBuilder::new()
         .with_title(title)
-        .with_vertex_shader(include_bytes!("../../../assets/shaders/model.vert.spv"))
-        .with_fragment_shader(include_bytes!("../../../assets/shaders/model.frag.spv"))
+        .with_vertex_shader(vertex_shader)
+        .with_fragment_shader(fragment_shader)
         .with_pipeline(&pipeline_layout)
-        .with_vertex_attr(vertex_desc, vec![position_attr, normal_attr])
-        .with_dimensions(dimensions.0, dimensions.1);
+        .with_vertex_attr(vertex_desc, vec![position_attr, normal_attr, uv_attr])
+        .with_dimensions(dimensions.0, dimensions.1)
+        .with_samples(settings.msaa_samples)
+        .with_depth_mode(settings.depth_mode)
+        .vsync(settings.vsync)
+        .with_pipeline_cache(PIPELINE_CACHE_PATH)
+        .prefer_discrete_gpu();
+
+    let mut ctx = builder
+        .build()
+        .unwrap_or_else(|err| panic!("failed to create render context: {}", err));
+
+    if std::env::args().any(|arg| arg == HOT_RELOAD_ARG) {
+        let shader_dir = std::path::Path::new(SHADER_DIR);
+        ctx.watch_shaders(
+            DEFAULT_PIPELINE,
+            shader_dir.join("model.vert.spv"),
+            shader_dir.join("model.frag.spv"),
+            &PipelineDesc::new(vertex_shader, fragment_shader),
+        );
+    }
 
-    let mut ctx = builder.build();
-    upload_models::<_RenderBackend>(&mut ctx);
+    // Debug views draw the model vertex shader with their own fragment shaders
+    let debug_shaders: [(DebugView, &str, &[u8]); 3] = [
+        (
+            DebugView::Normals,
+            "debug_normals.frag.spv",
+            include_bytes!("../../../assets/shaders/debug_normals.frag.spv"),
+        ),
+        (
+            DebugView::Depth,
+            "debug_depth.frag.spv",
+            include_bytes!("../../../assets/shaders/debug_depth.frag.spv"),
+        ),
+        (
+            DebugView::Overdraw,
+            "debug_overdraw.frag.spv",
+            include_bytes!("../../../assets/shaders/debug_overdraw.frag.spv"),
+        ),
+    ];
+    for &(view, file, debug_shader) in &debug_shaders {
+        let mut desc = PipelineDesc::new(vertex_shader, debug_shader);
+        if view.is_additive() {
+            desc = desc
+                .with_blend(BlendState::ADD)
+                .with_depth_test(DepthTest::Off);
+        }
+        match ctx.register_pipeline(&desc) {
+            Ok(handle) => {
+                ctx.debug_pipelines.push((view, handle));
+                if ctx.shader_watcher.is_enabled() {
+                    let shader_dir = std::path::Path::new(SHADER_DIR);
+                    ctx.watch_shaders(
+                        handle,
+                        shader_dir.join("model.vert.spv"),
+                        shader_dir.join(file),
+                        &desc,
+                    );
+                }
+            }
+            Err(err) => warn!("Failed to create {} debug view: {}", view.name(), err),
+        }
+    }
     ctx
 }
 
-pub fn render(ctx: &mut RenderContext<back::Backend>, world: &mut World<_RenderBackend>) {
+/// Prepare phase: writes the extracted matrices
+/// into each object's uniform buffer.
+pub fn prepare(
+    ctx: &mut RenderContext<back::Backend>,
+    world: &mut World<_RenderBackend>,
+    frame: &RenderWorld,
+) {
+    let extend = |v: Vec3, w: f32| vec4(v.x, v.y, v.z, w);
+    let light_direction = extend(frame.light_direction, 0.0);
+    let light_color = extend(frame.lighting.light_color, 1.0);
+    let ambient_color = extend(frame.lighting.ambient_color, 1.0);
+
+    let objects = world.get_objs_mut();
+    let draws = frame
+        .draws
+        .iter()
+        .chain(&frame.viewmodel_draws)
+        .chain(&frame.culled_draws);
+    for draw in draws {
+        let object = &mut objects[draw.object_index];
+        object.render.uniform.update(
+            &ctx.device,
+            MatrixBlock {
+                matrix: draw.matrix,
+                modelview: draw.modelview,
+                light_direction,
+                // Objects the light doesn't reach only get ambient light
+                light_color: if draw.lit {
+                    light_color
+                } else {
+                    vec4(0.0, 0.0, 0.0, 1.0)
+                },
+                ambient_color,
+            },
+        );
+    }
+}
+
+/// Submit phase: records and submits the command
+/// buffer for the frame, then presents it.
+pub fn submit(
+    ctx: &mut RenderContext<back::Backend>,
+    world: &World<_RenderBackend>,
+    frame: &RenderWorld,
+) {
+    // The frame buffers of the swapchain couldn't be
+    // created last time, so the frame is skipped to retry
+    if ctx.swapchain.is_some() && ctx.frame_buffers.is_empty() {
+        recreate_swapchain(ctx, "frame buffers missing");
+        return;
+    }
+
+    // The previous frame has finished, so pipelines can be replaced
+    ctx.reload_shaders();
+
+    // Debug views replace the pipeline of every object
+    let debug_pipeline = ctx
+        .debug_pipelines
+        .iter()
+        .find(|&&(view, _)| view == frame.debug_view)
+        .map(|&(_, handle)| handle);
+
     let device = &ctx.device;
     let frame_buffers = &ctx.frame_buffers;
     let (frame_fence, frame_semaphore) = (&ctx.frame_fence, &ctx.frame_semaphore);
 
     device.reset_fence(&frame_fence);
     ctx.command_pool.reset();
+    ctx.stats.begin_frame();
+
+    // Uploads queued since the last frame are copied before it
+    ctx.uploader.flush(device, &mut ctx.queue_group.queues[0]);
+
+    // Headless contexts have a single offscreen frame buffer
+    let acquired = match ctx.swapchain {
+        Some(ref mut swapchain) => {
+            swapchain.acquire_image(!0, FrameSync::Semaphore(frame_semaphore))
+        }
+        None => Ok(0),
+    };
+    let frame_index: SwapImageIndex = match acquired {
+        Ok(index) => index,
+        // The swapchain no longer matches the surface, so
+        // the frame is skipped while it is recreated
+        Err(_) => {
+            recreate_swapchain(ctx, "swapchain out of date");
+            return;
+        }
+    };
 
-    let frame_index: SwapImageIndex = ctx
-        .swapchain
-        .acquire_image(!0, FrameSync::Semaphore(frame_semaphore))
-        .unwrap();
+    // Overdraw is measured by copying the frame back,
+    // which captures share. Both are skipped if the
+    // surface doesn't allow swapchain images to be copied
+    let capture = ctx.capture_requested;
+    ctx.capture_requested = false;
+    if capture && !ctx.frames_readable {
+        warn!("Can't capture frames, since the surface doesn't allow copying them");
+    }
+    let readback = if ctx.frames_readable && (frame.debug_view == DebugView::Overdraw || capture) {
+        let image = match ctx.offscreen {
+            Some(ref offscreen) => Some(&offscreen.image),
+            None => ctx.swapchain_images.get(frame_index as usize),
+        };
+        image.map(|image| {
+            (
+                image,
+                ctx.frame_copy_barriers(image),
+                FrameReadback::new(ctx),
+            )
+        })
+    } else {
+        None
+    };
+
+    let clear_color = frame.clear_color(ctx.clear_color);
+    // Images are undefined until the first frame rendered
+    // to them, which clears them instead of loading them
+    let fresh = ctx
+        .fresh_images
+        .get(frame_index as usize)
+        .cloned()
+        .unwrap_or(true);
+    let color_load = match ctx.color_load {
+        LoadOp::Load if fresh => LoadOp::Clear,
+        load => load,
+    };
+    // Frames which load or discard the color attachment
+    // begin a compatible variant of the render pass
+    let render_pass = ctx
+        .load_passes
+        .iter()
+        .find(|&&(load, _)| load == color_load)
+        .map_or(&ctx.render_pass, |&(_, ref render_pass)| render_pass);
+
+    let resources = PassResources {
+        camera: frame.camera,
+        extent: ctx.extent,
+        render_pass: &ctx.render_pass,
+        color: match (&ctx.msaa_image, &ctx.offscreen) {
+            (&Some(ref msaa), _) => Some(&msaa.view),
+            (_, &Some(ref offscreen)) => Some(&offscreen.view),
+            _ => ctx.image_views.get(frame_index as usize),
+        },
+        depth: &ctx.depth_image_view,
+        draws: &frame.draws,
+        culled_draws: &frame.culled_draws,
+    };
 
     let finished_command_buffer = {
         let mut command_buffer = ctx.command_pool.acquire_command_buffer(false);
@@ -148,89 +498,230 @@ pub fn render(ctx: &mut RenderContext<back::Backend>, world: &mut World<_RenderB
 
         {
             let mut encoder = command_buffer.begin_render_pass_inline(
-                &ctx.render_pass,
+                render_pass,
                 &frame_buffers[frame_index as usize],
                 viewport.rect,
                 &[
-                    ClearValue::Color(ClearColor::Float([0.0, 0.0, 0.0, 1.0])),
-                    ClearValue::DepthStencil(ClearDepthStencil(1.0, 0)),
+                    ClearValue::Color(ClearColor::Float(clear_color)),
+                    ClearValue::DepthStencil(ClearDepthStencil(ctx.depth_mode.clear_depth(), 0)),
                 ],
             );
 
-            // Draw each object in the world
+            record_custom_passes(
+                &mut ctx.custom_passes,
+                InjectionPoint::BeforeOpaque,
+                &mut encoder,
+                &resources,
+            );
+
+            // Draw each extracted object
             // TODO distance checks, instanced rendering
-            for object in world.get_objs_mut().values_mut() {
-                render_obj(
-                    object,
+            let objects = world.get_objs();
+            for draw in &frame.draws {
+                let index_count = render_obj(
+                    &objects[draw.object_index],
                     &mut encoder,
-                    &ctx.device,
-                    &ctx.models,
-                    &ctx.pipeline,
+                    &ctx.meshes,
+                    &ctx.pipelines,
+                    debug_pipeline,
                     &ctx.pipeline_layout,
                 );
+                ctx.stats.record_draw(index_count);
+            }
+
+            // Viewmodels are drawn over the scene
+            if !frame.viewmodel_draws.is_empty() {
+                encoder.clear_attachments(
+                    &[AttachmentClear::DepthStencil {
+                        depth: Some(ctx.depth_mode.clear_depth()),
+                        stencil: None,
+                    }],
+                    &[ClearRect {
+                        rect: viewport.rect,
+                        layers: 0..1,
+                    }],
+                );
+                for draw in &frame.viewmodel_draws {
+                    let index_count = render_obj(
+                        &objects[draw.object_index],
+                        &mut encoder,
+                        &ctx.meshes,
+                        &ctx.pipelines,
+                        debug_pipeline,
+                        &ctx.pipeline_layout,
+                    );
+                    ctx.stats.record_draw(index_count);
+                }
+            }
+
+            for &point in &[InjectionPoint::AfterTransparent, InjectionPoint::BeforeUi] {
+                record_custom_passes(&mut ctx.custom_passes, point, &mut encoder, &resources);
             }
         }
 
+        if let Some((image, (ref before, ref after), ref readback)) = readback {
+            command_buffer.pipeline_barrier(
+                PipelineStage::COLOR_ATTACHMENT_OUTPUT..PipelineStage::TRANSFER,
+                Dependencies::empty(),
+                Some(before),
+            );
+            command_buffer.copy_image_to_buffer(
+                image,
+                Layout::TransferSrcOptimal,
+                readback.buffer(),
+                &[readback.copy_region()],
+            );
+            command_buffer.pipeline_barrier(
+                PipelineStage::TRANSFER..PipelineStage::BOTTOM_OF_PIPE,
+                Dependencies::empty(),
+                Some(after),
+            );
+        }
+
         command_buffer.finish()
     };
 
+    let wait_semaphores = match ctx.swapchain {
+        // Nothing uses the image before the render pass
+        // loads it as the color attachment
+        Some(_) => vec![(frame_semaphore, PipelineStage::COLOR_ATTACHMENT_OUTPUT)],
+        None => Vec::new(),
+    };
     let submission = Submission::new()
-        .wait_on(&[(frame_semaphore.borrow(), PipelineStage::BOTTOM_OF_PIPE)])
+        .wait_on(&wait_semaphores)
         .submit(vec![finished_command_buffer]);
 
     ctx.queue_group.queues[0].submit(submission, Some(&frame_fence));
+    ctx.latency.submitted(Instant::now());
 
     device.wait_for_fence(&frame_fence, !0);
+    ctx.uploader.poll(device);
+
+    let extent = ctx.extent;
+    ctx.stats.overdraw = None;
+    if let Some((_, _, readback)) = readback {
+        let mut pixels = readback.read(device);
+        // Swapchains may have picked a BGRA format
+        readback::swizzle_to_rgba(ctx.color_format, &mut pixels);
+        if frame.debug_view == DebugView::Overdraw {
+            ctx.stats.overdraw = Some(OverdrawStats::from_rgba(
+                extent.width,
+                extent.height,
+                &pixels,
+            ));
+        }
+        if capture {
+            ctx.captured_frame = Some(CapturedFrame {
+                width: extent.width,
+                height: extent.height,
+                rgba: pixels,
+            });
+        }
+    }
+
+    // Frame buffers share the multisampled image, if there is one
+    if ctx.msaa_image.is_some() {
+        for fresh in &mut ctx.fresh_images {
+            *fresh = false;
+        }
+    } else if let Some(fresh) = ctx.fresh_images.get_mut(frame_index as usize) {
+        *fresh = false;
+    }
+
+    let presented = match ctx.swapchain {
+        Some(ref mut swapchain) => swapchain
+            .present(&mut ctx.queue_group.queues[0], frame_index, &[])
+            .is_ok(),
+        None => true,
+    };
+    // Headless frames count as presented once they have finished
+    ctx.latency.presented(Instant::now());
+    ctx.stats.latency = ctx.latency.average();
+    if !presented {
+        recreate_swapchain(ctx, "swapchain out of date");
+    }
+}
 
-    ctx.swapchain
-        .present(&mut ctx.queue_group.queues[0], frame_index, &[])
-        .unwrap();
+/// Recreates the swapchain, logging any failure, since
+/// it is retried when the next frame can't be presented.
+fn recreate_swapchain(ctx: &mut RenderContext<back::Backend>, reason: &str) {
+    if let Err(err) = ctx.recreate_swapchain(reason) {
+        error!("Failed to recreate swapchain: {}", err);
+    }
 }
 
 /// Renders the object
-/// using its model buffer
-/// and uniform
+/// using its mesh,
+/// pipeline and uniform, returning the
+/// number of indices drawn. The object's
+/// pipeline is replaced by `override_pipeline`
+/// if one is given. Objects whose mesh was
+/// destroyed aren't drawn.
 fn render_obj(
-    object: &mut world::Object<_RenderBackend>,
+    object: &world::Object<_RenderBackend>,
     encoder: &mut RenderPassInlineEncoder<back::Backend, Primary>,
-    device: &<back::Backend as Backend>::Device,
-    models: &Vec<context::ModelBuffer<back::Backend>>,
-    pipeline: &<back::Backend as Backend>::GraphicsPipeline,
+    meshes: &HandleMap<MeshResource, IndexedMesh<back::Backend>>,
+    pipelines: &PipelineRegistry<back::Backend>,
+    override_pipeline: Option<PipelineHandle>,
     pipeline_layout: &<back::Backend as Backend>::PipelineLayout,
-) {
-    let model_buffer = &models[object.render.model_index];
-    encoder.bind_vertex_buffers(0, vec![(&model_buffer.vertices.buffer, 0)]);
-    encoder.bind_graphics_pipeline(pipeline);
+) -> usize {
+    let mesh = match meshes.get(object.render.mesh) {
+        Ok(mesh) => mesh,
+        Err(_) => return 0,
+    };
+    let pipeline = override_pipeline.unwrap_or(object.render.pipeline);
+    encoder.bind_graphics_pipeline(pipelines.get(pipeline));
     encoder.bind_graphics_descriptor_sets(
         pipeline_layout,
         0,
         vec![&object.render.uniform.desc_set],
         &[],
     );
-
-    let index_buffer_view = IndexBufferView {
-        buffer: &model_buffer.indices.buffer,
-        offset: 0,
-        index_type: IndexType::U32,
-    };
-    encoder.bind_index_buffer(index_buffer_view);
-
-    let (matrix, modelview) = mvp_matrix(object);
-
-    buffer_util::fill_buffer::<back::Backend, MatrixBlock>(
-        device,
-        &mut object.render.uniform.buffer.memory,
-        &[MatrixBlock { matrix, modelview }],
-    );
-
-    encoder.draw_indexed(0..(model_buffer.indices.element_count as u32), 0, 0..1);
+    draw_indexed_mesh(encoder, mesh)
 }
 
-/// Destroys the RenderContext.
-pub fn destroy(ctx: RenderContext<back::Backend>) {
+/// Destroys the RenderContext, along with
+/// the render data of the objects in `world`.
+pub fn destroy(ctx: RenderContext<back::Backend>, world: World<_RenderBackend>) {
     let device = ctx.device;
 
-    device.destroy_graphics_pipeline(ctx.pipeline);
+    if let Some(path) = ctx.pipeline_cache_path {
+        match device.get_pipeline_cache_data(&ctx.pipeline_cache) {
+            Ok(data) => {
+                if let Err(err) = std::fs::write(&path, data) {
+                    warn!("Failed to write {}: {}", path.display(), err);
+                }
+            }
+            Err(err) => warn!("Failed to read pipeline cache: {:?}", err),
+        }
+    }
+    device.destroy_pipeline_cache(ctx.pipeline_cache);
+
+    ctx.uploader.destroy(&device);
+    ctx.pipelines.destroy(&device);
+    for object in world.into_objs() {
+        object.render.uniform.destroy(&device);
+    }
+    device.destroy_descriptor_set_layout(ctx.set_layout);
+    for mesh in ctx.meshes.into_values() {
+        mesh.destroy(&device);
+    }
+    for texture in ctx.textures.into_values() {
+        texture.destroy(&device);
+    }
+    for target in ctx.targets.into_values() {
+        device.destroy_image_view(target.view);
+        device.destroy_image(target.image);
+        target.memory.free();
+    }
+    for buffer in ctx.buffers.into_values() {
+        device.destroy_buffer(buffer.buffer);
+        buffer.memory.free();
+    }
+    ctx.graph_images.destroy(&device);
+    for mut added in ctx.custom_passes {
+        added.pass.destroy(&device);
+    }
     device.destroy_pipeline_layout(ctx.pipeline_layout);
 
     for framebuffer in ctx.frame_buffers {
@@ -241,14 +732,36 @@ pub fn destroy(ctx: RenderContext<back::Backend>) {
         device.destroy_image_view(image_view);
     }
 
+    for (_, sampler) in ctx.samplers {
+        device.destroy_sampler(sampler);
+    }
+
+    if let Some(msaa) = ctx.msaa_image {
+        device.destroy_image_view(msaa.view);
+        device.destroy_image(msaa.image);
+        msaa.memory.free();
+    }
+
     device.destroy_render_pass(ctx.render_pass);
-    device.destroy_swapchain(ctx.swapchain);
+    for (_, render_pass) in ctx.load_passes {
+        device.destroy_render_pass(render_pass);
+    }
+    if let Some(swapchain) = ctx.swapchain {
+        device.destroy_swapchain(swapchain);
+    }
+    if let Some(offscreen) = ctx.offscreen {
+        device.destroy_image_view(offscreen.view);
+        device.destroy_image(offscreen.image);
+        offscreen.memory.free();
+    }
+    device.destroy_image_view(ctx.depth_image_view);
+    device.destroy_image(ctx.depth_image);
+    ctx.depth_image_memory.free();
 
     device.destroy_command_pool(ctx.command_pool.into_raw());
     device.destroy_fence(ctx.frame_fence);
     device.destroy_semaphore(ctx.frame_semaphore);
-
-    // TODO - descriptors
+    ctx.allocator.destroy(&device);
 }
 
 fn viewport(extent: &Extent) -> Viewport {
@@ -265,44 +778,27 @@ fn viewport(extent: &Extent) -> Viewport {
 
 /// Creates a descriptor set and pool and uniform buffer/memory
 /// for the object. The model_index is the index into the RenderContext's
-/// model vector and the shader_index is not used yet (GitHub issue #7)
+/// model vector and the shader_index is the slot of the pipeline
+/// it is drawn with, falling back to the default pipeline
 pub fn _create_obj_render<B: Backend>(
     model_index: usize,
     shader_index: usize,
     ctx: &mut RenderContext<B>,
 ) -> ObjectRender<B> {
-    // Allocate uniform buffer and init descriptor set
-    let mut desc_pool = ctx.device.create_descriptor_pool(
-        1,
-        &[DescriptorRangeDesc {
-            ty: DescriptorType::UniformBuffer,
-            count: 1,
-        }],
-    );
-    let desc_set = desc_pool.allocate_set(&ctx.set_layout).unwrap();
-
-    let (buffer, memory) = buffer_util::empty_buffer::<B, MatrixBlock>(
-        &ctx.device,
-        &ctx.memory_types,
-        Properties::CPU_VISIBLE,
-        Usage::UNIFORM,
-        1,
-    );
-
-    ctx.device.write_descriptor_sets(vec![DescriptorSetWrite {
-        set: &desc_set,
-        binding: 0,
-        array_offset: 0,
-        descriptors: Some(Descriptor::Buffer(&buffer, None..None)),
-    }]);
-
     ObjectRender {
+        mesh: ctx.models[model_index],
         model_index,
         shader_index,
-        uniform: UniformBuffer {
-            buffer: BufferMem::new(buffer, memory),
-            desc_set,
-            desc_pool,
-        },
+        pipeline: ctx
+            .pipelines
+            .handle(shader_index)
+            .unwrap_or(DEFAULT_PIPELINE),
+        uniform: UniformBuffer::new::<MatrixBlock>(
+            &ctx.device,
+            &ctx.allocator,
+            &ctx.set_layout,
+            &ctx.set_bindings,
+            0,
+        ),
     }
 }
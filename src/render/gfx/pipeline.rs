@@ -0,0 +1,331 @@
+//! Graphics pipelines.
+//!
+//! The factory creates the default pipeline from the builder's
+//! shaders. More can be registered on a `RenderContext` with
+//! different shaders, primitive topologies and rasterizer
+//! states. All pipelines share the context's vertex layout,
+//! descriptor set layout and render pass. Each object draws
+//! with the pipeline registered in slot `shader_index`.
+use super::*;
+use gfx_hal::pso::Multisampling;
+use profile::millis_since;
+use render::handle::PipelineResource;
+use std::time::Instant;
+
+/// The pipeline created with the context.
+pub const DEFAULT_PIPELINE: PipelineHandle = PipelineHandle::new(0, 0);
+
+/// Describes a graphics pipeline. Depth comparisons are
+/// written for standard depth, and are flipped when the
+/// context uses reversed depth.
+#[derive(Clone)]
+pub struct PipelineDesc<'a> {
+    /// SPIR-V for the vertex shader
+    pub vertex_shader: &'a [u8],
+    /// SPIR-V for the fragment shader
+    pub fragment_shader: &'a [u8],
+    pub primitive: Primitive,
+    pub rasterizer: Rasterizer,
+    pub depth_test: DepthTest,
+    pub blend: BlendState,
+    /// Per-instance attributes, for pipelines
+    /// drawing with `draw_instanced()`
+    pub instances: Option<InstanceLayout>,
+}
+
+impl<'a> PipelineDesc<'a> {
+    /// Creates a description which draws filled triangle lists
+    /// with depth testing and alpha blending, like the default
+    /// pipeline.
+    pub fn new(vertex_shader: &'a [u8], fragment_shader: &'a [u8]) -> PipelineDesc<'a> {
+        PipelineDesc {
+            vertex_shader,
+            fragment_shader,
+            primitive: Primitive::TriangleList,
+            rasterizer: Rasterizer::FILL,
+            depth_test: DepthTest::On {
+                fun: Comparison::Less,
+                write: true,
+            },
+            blend: BlendState::ALPHA,
+            instances: None,
+        }
+    }
+
+    pub fn with_primitive(mut self, primitive: Primitive) -> Self {
+        self.primitive = primitive;
+        self
+    }
+
+    pub fn with_rasterizer(mut self, rasterizer: Rasterizer) -> Self {
+        self.rasterizer = rasterizer;
+        self
+    }
+
+    pub fn with_depth_test(mut self, depth_test: DepthTest) -> Self {
+        self.depth_test = depth_test;
+        self
+    }
+
+    pub fn with_blend(mut self, blend: BlendState) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn with_instances(mut self, instances: InstanceLayout) -> Self {
+        self.instances = Some(instances);
+        self
+    }
+}
+
+/// State shared by every pipeline of a context.
+pub struct PipelineTarget<'a, B: Backend> {
+    pub render_pass: &'a B::RenderPass,
+    pub layout: &'a B::PipelineLayout,
+    pub vertex_desc: Option<VertexBufferDesc>,
+    pub attr_descs: &'a [AttributeDesc],
+    /// Samples per pixel of the render pass attachments
+    pub samples: u8,
+    pub depth_mode: DepthMode,
+    pub cache: Option<&'a B::PipelineCache>,
+}
+
+/// Creates a pipeline from `desc` which renders to `target`.
+pub fn create_pipeline<B: Backend>(
+    device: &B::Device,
+    target: &PipelineTarget<B>,
+    desc: &PipelineDesc,
+) -> Result<B::GraphicsPipeline, RenderError> {
+    let vertex_module = device
+        .create_shader_module(desc.vertex_shader)
+        .map_err(|err| RenderError::ShaderCompilation(format!("{:?}", err)))?;
+    let fragment_module = match device.create_shader_module(desc.fragment_shader) {
+        Ok(module) => module,
+        Err(err) => {
+            device.destroy_shader_module(vertex_module);
+            return Err(RenderError::ShaderCompilation(format!("{:?}", err)));
+        }
+    };
+
+    let pipeline = {
+        let shader_entries = GraphicsShaderSet {
+            vertex: entry_point::<B>(&vertex_module),
+            hull: None,
+            domain: None,
+            geometry: None,
+            fragment: Some(entry_point::<B>(&fragment_module)),
+        };
+
+        let subpass = Subpass {
+            index: 0,
+            main_pass: target.render_pass,
+        };
+
+        let mut pipeline_desc = GraphicsPipelineDesc::new(
+            shader_entries,
+            desc.primitive,
+            desc.rasterizer.clone(),
+            target.layout,
+            subpass,
+        );
+
+        pipeline_desc
+            .blender
+            .targets
+            .push(ColorBlendDesc(ColorMask::ALL, desc.blend));
+
+        // Vertex buffers
+        if let Some(vertex_desc) = target.vertex_desc.clone() {
+            pipeline_desc.vertex_buffers.push(vertex_desc);
+            pipeline_desc
+                .attributes
+                .extend(target.attr_descs.iter().cloned());
+        }
+        if let Some(ref instances) = desc.instances {
+            pipeline_desc.vertex_buffers.push(instances.desc.clone());
+            pipeline_desc
+                .attributes
+                .extend(instances.attributes.iter().cloned());
+        }
+
+        if target.samples > 1 {
+            pipeline_desc.multisampling = Some(Multisampling {
+                rasterization_samples: target.samples,
+                sample_shading: None,
+                sample_mask: !0,
+                alpha_coverage: false,
+                alpha_to_one: false,
+            });
+        }
+
+        pipeline_desc.depth_stencil = DepthStencilDesc {
+            depth: depth_test(desc.depth_test, target.depth_mode),
+            depth_bounds: false,
+            stencil: StencilTest::default(),
+        };
+
+        device
+            .create_graphics_pipeline(&pipeline_desc, target.cache)
+            .map_err(|err| RenderError::PipelineCreation(format!("{:?}", err)))
+    };
+
+    device.destroy_shader_module(vertex_module);
+    device.destroy_shader_module(fragment_module);
+    pipeline
+}
+
+/// Returns `test` with its comparison flipped
+/// if depth is laid out as reversed.
+fn depth_test(test: DepthTest, mode: DepthMode) -> DepthTest {
+    match test {
+        DepthTest::On { fun, write } if mode.is_reversed() => DepthTest::On {
+            fun: match fun {
+                Comparison::Less => Comparison::Greater,
+                Comparison::LessEqual => Comparison::GreaterEqual,
+                Comparison::Greater => Comparison::Less,
+                Comparison::GreaterEqual => Comparison::LessEqual,
+                other => other,
+            },
+            write,
+        },
+        test => test,
+    }
+}
+
+/// Returns the entry point of a shader's `main` function.
+fn entry_point<B: Backend>(module: &B::ShaderModule) -> EntryPoint<B> {
+    EntryPoint {
+        entry: "main",
+        module,
+        specialization: Specialization {
+            constants: &[],
+            data: &[],
+        },
+    }
+}
+
+/// The graphics pipelines of a context.
+pub struct PipelineRegistry<B: Backend> {
+    pipelines: HandleMap<PipelineResource, B::GraphicsPipeline>,
+}
+
+impl<B: Backend> PipelineRegistry<B> {
+    /// Creates a registry holding the default pipeline.
+    pub fn new(default: B::GraphicsPipeline) -> PipelineRegistry<B> {
+        let mut pipelines = HandleMap::new();
+        pipelines.insert(default);
+        PipelineRegistry { pipelines }
+    }
+
+    /// Adds a pipeline, returning its handle.
+    pub fn register(&mut self, pipeline: B::GraphicsPipeline) -> PipelineHandle {
+        self.pipelines.insert(pipeline)
+    }
+
+    /// Returns the pipeline with the given handle, or the
+    /// default pipeline if it has been unregistered.
+    pub fn get(&self, handle: PipelineHandle) -> &B::GraphicsPipeline {
+        self.pipelines
+            .get(handle)
+            .or_else(|_| self.pipelines.get(DEFAULT_PIPELINE))
+            .unwrap()
+    }
+
+    /// Returns the handle of the pipeline registered
+    /// in slot `index`, such as an object's `shader_index`.
+    pub fn handle(&self, index: usize) -> Option<PipelineHandle> {
+        self.pipelines.handle(index)
+    }
+
+    pub fn contains(&self, handle: PipelineHandle) -> bool {
+        self.pipelines.contains(handle)
+    }
+
+    /// Replaces the pipeline with the given handle,
+    /// returning the old pipeline to be destroyed.
+    /// Panics if the handle is stale.
+    pub fn replace(
+        &mut self,
+        handle: PipelineHandle,
+        pipeline: B::GraphicsPipeline,
+    ) -> B::GraphicsPipeline {
+        match self.pipelines.get_mut(handle) {
+            Ok(old) => std::mem::replace(old, pipeline),
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Removes a pipeline, returning it to be destroyed.
+    /// The default pipeline can't be removed.
+    pub fn unregister(
+        &mut self,
+        handle: PipelineHandle,
+    ) -> Result<B::GraphicsPipeline, StaleHandle> {
+        assert!(
+            handle != DEFAULT_PIPELINE,
+            "the default pipeline can't be unregistered"
+        );
+        self.pipelines.remove(handle)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pipelines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pipelines.is_empty()
+    }
+
+    /// Destroys every pipeline.
+    pub fn destroy(self, device: &B::Device) {
+        for pipeline in self.pipelines.into_values() {
+            device.destroy_graphics_pipeline(pipeline);
+        }
+    }
+}
+
+impl<B: Backend> RenderContext<B> {
+    /// Returns the state pipelines of this context share.
+    pub fn pipeline_target(&self) -> PipelineTarget<B> {
+        PipelineTarget {
+            render_pass: &self.render_pass,
+            layout: &self.pipeline_layout,
+            vertex_desc: self.vertex_desc.clone(),
+            attr_descs: &self.attr_descs,
+            samples: self.samples,
+            depth_mode: self.depth_mode,
+            cache: Some(&self.pipeline_cache),
+        }
+    }
+
+    /// Creates a pipeline and registers it, returning a handle
+    /// whose index can be used as an object's `shader_index`.
+    /// Draws are only grouped by the low 11 bits of the index
+    /// (see `render::sort_key`), so pipelines 2048 apart sort
+    /// as if they were the same.
+    pub fn register_pipeline(
+        &mut self,
+        desc: &PipelineDesc,
+    ) -> Result<PipelineHandle, RenderError> {
+        let start = Instant::now();
+        let pipeline = create_pipeline::<B>(&self.device, &self.pipeline_target(), desc)?;
+        let handle = self.pipelines.register(pipeline);
+        self.telemetry.record(RenderEvent {
+            kind: EventKind::PipelineCompilation,
+            reason: format!("pipeline {} registered", handle),
+            millis: millis_since(start),
+            bytes: None,
+        });
+        Ok(handle)
+    }
+
+    /// Unregisters and destroys a pipeline. Objects drawn
+    /// with it fall back to the default pipeline.
+    pub fn unregister_pipeline(&mut self, handle: PipelineHandle) -> Result<(), StaleHandle> {
+        let pipeline = self.pipelines.unregister(handle)?;
+        self.device.destroy_graphics_pipeline(pipeline);
+        self.shader_watcher.unwatch(handle);
+        self.debug_pipelines.retain(|&(_, debug)| debug != handle);
+        Ok(())
+    }
+}
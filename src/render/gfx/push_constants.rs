@@ -0,0 +1,52 @@
+//! Push constants, for small per-draw data such as a
+//! model matrix or tint color which would be wasteful
+//! to put in a uniform buffer.
+//!
+//! Ranges are declared on the pipeline layout with
+//! `RenderBuilder::with_push_constants()`.
+use super::*;
+
+/// Returns the size of `T` in 32-bit words, which is
+/// the unit push constant ranges are measured in.
+pub fn push_constant_words<T>() -> u32 {
+    let size = std::mem::size_of::<T>();
+    assert_eq!(size % 4, 0, "push constants must be a multiple of 4 bytes");
+    (size / 4) as u32
+}
+
+/// Records a write of `value` to the push constants of
+/// `stages`, starting `offset` words into the range.
+///
+/// # Safety
+///
+/// `value` is read as 32-bit words, so `T` must be plain data
+/// laid out the way the shader expects: `#[repr(C)]` (or a
+/// primitive, array or `Mat4`), aligned to at least 4 bytes,
+/// and without padding, whose bytes are undefined. A struct
+/// of `f32`s, `u32`s and vectors of them qualifies.
+pub unsafe fn push_constants<B: Backend, T: Copy>(
+    encoder: &mut RenderPassInlineEncoder<B, Primary>,
+    layout: &B::PipelineLayout,
+    stages: ShaderStageFlags,
+    offset: u32,
+    value: &T,
+) {
+    let words = push_constant_words::<T>() as usize;
+    assert!(
+        std::mem::align_of::<T>() >= 4,
+        "push constants must be 4-byte aligned"
+    );
+    let constants = std::slice::from_raw_parts(value as *const T as *const u32, words);
+    encoder.push_graphics_constants(layout, stages, offset, constants);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words() {
+        assert_eq!(push_constant_words::<Mat4>(), 16);
+        assert_eq!(push_constant_words::<[f32; 3]>(), 3);
+    }
+}
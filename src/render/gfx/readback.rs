@@ -0,0 +1,379 @@
+//! Reading rendered images and buffers back to the CPU.
+//!
+//! Frames are read back right away, waiting for the copy.
+//! Buffers, such as GPU culling counters, picking results or
+//! compute output, are copied without waiting: `read_buffer()`
+//! returns a `BufferReadback`, which is polled like an upload
+//! and read once the copy's fence has signalled. It isn't a
+//! `Future`, since nothing wakes a task when a fence signals.
+use super::buffer_util::align;
+use super::*;
+use gfx_hal::buffer;
+use gfx_hal::command::{BufferCopy, BufferImageCopy};
+use gfx_hal::image::{Offset, SubresourceLayers};
+use gfx_hal::memory::{Barrier, Dependencies};
+use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Bytes per pixel of frames
+const PIXEL_SIZE: u32 = 4;
+
+/// A host visible buffer a frame is copied into.
+pub struct FrameReadback<B: Backend> {
+    buffer: B::Buffer,
+    memory: Allocation<B>,
+    /// Bytes per row, aligned for copies
+    row_pitch: u32,
+    extent: Extent,
+}
+
+impl<B: Backend> FrameReadback<B> {
+    /// Creates a buffer which can hold a frame of `ctx`.
+    pub fn new(ctx: &RenderContext<B>) -> FrameReadback<B> {
+        let extent = ctx.extent;
+        let row_pitch = align(
+            extent.width * PIXEL_SIZE,
+            ctx.limits.min_buffer_copy_pitch_alignment as u32,
+        );
+        let (buffer, memory) = buffer_util::empty_buffer::<B, u8>(
+            &ctx.device,
+            &ctx.allocator,
+            Properties::CPU_VISIBLE,
+            Usage::TRANSFER_DST,
+            (row_pitch * extent.height) as usize,
+        );
+        FrameReadback {
+            buffer,
+            memory,
+            row_pitch,
+            extent,
+        }
+    }
+
+    pub fn buffer(&self) -> &B::Buffer {
+        &self.buffer
+    }
+
+    /// Returns the region to pass to `copy_image_to_buffer()`.
+    pub fn copy_region(&self) -> BufferImageCopy {
+        BufferImageCopy {
+            buffer_offset: 0,
+            buffer_width: self.row_pitch / PIXEL_SIZE,
+            buffer_height: self.extent.height,
+            image_layers: SubresourceLayers {
+                aspects: Aspects::COLOR,
+                level: 0,
+                layers: 0..1,
+            },
+            image_offset: Offset { x: 0, y: 0, z: 0 },
+            image_extent: self.extent,
+        }
+    }
+
+    /// Returns the copied pixels as RGBA rows from the top,
+    /// and frees the buffer. The copy must have finished.
+    pub fn read(self, device: &B::Device) -> Vec<u8> {
+        let (width, height) = (self.extent.width, self.extent.height);
+        let mut pixels = Vec::with_capacity((width * height * PIXEL_SIZE) as usize);
+        let rows = self
+            .memory
+            .read::<u8>(device, (self.row_pitch * height) as usize);
+        for row in rows.chunks(self.row_pitch as usize) {
+            pixels.extend_from_slice(&row[..(width * PIXEL_SIZE) as usize]);
+        }
+
+        device.destroy_buffer(self.buffer);
+        self.memory.free();
+        pixels
+    }
+}
+
+/// A copy of part of a buffer into a host visible buffer,
+/// which can be read once its fence has signalled. Dropping
+/// a readback waits for the copy before freeing it.
+pub struct BufferReadback<B: Backend> {
+    device: Arc<B::Device>,
+    /// Taken when the readback is read or dropped
+    staging: Option<Staging<B>>,
+    size: u64,
+}
+
+/// The resources of a buffer readback.
+struct Staging<B: Backend> {
+    buffer: B::Buffer,
+    memory: Allocation<B>,
+    /// Pool of the copy's command buffer, which is freed with
+    /// the readback so frames can't reset it while it runs
+    command_pool: CommandPool<B, Graphics>,
+    fence: B::Fence,
+}
+
+impl<B: Backend> Staging<B> {
+    /// Waits for the copy to finish and frees the resources.
+    fn destroy(self, device: &B::Device) {
+        device.wait_for_fence(&self.fence, !0);
+        device.destroy_fence(self.fence);
+        device.destroy_command_pool(self.command_pool.into_raw());
+        device.destroy_buffer(self.buffer);
+        self.memory.free();
+    }
+}
+
+impl<B: Backend> BufferReadback<B> {
+    /// Returns whether the copy has finished.
+    pub fn is_ready(&self) -> bool {
+        let staging = self.staging.as_ref().unwrap();
+        self.device.get_fence_status(&staging.fence)
+    }
+
+    /// Returns the copied bytes if the copy has finished,
+    /// or the readback to poll again later.
+    pub fn try_read(self) -> Result<Vec<u8>, BufferReadback<B>> {
+        if self.is_ready() {
+            Ok(self.read())
+        } else {
+            Err(self)
+        }
+    }
+
+    /// Waits for the copy to finish and returns the copied bytes.
+    pub fn wait(self) -> Vec<u8> {
+        self.read()
+    }
+
+    /// Waits for the copy, reads it and frees the readback.
+    fn read(mut self) -> Vec<u8> {
+        let staging = self.staging.take().unwrap();
+        self.device.wait_for_fence(&staging.fence, !0);
+        // Invalidates the mapped range if the memory isn't coherent
+        let data = staging.memory.read::<u8>(&self.device, self.size as usize);
+        staging.destroy(&self.device);
+        data
+    }
+}
+
+impl<B: Backend> Drop for BufferReadback<B> {
+    fn drop(&mut self) {
+        if let Some(staging) = self.staging.take() {
+            staging.destroy(&self.device);
+        }
+    }
+}
+
+/// An error produced when starting a buffer readback.
+#[derive(Debug)]
+pub enum ReadbackError {
+    Stale(StaleHandle),
+    /// The range is empty or ends past the buffer's size
+    InvalidRange {
+        range: Range<u64>,
+        size: u64,
+    },
+}
+
+impl fmt::Display for ReadbackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadbackError::Stale(err) => write!(f, "{}", err),
+            ReadbackError::InvalidRange { range, size } => write!(
+                f,
+                "can't read bytes {}..{} of a buffer of {} bytes",
+                range.start, range.end, size
+            ),
+        }
+    }
+}
+
+/// Checks that `range` is a non-empty range
+/// of bytes in a buffer of `size` bytes.
+fn check_range(range: &Range<u64>, size: u64) -> Result<(), ReadbackError> {
+    if range.start >= range.end || range.end > size {
+        return Err(ReadbackError::InvalidRange {
+            range: range.clone(),
+            size,
+        });
+    }
+    Ok(())
+}
+
+/// Reorders the channels of pixels copied from an image of
+/// `format` to RGBA, for swapchains which chose a BGRA format.
+pub fn swizzle_to_rgba(format: Format, pixels: &mut [u8]) {
+    match format {
+        Format::Bgra8Unorm | Format::Bgra8Srgb => {
+            for pixel in pixels.chunks_mut(PIXEL_SIZE as usize) {
+                pixel.swap(0, 2);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// The range of a frame's color image.
+fn color_range() -> SubresourceRange {
+    SubresourceRange {
+        aspects: Aspects::COLOR,
+        levels: 0..1,
+        layers: 0..1,
+    }
+}
+
+impl<B: Backend> RenderContext<B> {
+    /// Returns the layout the render pass leaves frames in.
+    pub fn frame_layout(&self) -> Layout {
+        match self.offscreen {
+            Some(_) => Layout::TransferSrcOptimal,
+            None => Layout::Present,
+        }
+    }
+
+    /// Returns barriers moving a frame's color image from
+    /// the layout the render pass leaves it in to one it can
+    /// be copied from, and back.
+    pub fn frame_copy_barriers<'a>(&self, image: &'a B::Image) -> (Barrier<'a, B>, Barrier<'a, B>) {
+        let layout = self.frame_layout();
+        (
+            Barrier::Image {
+                states: (Access::COLOR_ATTACHMENT_WRITE, layout)
+                    ..(Access::TRANSFER_READ, Layout::TransferSrcOptimal),
+                target: image,
+                range: color_range(),
+            },
+            Barrier::Image {
+                states: (Access::TRANSFER_READ, Layout::TransferSrcOptimal)
+                    ..(Access::empty(), layout),
+                target: image,
+                range: color_range(),
+            },
+        )
+    }
+
+    /// Copies the last frame rendered by a headless context
+    /// to the CPU, returning its pixels as RGBA rows from the
+    /// top, or `None` if the context renders to a window.
+    /// This waits for the copy to finish.
+    pub fn read_frame(&mut self) -> Option<Vec<u8>> {
+        if self.offscreen.is_none() {
+            return None;
+        }
+        let readback = FrameReadback::new(self);
+
+        {
+            let image = &self.offscreen.as_ref().unwrap().image;
+            let (before, after) = self.frame_copy_barriers(image);
+            let mut command_buffer = self.command_pool.acquire_command_buffer(false);
+
+            command_buffer.pipeline_barrier(
+                PipelineStage::COLOR_ATTACHMENT_OUTPUT..PipelineStage::TRANSFER,
+                Dependencies::empty(),
+                &[before],
+            );
+            command_buffer.copy_image_to_buffer(
+                image,
+                Layout::TransferSrcOptimal,
+                readback.buffer(),
+                &[readback.copy_region()],
+            );
+            command_buffer.pipeline_barrier(
+                PipelineStage::TRANSFER..PipelineStage::BOTTOM_OF_PIPE,
+                Dependencies::empty(),
+                &[after],
+            );
+
+            let fence = self.device.create_fence(false);
+            let submission = Submission::new().submit(vec![command_buffer.finish()]);
+            self.queue_group.queues[0].submit(submission, Some(&fence));
+            self.device.wait_for_fence(&fence, !0);
+            self.device.destroy_fence(fence);
+        }
+
+        Some(readback.read(&self.device))
+    }
+
+    /// Starts copying the bytes in `range` of the buffer
+    /// created with `create_buffer()` to the CPU, after the
+    /// frames and copies submitted before it. Fails if the
+    /// handle is stale or the range isn't in the buffer.
+    pub fn read_buffer(
+        &mut self,
+        handle: BufferHandle,
+        range: Range<u64>,
+    ) -> Result<BufferReadback<B>, ReadbackError> {
+        let buffer = self.buffers.get(handle).map_err(ReadbackError::Stale)?;
+        check_range(&range, buffer.element_count as u64)?;
+        let size = range.end - range.start;
+        let (staging_buffer, staging_memory) = buffer_util::empty_buffer::<B, u8>(
+            &self.device,
+            &self.allocator,
+            Properties::CPU_VISIBLE,
+            Usage::TRANSFER_DST,
+            size as usize,
+        );
+        let mut command_pool = self.device.create_command_pool_typed(
+            &self.queue_group,
+            CommandPoolCreateFlags::TRANSIENT,
+            1,
+        );
+
+        let submit = {
+            let mut command_buffer = command_pool.acquire_command_buffer(false);
+            // Shaders and copies may have just written the buffer
+            command_buffer.pipeline_barrier(
+                PipelineStage::VERTEX_SHADER
+                    | PipelineStage::FRAGMENT_SHADER
+                    | PipelineStage::COMPUTE_SHADER
+                    | PipelineStage::TRANSFER..PipelineStage::TRANSFER,
+                Dependencies::empty(),
+                &[Barrier::AllBuffers(
+                    buffer::Access::SHADER_WRITE | buffer::Access::TRANSFER_WRITE
+                        ..buffer::Access::TRANSFER_READ,
+                )],
+            );
+            command_buffer.copy_buffer(
+                &buffer.buffer,
+                &staging_buffer,
+                &[BufferCopy {
+                    src: range.start,
+                    dst: 0,
+                    size,
+                }],
+            );
+            command_buffer.pipeline_barrier(
+                PipelineStage::TRANSFER..PipelineStage::HOST,
+                Dependencies::empty(),
+                &[Barrier::AllBuffers(
+                    buffer::Access::TRANSFER_WRITE..buffer::Access::HOST_READ,
+                )],
+            );
+            command_buffer.finish()
+        };
+
+        let fence = self.device.create_fence(false);
+        self.queue_group.queues[0].submit(Submission::new().submit(vec![submit]), Some(&fence));
+        Ok(BufferReadback {
+            device: self.device.clone(),
+            staging: Some(Staging {
+                buffer: staging_buffer,
+                memory: staging_memory,
+                command_pool,
+                fence,
+            }),
+            size,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readback_ranges() {
+        assert!(check_range(&(0..16), 16).is_ok());
+        assert!(check_range(&(4..8), 16).is_ok());
+        assert!(check_range(&(8..8), 16).is_err());
+        assert!(check_range(&(8..4), 16).is_err());
+        assert!(check_range(&(8..17), 16).is_err());
+    }
+}
@@ -0,0 +1,634 @@
+//! SPIR-V reflection.
+//!
+//! Reads the descriptor bindings, push constant block and
+//! vertex inputs a shader declares, so that
+//! `RenderBuilder::with_reflection()` can build the pipeline
+//! layout and vertex attributes without them being written out
+//! by hand. Only the parts of SPIR-V needed for that are parsed.
+use super::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+
+const MAGIC: u32 = 0x0723_0203;
+const HEADER_WORDS: usize = 5;
+
+// Opcodes
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+// Decorations
+const BLOCK: u32 = 2;
+const BUFFER_BLOCK: u32 = 3;
+const ARRAY_STRIDE: u32 = 6;
+const MATRIX_STRIDE: u32 = 7;
+const BUILT_IN: u32 = 11;
+const LOCATION: u32 = 30;
+const BINDING: u32 = 33;
+const DESCRIPTOR_SET: u32 = 34;
+const OFFSET: u32 = 35;
+
+// Storage classes
+const UNIFORM_CONSTANT: u32 = 0;
+const INPUT: u32 = 1;
+const UNIFORM: u32 = 2;
+const PUSH_CONSTANT: u32 = 9;
+const STORAGE_BUFFER: u32 = 12;
+
+// Execution models
+const VERTEX: u32 = 0;
+const FRAGMENT: u32 = 4;
+
+/// Image dimensionality of texel buffers
+const DIM_BUFFER: u32 = 5;
+
+/// An error produced when reflecting a shader.
+#[derive(Debug, PartialEq)]
+pub enum ReflectError {
+    /// The data does not start with the SPIR-V magic number
+    InvalidMagic,
+    /// An instruction runs past the end of the data
+    Truncated,
+    /// A resource has a type which can't be described
+    UnsupportedType(String),
+}
+
+impl fmt::Display for ReflectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReflectError::InvalidMagic => write!(f, "not a SPIR-V module"),
+            ReflectError::Truncated => write!(f, "SPIR-V module is truncated"),
+            ReflectError::UnsupportedType(name) => write!(f, "unsupported type for {}", name),
+        }
+    }
+}
+
+/// A descriptor a shader declares.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReflectedBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub ty: DescriptorType,
+    /// Number of descriptors, for arrays
+    pub count: usize,
+}
+
+/// The interface of a shader module.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShaderReflection {
+    /// Stages of the module's entry points
+    pub stages: ShaderStageFlags,
+    pub bindings: Vec<ReflectedBinding>,
+    /// Size of the push constant block in bytes
+    pub push_constant_size: u32,
+    /// Locations and formats of the vertex inputs
+    /// of a vertex shader, in order of location
+    pub vertex_inputs: Vec<(u32, Format)>,
+}
+
+/// The types reflection cares about.
+#[derive(Clone, Debug)]
+enum Type {
+    Int { width: u32, signed: bool },
+    Float { width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array { element: u32, length: u32 },
+    RuntimeArray { element: u32 },
+    Struct { members: Vec<u32> },
+    Pointer { ty: u32 },
+}
+
+/// Everything read from a module, by result ID.
+struct Module {
+    stages: ShaderStageFlags,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    /// Decorations of each ID, with their first literal
+    decorations: HashMap<u32, Vec<(u32, u32)>>,
+    /// Decorations of each struct member
+    member_decorations: HashMap<(u32, u32), Vec<(u32, u32)>>,
+    /// Result ID, pointer type and storage class of each variable
+    variables: Vec<(u32, u32, u32)>,
+}
+
+impl Module {
+    fn new() -> Module {
+        Module {
+            stages: ShaderStageFlags::empty(),
+            types: HashMap::new(),
+            constants: HashMap::new(),
+            decorations: HashMap::new(),
+            member_decorations: HashMap::new(),
+            variables: Vec::new(),
+        }
+    }
+
+    fn decoration(&self, id: u32, decoration: u32) -> Option<u32> {
+        self.decorations
+            .get(&id)
+            .and_then(|list| list.iter().find(|&&(d, _)| d == decoration))
+            .map(|&(_, value)| value)
+    }
+
+    fn member_decoration(&self, id: u32, member: u32, decoration: u32) -> Option<u32> {
+        self.member_decorations
+            .get(&(id, member))
+            .and_then(|list| list.iter().find(|&&(d, _)| d == decoration))
+            .map(|&(_, value)| value)
+    }
+
+    /// Returns the type a pointer type points to.
+    fn pointee(&self, pointer: u32) -> Option<u32> {
+        match self.types.get(&pointer) {
+            Some(&Type::Pointer { ty }) => Some(ty),
+            _ => None,
+        }
+    }
+
+    /// Returns the size in bytes of a type inside a block.
+    fn size(&self, id: u32, matrix_stride: Option<u32>) -> u32 {
+        match self.types.get(&id) {
+            Some(&Type::Int { width, .. }) | Some(&Type::Float { width }) => width / 8,
+            Some(&Type::Vector { component, count }) => self.size(component, None) * count,
+            Some(&Type::Matrix { column, count }) => {
+                matrix_stride.unwrap_or_else(|| self.size(column, None)) * count
+            }
+            Some(&Type::Array { element, length }) => {
+                let stride = self
+                    .decoration(id, ARRAY_STRIDE)
+                    .unwrap_or_else(|| self.size(element, None));
+                stride * length
+            }
+            Some(&Type::Struct { ref members }) => (0..members.len() as u32)
+                .map(|member| {
+                    let offset = self.member_decoration(id, member, OFFSET).unwrap_or(0);
+                    let stride = self.member_decoration(id, member, MATRIX_STRIDE);
+                    offset + self.size(members[member as usize], stride)
+                })
+                .max()
+                .unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    /// Returns the descriptor type and count of
+    /// a variable of type `ty` in `storage_class`.
+    fn descriptor(&self, ty: u32, storage_class: u32) -> Option<(DescriptorType, usize)> {
+        match *self.types.get(&ty)? {
+            Type::Array { element, length } => self
+                .descriptor(element, storage_class)
+                .map(|(ty, count)| (ty, count * length as usize)),
+            Type::RuntimeArray { element } => self.descriptor(element, storage_class),
+            Type::Struct { .. } => match storage_class {
+                STORAGE_BUFFER => Some((DescriptorType::StorageBuffer, 1)),
+                _ if self.decoration(ty, BUFFER_BLOCK).is_some() => {
+                    Some((DescriptorType::StorageBuffer, 1))
+                }
+                _ if self.decoration(ty, BLOCK).is_some() => {
+                    Some((DescriptorType::UniformBuffer, 1))
+                }
+                _ => None,
+            },
+            Type::SampledImage => Some((DescriptorType::CombinedImageSampler, 1)),
+            Type::Sampler => Some((DescriptorType::Sampler, 1)),
+            Type::Image { dim, sampled } => Some((
+                match (dim == DIM_BUFFER, sampled) {
+                    (true, 2) => DescriptorType::StorageTexelBuffer,
+                    (true, _) => DescriptorType::UniformTexelBuffer,
+                    (false, 2) => DescriptorType::StorageImage,
+                    (false, _) => DescriptorType::SampledImage,
+                },
+                1,
+            )),
+            _ => None,
+        }
+    }
+
+    /// Returns the vertex attribute format of an input of type `ty`.
+    fn format(&self, ty: u32) -> Option<Format> {
+        let (component, count) = match *self.types.get(&ty)? {
+            Type::Vector { component, count } => (component, count),
+            _ => (ty, 1),
+        };
+        let format = match (self.types.get(&component)?, count) {
+            (&Type::Float { width: 32 }, 1) => Format::R32Float,
+            (&Type::Float { width: 32 }, 2) => Format::Rg32Float,
+            (&Type::Float { width: 32 }, 3) => Format::Rgb32Float,
+            (&Type::Float { width: 32 }, 4) => Format::Rgba32Float,
+            (
+                &Type::Int {
+                    width: 32,
+                    signed: true,
+                },
+                1,
+            ) => Format::R32Int,
+            (
+                &Type::Int {
+                    width: 32,
+                    signed: true,
+                },
+                2,
+            ) => Format::Rg32Int,
+            (
+                &Type::Int {
+                    width: 32,
+                    signed: true,
+                },
+                3,
+            ) => Format::Rgb32Int,
+            (
+                &Type::Int {
+                    width: 32,
+                    signed: true,
+                },
+                4,
+            ) => Format::Rgba32Int,
+            (
+                &Type::Int {
+                    width: 32,
+                    signed: false,
+                },
+                1,
+            ) => Format::R32Uint,
+            (
+                &Type::Int {
+                    width: 32,
+                    signed: false,
+                },
+                2,
+            ) => Format::Rg32Uint,
+            (
+                &Type::Int {
+                    width: 32,
+                    signed: false,
+                },
+                3,
+            ) => Format::Rgb32Uint,
+            (
+                &Type::Int {
+                    width: 32,
+                    signed: false,
+                },
+                4,
+            ) => Format::Rgba32Uint,
+            _ => return None,
+        };
+        Some(format)
+    }
+}
+
+/// Reads the interface of a SPIR-V module.
+pub fn reflect(spirv: &[u8]) -> Result<ShaderReflection, ReflectError> {
+    if spirv.len() % 4 != 0 {
+        return Err(ReflectError::Truncated);
+    }
+    let words = spirv
+        .chunks(4)
+        .map(|bytes| {
+            u32::from(bytes[0])
+                | u32::from(bytes[1]) << 8
+                | u32::from(bytes[2]) << 16
+                | u32::from(bytes[3]) << 24
+        })
+        .collect::<Vec<_>>();
+    reflect_words(&words)
+}
+
+fn reflect_words(words: &[u32]) -> Result<ShaderReflection, ReflectError> {
+    if words.len() < HEADER_WORDS || words[0] != MAGIC {
+        return Err(ReflectError::InvalidMagic);
+    }
+    let module = parse(&words[HEADER_WORDS..])?;
+
+    let mut reflection = ShaderReflection::new(module.stages);
+    for &(id, pointer, storage_class) in &module.variables {
+        let ty = match module.pointee(pointer) {
+            Some(ty) => ty,
+            None => continue,
+        };
+        let name = || format!("variable %{}", id);
+        match storage_class {
+            UNIFORM_CONSTANT | UNIFORM | STORAGE_BUFFER => {
+                let (descriptor_type, count) = module
+                    .descriptor(ty, storage_class)
+                    .ok_or_else(|| ReflectError::UnsupportedType(name()))?;
+                reflection.bindings.push(ReflectedBinding {
+                    set: module.decoration(id, DESCRIPTOR_SET).unwrap_or(0),
+                    binding: module.decoration(id, BINDING).unwrap_or(0),
+                    ty: descriptor_type,
+                    count,
+                });
+            }
+            PUSH_CONSTANT => {
+                reflection.push_constant_size = module.size(ty, None);
+            }
+            INPUT if module.stages.contains(ShaderStageFlags::VERTEX) => {
+                if module.decoration(id, BUILT_IN).is_some() {
+                    continue;
+                }
+                if let Some(location) = module.decoration(id, LOCATION) {
+                    let format = module
+                        .format(ty)
+                        .ok_or_else(|| ReflectError::UnsupportedType(name()))?;
+                    reflection.vertex_inputs.push((location, format));
+                }
+            }
+            _ => (),
+        }
+    }
+    reflection
+        .bindings
+        .sort_by_key(|binding| (binding.set, binding.binding));
+    reflection
+        .vertex_inputs
+        .sort_by_key(|&(location, _)| location);
+    Ok(reflection)
+}
+
+/// Reads the instructions after the header.
+fn parse(mut words: &[u32]) -> Result<Module, ReflectError> {
+    let mut module = Module::new();
+    while !words.is_empty() {
+        let count = (words[0] >> 16) as usize;
+        if count == 0 || count > words.len() {
+            return Err(ReflectError::Truncated);
+        }
+        let (op, operands) = (words[0] & 0xffff, &words[1..count]);
+        words = &words[count..];
+
+        let operand = |i: usize| operands.get(i).cloned().ok_or(ReflectError::Truncated);
+        match op {
+            OP_ENTRY_POINT => {
+                module.stages |= match operand(0)? {
+                    VERTEX => ShaderStageFlags::VERTEX,
+                    FRAGMENT => ShaderStageFlags::FRAGMENT,
+                    _ => ShaderStageFlags::empty(),
+                }
+            }
+            OP_DECORATE => {
+                let value = operands.get(2).cloned().unwrap_or(0);
+                module
+                    .decorations
+                    .entry(operand(0)?)
+                    .or_insert_with(Vec::new)
+                    .push((operand(1)?, value));
+            }
+            OP_MEMBER_DECORATE => {
+                let value = operands.get(3).cloned().unwrap_or(0);
+                module
+                    .member_decorations
+                    .entry((operand(0)?, operand(1)?))
+                    .or_insert_with(Vec::new)
+                    .push((operand(2)?, value));
+            }
+            OP_CONSTANT => {
+                module.constants.insert(operand(1)?, operand(2)?);
+            }
+            OP_VARIABLE => module
+                .variables
+                .push((operand(1)?, operand(0)?, operand(2)?)),
+            _ => {
+                let ty = match op {
+                    OP_TYPE_INT => Type::Int {
+                        width: operand(1)?,
+                        signed: operand(2)? != 0,
+                    },
+                    OP_TYPE_FLOAT => Type::Float { width: operand(1)? },
+                    OP_TYPE_VECTOR => Type::Vector {
+                        component: operand(1)?,
+                        count: operand(2)?,
+                    },
+                    OP_TYPE_MATRIX => Type::Matrix {
+                        column: operand(1)?,
+                        count: operand(2)?,
+                    },
+                    OP_TYPE_IMAGE => Type::Image {
+                        dim: operand(2)?,
+                        sampled: operand(6)?,
+                    },
+                    OP_TYPE_SAMPLER => Type::Sampler,
+                    OP_TYPE_SAMPLED_IMAGE => Type::SampledImage,
+                    OP_TYPE_ARRAY => Type::Array {
+                        element: operand(1)?,
+                        // Array lengths are constants, declared earlier
+                        length: module.constants.get(&operand(2)?).cloned().unwrap_or(1),
+                    },
+                    OP_TYPE_RUNTIME_ARRAY => Type::RuntimeArray {
+                        element: operand(1)?,
+                    },
+                    OP_TYPE_STRUCT => Type::Struct {
+                        members: operands.iter().skip(1).cloned().collect(),
+                    },
+                    OP_TYPE_POINTER => Type::Pointer { ty: operand(2)? },
+                    _ => continue,
+                };
+                module.types.insert(operand(0)?, ty);
+            }
+        }
+    }
+    Ok(module)
+}
+
+impl ShaderReflection {
+    fn new(stages: ShaderStageFlags) -> ShaderReflection {
+        ShaderReflection {
+            stages,
+            bindings: Vec::new(),
+            push_constant_size: 0,
+            vertex_inputs: Vec::new(),
+        }
+    }
+
+    /// Combines the interfaces of the shaders of a pipeline.
+    /// Bindings used by several shaders are merged.
+    pub fn merge(shaders: &[ShaderReflection]) -> ShaderReflection {
+        let mut merged = ShaderReflection::new(ShaderStageFlags::empty());
+        for shader in shaders {
+            merged.stages |= shader.stages;
+            merged.push_constant_size = merged.push_constant_size.max(shader.push_constant_size);
+            if !shader.vertex_inputs.is_empty() {
+                merged.vertex_inputs = shader.vertex_inputs.clone();
+            }
+            for binding in &shader.bindings {
+                if !merged.bindings.contains(binding) {
+                    merged.bindings.push(binding.clone());
+                }
+            }
+        }
+        merged
+            .bindings
+            .sort_by_key(|binding| (binding.set, binding.binding));
+        merged
+    }
+
+    /// Returns the bindings of descriptor set 0, visible to
+    /// every stage which uses them in `shaders`.
+    pub fn layout_bindings(shaders: &[ShaderReflection]) -> Vec<DescriptorSetLayoutBinding> {
+        let merged = ShaderReflection::merge(shaders);
+        merged
+            .bindings
+            .iter()
+            .filter(|binding| {
+                if binding.set != 0 {
+                    warn!(
+                        "Ignoring binding {} of descriptor set {}; only set 0 is supported",
+                        binding.binding, binding.set
+                    );
+                }
+                binding.set == 0
+            })
+            .map(|binding| DescriptorSetLayoutBinding {
+                binding: binding.binding,
+                ty: binding.ty,
+                count: binding.count,
+                stage_flags: shaders
+                    .iter()
+                    .filter(|shader| shader.bindings.contains(binding))
+                    .fold(ShaderStageFlags::empty(), |stages, shader| {
+                        stages | shader.stages
+                    }),
+                immutable_samplers: false,
+            })
+            .collect()
+    }
+
+    /// Returns the push constant range of `shaders`
+    /// in 32-bit words, if any of them has one.
+    pub fn push_constant_range(
+        shaders: &[ShaderReflection],
+    ) -> Option<(ShaderStageFlags, Range<u32>)> {
+        let stages = shaders
+            .iter()
+            .filter(|shader| shader.push_constant_size > 0)
+            .fold(ShaderStageFlags::empty(), |stages, shader| {
+                stages | shader.stages
+            });
+        let size = ShaderReflection::merge(shaders).push_constant_size;
+        if size > 0 {
+            Some((stages, 0..(size + 3) / 4))
+        } else {
+            None
+        }
+    }
+
+    /// Returns a vertex buffer with the vertex inputs packed
+    /// one after another in order of location, and their
+    /// attributes. The vertex struct must be laid out the same.
+    pub fn vertex_layout(&self) -> (VertexBufferDesc, Vec<AttributeDesc>) {
+        let mut offset = 0;
+        let attributes = self
+            .vertex_inputs
+            .iter()
+            .map(|&(location, format)| {
+                let attribute = AttributeDesc {
+                    location,
+                    binding: 0,
+                    element: Element { format, offset },
+                };
+                offset += u32::from(format.surface_desc().bits) / 8;
+                attribute
+            })
+            .collect();
+        let desc = VertexBufferDesc {
+            binding: 0,
+            stride: offset,
+            rate: 0,
+        };
+        (desc, attributes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inst(op: u32, operands: &[u32]) -> Vec<u32> {
+        let mut words = vec![(operands.len() as u32 + 1) << 16 | op];
+        words.extend_from_slice(operands);
+        words
+    }
+
+    /// A vertex shader with `layout(location = 0) in vec3`,
+    /// `layout(location = 1) in vec2`, a uniform block at
+    /// binding 1 and a push constant block with a vec4 at
+    /// offset 16.
+    fn vertex_shader() -> Vec<u32> {
+        let mut words = vec![MAGIC, 0x0001_0000, 0, 20, 0];
+        for inst in &[
+            inst(OP_ENTRY_POINT, &[VERTEX, 1, 0x6e69_616d, 0]),
+            inst(OP_DECORATE, &[10, LOCATION, 0]),
+            inst(OP_DECORATE, &[11, LOCATION, 1]),
+            inst(OP_DECORATE, &[6, BLOCK]),
+            inst(OP_DECORATE, &[12, BINDING, 1]),
+            inst(OP_DECORATE, &[12, DESCRIPTOR_SET, 0]),
+            inst(OP_MEMBER_DECORATE, &[6, 0, OFFSET, 16]),
+            inst(OP_TYPE_FLOAT, &[2, 32]),
+            inst(OP_TYPE_VECTOR, &[3, 2, 3]),
+            inst(OP_TYPE_VECTOR, &[4, 2, 2]),
+            inst(OP_TYPE_VECTOR, &[5, 2, 4]),
+            inst(OP_TYPE_STRUCT, &[6, 5]),
+            inst(OP_TYPE_POINTER, &[7, INPUT, 3]),
+            inst(OP_TYPE_POINTER, &[8, INPUT, 4]),
+            inst(OP_TYPE_POINTER, &[9, UNIFORM, 6]),
+            inst(OP_TYPE_POINTER, &[13, PUSH_CONSTANT, 6]),
+            inst(OP_VARIABLE, &[8, 11, INPUT]),
+            inst(OP_VARIABLE, &[7, 10, INPUT]),
+            inst(OP_VARIABLE, &[9, 12, UNIFORM]),
+            inst(OP_VARIABLE, &[13, 14, PUSH_CONSTANT]),
+        ] {
+            words.extend_from_slice(inst);
+        }
+        words
+    }
+
+    #[test]
+    fn reflects_vertex_shader() {
+        let reflection = reflect_words(&vertex_shader()).unwrap();
+        assert_eq!(reflection.stages, ShaderStageFlags::VERTEX);
+        assert_eq!(
+            reflection.bindings,
+            vec![ReflectedBinding {
+                set: 0,
+                binding: 1,
+                ty: DescriptorType::UniformBuffer,
+                count: 1,
+            }]
+        );
+        assert_eq!(reflection.push_constant_size, 32);
+        assert_eq!(
+            reflection.vertex_inputs,
+            vec![(0, Format::Rgb32Float), (1, Format::Rg32Float)]
+        );
+        assert_eq!(
+            ShaderReflection::push_constant_range(&[reflection]),
+            Some((ShaderStageFlags::VERTEX, 0..8))
+        );
+    }
+
+    #[test]
+    fn invalid_modules() {
+        assert_eq!(reflect(&[1, 2, 3]), Err(ReflectError::Truncated));
+        assert_eq!(reflect(&[0; 20]), Err(ReflectError::InvalidMagic));
+        let mut words = vertex_shader();
+        words.pop();
+        assert_eq!(reflect_words(&words), Err(ReflectError::Truncated));
+    }
+}
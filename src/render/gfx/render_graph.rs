@@ -0,0 +1,243 @@
+//! Running render graphs on the gfx backend.
+//!
+//! `prepare_graph()` compiles a graph and makes sure the images
+//! behind its transient attachments exist. The images are kept
+//! between frames and only recreated when a graph needs
+//! different ones. Before recording each step, the barriers
+//! from `graph_barriers()` are recorded with `pipeline_barrier()`.
+use super::*;
+use gfx_hal::memory::Barrier;
+use render::graph::{
+    self, AttachmentDesc, AttachmentId, AttachmentKind, AttachmentState, CompiledGraph, RenderGraph,
+};
+use std::ops::Range;
+
+/// The images behind the transient attachments of render graphs.
+pub struct GraphImages<B: Backend> {
+    images: Vec<(AttachmentDesc, ImageMem<B>)>,
+}
+
+impl<B: Backend> GraphImages<B> {
+    pub fn new() -> GraphImages<B> {
+        GraphImages { images: Vec::new() }
+    }
+
+    /// Creates the images `graph` needs, keeping
+    /// existing images whose descriptions match.
+    pub fn prepare(
+        &mut self,
+        device: &B::Device,
+        allocator: &MemoryAllocator<B>,
+        graph: &CompiledGraph,
+        color_format: Format,
+        depth_format: Format,
+    ) -> Result<(), RenderError> {
+        while self.images.len() > graph.image_descs.len() {
+            let (_, image) = self.images.pop().unwrap();
+            destroy_image(device, image);
+        }
+
+        for (index, &desc) in graph.image_descs.iter().enumerate() {
+            if self
+                .images
+                .get(index)
+                .map_or(false, |&(old, _)| old == desc)
+            {
+                continue;
+            }
+            let (format, usage, aspects) = match desc.kind {
+                AttachmentKind::Color => (
+                    color_format,
+                    image::Usage::COLOR_ATTACHMENT | image::Usage::SAMPLED,
+                    Aspects::COLOR,
+                ),
+                AttachmentKind::Depth => (
+                    depth_format,
+                    image::Usage::DEPTH_STENCIL_ATTACHMENT | image::Usage::SAMPLED,
+                    Aspects::DEPTH,
+                ),
+            };
+            let extent = Extent {
+                width: desc.width,
+                height: desc.height,
+                depth: 1,
+            };
+            let image = factory::create_image::<B>(
+                device,
+                allocator,
+                extent,
+                1,
+                desc.samples,
+                format,
+                usage,
+                aspects,
+            )
+            .map_err(RenderError::AttachmentCreation)?;
+
+            if index < self.images.len() {
+                let (_, old) = std::mem::replace(&mut self.images[index], (desc, image));
+                destroy_image(device, old);
+            } else {
+                self.images.push((desc, image));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the image behind a transient attachment of `graph`.
+    pub fn get(&self, graph: &CompiledGraph, attachment: AttachmentId) -> Option<&ImageMem<B>> {
+        graph
+            .image(attachment)
+            .and_then(|index| self.images.get(index))
+            .map(|&(_, ref image)| image)
+    }
+
+    pub fn destroy(self, device: &B::Device) {
+        for (_, image) in self.images {
+            destroy_image(device, image);
+        }
+    }
+}
+
+fn destroy_image<B: Backend>(device: &B::Device, image: ImageMem<B>) {
+    device.destroy_image_view(image.view);
+    device.destroy_image(image.image);
+    image.memory.free();
+}
+
+/// Returns the access and layout of an attachment
+/// in `state`, and the stages which use it.
+fn sync_state(state: AttachmentState) -> (Access, Layout, PipelineStage) {
+    match state {
+        AttachmentState::Undefined => (
+            Access::empty(),
+            Layout::Undefined,
+            PipelineStage::TOP_OF_PIPE,
+        ),
+        AttachmentState::ColorOutput => (
+            Access::COLOR_ATTACHMENT_READ | Access::COLOR_ATTACHMENT_WRITE,
+            Layout::ColorAttachmentOptimal,
+            PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+        ),
+        AttachmentState::DepthOutput => (
+            Access::DEPTH_STENCIL_ATTACHMENT_READ | Access::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            Layout::DepthStencilAttachmentOptimal,
+            PipelineStage::EARLY_FRAGMENT_TESTS | PipelineStage::LATE_FRAGMENT_TESTS,
+        ),
+        AttachmentState::Sampled => (
+            Access::SHADER_READ,
+            Layout::ShaderReadOnlyOptimal,
+            PipelineStage::FRAGMENT_SHADER,
+        ),
+        AttachmentState::Present => (
+            Access::empty(),
+            Layout::Present,
+            PipelineStage::BOTTOM_OF_PIPE,
+        ),
+    }
+}
+
+impl<B: Backend> RenderContext<B> {
+    /// Compiles `graph`, creating the images it needs.
+    /// Transient attachments use the surface's color
+    /// format and the depth buffer's format.
+    pub fn prepare_graph(&mut self, graph: &RenderGraph) -> Result<CompiledGraph, RenderError> {
+        let compiled = graph.compile().map_err(RenderError::Graph)?;
+        self.graph_images.prepare(
+            &self.device,
+            &self.allocator,
+            &compiled,
+            self.color_format,
+            self.depth_format,
+        )?;
+        Ok(compiled)
+    }
+
+    /// Converts the barriers of a step of `compiled` to image
+    /// barriers, returning them with the stages they wait
+    /// between, or `None` if there are none. `imported` holds
+    /// the images of imported attachments.
+    pub fn graph_barriers<'a>(
+        &'a self,
+        graph: &RenderGraph,
+        compiled: &CompiledGraph,
+        barriers: &[graph::Barrier],
+        imported: &[(AttachmentId, &'a B::Image)],
+    ) -> Option<(Range<PipelineStage>, Vec<Barrier<'a, B>>)> {
+        if barriers.is_empty() {
+            return None;
+        }
+
+        let mut stages = PipelineStage::empty()..PipelineStage::empty();
+        let image_barriers = barriers
+            .iter()
+            .map(|barrier| {
+                let target = match self.graph_images.get(compiled, barrier.attachment) {
+                    Some(image) => &image.image,
+                    None => imported
+                        .iter()
+                        .find(|&&(id, _)| id == barrier.attachment)
+                        .map(|&(_, image)| image)
+                        .unwrap_or_else(|| {
+                            panic!(
+                                "attachment {} has no image",
+                                graph.attachment_name(barrier.attachment)
+                            )
+                        }),
+                };
+                let aspects = match graph.attachment_desc(barrier.attachment).kind {
+                    AttachmentKind::Color => Aspects::COLOR,
+                    AttachmentKind::Depth => factory::depth_aspects(self.depth_format),
+                };
+
+                let (from_access, from_layout, from_stage) = sync_state(barrier.from);
+                let (to_access, to_layout, to_stage) = sync_state(barrier.to);
+                stages.start |= from_stage;
+                stages.end |= to_stage;
+                Barrier::Image {
+                    states: (from_access, from_layout)..(to_access, to_layout),
+                    target,
+                    range: SubresourceRange {
+                        aspects,
+                        levels: 0..1,
+                        layers: 0..1,
+                    },
+                }
+            })
+            .collect();
+        Some((stages, image_barriers))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use render::graph::PassDesc;
+
+    #[test]
+    fn alias_waits_for_reads() {
+        let mut graph = RenderGraph::new();
+        let output = graph.import_attachment(
+            "output",
+            AttachmentDesc::color(64, 64),
+            AttachmentState::Present,
+        );
+        let scene = graph.create_attachment("scene", AttachmentDesc::color(64, 64));
+        let bloom = graph.create_attachment("bloom", AttachmentDesc::color(64, 64));
+        let blurred = graph.create_attachment("blurred", AttachmentDesc::color(64, 64));
+        graph.add_pass(PassDesc::new("main").with_write(scene));
+        graph.add_pass(PassDesc::new("bright").with_read(scene).with_write(bloom));
+        graph.add_pass(PassDesc::new("blur").with_read(bloom).with_write(blurred));
+        graph.add_pass(PassDesc::new("post").with_read(blurred).with_write(output));
+
+        let compiled = graph.compile().unwrap();
+        let alias = compiled.steps[2]
+            .barriers
+            .iter()
+            .find(|barrier| barrier.attachment == blurred)
+            .unwrap();
+        let (_, layout, stage) = sync_state(alias.from);
+        assert_eq!(layout, Layout::ShaderReadOnlyOptimal);
+        assert_eq!(stage, PipelineStage::FRAGMENT_SHADER);
+    }
+}
@@ -0,0 +1,121 @@
+//! Resources owned by the context and referred to by handle.
+//!
+//! Meshes, textures, render targets and buffers created here
+//! are kept in the context and destroyed with it, unless they
+//! are destroyed earlier. Using a handle after its resource was
+//! destroyed returns a `StaleHandle` error instead of using
+//! whichever resource took its place.
+//!
+//! Destroying a resource doesn't wait for frames using it, so
+//! it should only be done between frames.
+use super::*;
+
+impl<B: Backend> RenderContext<B> {
+    /// Uploads a mesh, returning its handle.
+    pub fn create_mesh(&mut self, vertices: &[Vertex], indices: Indices) -> MeshHandle {
+        let mesh = IndexedMesh::new(&self.device_handle(), vertices, indices);
+        self.meshes.insert(mesh)
+    }
+
+    pub fn mesh(&self, handle: MeshHandle) -> Result<&IndexedMesh<B>, StaleHandle> {
+        self.meshes.get(handle)
+    }
+
+    /// Destroys a mesh, making its handle stale.
+    pub fn destroy_mesh(&mut self, handle: MeshHandle) -> Result<(), StaleHandle> {
+        self.meshes.remove(handle)?.destroy(&self.device);
+        Ok(())
+    }
+
+    /// Creates a texture from RGBA pixel data, with mipmaps
+    /// if `mipmaps` is set, and returns its handle.
+    pub fn create_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+        mipmaps: bool,
+    ) -> Result<TextureHandle, TextureError> {
+        let texture = if mipmaps {
+            Texture::with_mipmaps(self, width, height, pixels)?
+        } else {
+            Texture::new(self, width, height, pixels)?
+        };
+        Ok(self.textures.insert(texture))
+    }
+
+    pub fn texture(&self, handle: TextureHandle) -> Result<&Texture<B>, StaleHandle> {
+        self.textures.get(handle)
+    }
+
+    /// Destroys a texture, making its handle stale.
+    pub fn destroy_texture(&mut self, handle: TextureHandle) -> Result<(), StaleHandle> {
+        self.textures.remove(handle)?.destroy(&self.device);
+        Ok(())
+    }
+
+    /// Creates an image in the color format of the swapchain
+    /// which passes can render to, sample and copy from.
+    pub fn create_target(&mut self, width: u32, height: u32) -> Result<TargetHandle, RenderError> {
+        let target = factory::create_image::<B>(
+            &self.device,
+            &self.allocator,
+            Extent {
+                width,
+                height,
+                depth: 1,
+            },
+            1,
+            1,
+            self.color_format,
+            image::Usage::COLOR_ATTACHMENT | image::Usage::SAMPLED | image::Usage::TRANSFER_SRC,
+            Aspects::COLOR,
+        )
+        .map_err(RenderError::AttachmentCreation)?;
+        Ok(self.targets.insert(target))
+    }
+
+    pub fn target(&self, handle: TargetHandle) -> Result<&ImageMem<B>, StaleHandle> {
+        self.targets.get(handle)
+    }
+
+    /// Destroys a render target, making its handle stale.
+    pub fn destroy_target(&mut self, handle: TargetHandle) -> Result<(), StaleHandle> {
+        let target = self.targets.remove(handle)?;
+        self.device.destroy_image_view(target.view);
+        self.device.destroy_image(target.image);
+        target.memory.free();
+        Ok(())
+    }
+
+    /// Creates a device local buffer of `size` bytes, such as
+    /// for compute output, and returns its handle. The buffer
+    /// can also be copied from, so it can be read back with
+    /// `read_buffer()`.
+    pub fn create_buffer(&mut self, size: u64, usage: Usage) -> BufferHandle {
+        let (buffer, memory) = buffer_util::empty_buffer::<B, u8>(
+            &self.device,
+            &self.allocator,
+            Properties::DEVICE_LOCAL,
+            usage | Usage::TRANSFER_SRC,
+            size as usize,
+        );
+        self.buffers.insert(BufferMem {
+            buffer,
+            memory,
+            element_count: size as usize,
+        })
+    }
+
+    pub fn buffer(&self, handle: BufferHandle) -> Result<&BufferMem<B>, StaleHandle> {
+        self.buffers.get(handle)
+    }
+
+    /// Destroys a buffer, making its handle stale.
+    pub fn destroy_buffer(&mut self, handle: BufferHandle) -> Result<(), StaleHandle> {
+        let buffer = self.buffers.remove(handle)?;
+        self.device.destroy_buffer(buffer.buffer);
+        buffer.memory.free();
+        Ok(())
+    }
+}
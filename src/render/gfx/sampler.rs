@@ -0,0 +1,163 @@
+//! Samplers, which control how textures are filtered and
+//! what happens to coordinates outside of them.
+//!
+//! Samplers are cached by the `RenderContext`, so every
+//! texture using the same `SamplerDesc` shares one sampler.
+use super::*;
+use gfx_hal::image::{Anisotropic, Filter, SamplerInfo, WrapMode};
+
+/// How texels are combined when a texture is sampled.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum FilterMode {
+    Nearest,
+    Linear,
+}
+
+/// What happens to texture coordinates outside of 0 to 1.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum AddressMode {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+/// Describes a sampler.
+///
+/// ```ignore
+/// let sampler = ctx.sampler(SamplerDesc::new().with_anisotropy(16));
+/// ```
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct SamplerDesc {
+    pub min_filter: FilterMode,
+    pub mag_filter: FilterMode,
+    /// Filtering between mipmap levels
+    pub mip_filter: FilterMode,
+    /// Address modes for the u, v and w coordinates
+    pub address: (AddressMode, AddressMode, AddressMode),
+    /// Maximum anisotropy, or 1 to disable anisotropic filtering
+    pub anisotropy: u8,
+}
+
+impl Default for SamplerDesc {
+    /// Trilinear filtering with repeating coordinates.
+    fn default() -> Self {
+        SamplerDesc {
+            min_filter: FilterMode::Linear,
+            mag_filter: FilterMode::Linear,
+            mip_filter: FilterMode::Linear,
+            address: (
+                AddressMode::Repeat,
+                AddressMode::Repeat,
+                AddressMode::Repeat,
+            ),
+            anisotropy: 1,
+        }
+    }
+}
+
+impl SamplerDesc {
+    pub fn new() -> SamplerDesc {
+        SamplerDesc::default()
+    }
+
+    /// Sets the minification and magnification filters.
+    pub fn with_filter(mut self, filter: FilterMode) -> Self {
+        self.min_filter = filter;
+        self.mag_filter = filter;
+        self
+    }
+
+    pub fn with_min_filter(mut self, filter: FilterMode) -> Self {
+        self.min_filter = filter;
+        self
+    }
+
+    pub fn with_mag_filter(mut self, filter: FilterMode) -> Self {
+        self.mag_filter = filter;
+        self
+    }
+
+    pub fn with_mip_filter(mut self, filter: FilterMode) -> Self {
+        self.mip_filter = filter;
+        self
+    }
+
+    /// Sets the address mode of every coordinate.
+    pub fn with_address_mode(mut self, mode: AddressMode) -> Self {
+        self.address = (mode, mode, mode);
+        self
+    }
+
+    /// Enables anisotropic filtering. The level is clamped
+    /// to the device limit when the sampler is created.
+    pub fn with_anisotropy(mut self, anisotropy: u8) -> Self {
+        self.anisotropy = anisotropy.max(1);
+        self
+    }
+
+    /// Returns this description with the anisotropy
+    /// lowered to what `features` supports.
+    pub fn supported(mut self, features: &DeviceFeatures) -> SamplerDesc {
+        let max = features.max_anisotropy.max(1.0) as u8;
+        self.anisotropy = self.anisotropy.min(max);
+        self
+    }
+
+    /// Converts to gfx-hal's sampler description.
+    fn info(&self) -> SamplerInfo {
+        let filter = |mode| match mode {
+            FilterMode::Nearest => Filter::Nearest,
+            FilterMode::Linear => Filter::Linear,
+        };
+        let wrap = |mode| match mode {
+            AddressMode::Repeat => WrapMode::Tile,
+            AddressMode::Clamp => WrapMode::Clamp,
+            AddressMode::Mirror => WrapMode::Mirror,
+        };
+
+        let mut info = SamplerInfo::new(filter(self.min_filter), wrap(self.address.0));
+        info.mag_filter = filter(self.mag_filter);
+        info.mip_filter = filter(self.mip_filter);
+        info.wrap_mode = (
+            wrap(self.address.0),
+            wrap(self.address.1),
+            wrap(self.address.2),
+        );
+        info.anisotropic = if self.anisotropy > 1 {
+            Anisotropic::On(self.anisotropy)
+        } else {
+            Anisotropic::Off
+        };
+        info
+    }
+}
+
+impl<B: Backend> RenderContext<B> {
+    /// Returns a sampler matching `desc`, creating it
+    /// the first time each description is used.
+    pub fn sampler(&mut self, desc: SamplerDesc) -> &B::Sampler {
+        let desc = desc.supported(&self.features);
+        let device = &self.device;
+        self.samplers.entry(desc).or_insert_with(|| {
+            device
+                .create_sampler(desc.info())
+                .expect("failed to create sampler")
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anisotropy_is_clamped() {
+        let mut features = DeviceFeatures::default();
+        let desc = SamplerDesc::new().with_anisotropy(16);
+        assert_eq!(desc.supported(&features).anisotropy, 1);
+
+        features.max_anisotropy = 8.0;
+        assert_eq!(desc.supported(&features).anisotropy, 8);
+        assert_eq!(desc.with_anisotropy(4).supported(&features).anisotropy, 4);
+    }
+}
@@ -0,0 +1,273 @@
+//! Secondary windows, for tools such as the level
+//! editor and debug views.
+//!
+//! Each secondary window has its own surface, swapchain, depth
+//! buffer and frame buffers, and shares the context's device,
+//! render pass and pipelines, so anything drawn in the main
+//! window can be drawn in it too. Its surface must support
+//! the main window's color format, which the render pass
+//! was created for.
+//!
+//! Secondary windows are created on the context's events loop,
+//! so their events arrive with the main window's, told apart by
+//! `id()`. They are rendered between frames, and each waits for
+//! its frame to finish, as `submit()` does.
+use super::*;
+use gfx_hal::{QueueFamily, Surface};
+use profile::millis_since;
+use std::time::Instant;
+
+/// An OS window rendered into with the context's device.
+pub struct SecondaryWindow<B: Backend> {
+    pub window: winit::Window,
+    pub surface: B::Surface,
+    pub swapchain: Option<B::Swapchain>,
+    /// Images of the swapchain, which are owned by it
+    pub images: Vec<B::Image>,
+    pub image_views: Vec<B::ImageView>,
+    pub frame_buffers: Vec<B::Framebuffer>,
+    pub depth: Option<ImageMem<B>>,
+    /// The multisampled color target, if MSAA is enabled
+    pub msaa: Option<ImageMem<B>>,
+    pub extent: Extent,
+    /// Semaphore to wait before drawing to the frame
+    pub semaphore: B::Semaphore,
+}
+
+impl SecondaryWindow<back::Backend> {
+    /// Opens a window on the context's events loop. Fails
+    /// if the context is headless, or the window's surface
+    /// can't be presented to with the context's device.
+    pub fn new(
+        ctx: &mut RenderContext<back::Backend>,
+        title: &str,
+        width: u32,
+        height: u32,
+    ) -> Result<SecondaryWindow<back::Backend>, RenderError> {
+        let window = match ctx.events_loop {
+            Some(ref events_loop) => winit::WindowBuilder::new()
+                .with_title(title)
+                .with_dimensions((width, height).into())
+                .build(events_loop)
+                .map_err(|err| RenderError::WindowCreation(err.to_string()))?,
+            None => {
+                return Err(RenderError::WindowCreation(
+                    "headless contexts have no events loop".to_string(),
+                ))
+            }
+        };
+        let surface = ctx.instance.create_surface(&window);
+
+        let family = ctx.queue_group.family();
+        let presentable = ctx.adapter.queue_families.iter().any(|queue_family| {
+            queue_family.id() == family && surface.supports_queue_family(queue_family)
+        });
+        if !presentable {
+            return Err(RenderError::NoSuitableAdapter);
+        }
+        let (_, formats, _) = surface.compatibility(&ctx.adapter.physical_device);
+        if let Some(formats) = formats {
+            if !formats.contains(&ctx.color_format) {
+                return Err(RenderError::NoSurfaceFormat);
+            }
+        }
+
+        let mut secondary = SecondaryWindow {
+            window,
+            surface,
+            swapchain: None,
+            images: Vec::new(),
+            image_views: Vec::new(),
+            frame_buffers: Vec::new(),
+            depth: None,
+            msaa: None,
+            extent: Extent {
+                width,
+                height,
+                depth: 1,
+            },
+            semaphore: ctx.device.create_semaphore(),
+        };
+        secondary.recreate_swapchain(ctx, "secondary window created")?;
+        Ok(secondary)
+    }
+
+    /// Returns the id of the window's events.
+    pub fn id(&self) -> winit::WindowId {
+        self.window.id()
+    }
+
+    /// Recreates the swapchain and the images sized to it at
+    /// the surface's current size, such as after the window
+    /// was resized. Nothing is recreated while the window is
+    /// minimized, since the surface has no size.
+    pub fn recreate_swapchain(
+        &mut self,
+        ctx: &mut RenderContext<back::Backend>,
+        reason: &str,
+    ) -> Result<(), RenderError> {
+        let start = Instant::now();
+        let (caps, _, _) = self.surface.compatibility(&ctx.adapter.physical_device);
+        let mut swapchain_config = SwapchainConfig::from_caps(&caps, ctx.color_format);
+        let extent = swapchain_config.extent.to_extent();
+        if extent.width == 0 || extent.height == 0 {
+            return Ok(());
+        }
+        swapchain_config.present_mode = ctx.present_mode;
+
+        // As for the main window, nothing is replaced if the
+        // attachments can't be created, and the new swapchain
+        // is kept if its frame buffers can't be
+        let (depth, msaa) = swapchain::create_attachments::<back::Backend>(
+            &ctx.device,
+            &ctx.allocator,
+            extent,
+            ctx.samples,
+            ctx.color_format,
+            ctx.depth_format,
+        )?;
+        self.destroy_images(&ctx.device);
+        self.depth = Some(depth);
+        self.msaa = msaa;
+        self.extent = extent;
+        let (swapchain, backbuffer) =
+            ctx.device
+                .create_swapchain(&mut self.surface, swapchain_config, self.swapchain.take());
+        self.swapchain = Some(swapchain);
+        let (images, image_views, frame_buffers) = swapchain::create_frame_buffers::<back::Backend>(
+            &ctx.device,
+            &ctx.render_pass,
+            backbuffer,
+            ctx.color_format,
+            &self.depth.as_ref().unwrap().view,
+            self.msaa.as_ref(),
+            extent,
+        )?;
+        self.images = images;
+        self.image_views = image_views;
+        self.frame_buffers = frame_buffers;
+
+        ctx.telemetry.record(RenderEvent {
+            kind: EventKind::SwapchainCreation,
+            reason: reason.to_string(),
+            millis: millis_since(start),
+            bytes: None,
+        });
+        Ok(())
+    }
+
+    /// Renders a frame into the window, cleared to `clear_color`,
+    /// with `record` recording its draws as an injected pass
+    /// would. The frame is skipped while the swapchain is
+    /// recreated if it no longer matches the surface, or
+    /// couldn't be created last time.
+    ///
+    /// Must be called between frames of the main window.
+    pub fn render<F>(
+        &mut self,
+        ctx: &mut RenderContext<back::Backend>,
+        camera: CameraUniforms,
+        clear_color: [f32; 4],
+        record: F,
+    ) -> Result<(), RenderError>
+    where
+        F: FnOnce(
+            &mut RenderPassInlineEncoder<back::Backend, Primary>,
+            &PassResources<back::Backend>,
+        ),
+    {
+        if self.swapchain.is_none() || self.frame_buffers.is_empty() {
+            return self.recreate_swapchain(ctx, "swapchain incomplete");
+        }
+        let acquired = self
+            .swapchain
+            .as_mut()
+            .unwrap()
+            .acquire_image(!0, FrameSync::Semaphore(&self.semaphore));
+        let frame_index: SwapImageIndex = match acquired {
+            Ok(index) => index,
+            Err(_) => return self.recreate_swapchain(ctx, "swapchain out of date"),
+        };
+
+        ctx.device.reset_fence(&ctx.frame_fence);
+        ctx.command_pool.reset();
+        let finished_command_buffer = {
+            let depth = self.depth.as_ref().unwrap();
+            let resources = PassResources {
+                camera,
+                extent: self.extent,
+                render_pass: &ctx.render_pass,
+                color: match self.msaa {
+                    Some(ref msaa) => Some(&msaa.view),
+                    None => self.image_views.get(frame_index as usize),
+                },
+                depth: &depth.view,
+                draws: &[],
+                culled_draws: &[],
+            };
+
+            let mut command_buffer = ctx.command_pool.acquire_command_buffer(false);
+            let viewport = viewport(&self.extent);
+            command_buffer.set_viewports(0, &[viewport.clone()]);
+            command_buffer.set_scissors(0, &[viewport.rect]);
+            {
+                let mut encoder = command_buffer.begin_render_pass_inline(
+                    &ctx.render_pass,
+                    &self.frame_buffers[frame_index as usize],
+                    viewport.rect,
+                    &[
+                        ClearValue::Color(ClearColor::Float(clear_color)),
+                        ClearValue::DepthStencil(ClearDepthStencil(
+                            ctx.depth_mode.clear_depth(),
+                            0,
+                        )),
+                    ],
+                );
+                record(&mut encoder, &resources);
+            }
+            command_buffer.finish()
+        };
+
+        let wait_semaphores = vec![(&self.semaphore, PipelineStage::COLOR_ATTACHMENT_OUTPUT)];
+        let submission = Submission::new()
+            .wait_on(&wait_semaphores)
+            .submit(vec![finished_command_buffer]);
+        ctx.queue_group.queues[0].submit(submission, Some(&ctx.frame_fence));
+        ctx.device.wait_for_fence(&ctx.frame_fence, !0);
+
+        let presented = self
+            .swapchain
+            .as_mut()
+            .unwrap()
+            .present(&mut ctx.queue_group.queues[0], frame_index, &[])
+            .is_ok();
+        if !presented {
+            self.recreate_swapchain(ctx, "swapchain out of date")?;
+        }
+        Ok(())
+    }
+
+    /// Closes the window and frees its swapchain and
+    /// images. Must be called before the context is destroyed.
+    pub fn destroy(mut self, device: &<back::Backend as Backend>::Device) {
+        self.destroy_images(device);
+        if let Some(swapchain) = self.swapchain {
+            device.destroy_swapchain(swapchain);
+        }
+        device.destroy_semaphore(self.semaphore);
+    }
+
+    /// Frees the images sized to the window, leaving the swapchain.
+    fn destroy_images(&mut self, device: &<back::Backend as Backend>::Device) {
+        for frame_buffer in self.frame_buffers.drain(..) {
+            device.destroy_framebuffer(frame_buffer);
+        }
+        for view in self.image_views.drain(..) {
+            device.destroy_image_view(view);
+        }
+        self.images.clear();
+        for image in self.msaa.take().into_iter().chain(self.depth.take()) {
+            image.destroy(device);
+        }
+    }
+}
@@ -0,0 +1,268 @@
+//! Images sized to the window, and recreating them.
+//!
+//! The swapchain, the depth buffer, the multisampled color
+//! target and the frame buffers all have the window's size.
+//! When the window is resized, or the swapchain no longer
+//! matches the surface, they are all destroyed and recreated
+//! at the surface's current size.
+//!
+//! Headless contexts render into an image of a fixed size,
+//! so they never recreate anything.
+use super::*;
+use gfx_hal::Surface;
+use profile::millis_since;
+use std::time::Instant;
+use winit::dpi::{LogicalPosition, LogicalSize};
+
+/// Creates the depth buffer and, if `samples` is more than
+/// one, the multisampled color target which is resolved
+/// into the swapchain image.
+pub fn create_attachments<B: Backend>(
+    device: &B::Device,
+    allocator: &MemoryAllocator<B>,
+    extent: Extent,
+    samples: u8,
+    color_format: Format,
+    depth_format: Format,
+) -> Result<(ImageMem<B>, Option<ImageMem<B>>), RenderError> {
+    let depth = factory::create_image::<B>(
+        device,
+        allocator,
+        extent,
+        1,
+        samples,
+        depth_format,
+        image::Usage::DEPTH_STENCIL_ATTACHMENT,
+        factory::depth_aspects(depth_format),
+    )
+    .map_err(RenderError::DepthBufferCreation)?;
+
+    let msaa = if samples > 1 {
+        Some(
+            factory::create_image::<B>(
+                device,
+                allocator,
+                extent,
+                1,
+                samples,
+                color_format,
+                image::Usage::COLOR_ATTACHMENT | image::Usage::TRANSIENT_ATTACHMENT,
+                Aspects::COLOR,
+            )
+            .map_err(RenderError::SwapchainCreation)?,
+        )
+    } else {
+        None
+    };
+    Ok((depth, msaa))
+}
+
+/// Creates a frame buffer drawing into `color`.
+pub fn create_frame_buffer<B: Backend>(
+    device: &B::Device,
+    render_pass: &B::RenderPass,
+    color: &B::ImageView,
+    depth: &B::ImageView,
+    msaa: Option<&ImageMem<B>>,
+    extent: Extent,
+) -> Result<B::Framebuffer, RenderError> {
+    let attachments = match msaa {
+        Some(msaa) => vec![&msaa.view, depth, color],
+        None => vec![color, depth],
+    };
+    device
+        .create_framebuffer(render_pass, attachments, extent)
+        .map_err(|err| RenderError::SwapchainCreation(format!("{:?}", err)))
+}
+
+/// Creates views and frame buffers of the images of
+/// `backbuffer`, returning the images, views and buffers.
+pub fn create_frame_buffers<B: Backend>(
+    device: &B::Device,
+    render_pass: &B::RenderPass,
+    backbuffer: Backbuffer<B>,
+    color_format: Format,
+    depth: &B::ImageView,
+    msaa: Option<&ImageMem<B>>,
+    extent: Extent,
+) -> Result<(Vec<B::Image>, Vec<B::ImageView>, Vec<B::Framebuffer>), RenderError> {
+    match backbuffer {
+        Backbuffer::Images(images) => {
+            let color_range = SubresourceRange {
+                aspects: Aspects::COLOR,
+                levels: 0..1,
+                layers: 0..1,
+            };
+            let image_views = images
+                .iter()
+                .map(|image| {
+                    device
+                        .create_image_view(
+                            image,
+                            ViewKind::D2,
+                            color_format,
+                            Swizzle::NO,
+                            color_range.clone(),
+                        )
+                        .map_err(|err| RenderError::SwapchainCreation(format!("{:?}", err)))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let frame_buffers = image_views
+                .iter()
+                .map(|view| {
+                    create_frame_buffer::<B>(device, render_pass, view, depth, msaa, extent)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok((images, image_views, frame_buffers))
+        }
+
+        // For OpenGL backend
+        Backbuffer::Framebuffer(fbo) => Ok((vec![], vec![], vec![fbo])),
+    }
+}
+
+impl<B: Backend> RenderContext<B> {
+    /// Recreates the swapchain and the images sized to it at
+    /// the surface's current size. `reason` is recorded with
+    /// the time it took. Nothing is recreated while the
+    /// window is minimized, since the surface has no size.
+    ///
+    /// Must be called between frames.
+    pub fn recreate_swapchain(&mut self, reason: &str) -> Result<(), RenderError> {
+        let surface = match self.surface {
+            Some(ref mut surface) => surface,
+            None => return Ok(()),
+        };
+        let start = Instant::now();
+        let (caps, _, _) = surface.compatibility(&self.adapter.physical_device);
+        let mut swapchain_config = SwapchainConfig::from_caps(&caps, self.color_format);
+        let extent = swapchain_config.extent.to_extent();
+        if extent.width == 0 || extent.height == 0 {
+            return Ok(());
+        }
+        swapchain_config.present_mode = self.present_mode;
+        // Frames are copied out for the overdraw statistics
+        // and captures, if the surface allows it
+        let readable = caps.usage.contains(image::Usage::TRANSFER_SRC);
+        if readable {
+            swapchain_config.image_usage |= image::Usage::TRANSFER_SRC;
+        }
+
+        // The attachments are created first, so nothing
+        // has been replaced yet if they can't be
+        let device = &self.device;
+        let (depth, msaa) = create_attachments::<B>(
+            device,
+            &self.allocator,
+            extent,
+            self.samples,
+            self.color_format,
+            self.depth_format,
+        )?;
+
+        // Every frame has finished, since `submit()` waits for
+        // its fence, so nothing still uses the old images
+        for frame_buffer in self.frame_buffers.drain(..) {
+            device.destroy_framebuffer(frame_buffer);
+        }
+        for view in self.image_views.drain(..) {
+            device.destroy_image_view(view);
+        }
+        self.swapchain_images.clear();
+        if let Some(old_msaa) = std::mem::replace(&mut self.msaa_image, msaa) {
+            device.destroy_image_view(old_msaa.view);
+            device.destroy_image(old_msaa.image);
+            old_msaa.memory.free();
+        }
+        let old_depth_view = std::mem::replace(&mut self.depth_image_view, depth.view);
+        device.destroy_image_view(old_depth_view);
+        let old_depth = std::mem::replace(&mut self.depth_image, depth.image);
+        device.destroy_image(old_depth);
+        std::mem::replace(&mut self.depth_image_memory, depth.memory).free();
+        self.extent = extent;
+
+        // The old swapchain is retired by the new one, which is
+        // kept even if its frame buffers can't be created, so
+        // the next frame tries again instead of going headless
+        let (swapchain, backbuffer) =
+            device.create_swapchain(surface, swapchain_config, self.swapchain.take());
+        self.swapchain = Some(swapchain);
+        self.frames_readable = readable;
+        let (images, image_views, frame_buffers) = create_frame_buffers::<B>(
+            device,
+            &self.render_pass,
+            backbuffer,
+            self.color_format,
+            &self.depth_image_view,
+            self.msaa_image.as_ref(),
+            extent,
+        )?;
+        self.swapchain_images = images;
+        self.image_views = image_views;
+        self.frame_buffers = frame_buffers;
+        self.fresh_images = vec![true; self.frame_buffers.len()];
+
+        self.telemetry.record(RenderEvent {
+            kind: EventKind::SwapchainCreation,
+            reason: reason.to_string(),
+            millis: millis_since(start),
+            bytes: None,
+        });
+        // Return the memory of the old images to the device
+        self.allocator.trim(device);
+        Ok(())
+    }
+
+    /// Switches the window to `mode`, putting it back where
+    /// it was when it returns to windowed mode, and recreates
+    /// the swapchain at its new size.
+    pub fn set_window_mode(&mut self, mode: WindowMode) -> Result<(), RenderError> {
+        if mode == self.window_mode.mode() {
+            return Ok(());
+        }
+        match self.window {
+            Some(ref window) => {
+                let restore = self.window_mode.switch(mode, window_rect(window));
+                apply_window_mode(window, mode, restore);
+            }
+            None => return Ok(()),
+        }
+        info!("Window mode: {}", mode);
+        self.recreate_swapchain(&format!("switched to {}", mode))
+    }
+}
+
+/// Returns where `window` is, if the platform can tell.
+fn window_rect(window: &winit::Window) -> Option<WindowRect> {
+    let position = window.get_outer_position()?;
+    let size = window.get_inner_size()?;
+    Some(WindowRect {
+        x: position.x,
+        y: position.y,
+        width: size.width,
+        height: size.height,
+    })
+}
+
+/// Makes `window` windowed at `restore`, or fullscreen on its current monitor.
+fn apply_window_mode(window: &winit::Window, mode: WindowMode, restore: Option<WindowRect>) {
+    match mode {
+        WindowMode::Windowed => {
+            window.set_fullscreen(None);
+            window.set_decorations(true);
+            if let Some(rect) = restore {
+                window.set_inner_size(LogicalSize::new(rect.width, rect.height));
+                window.set_position(LogicalPosition::new(rect.x, rect.y));
+            }
+        }
+        WindowMode::Borderless => {
+            let monitor = window.get_current_monitor();
+            let hidpi_factor = monitor.get_hidpi_factor();
+            window.set_fullscreen(None);
+            window.set_decorations(false);
+            window.set_position(monitor.get_position().to_logical(hidpi_factor));
+            window.set_inner_size(monitor.get_dimensions().to_logical(hidpi_factor));
+        }
+        WindowMode::Exclusive => window.set_fullscreen(Some(window.get_current_monitor())),
+    }
+}
@@ -0,0 +1,692 @@
+//! Textures uploaded to the GPU.
+//!
+//! Pixels are uploaded into a device local image with the
+//! context's `Uploader`, which leaves the image in a layout
+//! fragment shaders can sample.
+//!
+//! Mipmaps are generated on the CPU by averaging each 2x2
+//! block of the level above, and uploaded with the image.
+//!
+//! A `TextureArray` holds layers of the same size in one
+//! image, bound once and indexed per draw with a push
+//! constant. Terrain splat layers, decal atlases and shadow
+//! cascades use them instead of a descriptor set per texture.
+//!
+//! A `Texture3D` is a volume of linear RGBA texels, sampled as
+//! a `sampler3D`, for color grading LUTs, noise volumes for
+//! volumetric fog and flow fields.
+use super::buffer_util::align;
+use super::*;
+use gfx_hal::command::BufferImageCopy;
+use gfx_hal::image::{Offset, SubresourceLayers};
+use std::fmt;
+
+/// Bytes per pixel of RGBA textures
+const PIXEL_SIZE: u32 = 4;
+
+/// Number of layers every device supports in a texture array
+pub const MAX_TEXTURE_LAYERS: u32 = 256;
+/// Width, height and depth every device supports in a 3D texture
+pub const MAX_TEXTURE_3D_SIZE: u32 = 256;
+
+/// An error produced when creating a texture.
+#[derive(Debug)]
+pub enum TextureError {
+    /// The pixel data was not `width * height * 4` bytes long
+    InvalidSize {
+        expected: usize,
+        actual: usize,
+    },
+    /// The texture is larger than the device supports
+    TooLarge(u32),
+    /// A texture has no texels, as one of its sizes is zero
+    Empty,
+    /// A texture array was created without layers
+    NoLayers,
+    /// A texture array has more than `MAX_TEXTURE_LAYERS` layers
+    TooManyLayers(usize),
+    ImageCreation(String),
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TextureError::InvalidSize { expected, actual } => write!(
+                f,
+                "expected {} bytes of pixel data, got {}",
+                expected, actual
+            ),
+            TextureError::TooLarge(max) => {
+                write!(f, "texture is larger than the maximum size of {}", max)
+            }
+            TextureError::Empty => write!(f, "texture has a size of zero"),
+            TextureError::NoLayers => write!(f, "texture array has no layers"),
+            TextureError::TooManyLayers(layers) => write!(
+                f,
+                "texture array has {} layers, the maximum is {}",
+                layers, MAX_TEXTURE_LAYERS
+            ),
+            TextureError::ImageCreation(err) => write!(f, "failed to create image: {}", err),
+        }
+    }
+}
+
+/// A sampled 2D texture.
+pub struct Texture<B: Backend> {
+    pub image: ImageMem<B>,
+    pub width: u32,
+    pub height: u32,
+    /// Number of mip levels, including the full size image
+    pub levels: u8,
+}
+
+impl<B: Backend> Texture<B> {
+    /// Creates a texture from RGBA pixel data with
+    /// 8 bits per channel, in rows from top to bottom.
+    ///
+    /// This waits for the upload to finish, so it
+    /// should not be called while recording a frame.
+    pub fn new(
+        ctx: &mut RenderContext<B>,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+    ) -> Result<Texture<B>, TextureError> {
+        check_size(ctx.features.max_texture_size, width, height, pixels)?;
+        Texture::upload(ctx, &[(width, height, pixels)])
+    }
+
+    /// Like `new`, but also generates a full chain of mipmaps on
+    /// the CPU, so the texture does not shimmer in the distance.
+    pub fn with_mipmaps(
+        ctx: &mut RenderContext<B>,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+    ) -> Result<Texture<B>, TextureError> {
+        check_size(ctx.features.max_texture_size, width, height, pixels)?;
+        let chain = mip_chain(width, height, pixels);
+        let levels = std::iter::once((width, height, pixels))
+            .chain(chain.iter().map(|&(w, h, ref level)| (w, h, &level[..])))
+            .collect::<Vec<_>>();
+        Texture::upload(ctx, &levels)
+    }
+
+    /// Uploads each mip level in `levels`,
+    /// starting with the full size image.
+    fn upload(
+        ctx: &mut RenderContext<B>,
+        levels: &[(u32, u32, &[u8])],
+    ) -> Result<Texture<B>, TextureError> {
+        let (width, height, _) = levels[0];
+        let image = factory::create_image::<B>(
+            &ctx.device,
+            &ctx.allocator,
+            Extent {
+                width,
+                height,
+                depth: 1,
+            },
+            levels.len() as image::Level,
+            1,
+            Format::Rgba8Srgb,
+            image::Usage::TRANSFER_DST | image::Usage::SAMPLED,
+            Aspects::COLOR,
+        )
+        .map_err(TextureError::ImageCreation)?;
+        let range = SubresourceRange {
+            aspects: Aspects::COLOR,
+            levels: 0..levels.len() as image::Level,
+            layers: 0..1,
+        };
+
+        let (copies, data) = stage_layers(&ctx.limits, &[levels]);
+        let upload = ctx.upload_image(&image.image, range, &copies, &data);
+        ctx.finish_upload(upload);
+
+        Ok(Texture {
+            image,
+            width,
+            height,
+            levels: levels.len() as u8,
+        })
+    }
+
+    /// Writes this texture and `sampler` to the combined image
+    /// sampler at `binding` of `set`. The set's layout should be
+    /// built with `DescriptorLayoutBuilder::with_texture()`.
+    pub fn write_descriptor(
+        &self,
+        device: &B::Device,
+        set: &B::DescriptorSet,
+        binding: u32,
+        sampler: &B::Sampler,
+    ) {
+        write_image_descriptor::<B>(device, set, binding, &self.image.view, sampler);
+    }
+
+    /// Frees the texture's image, view and memory.
+    pub fn destroy(self, device: &B::Device) {
+        self.image.destroy(device);
+    }
+}
+
+/// A sampled 2D texture array, with
+/// every layer the same size.
+pub struct TextureArray<B: Backend> {
+    pub image: ImageMem<B>,
+    pub width: u32,
+    pub height: u32,
+    pub layers: u16,
+    /// Number of mip levels, including the full size image
+    pub levels: u8,
+}
+
+impl<B: Backend> TextureArray<B> {
+    /// Creates a texture array from the RGBA pixel data of
+    /// each layer, laid out as for `Texture::new()`.
+    ///
+    /// This waits for the upload to finish, so it
+    /// should not be called while recording a frame.
+    pub fn new(
+        ctx: &mut RenderContext<B>,
+        width: u32,
+        height: u32,
+        layers: &[&[u8]],
+    ) -> Result<TextureArray<B>, TextureError> {
+        check_layers(ctx, width, height, layers)?;
+        let layers = layers
+            .iter()
+            .map(|&pixels| vec![(width, height, pixels)])
+            .collect::<Vec<_>>();
+        TextureArray::upload(ctx, &layers)
+    }
+
+    /// Like `new`, but also generates a full chain
+    /// of mipmaps for each layer on the CPU.
+    pub fn with_mipmaps(
+        ctx: &mut RenderContext<B>,
+        width: u32,
+        height: u32,
+        layers: &[&[u8]],
+    ) -> Result<TextureArray<B>, TextureError> {
+        check_layers(ctx, width, height, layers)?;
+        let chains = layers
+            .iter()
+            .map(|pixels| mip_chain(width, height, pixels))
+            .collect::<Vec<_>>();
+        let layers = layers
+            .iter()
+            .zip(&chains)
+            .map(|(&pixels, chain)| {
+                std::iter::once((width, height, pixels))
+                    .chain(chain.iter().map(|&(w, h, ref level)| (w, h, &level[..])))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        TextureArray::upload(ctx, &layers)
+    }
+
+    /// Uploads the mip levels of each layer in `layers`.
+    fn upload(
+        ctx: &mut RenderContext<B>,
+        layers: &[Vec<(u32, u32, &[u8])>],
+    ) -> Result<TextureArray<B>, TextureError> {
+        let (width, height, _) = layers[0][0];
+        let levels = layers[0].len() as image::Level;
+        let layer_count = layers.len() as image::Layer;
+        let image = factory::create_image_of_kind::<B>(
+            &ctx.device,
+            &ctx.allocator,
+            image::Kind::D2(width, height, layer_count, 1),
+            ViewKind::D2Array,
+            levels,
+            Format::Rgba8Srgb,
+            image::Usage::TRANSFER_DST | image::Usage::SAMPLED,
+            Aspects::COLOR,
+        )
+        .map_err(TextureError::ImageCreation)?;
+        let range = SubresourceRange {
+            aspects: Aspects::COLOR,
+            levels: 0..levels,
+            layers: 0..layer_count,
+        };
+
+        let layers = layers.iter().map(|levels| &levels[..]).collect::<Vec<_>>();
+        let (copies, data) = stage_layers(&ctx.limits, &layers);
+        let upload = ctx.upload_image(&image.image, range, &copies, &data);
+        ctx.finish_upload(upload);
+
+        Ok(TextureArray {
+            image,
+            width,
+            height,
+            layers: layer_count,
+            levels: levels as u8,
+        })
+    }
+
+    /// Writes this array and `sampler` to the combined image
+    /// sampler at `binding` of `set`, which shaders sample as
+    /// a `sampler2DArray`. For an object's set, the binding is
+    /// added with `RenderBuilder::with_texture_array()`.
+    pub fn write_descriptor(
+        &self,
+        device: &B::Device,
+        set: &B::DescriptorSet,
+        binding: u32,
+        sampler: &B::Sampler,
+    ) {
+        write_image_descriptor::<B>(device, set, binding, &self.image.view, sampler);
+    }
+
+    /// Records which layer the next draws sample, as a
+    /// push constant `offset` words into the range of
+    /// `stages`. Panics if the array has no such layer.
+    pub fn push_layer(
+        &self,
+        encoder: &mut RenderPassInlineEncoder<B, Primary>,
+        layout: &B::PipelineLayout,
+        stages: ShaderStageFlags,
+        offset: u32,
+        layer: u32,
+    ) {
+        assert!(
+            layer < u32::from(self.layers),
+            "layer {} is out of range, the array has {}",
+            layer,
+            self.layers
+        );
+        // Safe since a `u32` is a single word
+        unsafe { push_constants(encoder, layout, stages, offset, &layer) };
+    }
+
+    /// Frees the array's image, view and memory.
+    pub fn destroy(self, device: &B::Device) {
+        self.image.destroy(device);
+    }
+}
+
+/// A sampled 3D texture without mipmaps.
+pub struct Texture3D<B: Backend> {
+    pub image: ImageMem<B>,
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+}
+
+impl<B: Backend> Texture3D<B> {
+    /// Creates a 3D texture from linear RGBA texels with 8 bits
+    /// per channel, in rows from top to bottom of each slice
+    /// from front to back.
+    ///
+    /// This waits for the upload to finish, so it
+    /// should not be called while recording a frame.
+    pub fn new(
+        ctx: &mut RenderContext<B>,
+        width: u32,
+        height: u32,
+        depth: u32,
+        texels: &[u8],
+    ) -> Result<Texture3D<B>, TextureError> {
+        if width == 0 || height == 0 || depth == 0 {
+            return Err(TextureError::Empty);
+        }
+        if width > MAX_TEXTURE_3D_SIZE
+            || height > MAX_TEXTURE_3D_SIZE
+            || depth > MAX_TEXTURE_3D_SIZE
+        {
+            return Err(TextureError::TooLarge(MAX_TEXTURE_3D_SIZE));
+        }
+        let expected = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|texels| texels.checked_mul(depth as usize))
+            .and_then(|texels| texels.checked_mul(PIXEL_SIZE as usize))
+            .ok_or(TextureError::TooLarge(MAX_TEXTURE_3D_SIZE))?;
+        if texels.len() != expected {
+            return Err(TextureError::InvalidSize {
+                expected,
+                actual: texels.len(),
+            });
+        }
+
+        let image = factory::create_image_of_kind::<B>(
+            &ctx.device,
+            &ctx.allocator,
+            image::Kind::D3(width, height, depth),
+            ViewKind::D3,
+            1,
+            Format::Rgba8Unorm,
+            image::Usage::TRANSFER_DST | image::Usage::SAMPLED,
+            Aspects::COLOR,
+        )
+        .map_err(TextureError::ImageCreation)?;
+        let range = SubresourceRange {
+            aspects: Aspects::COLOR,
+            levels: 0..1,
+            layers: 0..1,
+        };
+
+        // Each row of every slice starts at an aligned pitch
+        let pitch_alignment = ctx.limits.min_buffer_copy_pitch_alignment as u32;
+        let row_pitch = align(width * PIXEL_SIZE, pitch_alignment);
+        let copy = BufferImageCopy {
+            buffer_offset: 0,
+            buffer_width: row_pitch / PIXEL_SIZE,
+            buffer_height: height,
+            image_layers: SubresourceLayers {
+                aspects: Aspects::COLOR,
+                level: 0,
+                layers: 0..1,
+            },
+            image_offset: Offset { x: 0, y: 0, z: 0 },
+            image_extent: Extent {
+                width,
+                height,
+                depth,
+            },
+        };
+        let mut data = vec![0; (row_pitch * height * depth) as usize];
+        for (y, row) in texels.chunks((width * PIXEL_SIZE) as usize).enumerate() {
+            let start = y * row_pitch as usize;
+            data[start..start + row.len()].copy_from_slice(row);
+        }
+        let upload = ctx.upload_image(&image.image, range, &[copy], &data);
+        ctx.finish_upload(upload);
+
+        Ok(Texture3D {
+            image,
+            width,
+            height,
+            depth,
+        })
+    }
+
+    /// Writes this texture and `sampler` to the combined image
+    /// sampler at `binding` of `set`, which shaders sample as
+    /// a `sampler3D`.
+    pub fn write_descriptor(
+        &self,
+        device: &B::Device,
+        set: &B::DescriptorSet,
+        binding: u32,
+        sampler: &B::Sampler,
+    ) {
+        write_image_descriptor::<B>(device, set, binding, &self.image.view, sampler);
+    }
+
+    /// Frees the texture's image, view and memory.
+    pub fn destroy(self, device: &B::Device) {
+        self.image.destroy(device);
+    }
+}
+
+/// Writes `view` and `sampler` to the combined image
+/// sampler at `binding` of `set`.
+fn write_image_descriptor<B: Backend>(
+    device: &B::Device,
+    set: &B::DescriptorSet,
+    binding: u32,
+    view: &B::ImageView,
+    sampler: &B::Sampler,
+) {
+    device.write_descriptor_sets(vec![DescriptorSetWrite {
+        set,
+        binding,
+        array_offset: 0,
+        descriptors: Some(Descriptor::CombinedImageSampler(
+            view,
+            Layout::ShaderReadOnlyOptimal,
+            sampler,
+        )),
+    }]);
+}
+
+/// Lays out the mip levels of each layer in `layers` in a
+/// staging buffer, returning the copies into the image's
+/// levels and layers and the data to upload.
+fn stage_layers(
+    limits: &Limits,
+    layers: &[&[(u32, u32, &[u8])]],
+) -> (Vec<BufferImageCopy>, Vec<u8>) {
+    // Rows and levels in the staging buffer must be aligned
+    // to the device's copy pitch and offset alignments
+    let pitch_alignment = limits.min_buffer_copy_pitch_alignment as u32;
+    let offset_alignment = limits.min_buffer_copy_offset_alignment as u32;
+    let mut copies = Vec::new();
+    let mut upload_size = 0;
+    for (layer, levels) in layers.iter().enumerate() {
+        let layer = layer as image::Layer;
+        for (level, &(width, height, _)) in levels.iter().enumerate() {
+            let row_pitch = align(width * PIXEL_SIZE, pitch_alignment);
+            copies.push(BufferImageCopy {
+                buffer_offset: upload_size as u64,
+                buffer_width: row_pitch / PIXEL_SIZE,
+                buffer_height: height,
+                image_layers: SubresourceLayers {
+                    aspects: Aspects::COLOR,
+                    level: level as image::Level,
+                    layers: layer..layer + 1,
+                },
+                image_offset: Offset { x: 0, y: 0, z: 0 },
+                image_extent: Extent {
+                    width,
+                    height,
+                    depth: 1,
+                },
+            });
+            upload_size = align(upload_size + row_pitch * height, offset_alignment);
+        }
+    }
+
+    // The data is laid out as `copies` describe
+    let mut data = vec![0; upload_size as usize];
+    let levels = layers.iter().flat_map(|levels| levels.iter());
+    for (copy, &(width, _, pixels)) in copies.iter().zip(levels) {
+        let row_pitch = (copy.buffer_width * PIXEL_SIZE) as usize;
+        for (y, row) in pixels.chunks((width * PIXEL_SIZE) as usize).enumerate() {
+            let start = copy.buffer_offset as usize + y * row_pitch;
+            data[start..start + row.len()].copy_from_slice(row);
+        }
+    }
+    (copies, data)
+}
+
+/// Checks that `pixels` holds a `width` by `height` image
+/// which a device with `max_size` textures can create.
+fn check_size(max_size: u32, width: u32, height: u32, pixels: &[u8]) -> Result<(), TextureError> {
+    if width == 0 || height == 0 {
+        return Err(TextureError::Empty);
+    }
+    if width > max_size || height > max_size {
+        return Err(TextureError::TooLarge(max_size));
+    }
+    // Sized in `usize`, since the limit can be large
+    // enough for the byte size to overflow a `u32`
+    let expected = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|texels| texels.checked_mul(PIXEL_SIZE as usize))
+        .ok_or(TextureError::TooLarge(max_size))?;
+    if pixels.len() != expected {
+        return Err(TextureError::InvalidSize {
+            expected,
+            actual: pixels.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Checks that there are layers the device can create
+/// in an array, each holding a `width` by `height` image.
+fn check_layers<B: Backend>(
+    ctx: &RenderContext<B>,
+    width: u32,
+    height: u32,
+    layers: &[&[u8]],
+) -> Result<(), TextureError> {
+    if layers.is_empty() {
+        return Err(TextureError::NoLayers);
+    }
+    if layers.len() > MAX_TEXTURE_LAYERS as usize {
+        return Err(TextureError::TooManyLayers(layers.len()));
+    }
+    for pixels in layers {
+        check_size(ctx.features.max_texture_size, width, height, pixels)?;
+    }
+    Ok(())
+}
+
+/// Generates every mip level below the full size image,
+/// down to 1x1, as `(width, height, pixels)`.
+pub fn mip_chain(width: u32, height: u32, pixels: &[u8]) -> Vec<(u32, u32, Vec<u8>)> {
+    let mut chain: Vec<(u32, u32, Vec<u8>)> = Vec::new();
+    let (mut width, mut height) = (width, height);
+    while width > 1 || height > 1 {
+        let level = match chain.last() {
+            Some(&(_, _, ref above)) => downsample(width, height, above),
+            None => downsample(width, height, pixels),
+        };
+        width = (width / 2).max(1);
+        height = (height / 2).max(1);
+        chain.push((width, height, level));
+    }
+    chain
+}
+
+/// Halves an RGBA image by averaging each 2x2 block. With
+/// an odd width or height the last row or column is dropped.
+///
+/// The color channels are sRGB encoded, so they are averaged
+/// in linear space, or the smaller levels would get darker.
+/// Alpha is already linear.
+fn downsample(width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let (new_width, new_height) = ((width / 2).max(1), (height / 2).max(1));
+    let texel = |x: usize, y: usize, channel: usize| {
+        let (x, y) = (x.min(width - 1), y.min(height - 1));
+        let value = f32::from(pixels[(y * width + x) * PIXEL_SIZE as usize + channel]) / 255.0;
+        if channel == 3 {
+            value
+        } else {
+            srgb_to_linear(value)
+        }
+    };
+
+    let mut result = Vec::with_capacity(new_width * new_height * PIXEL_SIZE as usize);
+    for y in 0..new_height {
+        for x in 0..new_width {
+            for channel in 0..PIXEL_SIZE as usize {
+                let average = (texel(x * 2, y * 2, channel)
+                    + texel(x * 2 + 1, y * 2, channel)
+                    + texel(x * 2, y * 2 + 1, channel)
+                    + texel(x * 2 + 1, y * 2 + 1, channel))
+                    / 4.0;
+                let value = if channel == 3 {
+                    average
+                } else {
+                    linear_to_srgb(average)
+                };
+                result.push((value * 255.0).round() as u8);
+            }
+        }
+    }
+    result
+}
+
+/// Decodes an sRGB value between 0 and 1.
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encodes a linear value between 0 and 1 as sRGB.
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mip_chain_sizes() {
+        let pixels = vec![255; 8 * 2 * 4];
+        let sizes = mip_chain(8, 2, &pixels)
+            .iter()
+            .map(|&(width, height, ref level)| {
+                assert_eq!(level.len(), (width * height * 4) as usize);
+                (width, height)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, vec![(4, 1), (2, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn downsample_averages() {
+        let pixels = [
+            0, 0, 0, 255, 100, 0, 0, 255, //
+            0, 200, 0, 255, 0, 0, 40, 0,
+        ];
+        assert_eq!(downsample(2, 2, &pixels), vec![50, 106, 16, 191]);
+    }
+
+    #[test]
+    fn downsample_is_linear() {
+        // Half black and half white is half as bright,
+        // which is 188 rather than 128 in sRGB
+        let pixels = [
+            0, 0, 0, 255, 255, 255, 255, 255, //
+            255, 255, 255, 255, 0, 0, 0, 255,
+        ];
+        assert_eq!(downsample(2, 2, &pixels), vec![188, 188, 188, 255]);
+    }
+
+    #[test]
+    fn stage_layers_offsets() {
+        let limits = Limits {
+            min_buffer_copy_pitch_alignment: 16,
+            min_buffer_copy_offset_alignment: 64,
+            ..Limits::default()
+        };
+        let full = [1; 16];
+        let mip = [2; 4];
+        let levels = [(2, 2, &full[..]), (1, 1, &mip[..])];
+        let (copies, data) = stage_layers(&limits, &[&levels, &levels]);
+
+        let offsets = copies
+            .iter()
+            .map(|copy| copy.buffer_offset)
+            .collect::<Vec<_>>();
+        assert_eq!(offsets, vec![0, 64, 128, 192]);
+        let targets = copies
+            .iter()
+            .map(|copy| (copy.image_layers.level, copy.image_layers.layers.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(targets, vec![(0, 0..1), (1, 0..1), (0, 1..2), (1, 1..2)]);
+        // Rows are padded to the pitch alignment
+        assert!(copies.iter().all(|copy| copy.buffer_width == 4));
+        assert_eq!(data.len(), 256);
+        assert_eq!(&data[0..8], &[1; 8]);
+        assert_eq!(&data[8..16], &[0; 8]);
+        assert_eq!(&data[16..24], &[1; 8]);
+        assert_eq!(&data[192..196], &[2; 4]);
+    }
+
+    #[test]
+    fn empty_sizes() {
+        let empty = |result: Result<(), TextureError>| match result {
+            Err(TextureError::Empty) => true,
+            _ => false,
+        };
+        assert!(empty(check_size(4096, 0, 4, &[])));
+        assert!(empty(check_size(4096, 4, 0, &[])));
+        assert!(check_size(4096, 1, 1, &[0; 4]).is_ok());
+    }
+}
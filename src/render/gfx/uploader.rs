@@ -0,0 +1,320 @@
+//! Batched uploads through staging buffers.
+//!
+//! Device local buffers and images can't be written by the
+//! CPU, so their data is written into a CPU visible staging
+//! buffer and copied on the GPU. The `Uploader` records the
+//! copy of each queued upload right away, and submits every
+//! copy queued since the last frame together when the frame
+//! is submitted, before the frame's own commands.
+//!
+//! Each upload gets an `UploadId`. Once `is_complete()` returns
+//! true for it, the copy has finished and its staging buffer
+//! has been freed. Frames submitted after an upload was
+//! flushed see its data without waiting for it.
+use super::*;
+use gfx_hal::buffer;
+use gfx_hal::command::{BufferCopy, BufferImageCopy, OneShot, Submit};
+use gfx_hal::memory::Barrier;
+use gfx_hal::CommandQueue;
+use std::collections::VecDeque;
+
+/// Identifies a queued upload. Ids increase in the
+/// order uploads are queued.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UploadId(u64);
+
+/// Uploads submitted together, and the fence
+/// which signals when they have finished.
+struct Batch<B: Backend> {
+    fence: B::Fence,
+    staging: Vec<(B::Buffer, Allocation<B>)>,
+    /// The last upload in the batch
+    last: u64,
+}
+
+/// Queues uploads and submits them once per frame.
+pub struct Uploader<B: Backend> {
+    /// Pool of the upload command buffers, which
+    /// is reset whenever no uploads are left
+    command_pool: CommandPool<B, Graphics>,
+    /// Copies recorded since the last flush
+    pending: Vec<Submit<B, Graphics, OneShot, Primary>>,
+    /// Staging buffers read by the pending copies
+    staging: Vec<(B::Buffer, Allocation<B>)>,
+    /// Batches which have been submitted, oldest first
+    in_flight: VecDeque<Batch<B>>,
+    /// The id of the next upload
+    next: u64,
+    /// Every upload up to this one has finished
+    completed: u64,
+}
+
+impl<B: Backend> Uploader<B> {
+    /// Creates an uploader submitting to queues of `queue_group`.
+    pub fn new(device: &B::Device, queue_group: &QueueGroup<B, Graphics>) -> Uploader<B> {
+        let max_buffers = 16;
+        Uploader {
+            command_pool: device.create_command_pool_typed(
+                queue_group,
+                CommandPoolCreateFlags::empty(),
+                max_buffers,
+            ),
+            pending: Vec::new(),
+            staging: Vec::new(),
+            in_flight: VecDeque::new(),
+            next: 1,
+            completed: 0,
+        }
+    }
+
+    /// Queues a copy of `data` into `buffer`, starting `offset`
+    /// bytes in. The buffer needs `Usage::TRANSFER_DST`.
+    pub fn upload_buffer<T: Copy>(
+        &mut self,
+        device: &B::Device,
+        allocator: &MemoryAllocator<B>,
+        buffer: &B::Buffer,
+        offset: u64,
+        data: &[T],
+    ) -> UploadId {
+        assert!(!data.is_empty(), "uploads can't be empty");
+        let (staging_buffer, staging_memory) = buffer_util::create_buffer::<B, T>(
+            device,
+            allocator,
+            Properties::CPU_VISIBLE,
+            Usage::TRANSFER_SRC,
+            data,
+        );
+
+        let submit = {
+            let mut command_buffer = self.command_pool.acquire_command_buffer(false);
+            command_buffer.copy_buffer(
+                &staging_buffer,
+                buffer,
+                &[BufferCopy {
+                    src: 0,
+                    dst: offset,
+                    size: (data.len() * std::mem::size_of::<T>()) as u64,
+                }],
+            );
+            command_buffer.pipeline_barrier(
+                PipelineStage::TRANSFER..reading_stages(),
+                Dependencies::empty(),
+                &[Barrier::AllBuffers(
+                    buffer::Access::TRANSFER_WRITE
+                        ..buffer::Access::VERTEX_BUFFER_READ
+                            | buffer::Access::INDEX_BUFFER_READ
+                            | buffer::Access::UNIFORM_READ
+                            | buffer::Access::SHADER_READ
+                            | buffer::Access::INDIRECT_COMMAND_READ,
+                )],
+            );
+            command_buffer.finish()
+        };
+        self.queue(submit, (staging_buffer, staging_memory))
+    }
+
+    /// Queues copies of `data` into `image`, which is moved
+    /// from an undefined layout to one shaders can sample.
+    /// `copies` give the layout of the data in `data`, and
+    /// `range` should cover every subresource they write.
+    /// The image needs `image::Usage::TRANSFER_DST`.
+    pub fn upload_image(
+        &mut self,
+        device: &B::Device,
+        allocator: &MemoryAllocator<B>,
+        image: &B::Image,
+        range: SubresourceRange,
+        copies: &[BufferImageCopy],
+        data: &[u8],
+    ) -> UploadId {
+        assert!(!data.is_empty(), "uploads can't be empty");
+        let (staging_buffer, staging_memory) = buffer_util::create_buffer::<B, u8>(
+            device,
+            allocator,
+            Properties::CPU_VISIBLE,
+            Usage::TRANSFER_SRC,
+            data,
+        );
+
+        let submit = {
+            let mut command_buffer = self.command_pool.acquire_command_buffer(false);
+            command_buffer.pipeline_barrier(
+                PipelineStage::TOP_OF_PIPE..PipelineStage::TRANSFER,
+                Dependencies::empty(),
+                &[Barrier::Image {
+                    states: (Access::empty(), Layout::Undefined)
+                        ..(Access::TRANSFER_WRITE, Layout::TransferDstOptimal),
+                    target: image,
+                    range: range.clone(),
+                }],
+            );
+            command_buffer.copy_buffer_to_image(
+                &staging_buffer,
+                image,
+                Layout::TransferDstOptimal,
+                copies,
+            );
+            command_buffer.pipeline_barrier(
+                PipelineStage::TRANSFER..reading_stages(),
+                Dependencies::empty(),
+                &[Barrier::Image {
+                    states: (Access::TRANSFER_WRITE, Layout::TransferDstOptimal)
+                        ..(Access::SHADER_READ, Layout::ShaderReadOnlyOptimal),
+                    target: image,
+                    range,
+                }],
+            );
+            command_buffer.finish()
+        };
+        self.queue(submit, (staging_buffer, staging_memory))
+    }
+
+    fn queue(
+        &mut self,
+        submit: Submit<B, Graphics, OneShot, Primary>,
+        staging: (B::Buffer, Allocation<B>),
+    ) -> UploadId {
+        self.pending.push(submit);
+        self.staging.push(staging);
+        self.next += 1;
+        UploadId(self.next - 1)
+    }
+
+    /// Returns the number of uploads which haven't been flushed.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Submits every queued upload to `queue`.
+    pub fn flush(&mut self, device: &B::Device, queue: &mut CommandQueue<B, Graphics>) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let fence = device.create_fence(false);
+        let pending = std::mem::replace(&mut self.pending, Vec::new());
+        queue.submit(Submission::new().submit(pending), Some(&fence));
+        self.in_flight.push_back(Batch {
+            fence,
+            staging: std::mem::replace(&mut self.staging, Vec::new()),
+            last: self.next - 1,
+        });
+    }
+
+    /// Frees the staging buffers of uploads which have finished.
+    pub fn poll(&mut self, device: &B::Device) {
+        while self
+            .in_flight
+            .front()
+            .map_or(false, |batch| device.get_fence_status(&batch.fence))
+        {
+            self.retire(device);
+        }
+        self.reset_if_idle();
+    }
+
+    /// Returns whether the upload `id` has finished.
+    pub fn is_complete(&self, id: UploadId) -> bool {
+        id.0 <= self.completed
+    }
+
+    /// Flushes the upload `id` if it is still queued,
+    /// and waits for it to finish.
+    pub fn wait(
+        &mut self,
+        device: &B::Device,
+        queue: &mut CommandQueue<B, Graphics>,
+        id: UploadId,
+    ) {
+        if self.last_flushed() < id.0 {
+            self.flush(device, queue);
+        }
+        while !self.is_complete(id) {
+            device.wait_for_fence(&self.in_flight[0].fence, !0);
+            self.retire(device);
+        }
+        self.reset_if_idle();
+    }
+
+    /// Returns the last upload which has been flushed.
+    fn last_flushed(&self) -> u64 {
+        self.in_flight
+            .back()
+            .map_or(self.completed, |batch| batch.last)
+    }
+
+    /// Frees the oldest batch, which must have finished.
+    fn retire(&mut self, device: &B::Device) {
+        let batch = self.in_flight.pop_front().unwrap();
+        device.destroy_fence(batch.fence);
+        for (buffer, memory) in batch.staging {
+            device.destroy_buffer(buffer);
+            memory.free();
+        }
+        self.completed = batch.last;
+    }
+
+    /// Reuses the command buffers once none are in use.
+    fn reset_if_idle(&mut self) {
+        if self.pending.is_empty() && self.in_flight.is_empty() {
+            self.command_pool.reset();
+        }
+    }
+
+    /// Waits for submitted uploads and frees the uploader.
+    /// Uploads which were never flushed are dropped.
+    pub fn destroy(mut self, device: &B::Device) {
+        while !self.in_flight.is_empty() {
+            device.wait_for_fence(&self.in_flight[0].fence, !0);
+            self.retire(device);
+        }
+        for (buffer, memory) in self.staging {
+            device.destroy_buffer(buffer);
+            memory.free();
+        }
+        device.destroy_command_pool(self.command_pool.into_raw());
+    }
+}
+
+/// Stages which may read uploaded data.
+fn reading_stages() -> PipelineStage {
+    PipelineStage::DRAW_INDIRECT
+        | PipelineStage::VERTEX_INPUT
+        | PipelineStage::VERTEX_SHADER
+        | PipelineStage::FRAGMENT_SHADER
+        | PipelineStage::COMPUTE_SHADER
+}
+
+impl<B: Backend> RenderContext<B> {
+    /// Queues a copy of `data` into `buffer`, which is
+    /// submitted with the next frame.
+    pub fn upload_buffer<T: Copy>(
+        &mut self,
+        buffer: &B::Buffer,
+        offset: u64,
+        data: &[T],
+    ) -> UploadId {
+        self.uploader
+            .upload_buffer(&self.device, &self.allocator, buffer, offset, data)
+    }
+
+    /// Queues a copy of `data` into `image`, which is
+    /// submitted with the next frame.
+    pub fn upload_image(
+        &mut self,
+        image: &B::Image,
+        range: SubresourceRange,
+        copies: &[BufferImageCopy],
+        data: &[u8],
+    ) -> UploadId {
+        self.uploader
+            .upload_image(&self.device, &self.allocator, image, range, copies, data)
+    }
+
+    /// Submits the upload `id` without waiting for the next
+    /// frame, and waits for it to finish.
+    pub fn finish_upload(&mut self, id: UploadId) {
+        self.uploader
+            .wait(&self.device, &mut self.queue_group.queues[0], id);
+    }
+}
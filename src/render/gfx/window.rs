@@ -0,0 +1,61 @@
+//! The window's cursor.
+//!
+//! The icon is set when the window is built, with
+//! `RenderBuilder::with_icon()`, or later with `set_icon()`.
+//! Headless contexts have no window, so changing the cursor
+//! or icon does nothing.
+use super::*;
+
+impl<B: Backend> RenderContext<B> {
+    /// Changes what is shown at the mouse position.
+    pub fn set_cursor(&mut self, cursor: Cursor) {
+        if let Some(ref window) = self.window {
+            match cursor.system() {
+                Some(system) => {
+                    window.set_cursor(mouse_cursor(system));
+                    window.hide_cursor(false);
+                }
+                None => window.hide_cursor(true),
+            }
+        }
+        self.cursor = cursor;
+    }
+
+    /// Hides the cursor, or shows the system arrow.
+    pub fn hide_cursor(&mut self, hidden: bool) {
+        self.set_cursor(if hidden {
+            Cursor::Hidden
+        } else {
+            Cursor::default()
+        });
+    }
+
+    pub fn cursor(&self) -> &Cursor {
+        &self.cursor
+    }
+
+    /// Replaces the window's icon with RGBA pixel data,
+    /// laid out as for `RenderBuilder::with_icon()`.
+    pub fn set_icon(&self, rgba: &[u8], width: u32, height: u32) -> Result<(), winit::BadIcon> {
+        let icon = winit::Icon::from_rgba(rgba.to_vec(), width, height)?;
+        if let Some(ref window) = self.window {
+            window.set_window_icon(Some(icon));
+        }
+        Ok(())
+    }
+}
+
+/// Returns winit's cursor for `cursor`.
+fn mouse_cursor(cursor: SystemCursor) -> winit::MouseCursor {
+    match cursor {
+        SystemCursor::Arrow => winit::MouseCursor::Default,
+        SystemCursor::Crosshair => winit::MouseCursor::Crosshair,
+        SystemCursor::Hand => winit::MouseCursor::Hand,
+        SystemCursor::Text => winit::MouseCursor::Text,
+        SystemCursor::Wait => winit::MouseCursor::Wait,
+        SystemCursor::Move => winit::MouseCursor::Move,
+        SystemCursor::NotAllowed => winit::MouseCursor::NotAllowed,
+        SystemCursor::ResizeHorizontal => winit::MouseCursor::EwResize,
+        SystemCursor::ResizeVertical => winit::MouseCursor::NsResize,
+    }
+}
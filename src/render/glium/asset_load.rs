@@ -1,7 +1,14 @@
 use super::*;
+use profile::millis_since;
+use std::time::Instant;
 
-pub fn upload_model(ctx: &mut RenderContext, models: Vec<tobj::Model>) {
+/// Uploads a model, returning its index in `ctx.models`.
+pub fn upload_model(ctx: &mut RenderContext, models: Vec<tobj::Model>) -> usize {
+    let start = Instant::now();
     let (vertices, indices) = combine_models(models);
+    let bytes = (vertices.len() * std::mem::size_of::<Vertex>()
+        + indices.len() * std::mem::size_of::<u32>()) as u64;
+    ctx.stats.model_bytes += bytes;
     let vertices = unsafe { std::mem::transmute::<_, Vec<_Vertex>>(vertices) };
 
     let vertices = glium::VertexBuffer::new(&ctx.display, &vertices).unwrap();
@@ -13,4 +20,11 @@ pub fn upload_model(ctx: &mut RenderContext, models: Vec<tobj::Model>) {
     ).unwrap();
 
     ctx.models.push(ModelBuffer { vertices, indices });
+    ctx.telemetry.record(RenderEvent {
+        kind: EventKind::Upload,
+        reason: format!("model {}", ctx.models.len() - 1),
+        millis: millis_since(start),
+        bytes: Some(bytes),
+    });
+    ctx.models.len() - 1
 }
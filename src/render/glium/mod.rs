@@ -4,47 +4,115 @@ pub mod asset_load;
 use super::*;
 use glium;
 use glium::glutin;
+use glium::glutin::dpi::{LogicalPosition, LogicalSize};
 use glium::Surface;
+use std::time::Instant;
 
 pub enum _RenderBackend {}
 
 /// So we can use `implement_vertex!`, we have to
 /// use a secondary struct we can transmute to
 #[derive(Copy, Clone)]
+#[repr(C)]
 struct _Vertex {
     a_position: (f32, f32, f32),
     a_normal: (f32, f32, f32),
+    a_uv: (f32, f32),
 }
 
-implement_vertex!(_Vertex, a_position, a_normal);
+implement_vertex!(_Vertex, a_position, a_normal, a_uv);
 
 impl RenderBackend for _RenderBackend {
     type ObjectRender = ObjectRender;
     type RenderContext = RenderContext;
 
-    fn upload_model(ctx: &mut RenderContext, models: Vec<tobj::Model>) {
-        asset_load::upload_model(ctx, models);
+    fn upload_model(ctx: &mut RenderContext, models: Vec<tobj::Model>) -> usize {
+        asset_load::upload_model(ctx, models)
     }
 
     /// Creates a `RenderContext` with the specified
-    /// window title and dimensions
-    fn create_context(title: &str, dimensions: (u32, u32)) -> RenderContext {
+    /// window title, dimensions and graphics settings
+    fn create_context(
+        title: &str,
+        dimensions: (u32, u32),
+        settings: &GraphicsSettings,
+    ) -> RenderContext {
         let events_loop = glutin::EventsLoop::new();
-        let window = glutin::WindowBuilder::new()
-            .with_title(title)
-            .with_dimensions(dimensions.into());
-        let context = glutin::ContextBuilder::new()
-            .with_depth_buffer(24)
-            .with_vsync(true)
-            .with_multisampling(4);
-        let display = glium::Display::new(window, context, &events_loop).unwrap();
-        let program = compile_program(&display);
+        // The sample count can't be checked until a context exists,
+        // so fewer samples are tried until a pixel format is found
+        let mut samples = settings.msaa_samples.max(1);
+        let display = loop {
+            let window = glutin::WindowBuilder::new()
+                .with_title(title)
+                .with_dimensions(dimensions.into());
+            let context = glutin::ContextBuilder::new()
+                .with_depth_buffer(24)
+                .with_vsync(settings.vsync)
+                .with_multisampling(if samples > 1 { u16::from(samples) } else { 0 });
+            match glium::Display::new(window, context, &events_loop) {
+                Ok(display) => break display,
+                Err(err) => {
+                    if samples == 1 {
+                        panic!("failed to create display: {}", err);
+                    }
+                    warn!("{}x MSAA is not supported, using {}x", samples, samples / 2);
+                    samples /= 2;
+                }
+            }
+        };
+        crash::set_adapter_info(format!(
+            "{} ({})",
+            display.get_opengl_renderer_string(),
+            display.get_opengl_version_string()
+        ));
+        let program = compile_program(&display, include_str!("../../shaders/model.glium.frag"));
+        let debug_programs = vec![
+            (
+                DebugView::Normals,
+                compile_program(
+                    &display,
+                    include_str!("../../shaders/debug_normals.glium.frag"),
+                ),
+            ),
+            (
+                DebugView::Depth,
+                compile_program(
+                    &display,
+                    include_str!("../../shaders/debug_depth.glium.frag"),
+                ),
+            ),
+            (
+                DebugView::Overdraw,
+                compile_program(
+                    &display,
+                    include_str!("../../shaders/debug_overdraw.glium.frag"),
+                ),
+            ),
+        ];
+        let mut features = device_features(&display);
+        if samples < settings.msaa_samples {
+            // The driver reports more samples than it could create
+            features.max_samples = samples;
+            features.sample_counts &= counts_up_to(samples);
+        }
 
         RenderContext {
             display,
             models: Vec::new(),
             program,
+            debug_programs,
             events_loop,
+            stats: RenderStats::default(),
+            telemetry: Telemetry::new(),
+            features,
+            depth_mode: settings.depth_mode,
+            latency: LatencyTracker::new(),
+            window_mode: WindowModeState::new(),
+            cursor: Cursor::default(),
+            clear_color: None,
+            color_load: LoadOp::Clear,
+            capture_requested: false,
+            captured_frame: None,
         }
     }
 
@@ -55,6 +123,80 @@ impl RenderBackend for _RenderBackend {
     ) -> ObjectRender {
         ObjectRender { model_index }
     }
+
+    fn model_index(render: &ObjectRender) -> usize {
+        render.model_index
+    }
+
+    fn shader_index(_render: &ObjectRender) -> usize {
+        0
+    }
+
+    fn stats(ctx: &RenderContext) -> &RenderStats {
+        &ctx.stats
+    }
+
+    fn telemetry(ctx: &RenderContext) -> &Telemetry {
+        &ctx.telemetry
+    }
+
+    fn features(ctx: &RenderContext) -> &DeviceFeatures {
+        &ctx.features
+    }
+
+    /// Finishing the GL commands of the previous frame also
+    /// stops the driver from queueing frames ahead.
+    fn begin_input(ctx: &mut RenderContext, settings: &GraphicsSettings) {
+        if settings.low_latency {
+            ctx.display.finish();
+        }
+        ctx.latency.input_sampled(Instant::now());
+    }
+
+    fn set_title(ctx: &mut RenderContext, title: &str) {
+        ctx.display.gl_window().set_title(title);
+    }
+
+    fn set_window_mode(ctx: &mut RenderContext, mode: WindowMode) {
+        ctx.set_window_mode(mode);
+    }
+
+    fn set_cursor(ctx: &mut RenderContext, cursor: Cursor) {
+        ctx.set_cursor(cursor);
+    }
+
+    fn set_clear_color(ctx: &mut RenderContext, color: Option<[f32; 4]>) {
+        ctx.clear_color = color;
+    }
+
+    /// Only the depth buffer is cleared unless the color is.
+    /// Uncleared, the default framebuffer holds whatever the
+    /// driver left in it, usually an earlier frame.
+    fn set_color_load(ctx: &mut RenderContext, load: LoadOp) {
+        ctx.color_load = load;
+    }
+
+    fn capture_next_frame(ctx: &mut RenderContext) {
+        ctx.capture_requested = true;
+    }
+
+    fn take_captured_frame(ctx: &mut RenderContext) -> Option<CapturedFrame> {
+        ctx.captured_frame.take()
+    }
+
+    /// The default framebuffer follows the window's size.
+    fn window_resized(_ctx: &mut RenderContext) {}
+
+    /// Uniforms are passed directly to each draw
+    /// call, so there is nothing to prepare.
+    fn prepare(_ctx: &mut RenderContext, _world: &mut World<Self>, _frame: &RenderWorld) {}
+
+    fn submit(ctx: &mut RenderContext, world: &World<Self>, frame: &RenderWorld) {
+        submit(ctx, world, frame);
+    }
+
+    /// glium frees its resources when they are dropped.
+    fn destroy(_ctx: RenderContext, _world: World<Self>) {}
 }
 
 /// Holds state data for OpenGL
@@ -62,48 +204,271 @@ pub struct RenderContext {
     pub display: glium::Display,
     pub models: Vec<ModelBuffer>,
     pub program: glium::Program,
+    /// Programs which replace `program` while
+    /// their debug view is selected
+    pub debug_programs: Vec<(DebugView, glium::Program)>,
     pub events_loop: glutin::EventsLoop,
+    /// Counters for the stats overlay
+    pub stats: RenderStats,
+    /// Timings of expensive events
+    pub telemetry: Telemetry,
+    pub features: DeviceFeatures,
+    /// How depth is laid out. Without clip control OpenGL
+    /// maps depth from -1 to 1, so reversed depth is no more
+    /// precise here, but it keeps projections the same as
+    /// in the other backends.
+    pub depth_mode: DepthMode,
+    /// Timestamps of the current frame and recent latencies
+    pub latency: LatencyTracker,
+    /// The window's mode, and where it was while windowed
+    pub window_mode: WindowModeState,
+    /// What is shown at the mouse position
+    pub cursor: Cursor,
+    /// Color frames are cleared to, or `None` for the sky color
+    pub clear_color: Option<[f32; 4]>,
+    /// What frames do with the previous contents of the color buffer
+    pub color_load: LoadOp,
+    /// Whether to copy the next frame back to the CPU
+    pub capture_requested: bool,
+    /// The copy of the last captured frame, until it is taken
+    pub captured_frame: Option<CapturedFrame>,
 }
 
-pub fn render(ctx: &mut RenderContext, world: &mut World<_RenderBackend>) {
-    let mut frame = ctx.display.draw();
-    frame.clear_color_and_depth((0.0, 0.0, 0.0, 1.0), 1.0);
-    for object in world.get_objs().values() {
-        render_obj(ctx, &mut frame, object);
+impl RenderContext {
+    /// Switches the window to `mode`, putting it back
+    /// where it was when it returns to windowed mode.
+    pub fn set_window_mode(&mut self, mode: WindowMode) {
+        if mode == self.window_mode.mode() {
+            return;
+        }
+        let window = self.display.gl_window();
+        let restore = self.window_mode.switch(mode, window_rect(&window));
+        match mode {
+            WindowMode::Windowed => {
+                window.set_fullscreen(None);
+                window.set_decorations(true);
+                if let Some(rect) = restore {
+                    window.set_inner_size(LogicalSize::new(rect.width, rect.height));
+                    window.set_position(LogicalPosition::new(rect.x, rect.y));
+                }
+            }
+            WindowMode::Borderless => {
+                let monitor = window.get_current_monitor();
+                let hidpi_factor = monitor.get_hidpi_factor();
+                window.set_fullscreen(None);
+                window.set_decorations(false);
+                window.set_position(monitor.get_position().to_logical(hidpi_factor));
+                window.set_inner_size(monitor.get_dimensions().to_logical(hidpi_factor));
+            }
+            WindowMode::Exclusive => window.set_fullscreen(Some(window.get_current_monitor())),
+        }
+        info!("Window mode: {}", mode);
+    }
+
+    /// Changes what is shown at the mouse position.
+    pub fn set_cursor(&mut self, cursor: Cursor) {
+        {
+            let window = self.display.gl_window();
+            match cursor.system() {
+                Some(system) => {
+                    window.set_cursor(mouse_cursor(system));
+                    window.hide_cursor(false);
+                }
+                None => window.hide_cursor(true),
+            }
+        }
+        self.cursor = cursor;
+    }
+
+    /// Hides the cursor, or shows the system arrow.
+    pub fn hide_cursor(&mut self, hidden: bool) {
+        self.set_cursor(if hidden {
+            Cursor::Hidden
+        } else {
+            Cursor::default()
+        });
+    }
+
+    pub fn cursor(&self) -> &Cursor {
+        &self.cursor
+    }
+
+    /// Sets the window's icon from RGBA pixel data with
+    /// 8 bits per channel, in rows from top to bottom.
+    pub fn set_icon(&self, rgba: &[u8], width: u32, height: u32) -> Result<(), glutin::BadIcon> {
+        let icon = glutin::Icon::from_rgba(rgba.to_vec(), width, height)?;
+        self.display.gl_window().set_window_icon(Some(icon));
+        Ok(())
     }
-    frame.finish().unwrap();
 }
 
-fn render_obj(ctx: &RenderContext, frame: &mut glium::Frame, object: &Object<_RenderBackend>) {
-    let (matrix, modelview) = mvp_matrix(object);
+/// Returns glutin's cursor for `cursor`.
+fn mouse_cursor(cursor: SystemCursor) -> glutin::MouseCursor {
+    match cursor {
+        SystemCursor::Arrow => glutin::MouseCursor::Default,
+        SystemCursor::Crosshair => glutin::MouseCursor::Crosshair,
+        SystemCursor::Hand => glutin::MouseCursor::Hand,
+        SystemCursor::Text => glutin::MouseCursor::Text,
+        SystemCursor::Wait => glutin::MouseCursor::Wait,
+        SystemCursor::Move => glutin::MouseCursor::Move,
+        SystemCursor::NotAllowed => glutin::MouseCursor::NotAllowed,
+        SystemCursor::ResizeHorizontal => glutin::MouseCursor::EwResize,
+        SystemCursor::ResizeVertical => glutin::MouseCursor::NsResize,
+    }
+}
+
+/// Returns where `window` is, if the platform can tell.
+fn window_rect(window: &glutin::Window) -> Option<WindowRect> {
+    let position = window.get_outer_position()?;
+    let size = window.get_inner_size()?;
+    Some(WindowRect {
+        x: position.x,
+        y: position.y,
+        width: size.width,
+        height: size.height,
+    })
+}
+
+/// Submit phase: draws every extracted object.
+pub fn submit(ctx: &mut RenderContext, world: &World<_RenderBackend>, frame: &RenderWorld) {
+    let mut target = ctx.display.draw();
+    match ctx.color_load {
+        LoadOp::Clear => {
+            let [r, g, b, a] = frame.clear_color(ctx.clear_color);
+            target.clear_color_and_depth((r, g, b, a), ctx.depth_mode.clear_depth());
+        }
+        LoadOp::Load | LoadOp::DontCare => target.clear_depth(ctx.depth_mode.clear_depth()),
+    }
+    ctx.stats.begin_frame();
+    let objects = world.get_objs();
+    for draw in &frame.draws {
+        let index_count = render_obj(ctx, &mut target, frame, &objects[draw.object_index], draw);
+        ctx.stats.record_draw(index_count);
+    }
+
+    // Viewmodels are drawn over the scene
+    if !frame.viewmodel_draws.is_empty() {
+        target.clear_depth(ctx.depth_mode.clear_depth());
+        for draw in &frame.viewmodel_draws {
+            let index_count =
+                render_obj(ctx, &mut target, frame, &objects[draw.object_index], draw);
+            ctx.stats.record_draw(index_count);
+        }
+    }
+    // Captured before presenting, since the front buffer
+    // isn't reliably readable after a swap
+    if ctx.capture_requested {
+        ctx.capture_requested = false;
+        match capture_frame(ctx, &target) {
+            Ok(frame) => ctx.captured_frame = Some(frame),
+            Err(err) => error!("Failed to capture frame: {:?}", err),
+        }
+    }
+
+    // Commands are submitted as the frame is swapped
+    ctx.latency.submitted(Instant::now());
+    target.finish().unwrap();
+    ctx.latency.presented(Instant::now());
+    ctx.stats.latency = ctx.latency.average();
+}
+
+/// Copies the unpresented frame into a texture and reads it back.
+fn capture_frame(
+    ctx: &RenderContext,
+    target: &glium::Frame,
+) -> Result<CapturedFrame, glium::texture::TextureCreationError> {
+    let (width, height) = target.get_dimensions();
+    let texture = glium::texture::Texture2d::empty(&ctx.display, width, height)?;
+    target.blit_whole_color_to(
+        &texture.as_surface(),
+        &glium::BlitTarget {
+            left: 0,
+            bottom: 0,
+            width: width as i32,
+            height: height as i32,
+        },
+        glium::uniforms::MagnifySamplerFilter::Nearest,
+    );
+    let image: glium::texture::RawImage2d<u8> = texture.read();
+    Ok(CapturedFrame::from_bottom_rows(
+        image.width,
+        image.height,
+        &image.data,
+    ))
+}
+
+/// Renders the object, returning the number of indices drawn
+fn render_obj(
+    ctx: &RenderContext,
+    target: &mut glium::Frame,
+    frame: &RenderWorld,
+    object: &Object<_RenderBackend>,
+    draw: &DrawItem,
+) -> usize {
     let (matrix, modelview) = unsafe {
-        let matrix = std::mem::transmute::<_, [[f32; 4]; 4]>(matrix);
-        let modelview = std::mem::transmute::<_, [[f32; 4]; 4]>(modelview);
+        let matrix = std::mem::transmute::<_, [[f32; 4]; 4]>(draw.matrix);
+        let modelview = std::mem::transmute::<_, [[f32; 4]; 4]>(draw.modelview);
         (matrix, modelview)
     };
+    let light = |v: Vec3| [v.x, v.y, v.z];
+    // Objects the light doesn't reach only get ambient light
+    let light_color = if draw.lit {
+        frame.lighting.light_color
+    } else {
+        vec3(0.0, 0.0, 0.0)
+    };
     let uniforms = uniform! {
         matrix: matrix,
-        modelview: modelview
+        modelview: modelview,
+        light_dir: light(frame.light_direction),
+        light_color: light(light_color),
+        ambient_color: light(frame.lighting.ambient_color)
     };
 
-    let draw_params = glium::DrawParameters {
-        depth: glium::Depth {
-            test: glium::draw_parameters::DepthTest::IfLess,
-            write: true,
+    let additive = glium::BlendingFunction::Addition {
+        source: glium::LinearBlendingFactor::One,
+        destination: glium::LinearBlendingFactor::One,
+    };
+    let draw_params = if frame.debug_view.is_additive() {
+        glium::DrawParameters {
+            blend: glium::Blend {
+                color: additive,
+                alpha: additive,
+                ..Default::default()
+            },
             ..Default::default()
-        },
-        ..Default::default()
+        }
+    } else {
+        glium::DrawParameters {
+            depth: glium::Depth {
+                test: if ctx.depth_mode.is_reversed() {
+                    glium::draw_parameters::DepthTest::IfMore
+                } else {
+                    glium::draw_parameters::DepthTest::IfLess
+                },
+                write: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
     };
+    let program = ctx
+        .debug_programs
+        .iter()
+        .find(|&&(view, _)| view == frame.debug_view)
+        .map(|&(_, ref program)| program)
+        .unwrap_or(&ctx.program);
     let model = &ctx.models[object.render.model_index];
-    frame
+    target
         .draw(
             &model.vertices,
             &model.indices,
-            &ctx.program,
+            program,
             &uniforms,
             &draw_params,
         )
         .unwrap();
+    model.indices.len()
 }
 
 /// Vertex and index buffers
@@ -119,12 +484,42 @@ pub struct ObjectRender {
     model_index: usize,
 }
 
-fn compile_program(display: &glium::Display) -> glium::Program {
+/// Queries the limits and extensions of the OpenGL context.
+fn device_features(display: &glium::Display) -> DeviceFeatures {
+    let capabilities = display.get_capabilities();
+    let version = display.get_opengl_version();
+    let max_samples = capabilities.max_samples.unwrap_or(1) as u8;
+    DeviceFeatures {
+        max_texture_size: capabilities.max_viewport_dims.0 as u32,
+        max_anisotropy: display
+            .get_max_anisotropy_support()
+            .map(|anisotropy| anisotropy as f32)
+            .unwrap_or(1.0),
+        max_samples,
+        sample_counts: counts_up_to(max_samples),
+        storage_buffers: *version >= glium::Version(glium::Api::Gl, 4, 3),
+        tessellation: capabilities.max_patch_vertices.is_some(),
+        geometry_shaders: *version >= glium::Version(glium::Api::Gl, 3, 2),
+        multi_draw_indirect: *version >= glium::Version(glium::Api::Gl, 4, 3),
+    }
+}
+
+/// Returns a mask of the sample counts up to `max`, any
+/// of which can be requested for the default framebuffer.
+fn counts_up_to(max: u8) -> u8 {
+    (0..8)
+        .map(|bit| 1u8 << bit)
+        .filter(|&count| count <= max)
+        .fold(0, |mask, count| mask | count)
+}
+
+/// Compiles a program drawing models with the given fragment shader.
+fn compile_program(display: &glium::Display, fragment_shader: &str) -> glium::Program {
     // Load from GLSL instead of compiled SPIR-V for now
     glium::Program::from_source(
         display,
-        include_str!("../../shaders/model.glium.vert"),
-        include_str!("../../shaders/model.glium.frag"),
+        &shader_constants::inject(include_str!("../../shaders/model.glium.vert")),
+        &shader_constants::inject(fragment_shader),
         None,
     ).unwrap()
 }
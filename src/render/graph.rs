@@ -0,0 +1,753 @@
+//! Render graphs.
+//!
+//! Each frame, passes are declared with the attachments they
+//! read and write, and compiling the graph works out the rest:
+//!
+//! - Passes are ordered so every pass runs after the passes
+//!   writing what it reads. Passes writing the same attachment
+//!   run in the order they were added.
+//! - Passes which contribute nothing to an imported attachment
+//!   are culled.
+//! - Transient attachments, which only live within the frame,
+//!   are assigned images. Attachments with the same description
+//!   whose lifetimes don't overlap share an image.
+//! - Each pass gets the barriers transitioning its attachments
+//!   from their previous use.
+//!
+//! Imported attachments are owned outside the graph, such as
+//! the swapchain image, and are left in their final state.
+//!
+//! `to_dot()` draws a compiled graph with Graphviz, for
+//! checking the order and barriers it ended up with.
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// Identifies an attachment of a `RenderGraph`.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AttachmentId(pub usize);
+
+/// Identifies a pass of a `RenderGraph`.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PassId(pub usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttachmentKind {
+    Color,
+    Depth,
+}
+
+/// Describes the image behind an attachment. The backend
+/// picks the format from the kind.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AttachmentDesc {
+    pub kind: AttachmentKind,
+    pub width: u32,
+    pub height: u32,
+    pub samples: u8,
+}
+
+impl AttachmentDesc {
+    pub fn color(width: u32, height: u32) -> AttachmentDesc {
+        AttachmentDesc {
+            kind: AttachmentKind::Color,
+            width,
+            height,
+            samples: 1,
+        }
+    }
+
+    pub fn depth(width: u32, height: u32) -> AttachmentDesc {
+        AttachmentDesc {
+            kind: AttachmentKind::Depth,
+            ..AttachmentDesc::color(width, height)
+        }
+    }
+
+    pub fn with_samples(mut self, samples: u8) -> Self {
+        self.samples = samples;
+        self
+    }
+}
+
+/// How an attachment is being used, which
+/// determines its layout and access.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttachmentState {
+    /// Contents are undefined and may be discarded
+    Undefined,
+    /// Written as a color attachment
+    ColorOutput,
+    /// Tested and written as a depth attachment
+    DepthOutput,
+    /// Read by shaders
+    Sampled,
+    /// Ready to be presented
+    Present,
+}
+
+impl AttachmentState {
+    fn is_write(self) -> bool {
+        match self {
+            AttachmentState::ColorOutput | AttachmentState::DepthOutput => true,
+            _ => false,
+        }
+    }
+}
+
+/// Moves an attachment from one state to another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Barrier {
+    pub attachment: AttachmentId,
+    pub from: AttachmentState,
+    pub to: AttachmentState,
+}
+
+/// Describes a pass by the attachments it uses. A pass
+/// writing an attachment it also reads keeps its contents.
+#[derive(Clone, Debug)]
+pub struct PassDesc {
+    pub name: String,
+    pub reads: Vec<AttachmentId>,
+    pub writes: Vec<AttachmentId>,
+}
+
+impl PassDesc {
+    pub fn new(name: &str) -> PassDesc {
+        PassDesc {
+            name: name.to_string(),
+            reads: Vec::new(),
+            writes: Vec::new(),
+        }
+    }
+
+    pub fn with_read(mut self, attachment: AttachmentId) -> Self {
+        self.reads.push(attachment);
+        self
+    }
+
+    pub fn with_write(mut self, attachment: AttachmentId) -> Self {
+        self.writes.push(attachment);
+        self
+    }
+}
+
+/// An error produced when compiling a render graph.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GraphError {
+    /// A pass uses an attachment which was not created
+    UnknownAttachment { pass: String, attachment: usize },
+    /// A pass reads a transient attachment no pass writes
+    Unwritten { pass: String, attachment: String },
+    /// The passes depend on each other in a cycle
+    Cycle(Vec<String>),
+    /// A pass names an attachment the graph doesn't have
+    UnknownResource { pass: String, resource: String },
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GraphError::UnknownAttachment { pass, attachment } => {
+                write!(f, "pass {} uses unknown attachment {}", pass, attachment)
+            }
+            GraphError::Unwritten { pass, attachment } => write!(
+                f,
+                "pass {} reads {}, which no pass writes",
+                pass, attachment
+            ),
+            GraphError::Cycle(passes) => {
+                write!(f, "passes depend on each other: {}", passes.join(", "))
+            }
+            GraphError::UnknownResource { pass, resource } => {
+                write!(f, "pass {} uses unknown resource {}", pass, resource)
+            }
+        }
+    }
+}
+
+struct Attachment {
+    name: String,
+    desc: AttachmentDesc,
+    /// The state imported attachments are left in
+    imported: Option<AttachmentState>,
+}
+
+/// The passes of a frame and the attachments they use.
+pub struct RenderGraph {
+    attachments: Vec<Attachment>,
+    passes: Vec<PassDesc>,
+}
+
+impl RenderGraph {
+    pub fn new() -> RenderGraph {
+        RenderGraph {
+            attachments: Vec::new(),
+            passes: Vec::new(),
+        }
+    }
+
+    /// Adds an attachment which only lives within the frame.
+    pub fn create_attachment(&mut self, name: &str, desc: AttachmentDesc) -> AttachmentId {
+        self.add_attachment(name, desc, None)
+    }
+
+    /// Adds an attachment owned outside the graph, which is
+    /// transitioned to `final_state` after the last pass.
+    pub fn import_attachment(
+        &mut self,
+        name: &str,
+        desc: AttachmentDesc,
+        final_state: AttachmentState,
+    ) -> AttachmentId {
+        self.add_attachment(name, desc, Some(final_state))
+    }
+
+    fn add_attachment(
+        &mut self,
+        name: &str,
+        desc: AttachmentDesc,
+        imported: Option<AttachmentState>,
+    ) -> AttachmentId {
+        self.attachments.push(Attachment {
+            name: name.to_string(),
+            desc,
+            imported,
+        });
+        AttachmentId(self.attachments.len() - 1)
+    }
+
+    pub fn add_pass(&mut self, pass: PassDesc) -> PassId {
+        self.passes.push(pass);
+        PassId(self.passes.len() - 1)
+    }
+
+    pub fn pass(&self, id: PassId) -> &PassDesc {
+        &self.passes[id.0]
+    }
+
+    pub fn attachment_desc(&self, id: AttachmentId) -> AttachmentDesc {
+        self.attachments[id.0].desc
+    }
+
+    pub fn attachment_name(&self, id: AttachmentId) -> &str {
+        &self.attachments[id.0].name
+    }
+
+    /// Finds an attachment by name.
+    pub fn attachment(&self, name: &str) -> Option<AttachmentId> {
+        self.attachments
+            .iter()
+            .position(|attachment| attachment.name == name)
+            .map(AttachmentId)
+    }
+
+    /// Orders the passes and assigns images and barriers.
+    pub fn compile(&self) -> Result<CompiledGraph, GraphError> {
+        self.validate()?;
+        let live = self.live_passes();
+        let order = self.order(&live)?;
+        let lifetimes = self.lifetimes(&order);
+        let images = self.assign_images(&lifetimes);
+        let (steps, final_barriers) = self.barriers(&order, &images.1);
+        Ok(CompiledGraph {
+            steps,
+            final_barriers,
+            image_descs: images.0,
+            attachment_images: images.1,
+            lifetimes,
+        })
+    }
+
+    /// Describes `compiled`, which must have been compiled
+    /// from this graph, in the Graphviz DOT language.
+    ///
+    /// Passes are boxes numbered in the order they run, and
+    /// culled passes are dashed. Attachments are ellipses,
+    /// labeled with their image and the steps they live for.
+    /// Edges go from attachments to the passes reading them
+    /// and from passes to the attachments they write, labeled
+    /// with the barrier before the pass.
+    pub fn to_dot(&self, compiled: &CompiledGraph) -> String {
+        let mut dot = String::from("digraph frame {\n    rankdir=LR;\n");
+        let mut steps = vec![None; self.passes.len()];
+        for (index, step) in compiled.steps.iter().enumerate() {
+            steps[step.pass.0] = Some((index, step));
+        }
+
+        for (pass, desc) in self.passes.iter().enumerate() {
+            dot += &match steps[pass] {
+                Some((index, _)) => format!(
+                    "    pass{} [shape=box, label=\"{}: {}\"];\n",
+                    pass,
+                    index,
+                    escape(&desc.name)
+                ),
+                None => format!(
+                    "    pass{} [shape=box, style=dashed, label=\"{} (culled)\"];\n",
+                    pass,
+                    escape(&desc.name)
+                ),
+            };
+        }
+
+        for (id, attachment) in self.attachments.iter().enumerate() {
+            let desc = attachment.desc;
+            let mut label = format!(
+                "{}\\n{:?} {}x{}",
+                escape(&attachment.name),
+                desc.kind,
+                desc.width,
+                desc.height
+            );
+            if desc.samples > 1 {
+                label += &format!(" x{}", desc.samples);
+            }
+            label += &match (attachment.imported, compiled.image(AttachmentId(id))) {
+                (Some(state), _) => format!("\\nimported, left {:?}", state),
+                (None, Some(image)) => {
+                    let (first, last) = compiled.lifetimes[id].unwrap();
+                    format!("\\nimage {}, steps {}-{}", image, first, last)
+                }
+                (None, None) => "\\nunused".to_string(),
+            };
+            // Imported attachments are drawn with a double border
+            let style = if attachment.imported.is_some() {
+                ", peripheries=2"
+            } else {
+                ""
+            };
+            dot += &format!("    attachment{} [label=\"{}\"{}];\n", id, label, style);
+        }
+
+        for (pass, desc) in self.passes.iter().enumerate() {
+            let barrier = |id: AttachmentId| {
+                steps[pass]
+                    .and_then(|(_, step)| step.barriers.iter().find(|b| b.attachment == id))
+                    .map_or(String::new(), |b| {
+                        format!(" [label=\"{:?} -> {:?}\"]", b.from, b.to)
+                    })
+            };
+            for &id in &desc.reads {
+                if !desc.writes.contains(&id) {
+                    dot += &format!("    attachment{} -> pass{}{};\n", id.0, pass, barrier(id));
+                }
+            }
+            for &id in &desc.writes {
+                dot += &format!("    pass{} -> attachment{}{};\n", pass, id.0, barrier(id));
+            }
+        }
+        dot += "}\n";
+        dot
+    }
+
+    fn validate(&self) -> Result<(), GraphError> {
+        for pass in &self.passes {
+            for &id in pass.reads.iter().chain(&pass.writes) {
+                if id.0 >= self.attachments.len() {
+                    return Err(GraphError::UnknownAttachment {
+                        pass: pass.name.clone(),
+                        attachment: id.0,
+                    });
+                }
+            }
+            for &id in &pass.reads {
+                let attachment = &self.attachments[id.0];
+                if attachment.imported.is_none() && self.writers(id).is_empty() {
+                    return Err(GraphError::Unwritten {
+                        pass: pass.name.clone(),
+                        attachment: attachment.name.clone(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the passes writing `id`, in the order they were added.
+    fn writers(&self, id: AttachmentId) -> Vec<usize> {
+        (0..self.passes.len())
+            .filter(|&pass| self.passes[pass].writes.contains(&id))
+            .collect()
+    }
+
+    /// Returns the passes `pass` has to run after: the writers
+    /// of what it reads, and the previous writers of what it writes.
+    fn dependencies(&self, pass: usize) -> BTreeSet<usize> {
+        let desc = &self.passes[pass];
+        let mut dependencies = BTreeSet::new();
+        for &id in &desc.reads {
+            dependencies.extend(self.writers(id).into_iter().filter(|&p| p != pass));
+        }
+        for &id in &desc.writes {
+            dependencies.extend(self.writers(id).into_iter().take_while(|&p| p != pass));
+        }
+        dependencies
+    }
+
+    /// Marks the passes which lead to an imported attachment.
+    fn live_passes(&self) -> Vec<bool> {
+        let mut live = vec![false; self.passes.len()];
+        let mut stack = (0..self.passes.len())
+            .filter(|&pass| {
+                self.passes[pass]
+                    .writes
+                    .iter()
+                    .any(|id| self.attachments[id.0].imported.is_some())
+            })
+            .collect::<Vec<_>>();
+        while let Some(pass) = stack.pop() {
+            if !live[pass] {
+                live[pass] = true;
+                stack.extend(self.dependencies(pass));
+            }
+        }
+        live
+    }
+
+    /// Sorts the live passes so each runs after its dependencies.
+    /// Of the passes which are ready, the first added runs first.
+    fn order(&self, live: &[bool]) -> Result<Vec<usize>, GraphError> {
+        let dependencies = (0..self.passes.len())
+            .map(|pass| self.dependencies(pass))
+            .collect::<Vec<_>>();
+        let mut remaining = (0..self.passes.len())
+            .filter(|&pass| live[pass])
+            .collect::<BTreeSet<_>>();
+        let mut order = Vec::with_capacity(remaining.len());
+
+        while !remaining.is_empty() {
+            let ready = remaining.iter().cloned().find(|&pass| {
+                dependencies[pass]
+                    .iter()
+                    .all(|dep| !remaining.contains(dep))
+            });
+            match ready {
+                Some(pass) => {
+                    remaining.remove(&pass);
+                    order.push(pass);
+                }
+                None => {
+                    return Err(GraphError::Cycle(
+                        remaining
+                            .iter()
+                            .map(|&pass| self.passes[pass].name.clone())
+                            .collect(),
+                    ));
+                }
+            }
+        }
+        Ok(order)
+    }
+
+    /// Returns the first and last step of `order`
+    /// each attachment is used in.
+    fn lifetimes(&self, order: &[usize]) -> Vec<Option<(usize, usize)>> {
+        let mut lifetimes = vec![None; self.attachments.len()];
+        for (step, &pass) in order.iter().enumerate() {
+            let desc = &self.passes[pass];
+            for &id in desc.reads.iter().chain(&desc.writes) {
+                let lifetime = lifetimes[id.0].get_or_insert((step, step));
+                lifetime.1 = step;
+            }
+        }
+        lifetimes
+    }
+
+    /// Assigns an image to each transient attachment which is
+    /// used, sharing images between attachments with equal
+    /// descriptions whose lifetimes don't overlap.
+    fn assign_images(
+        &self,
+        lifetimes: &[Option<(usize, usize)>],
+    ) -> (Vec<AttachmentDesc>, Vec<Option<usize>>) {
+        let mut transient = (0..self.attachments.len())
+            .filter(|&id| self.attachments[id].imported.is_none())
+            .filter_map(|id| lifetimes[id].map(|lifetime| (lifetime, id)))
+            .collect::<Vec<_>>();
+        transient.sort();
+
+        let mut image_descs = Vec::new();
+        // The last step each image is used in
+        let mut image_ends: Vec<usize> = Vec::new();
+        let mut attachment_images = vec![None; self.attachments.len()];
+        for ((first, last), id) in transient {
+            let desc = self.attachments[id].desc;
+            let free = (0..image_descs.len())
+                .find(|&image| image_descs[image] == desc && image_ends[image] < first);
+            let image = match free {
+                Some(image) => image,
+                None => {
+                    image_descs.push(desc);
+                    image_ends.push(last);
+                    image_descs.len() - 1
+                }
+            };
+            image_ends[image] = last;
+            attachment_images[id] = Some(image);
+        }
+        (image_descs, attachment_images)
+    }
+
+    /// Returns the barriers before each pass of `order`,
+    /// and those leaving imported attachments in their
+    /// final states.
+    ///
+    /// An attachment sharing an image with an earlier one
+    /// starts in the state the earlier one was left in, so
+    /// its first write waits for the earlier reads.
+    fn barriers(
+        &self,
+        order: &[usize],
+        attachment_images: &[Option<usize>],
+    ) -> (Vec<Step>, Vec<Barrier>) {
+        let mut states = vec![AttachmentState::Undefined; self.attachments.len()];
+        // The attachment which last used each image
+        let mut image_users = vec![None; self.attachments.len()];
+        let mut transition =
+            |id: AttachmentId, to: AttachmentState, barriers: &mut Vec<Barrier>| {
+                if let Some(image) = attachment_images.get(id.0).and_then(|&image| image) {
+                    if let Some(previous) = image_users[image] {
+                        if previous != id.0 {
+                            states[id.0] = states[previous];
+                        }
+                    }
+                    image_users[image] = Some(id.0);
+                }
+                let from = states[id.0];
+                // Writes need a barrier even in the same state,
+                // so the previous write finishes first
+                if from != to || to.is_write() {
+                    barriers.push(Barrier {
+                        attachment: id,
+                        from,
+                        to,
+                    });
+                }
+                states[id.0] = to;
+            };
+
+        let mut steps = Vec::with_capacity(order.len());
+        for &pass in order {
+            let desc = &self.passes[pass];
+            let mut barriers = Vec::new();
+            for &id in &desc.reads {
+                if !desc.writes.contains(&id) {
+                    transition(id, AttachmentState::Sampled, &mut barriers);
+                }
+            }
+            for &id in &desc.writes {
+                let state = match self.attachments[id.0].desc.kind {
+                    AttachmentKind::Color => AttachmentState::ColorOutput,
+                    AttachmentKind::Depth => AttachmentState::DepthOutput,
+                };
+                transition(id, state, &mut barriers);
+            }
+            steps.push(Step {
+                pass: PassId(pass),
+                barriers,
+            });
+        }
+
+        let mut final_barriers = Vec::new();
+        for (id, attachment) in self.attachments.iter().enumerate() {
+            if let Some(state) = attachment.imported {
+                transition(AttachmentId(id), state, &mut final_barriers);
+            }
+        }
+        (steps, final_barriers)
+    }
+}
+
+/// Escapes a name for a quoted DOT string.
+fn escape(name: &str) -> String {
+    name.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// A pass to run and the barriers to record before it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    pub pass: PassId,
+    pub barriers: Vec<Barrier>,
+}
+
+/// The result of compiling a `RenderGraph`.
+#[derive(Clone, Debug)]
+pub struct CompiledGraph {
+    /// Passes in the order they run. Culled passes are left out.
+    pub steps: Vec<Step>,
+    /// Barriers to record after the last step
+    pub final_barriers: Vec<Barrier>,
+    /// The images needed for transient attachments
+    pub image_descs: Vec<AttachmentDesc>,
+    /// The index into `image_descs` of each attachment,
+    /// or `None` if it is imported or unused
+    pub attachment_images: Vec<Option<usize>>,
+    /// The first and last step each attachment
+    /// is used in, or `None` if it is unused
+    pub lifetimes: Vec<Option<(usize, usize)>>,
+}
+
+impl CompiledGraph {
+    /// Returns the index of the image behind `attachment`.
+    pub fn image(&self, attachment: AttachmentId) -> Option<usize> {
+        self.attachment_images[attachment.0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_culls_and_aliases() {
+        let mut graph = RenderGraph::new();
+        let backbuffer = graph.import_attachment(
+            "backbuffer",
+            AttachmentDesc::color(64, 64),
+            AttachmentState::Present,
+        );
+        let depth = graph.create_attachment("depth", AttachmentDesc::depth(64, 64));
+        let scene = graph.create_attachment("scene", AttachmentDesc::color(64, 64));
+        let bloom = graph.create_attachment("bloom", AttachmentDesc::color(64, 64));
+        let blurred = graph.create_attachment("blurred", AttachmentDesc::color(64, 64));
+        let unused = graph.create_attachment("unused", AttachmentDesc::color(64, 64));
+
+        // Added out of order, and with a pass nothing needs
+        let post = graph.add_pass(
+            PassDesc::new("post")
+                .with_read(blurred)
+                .with_write(backbuffer),
+        );
+        let blur = graph.add_pass(PassDesc::new("blur").with_read(bloom).with_write(blurred));
+        let bright = graph.add_pass(PassDesc::new("bright").with_read(scene).with_write(bloom));
+        graph.add_pass(PassDesc::new("debug").with_read(depth).with_write(unused));
+        let main = graph.add_pass(PassDesc::new("main").with_write(scene).with_write(depth));
+
+        let compiled = graph.compile().unwrap();
+        let passes = compiled
+            .steps
+            .iter()
+            .map(|step| step.pass)
+            .collect::<Vec<_>>();
+        assert_eq!(passes, vec![main, bright, blur, post]);
+
+        // Scene is last used by bright, so blur can write over it
+        assert_eq!(compiled.image_descs.len(), 3);
+        assert_eq!(compiled.image(blurred), compiled.image(scene));
+        assert_ne!(compiled.image(bloom), compiled.image(scene));
+        assert_eq!(compiled.image(backbuffer), None);
+        assert_eq!(compiled.image(unused), None);
+
+        assert_eq!(
+            compiled.steps[1].barriers,
+            vec![
+                Barrier {
+                    attachment: scene,
+                    from: AttachmentState::ColorOutput,
+                    to: AttachmentState::Sampled,
+                },
+                Barrier {
+                    attachment: bloom,
+                    from: AttachmentState::Undefined,
+                    to: AttachmentState::ColorOutput,
+                },
+            ]
+        );
+        assert_eq!(
+            compiled.final_barriers,
+            vec![Barrier {
+                attachment: backbuffer,
+                from: AttachmentState::ColorOutput,
+                to: AttachmentState::Present,
+            }]
+        );
+    }
+
+    #[test]
+    fn aliased_barriers() {
+        let mut graph = RenderGraph::new();
+        let output = graph.import_attachment(
+            "output",
+            AttachmentDesc::color(64, 64),
+            AttachmentState::Present,
+        );
+        let scene = graph.create_attachment("scene", AttachmentDesc::color(64, 64));
+        let bloom = graph.create_attachment("bloom", AttachmentDesc::color(64, 64));
+        let blurred = graph.create_attachment("blurred", AttachmentDesc::color(64, 64));
+        graph.add_pass(PassDesc::new("main").with_write(scene));
+        graph.add_pass(PassDesc::new("bright").with_read(scene).with_write(bloom));
+        graph.add_pass(PassDesc::new("blur").with_read(bloom).with_write(blurred));
+        graph.add_pass(PassDesc::new("post").with_read(blurred).with_write(output));
+
+        let compiled = graph.compile().unwrap();
+        assert_eq!(compiled.image(blurred), compiled.image(scene));
+        // Blur writes over scene, which bright was sampling
+        assert_eq!(
+            compiled.steps[2].barriers[1],
+            Barrier {
+                attachment: blurred,
+                from: AttachmentState::Sampled,
+                to: AttachmentState::ColorOutput,
+            }
+        );
+    }
+
+    #[test]
+    fn dot() {
+        let mut graph = RenderGraph::new();
+        let output = graph.import_attachment(
+            "output",
+            AttachmentDesc::color(64, 64),
+            AttachmentState::Present,
+        );
+        let scene = graph.create_attachment("scene", AttachmentDesc::color(64, 64));
+        let unused = graph.create_attachment("unused", AttachmentDesc::color(64, 64));
+        graph.add_pass(PassDesc::new("main").with_write(scene));
+        graph.add_pass(PassDesc::new("post").with_read(scene).with_write(output));
+        graph.add_pass(PassDesc::new("debug").with_read(scene).with_write(unused));
+
+        let compiled = graph.compile().unwrap();
+        assert_eq!(
+            graph.to_dot(&compiled),
+            r#"digraph frame {
+    rankdir=LR;
+    pass0 [shape=box, label="0: main"];
+    pass1 [shape=box, label="1: post"];
+    pass2 [shape=box, style=dashed, label="debug (culled)"];
+    attachment0 [label="output\nColor 64x64\nimported, left Present", peripheries=2];
+    attachment1 [label="scene\nColor 64x64\nimage 0, steps 0-1"];
+    attachment2 [label="unused\nColor 64x64\nunused"];
+    pass0 -> attachment1 [label="Undefined -> ColorOutput"];
+    attachment1 -> pass1 [label="ColorOutput -> Sampled"];
+    pass1 -> attachment0 [label="Undefined -> ColorOutput"];
+    attachment1 -> pass2;
+    pass2 -> attachment2;
+}
+"#
+        );
+    }
+
+    #[test]
+    fn cycle() {
+        let mut graph = RenderGraph::new();
+        let output = graph.import_attachment(
+            "output",
+            AttachmentDesc::color(64, 64),
+            AttachmentState::Present,
+        );
+        let a = graph.create_attachment("a", AttachmentDesc::color(64, 64));
+        let b = graph.create_attachment("b", AttachmentDesc::color(64, 64));
+        graph.add_pass(PassDesc::new("first").with_read(b).with_write(a));
+        graph.add_pass(
+            PassDesc::new("second")
+                .with_read(a)
+                .with_write(b)
+                .with_write(output),
+        );
+
+        assert_eq!(
+            graph.compile().unwrap_err(),
+            GraphError::Cycle(vec!["first".to_string(), "second".to_string()])
+        );
+    }
+}
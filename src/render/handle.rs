@@ -0,0 +1,274 @@
+//! Generational handles to renderer resources.
+//!
+//! Meshes, textures, pipelines, render targets and buffers are
+//! referred to by handles rather than by backend types, so code
+//! using the renderer doesn't change when backends do. A handle
+//! is the index of a slot and the generation of that slot when
+//! the resource was created. Destroying a resource frees its
+//! slot and increases the slot's generation, so a handle which
+//! outlives its resource is reported as stale instead of
+//! referring to whatever reuses the slot.
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+
+/// A kind of resource handles can refer to.
+pub trait Resource {
+    /// Names the kind in errors
+    const NAME: &'static str;
+}
+
+pub enum MeshResource {}
+pub enum TextureResource {}
+pub enum PipelineResource {}
+pub enum TargetResource {}
+pub enum BufferResource {}
+
+impl Resource for MeshResource {
+    const NAME: &'static str = "mesh";
+}
+
+impl Resource for TextureResource {
+    const NAME: &'static str = "texture";
+}
+
+impl Resource for PipelineResource {
+    const NAME: &'static str = "pipeline";
+}
+
+impl Resource for TargetResource {
+    const NAME: &'static str = "render target";
+}
+
+impl Resource for BufferResource {
+    const NAME: &'static str = "buffer";
+}
+
+pub type MeshHandle = Handle<MeshResource>;
+pub type TextureHandle = Handle<TextureResource>;
+pub type PipelineHandle = Handle<PipelineResource>;
+pub type TargetHandle = Handle<TargetResource>;
+pub type BufferHandle = Handle<BufferResource>;
+
+/// Identifies a resource of kind `R`.
+pub struct Handle<R> {
+    index: u32,
+    generation: u32,
+    marker: PhantomData<fn() -> R>,
+}
+
+impl<R> Handle<R> {
+    /// Creates the handle of the resource created
+    /// `generation` times before in slot `index`.
+    pub const fn new(index: u32, generation: u32) -> Handle<R> {
+        Handle {
+            index,
+            generation,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn index(self) -> usize {
+        self.index as usize
+    }
+
+    pub fn generation(self) -> u32 {
+        self.generation
+    }
+}
+
+// Implemented by hand, since deriving would require `R` to
+// implement the traits too
+impl<R> Clone for Handle<R> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<R> Copy for Handle<R> {}
+
+impl<R> PartialEq for Handle<R> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<R> Eq for Handle<R> {}
+
+impl<R> Hash for Handle<R> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<R: Resource> fmt::Debug for Handle<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}({}.{})", R::NAME, self.index, self.generation)
+    }
+}
+
+impl<R> fmt::Display for Handle<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.index, self.generation)
+    }
+}
+
+/// Use of a handle whose resource was destroyed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StaleHandle {
+    pub kind: &'static str,
+    pub index: usize,
+    pub generation: u32,
+}
+
+impl StaleHandle {
+    fn new<R: Resource>(handle: Handle<R>) -> StaleHandle {
+        StaleHandle {
+            kind: R::NAME,
+            index: handle.index(),
+            generation: handle.generation,
+        }
+    }
+}
+
+impl fmt::Display for StaleHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {}.{} was used after being destroyed",
+            self.kind, self.index, self.generation
+        )
+    }
+}
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+/// Resources of kind `R` stored by handle.
+pub struct HandleMap<R, T> {
+    slots: Vec<Slot<T>>,
+    /// Indices of empty slots
+    free: Vec<u32>,
+    marker: PhantomData<fn() -> R>,
+}
+
+impl<R: Resource, T> HandleMap<R, T> {
+    pub fn new() -> HandleMap<R, T> {
+        HandleMap {
+            slots: Vec::new(),
+            free: Vec::new(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Stores `value`, returning its handle.
+    pub fn insert(&mut self, value: T) -> Handle<R> {
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.value = Some(value);
+                Handle::new(index, slot.generation)
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    value: Some(value),
+                });
+                Handle::new(self.slots.len() as u32 - 1, 0)
+            }
+        }
+    }
+
+    pub fn get(&self, handle: Handle<R>) -> Result<&T, StaleHandle> {
+        match self.slots.get(handle.index()) {
+            Some(&Slot {
+                generation,
+                value: Some(ref value),
+            }) if generation == handle.generation => Ok(value),
+            _ => Err(StaleHandle::new(handle)),
+        }
+    }
+
+    pub fn get_mut(&mut self, handle: Handle<R>) -> Result<&mut T, StaleHandle> {
+        match self.slots.get_mut(handle.index()) {
+            Some(&mut Slot {
+                generation,
+                value: Some(ref mut value),
+            }) if generation == handle.generation => Ok(value),
+            _ => Err(StaleHandle::new(handle)),
+        }
+    }
+
+    pub fn contains(&self, handle: Handle<R>) -> bool {
+        self.get(handle).is_ok()
+    }
+
+    /// Returns the handle of the resource in slot `index`, if any.
+    pub fn handle(&self, index: usize) -> Option<Handle<R>> {
+        self.slots
+            .get(index)
+            .filter(|slot| slot.value.is_some())
+            .map(|slot| Handle::new(index as u32, slot.generation))
+    }
+
+    /// Takes the resource out, making its handle stale.
+    pub fn remove(&mut self, handle: Handle<R>) -> Result<T, StaleHandle> {
+        self.get(handle)?;
+        let slot = &mut self.slots[handle.index()];
+        slot.generation += 1;
+        self.free.push(handle.index);
+        Ok(slot.value.take().unwrap())
+    }
+
+    /// Returns the number of resources stored.
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Takes every resource out, for destroying them.
+    pub fn into_values(self) -> Vec<T> {
+        self.slots
+            .into_iter()
+            .filter_map(|slot| slot.value)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_handles() {
+        let mut meshes = HandleMap::<MeshResource, &str>::new();
+        let cube = meshes.insert("cube");
+        let sphere = meshes.insert("sphere");
+        assert_eq!(meshes.get(cube), Ok(&"cube"));
+
+        assert_eq!(meshes.remove(cube), Ok("cube"));
+        let error = StaleHandle {
+            kind: "mesh",
+            index: 0,
+            generation: 0,
+        };
+        assert_eq!(meshes.get(cube), Err(error));
+        assert_eq!(meshes.remove(cube), Err(error));
+
+        // The slot is reused with a new generation
+        let cone = meshes.insert("cone");
+        assert_eq!(cone.index(), cube.index());
+        assert_ne!(cone, cube);
+        assert_eq!(meshes.get(cube), Err(error));
+        assert_eq!(meshes.handle(0), Some(cone));
+        assert_eq!(meshes.len(), 2);
+        assert_eq!(meshes.get(sphere), Ok(&"sphere"));
+        assert_eq!(meshes.into_values(), vec!["cone", "sphere"]);
+    }
+}
@@ -0,0 +1,227 @@
+//! Passes added to the built-in frame by users.
+//!
+//! The built-in frame is described by a render graph with
+//! the engine's passes and resources. Injected passes are
+//! added to it at an `InjectionPoint`, and name the engine
+//! resources they use rather than holding attachment ids,
+//! so they keep working as the built-in frame changes.
+//!
+//! Passes at the same injection point run in the order they
+//! were added. Like any other pass in the graph, a pass which
+//! contributes nothing to the frame is culled.
+use super::*;
+
+/// The frame's color attachment, which is presented
+pub const COLOR: &str = "color";
+/// The frame's depth buffer
+pub const DEPTH: &str = "depth";
+
+/// Where in the built-in frame a pass runs.
+///
+/// There are no transparent or UI passes yet, so the last
+/// two points both follow the scene, in the order listed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InjectionPoint {
+    /// Before opaque objects are drawn, after the
+    /// color and depth attachments are cleared
+    BeforeOpaque,
+    /// After all objects and viewmodels are drawn
+    AfterTransparent,
+    /// After `AfterTransparent`, before the UI is drawn
+    BeforeUi,
+}
+
+impl InjectionPoint {
+    pub fn name(self) -> &'static str {
+        match self {
+            InjectionPoint::BeforeOpaque => "before opaque",
+            InjectionPoint::AfterTransparent => "after transparent",
+            InjectionPoint::BeforeUi => "before UI",
+        }
+    }
+}
+
+/// Describes a pass by the engine resources it uses.
+#[derive(Clone, Debug)]
+pub struct InjectedPass {
+    pub name: String,
+    pub point: InjectionPoint,
+    pub reads: Vec<String>,
+    pub writes: Vec<String>,
+    /// Layers of the objects the pass draws, for passes
+    /// which draw the scene, such as shadow, reflection or
+    /// minimap passes
+    pub layers: LayerMask,
+}
+
+impl InjectedPass {
+    pub fn new(name: &str, point: InjectionPoint) -> InjectedPass {
+        InjectedPass {
+            name: name.to_string(),
+            point,
+            reads: Vec::new(),
+            writes: Vec::new(),
+            layers: LayerMask::ALL,
+        }
+    }
+
+    pub fn with_read(mut self, resource: &str) -> Self {
+        self.reads.push(resource.to_string());
+        self
+    }
+
+    pub fn with_write(mut self, resource: &str) -> Self {
+        self.writes.push(resource.to_string());
+        self
+    }
+
+    pub fn with_layers(mut self, layers: LayerMask) -> Self {
+        self.layers = layers;
+        self
+    }
+}
+
+/// The camera of a frame, for injected passes
+/// drawing into the same space as the scene.
+#[derive(Clone, Copy, Debug)]
+pub struct CameraUniforms {
+    /// World to view space, relative to the camera
+    /// like object positions are
+    pub view: Mat4,
+    /// View to clip space, including any jitter
+    pub projection: Mat4,
+    pub view_projection: Mat4,
+}
+
+impl Default for CameraUniforms {
+    fn default() -> Self {
+        CameraUniforms {
+            view: num::one(),
+            projection: num::one(),
+            view_projection: num::one(),
+        }
+    }
+}
+
+/// The built-in frame with passes injected into it.
+pub struct FrameGraph {
+    pub graph: RenderGraph,
+    pub compiled: CompiledGraph,
+    /// The id of each injected pass, in the order given
+    pub injected: Vec<PassId>,
+}
+
+impl FrameGraph {
+    /// Returns whether the injected pass at `index` runs,
+    /// rather than being culled.
+    pub fn is_live(&self, index: usize) -> bool {
+        let id = self.injected[index];
+        self.compiled.steps.iter().any(|step| step.pass == id)
+    }
+
+    /// Describes the frame in the Graphviz DOT language.
+    pub fn to_dot(&self) -> String {
+        self.graph.to_dot(&self.compiled)
+    }
+}
+
+/// Describes the built-in frame with `passes` injected. Fails
+/// if a pass names a resource the engine doesn't provide, or
+/// if the passes can't be ordered.
+pub fn frame_graph<'a, I>(width: u32, height: u32, passes: I) -> Result<FrameGraph, GraphError>
+where
+    I: IntoIterator<Item = &'a InjectedPass>,
+{
+    let mut graph = RenderGraph::new();
+    let color = graph.import_attachment(
+        COLOR,
+        AttachmentDesc::color(width, height),
+        AttachmentState::Present,
+    );
+    let depth = graph.create_attachment(DEPTH, AttachmentDesc::depth(width, height));
+
+    let passes = passes.into_iter().collect::<Vec<_>>();
+    let mut injected = vec![PassId(0); passes.len()];
+    let mut inject = |graph: &mut RenderGraph, point: InjectionPoint| -> Result<(), GraphError> {
+        for (index, pass) in passes.iter().enumerate() {
+            if pass.point != point {
+                continue;
+            }
+            let mut desc = PassDesc::new(&pass.name);
+            for name in &pass.reads {
+                desc = desc.with_read(resource(graph, pass, name)?);
+            }
+            for name in &pass.writes {
+                desc = desc.with_write(resource(graph, pass, name)?);
+            }
+            injected[index] = graph.add_pass(desc);
+        }
+        Ok(())
+    };
+
+    inject(&mut graph, InjectionPoint::BeforeOpaque)?;
+    graph.add_pass(PassDesc::new("opaque").with_write(color).with_write(depth));
+    graph.add_pass(
+        PassDesc::new("viewmodels")
+            .with_write(color)
+            .with_write(depth),
+    );
+    inject(&mut graph, InjectionPoint::AfterTransparent)?;
+    inject(&mut graph, InjectionPoint::BeforeUi)?;
+
+    let compiled = graph.compile()?;
+    Ok(FrameGraph {
+        graph,
+        compiled,
+        injected,
+    })
+}
+
+fn resource(
+    graph: &RenderGraph,
+    pass: &InjectedPass,
+    name: &str,
+) -> Result<AttachmentId, GraphError> {
+    graph
+        .attachment(name)
+        .ok_or_else(|| GraphError::UnknownResource {
+            pass: pass.name.clone(),
+            resource: name.to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn injects_at_points() {
+        let passes = vec![
+            InjectedPass::new("outline", InjectionPoint::AfterTransparent)
+                .with_read(DEPTH)
+                .with_write(COLOR),
+            InjectedPass::new("sky", InjectionPoint::BeforeOpaque).with_write(COLOR),
+            InjectedPass::new("probe", InjectionPoint::BeforeUi).with_read(DEPTH),
+        ];
+        let frame = frame_graph(64, 64, &passes).unwrap();
+        let order = frame
+            .compiled
+            .steps
+            .iter()
+            .map(|step| frame.graph.pass(step.pass).name.as_str())
+            .collect::<Vec<_>>();
+        // The probe writes nothing, so it is culled
+        assert_eq!(order, vec!["sky", "opaque", "viewmodels", "outline"]);
+        assert!(frame.is_live(0));
+        assert!(!frame.is_live(2));
+
+        let unknown = InjectedPass::new("fog", InjectionPoint::BeforeUi).with_read("gbuffer");
+        assert_eq!(
+            frame_graph(64, 64, &[unknown]).err(),
+            Some(GraphError::UnknownResource {
+                pass: "fog".to_string(),
+                resource: "gbuffer".to_string(),
+            })
+        );
+    }
+}
@@ -0,0 +1,144 @@
+//! Input-to-present latency.
+//!
+//! Backends take three timestamps each frame: when input is
+//! sampled, when the frame's commands are submitted and when
+//! the frame is presented. The time between them shows how
+//! long input waits before it is seen on screen, and whether
+//! it waits on the CPU or behind the GPU.
+//!
+//! All three are taken on the CPU. "Presented" is when the
+//! backend's present call returns, not when the image reaches
+//! the display, so the time spent waiting in the present queue
+//! and for scanout is not included.
+//!
+//! In low latency mode (`GraphicsSettings::low_latency`) the
+//! previous frame is waited for on the GPU before input is
+//! sampled. The CPU then can't queue frames ahead of the GPU,
+//! so input is never sampled for a frame which would wait
+//! behind others, at the cost of overlapping less CPU and GPU
+//! work. It suits action games more than throughput does.
+use std::collections::VecDeque;
+use std::time::Instant;
+
+/// Number of frames averaged
+const HISTORY_LEN: usize = 60;
+
+/// The latency of a frame, in milliseconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LatencySample {
+    /// From sampling input to submitting the frame
+    pub input_to_submit: f64,
+    /// From submitting the frame to its present call returning
+    pub submit_to_present: f64,
+}
+
+impl LatencySample {
+    /// Returns the time from sampling input to presenting.
+    pub fn input_to_present(&self) -> f64 {
+        self.input_to_submit + self.submit_to_present
+    }
+}
+
+/// Measures the latency of recent frames.
+pub struct LatencyTracker {
+    input: Option<Instant>,
+    submit: Option<Instant>,
+    samples: VecDeque<LatencySample>,
+}
+
+impl LatencyTracker {
+    pub fn new() -> LatencyTracker {
+        LatencyTracker {
+            input: None,
+            submit: None,
+            samples: VecDeque::with_capacity(HISTORY_LEN),
+        }
+    }
+
+    /// Records that input for the next frame was sampled at `time`.
+    pub fn input_sampled(&mut self, time: Instant) {
+        self.input = Some(time);
+        self.submit = None;
+    }
+
+    /// Records that the frame was submitted at `time`.
+    pub fn submitted(&mut self, time: Instant) {
+        self.submit = Some(time);
+    }
+
+    /// Records that the present call for the frame returned at
+    /// `time`, returning its latency. Frames which weren't given
+    /// every timestamp, such as those rendered without sampling
+    /// input, are skipped.
+    pub fn presented(&mut self, time: Instant) -> Option<LatencySample> {
+        let (input, submit) = match (self.input.take(), self.submit.take()) {
+            (Some(input), Some(submit)) => (input, submit),
+            _ => return None,
+        };
+        let sample = LatencySample {
+            input_to_submit: millis_between(input, submit),
+            submit_to_present: millis_between(submit, time),
+        };
+        if self.samples.len() == HISTORY_LEN {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+        Some(sample)
+    }
+
+    /// Returns the latency of the last presented frame.
+    pub fn last(&self) -> Option<LatencySample> {
+        self.samples.back().cloned()
+    }
+
+    /// Returns the average latency of recent frames.
+    pub fn average(&self) -> Option<LatencySample> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let count = self.samples.len() as f64;
+        let sum = self
+            .samples
+            .iter()
+            .fold(LatencySample::default(), |sum, sample| LatencySample {
+                input_to_submit: sum.input_to_submit + sample.input_to_submit,
+                submit_to_present: sum.submit_to_present + sample.submit_to_present,
+            });
+        Some(LatencySample {
+            input_to_submit: sum.input_to_submit / count,
+            submit_to_present: sum.submit_to_present / count,
+        })
+    }
+}
+
+fn millis_between(start: Instant, end: Instant) -> f64 {
+    if end <= start {
+        return 0.0;
+    }
+    let elapsed = end - start;
+    (elapsed.as_secs() as f64) * 1000.0 + (elapsed.subsec_nanos() as f64) / 1_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn input_to_present() {
+        let mut latency = LatencyTracker::new();
+        let input = Instant::now();
+        latency.input_sampled(input);
+        latency.submitted(input + Duration::from_millis(4));
+        let sample = latency
+            .presented(input + Duration::from_millis(10))
+            .unwrap();
+        assert!((sample.input_to_submit - 4.0).abs() < 0.01);
+        assert!((sample.input_to_present() - 10.0).abs() < 0.01);
+
+        // Frames without sampled input aren't measured
+        latency.submitted(input + Duration::from_millis(20));
+        assert_eq!(latency.presented(input + Duration::from_millis(30)), None);
+        assert_eq!(latency.average(), latency.last());
+    }
+}
@@ -0,0 +1,136 @@
+//! Render layers and the masks selecting them.
+//!
+//! Every object is on one of 32 layers. Cameras, lights and
+//! passes carry a `LayerMask` of the layers they see: a camera
+//! only draws objects on layers in its `culling_mask`, the
+//! scene's light only lights objects in its own mask, and
+//! passes such as shadow, reflection or minimap passes only
+//! draw the layers their `InjectedPass` includes.
+//!
+//! Layers also order drawing: within a render priority,
+//! objects on lower layers are drawn first (see `sort_key`).
+use std::fmt;
+use std::ops::{BitAnd, BitOr, Not};
+
+/// Number of layers
+pub const LAYER_COUNT: u8 = 32;
+
+/// One of the 32 render layers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Layer(u8);
+
+impl Layer {
+    /// The layer objects are created on
+    pub const DEFAULT: Layer = Layer(0);
+
+    /// Panics if `index` isn't below `LAYER_COUNT`.
+    pub fn new(index: u8) -> Layer {
+        assert!(
+            index < LAYER_COUNT,
+            "layer {} is out of range, there are only {}",
+            index,
+            LAYER_COUNT
+        );
+        Layer(index)
+    }
+
+    pub fn index(self) -> u8 {
+        self.0
+    }
+
+    /// Returns the mask of only this layer.
+    pub fn mask(self) -> LayerMask {
+        LayerMask(1 << self.0)
+    }
+}
+
+impl Default for Layer {
+    fn default() -> Self {
+        Layer::DEFAULT
+    }
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "layer {}", self.0)
+    }
+}
+
+/// A set of layers, with bit `n` set for layer `n`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LayerMask(pub u32);
+
+impl LayerMask {
+    pub const ALL: LayerMask = LayerMask(!0);
+    pub const NONE: LayerMask = LayerMask(0);
+
+    /// Returns the mask of `layers`.
+    pub fn of(layers: &[Layer]) -> LayerMask {
+        layers
+            .iter()
+            .fold(LayerMask::NONE, |mask, &layer| mask.with(layer))
+    }
+
+    pub fn contains(self, layer: Layer) -> bool {
+        self.0 & (1 << layer.0) != 0
+    }
+
+    /// Returns this mask with `layer` included.
+    pub fn with(self, layer: Layer) -> LayerMask {
+        self | layer.mask()
+    }
+
+    /// Returns this mask with `layer` excluded.
+    pub fn without(self, layer: Layer) -> LayerMask {
+        self & !layer.mask()
+    }
+}
+
+impl Default for LayerMask {
+    fn default() -> Self {
+        LayerMask::ALL
+    }
+}
+
+impl BitOr for LayerMask {
+    type Output = LayerMask;
+
+    fn bitor(self, other: LayerMask) -> LayerMask {
+        LayerMask(self.0 | other.0)
+    }
+}
+
+impl BitAnd for LayerMask {
+    type Output = LayerMask;
+
+    fn bitand(self, other: LayerMask) -> LayerMask {
+        LayerMask(self.0 & other.0)
+    }
+}
+
+impl Not for LayerMask {
+    type Output = LayerMask;
+
+    fn not(self) -> LayerMask {
+        LayerMask(!self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks() {
+        let minimap = Layer::new(5);
+        let mask = LayerMask::ALL.without(minimap);
+        assert!(mask.contains(Layer::DEFAULT));
+        assert!(!mask.contains(minimap));
+        assert_eq!(mask.with(minimap), LayerMask::ALL);
+        assert_eq!(
+            LayerMask::of(&[Layer::DEFAULT, minimap]),
+            LayerMask(0b100001)
+        );
+        assert!(LayerMask::ALL.contains(Layer::new(LAYER_COUNT - 1)));
+    }
+}
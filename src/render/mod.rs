@@ -9,30 +9,320 @@ pub use self::gfx::*;
 #[cfg(feature = "gl")]
 pub use self::glium::*;
 
+pub use self::backend::{BackendError, BackendKind, BACKEND_ENV};
+pub use self::camera::{Camera, CameraPath, Keyframe, Projection};
+pub use self::camera_effects::CameraEffects;
+pub use self::capture::CapturedFrame;
+pub use self::clear::LoadOp;
+pub use self::cursor::{Cursor, SystemCursor};
+pub use self::debug_view::{DebugView, OverdrawStats};
+pub use self::depth::DepthMode;
+pub use self::features::DeviceFeatures;
+pub use self::graph::{
+    AttachmentDesc, AttachmentId, AttachmentState, CompiledGraph, GraphError, PassDesc, PassId,
+    RenderGraph,
+};
+pub use self::handle::{
+    BufferHandle, Handle, HandleMap, MeshHandle, PipelineHandle, StaleHandle, TargetHandle,
+    TextureHandle,
+};
+pub use self::injection::{CameraUniforms, FrameGraph, InjectedPass, InjectionPoint};
+pub use self::latency::{LatencySample, LatencyTracker};
+pub use self::layer::{Layer, LayerMask};
+pub use self::quality::{GraphicsQuality, GraphicsSettings};
+pub use self::sort_key::SortKey;
+pub use self::telemetry::{EventKind, RenderEvent, Telemetry};
+pub use self::time_of_day::{Lighting, TimeOfDay};
+pub use self::window_mode::{WindowMode, WindowModeState, WindowRect};
+
+pub mod backend;
+pub mod camera;
+pub mod camera_effects;
+pub mod capture;
+pub mod clear;
+pub mod cursor;
+pub mod debug_view;
+pub mod depth;
+pub mod features;
+pub mod graph;
+pub mod handle;
+pub mod injection;
+pub mod latency;
+pub mod layer;
+pub mod quality;
+pub mod shader_constants;
+pub mod sort_key;
+pub mod telemetry;
+pub mod time_of_day;
+pub mod window_mode;
+
 use super::*;
-use std::path::Path;
+use mods::{ModLoader, ASSETS_DIR};
+use std::path::{Path, PathBuf};
+
+/// Vertical field of view for viewmodels, in degrees
+pub const VIEWMODEL_FOV: f32 = 60.0;
+/// The far clipping distance for viewmodels
+pub const VIEWMODEL_DISTANCE: f32 = 16.0;
 
 /// A render backend.
-pub trait RenderBackend {
+///
+/// Each frame is rendered in three phases: the world is first
+/// extracted into a `RenderWorld` (see `extract()`), the backend
+/// then prepares GPU resources from it, and finally records and
+/// submits its commands.
+pub trait RenderBackend: Sized {
     /// Render data associated with an object
     type ObjectRender;
     type RenderContext;
 
-    fn upload_model(ctx: &mut Self::RenderContext, models: Vec<tobj::Model>);
-    fn create_context(title: &str, dimensions: (u32, u32)) -> Self::RenderContext;
+    /// Uploads a model, returning its index.
+    fn upload_model(ctx: &mut Self::RenderContext, models: Vec<tobj::Model>) -> usize;
+    /// Creates a context, reducing any `settings` the device
+    /// doesn't support as `DeviceFeatures::supported_settings` does.
+    fn create_context(
+        title: &str,
+        dimensions: (u32, u32),
+        settings: &GraphicsSettings,
+    ) -> Self::RenderContext;
     fn create_obj_render(
         model_index: usize,
         shader_index: usize,
         render: &mut Self::RenderContext,
     ) -> Self::ObjectRender;
+    /// Returns the index of the object's model.
+    fn model_index(render: &Self::ObjectRender) -> usize;
+    /// Returns the index of the object's shader.
+    fn shader_index(render: &Self::ObjectRender) -> usize;
+    /// Returns the counters collected while rendering the last frame.
+    fn stats(ctx: &Self::RenderContext) -> &RenderStats;
+    /// Returns the timings of expensive events, such as
+    /// pipeline compilation and uploads.
+    fn telemetry(ctx: &Self::RenderContext) -> &Telemetry;
+    /// Returns the limits and optional features of the device.
+    fn features(ctx: &Self::RenderContext) -> &DeviceFeatures;
+    fn set_title(ctx: &mut Self::RenderContext, title: &str);
+    /// Called right before input for the next frame is sampled.
+    /// In low latency mode this first waits for the previous
+    /// frame to finish on the GPU.
+    fn begin_input(ctx: &mut Self::RenderContext, settings: &GraphicsSettings);
+    /// Switches the window to `mode`.
+    fn set_window_mode(ctx: &mut Self::RenderContext, mode: WindowMode);
+    /// Changes what is shown at the mouse position.
+    fn set_cursor(ctx: &mut Self::RenderContext, cursor: Cursor);
+    /// Sets the color frames are cleared to, or
+    /// with `None`, the sky color.
+    fn set_clear_color(ctx: &mut Self::RenderContext, color: Option<[f32; 4]>);
+    /// Sets what frames do with the previous contents
+    /// of the color attachment.
+    fn set_color_load(ctx: &mut Self::RenderContext, load: LoadOp);
+    /// Copies the next frame submitted back to the CPU.
+    fn capture_next_frame(ctx: &mut Self::RenderContext);
+    /// Returns the frame copied since `capture_next_frame()`
+    /// was called, once it has been submitted.
+    fn take_captured_frame(ctx: &mut Self::RenderContext) -> Option<CapturedFrame>;
+    /// Called when the window's size changes, so
+    /// images sized to it can be recreated.
+    fn window_resized(ctx: &mut Self::RenderContext);
+    /// Uploads per-object data for the extracted frame.
+    fn prepare(ctx: &mut Self::RenderContext, world: &mut World<Self>, frame: &RenderWorld);
+    /// Records, submits and presents the extracted frame.
+    fn submit(ctx: &mut Self::RenderContext, world: &World<Self>, frame: &RenderWorld);
+    /// Frees the context and the render data of the objects
+    /// in `world`, saving anything which is kept between runs.
+    fn destroy(ctx: Self::RenderContext, world: World<Self>);
+}
+
+/// Render data extracted from a single object.
+#[derive(Clone, Debug)]
+pub struct DrawItem {
+    /// Index of the object in `World::get_objs()`
+    pub object_index: usize,
+    /// Determines the order the item is drawn in
+    pub sort_key: SortKey,
+    /// The full MVP matrix
+    pub matrix: Mat4,
+    pub modelview: Mat4,
+    /// Whether the object is on a layer the scene's
+    /// light reaches, rather than only lit by ambient light
+    pub lit: bool,
+}
+
+impl DrawItem {
+    /// Returns the layer of the object.
+    pub fn layer(&self) -> Layer {
+        self.sort_key.layer()
+    }
+}
+
+/// Everything needed to draw a frame, extracted
+/// from the world before any GPU work begins.
+pub struct RenderWorld {
+    pub draws: Vec<DrawItem>,
+    /// Draws for viewmodel objects, which are drawn after
+    /// `draws` once the depth buffer has been cleared
+    pub viewmodel_draws: Vec<DrawItem>,
+    /// Draws for objects on layers the camera's culling mask
+    /// leaves out, which only injected passes draw
+    pub culled_draws: Vec<DrawItem>,
+    pub lighting: Lighting,
+    /// Direction towards the light in view space
+    pub light_direction: Vec3,
+    /// Shader output to show instead of shaded color,
+    /// which is kept between frames
+    pub debug_view: DebugView,
+    /// The scene camera, for injected passes
+    pub camera: CameraUniforms,
+}
+
+impl RenderWorld {
+    pub fn new() -> RenderWorld {
+        let lighting = TimeOfDay::default().lighting();
+        RenderWorld {
+            draws: Vec::new(),
+            viewmodel_draws: Vec::new(),
+            culled_draws: Vec::new(),
+            lighting,
+            light_direction: lighting.direction,
+            debug_view: DebugView::Off,
+            camera: CameraUniforms::default(),
+        }
+    }
+
+    /// Returns the color to clear the frame to: `color` if
+    /// one is set, or else the sky color. Additive debug
+    /// views always add up fragments over black.
+    pub fn clear_color(&self, color: Option<[f32; 4]>) -> [f32; 4] {
+        if self.debug_view.is_additive() {
+            return [0.0, 0.0, 0.0, 1.0];
+        }
+        let sky = self.lighting.sky_color;
+        color.unwrap_or([sky.x, sky.y, sky.z, 1.0])
+    }
+}
+
+/// Extract phase: computes the render data for
+/// every object in the world, replacing the
+/// previous contents of `frame`. Objects beyond
+/// the view distance are skipped, and draws are
+/// sorted by their `SortKey`. Objects the camera's
+/// culling mask leaves out go to `culled_draws`.
+pub fn extract<B: RenderBackend>(
+    world: &World<B>,
+    frame: &mut RenderWorld,
+    camera: &Camera,
+    lighting: &Lighting,
+    settings: &GraphicsSettings,
+) {
+    frame.draws.clear();
+    frame.viewmodel_draws.clear();
+    frame.culled_draws.clear();
+    frame.lighting = *lighting;
+    let direction = camera.view()
+        * vec4(
+            lighting.direction.x,
+            lighting.direction.y,
+            lighting.direction.z,
+            0.0,
+        );
+    frame.light_direction = normalize(vec3(direction.x, direction.y, direction.z));
+    let view = camera.relative_view();
+    let projection = camera.projection(settings.view_distance, settings.depth_mode);
+    frame.camera = CameraUniforms {
+        view,
+        projection,
+        view_projection: projection * view,
+    };
+
+    for (object_index, object) in world.get_objs().iter().enumerate() {
+        let (matrix, modelview) = mvp_matrix(object, camera, settings);
+        // The camera looks down -Z in view space
+        let depth = -modelview[3].z;
+        if !object.viewmodel && depth > settings.view_distance {
+            continue;
+        }
+        let sort_key = SortKey::new(
+            object.render_priority,
+            object.layer,
+            B::shader_index(&object.render),
+            B::model_index(&object.render),
+            depth,
+            settings.view_distance,
+        );
+        let draw = DrawItem {
+            object_index,
+            sort_key,
+            matrix,
+            modelview,
+            lit: lighting.culling_mask.contains(object.layer),
+        };
+        if !camera.culling_mask.contains(object.layer) {
+            frame.culled_draws.push(draw);
+        } else if object.viewmodel {
+            frame.viewmodel_draws.push(draw);
+        } else {
+            frame.draws.push(draw);
+        }
+    }
+    // Stable, so equal keys keep world order
+    frame.draws.sort_by_key(|draw| draw.sort_key);
+    frame.viewmodel_draws.sort_by_key(|draw| draw.sort_key);
+    frame.culled_draws.sort_by_key(|draw| draw.sort_key);
+}
+
+/// Renders a frame by running all three phases in order.
+pub fn render_frame<B: RenderBackend>(
+    ctx: &mut B::RenderContext,
+    world: &mut World<B>,
+    frame: &mut RenderWorld,
+    camera: &Camera,
+    lighting: &Lighting,
+    settings: &GraphicsSettings,
+) {
+    extract(world, frame, camera, lighting, settings);
+    B::prepare(ctx, world, frame);
+    B::submit(ctx, world, frame);
+}
+
+/// Counters exposed by a render backend.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RenderStats {
+    /// Draw calls issued in the last frame
+    pub draw_calls: u32,
+    /// Triangles drawn in the last frame
+    pub triangles: u64,
+    /// Size of all uploaded vertex and index data in bytes
+    pub model_bytes: u64,
+    /// Fill rate of the last frame, while the overdraw
+    /// view is selected on backends which measure it
+    pub overdraw: Option<OverdrawStats>,
+    /// Average CPU side input-to-present latency of recent frames
+    pub latency: Option<LatencySample>,
 }
 
-/// A three-dimensional vertex
-/// with a position and normal.
+impl RenderStats {
+    /// Resets the per-frame counters.
+    pub fn begin_frame(&mut self) {
+        self.draw_calls = 0;
+        self.triangles = 0;
+    }
+
+    /// Records a draw call with the specified number of indices.
+    pub fn record_draw(&mut self, index_count: usize) {
+        self.draw_calls += 1;
+        self.triangles += (index_count / 3) as u64;
+    }
+}
+
+/// A three-dimensional vertex with a
+/// position, normal and texture coordinates.
+/// Vertex layouts read the fields at offsets 0, 12 and 24.
 #[derive(Clone, Copy)]
+#[repr(C)]
 pub struct Vertex {
     pub a_position: Vec3,
     pub a_normal: Vec3,
+    pub a_uv: Vec2,
 }
 
 impl Vertex {
@@ -42,15 +332,32 @@ impl Vertex {
         Vertex {
             a_position: vec3(x, y, z),
             a_normal: vec3(nx, ny, nz),
+            a_uv: vec2(0.0, 0.0),
         }
     }
+
+    /// Sets the texture coordinates of the vertex.
+    pub fn with_uv(mut self, u: f32, v: f32) -> Self {
+        self.a_uv = vec2(u, v);
+        self
+    }
 }
 
 /// Produces a model-view-projection matrix
 /// for the specified object.
-fn mvp_matrix<B: RenderBackend>(object: &Object<B>) -> (Mat4, Mat4) {
+fn mvp_matrix<B: RenderBackend>(
+    object: &Object<B>,
+    camera: &Camera,
+    settings: &GraphicsSettings,
+) -> (Mat4, Mat4) {
     use glm::ext::*;
-    let translation = translate(&num::one(), object.location.to_vec());
+    // Viewmodels are already relative to the camera
+    let offset = if object.viewmodel {
+        object.location.to_vec()
+    } else {
+        camera.relative(&object.location)
+    };
+    let translation = translate(&num::one(), offset);
 
     let rotation: Mat4 = rotate(
         &num::one(),
@@ -66,30 +373,108 @@ fn mvp_matrix<B: RenderBackend>(object: &Object<B>) -> (Mat4, Mat4) {
     let scale: Mat4 = num::one();
     let model = translation * rotation * scale;
 
-    // TODO moving camera
-    let view = look_at(
-        vec3(512.0, 512.0, 512.0),
-        vec3(512.0, 0.0, 512.0),
-        vec3(1.0, 0.0, 0.0),
-    );
-
-    // TODO view distance, custom aspect ratio
-    let projection = perspective(45.0f32, 4.0 / 3.0, 0.1, 1024.0);
+    let (view, projection): (Mat4, Mat4) = if object.viewmodel {
+        // Viewmodels are placed relative to the camera and use their
+        // own field of view, so they are unaffected by the scene
+        (
+            num::one(),
+            camera.jittered(camera::perspective(
+                radians(VIEWMODEL_FOV),
+                camera.aspect,
+                0.01,
+                Some(VIEWMODEL_DISTANCE),
+                settings.depth_mode,
+            )),
+        )
+    } else {
+        (
+            camera.relative_view(),
+            camera.projection(settings.view_distance, settings.depth_mode),
+        )
+    };
     (projection * view * model, view * model)
 }
 
+/// Model files uploaded at startup, relative to the assets
+/// directory. The index of each path is the index of the model.
+const MODEL_PATHS: [&str; 3] = ["models/cube.obj", "models/sword.obj", "models/wall.obj"];
+
 /// Uploads vertex buffer data for models to the GPU,
 /// adding the memory and buffers to the `RenderContext`'s
-/// list of models
-pub fn upload_models<B: RenderBackend>(ctx: &mut B::RenderContext) {
-    load_and_upload_model::<B>(ctx, Path::new("assets/models/cube.obj"));
-    load_and_upload_model::<B>(ctx, Path::new("assets/models/sword.obj"));
-    load_and_upload_model::<B>(ctx, Path::new("assets/models/wall.obj"));
+/// list of models. Models are loaded from `mods` where
+/// they override the base assets. A mod's model which
+/// can't be loaded is reported, and the base asset is
+/// used instead.
+pub fn upload_models<B: RenderBackend>(ctx: &mut B::RenderContext, mods: &ModLoader) {
+    let paths = MODEL_PATHS
+        .iter()
+        .map(|path| mods.resolve(path))
+        .collect::<Vec<_>>();
+    let loaded = load_models(&paths);
+    for ((asset, path), models) in MODEL_PATHS.iter().zip(&paths).zip(loaded) {
+        let models = models.or_else(|err| {
+            let base = Path::new(ASSETS_DIR).join(asset);
+            if *path == base {
+                return Err(err);
+            }
+            error!("Failed to load {}: {}", path.display(), err);
+            load_model(&base)
+        });
+        // Model indices are fixed, so a broken
+        // base asset can't be left out
+        match models {
+            Ok(models) => {
+                B::upload_model(ctx, models);
+            }
+            Err(err) => panic!("failed to load base asset {}: {}", asset, err),
+        }
+    }
 }
 
-fn load_and_upload_model<B: RenderBackend>(ctx: &mut B::RenderContext, path: &Path) {
-    let (models, _) = tobj::load_obj(path).expect("failed to load model file");
-    B::upload_model(ctx, models);
+/// Parses model files, one thread per file, returning
+/// the models in the same order as `paths`. Only parsing
+/// happens off the calling thread; uploading must happen
+/// on the thread which owns the context.
+fn load_models(paths: &[PathBuf]) -> Vec<Result<Vec<tobj::Model>, String>> {
+    std::thread::scope(|scope| {
+        let handles = paths
+            .iter()
+            .map(|path| {
+                std::thread::Builder::new()
+                    .name(format!("load {}", path.display()))
+                    .spawn_scoped(scope, move || load_model(path))
+                    .expect("failed to spawn loader thread")
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| Err("the loader thread panicked".to_string()))
+            })
+            .collect()
+    })
+}
+
+/// Parses a model file.
+fn load_model(path: &Path) -> Result<Vec<tobj::Model>, String> {
+    let (models, _) = tobj::load_obj(path).map_err(|err| format!("{:?}", err))?;
+    Ok(models)
+}
+
+/// Loads and uploads a single model file, returning its index.
+/// Files without any triangles are rejected.
+pub fn import_model<B: RenderBackend>(
+    ctx: &mut B::RenderContext,
+    path: &Path,
+) -> Result<usize, String> {
+    let models = load_model(path)?;
+    if models.iter().all(|model| model.mesh.indices.len() < 3) {
+        return Err("the model has no triangles".to_string());
+    }
+    Ok(B::upload_model(ctx, models))
 }
 
 /// Combines all models into one vector of vertices and indices.
@@ -100,47 +485,122 @@ pub fn combine_models(mut models: Vec<tobj::Model>) -> (Vec<Vertex>, Vec<u32>) {
     while models.len() != 0 {
         let model = models.remove(0);
         let mut mesh = model.mesh;
+        if mesh.normals.len() != mesh.positions.len() {
+            flat_normals(&mut mesh);
+        }
 
         // If there are multiple models, we need
         // to offset the indices
         let offset = vertices.len() as u32;
         mesh.indices = mesh.indices.iter().map(|el| el + offset).collect();
 
-        vertices.append(&mut positions_to_vertices(&mesh.positions, &mesh.normals));
+        vertices.append(&mut positions_to_vertices(
+            &mesh.positions,
+            &mesh.normals,
+            &mesh.texcoords,
+        ));
 
         indices.append(&mut mesh.indices);
     }
     (vertices, indices)
 }
 
+/// Gives a mesh without normals the normal of each triangle,
+/// splitting its vertices so every triangle has its own.
+fn flat_normals(mesh: &mut tobj::Mesh) {
+    let has_uvs = mesh.texcoords.len() / 2 == mesh.positions.len() / 3;
+    let (mut positions, mut normals, mut texcoords) = (Vec::new(), Vec::new(), Vec::new());
+    for triangle in mesh
+        .indices
+        .chunks(3)
+        .filter(|triangle| triangle.len() == 3)
+    {
+        let corner = |index: u32| {
+            let index = index as usize * 3;
+            glm::vec3(
+                mesh.positions[index],
+                mesh.positions[index + 1],
+                mesh.positions[index + 2],
+            )
+        };
+        let (a, b, c) = (
+            corner(triangle[0]),
+            corner(triangle[1]),
+            corner(triangle[2]),
+        );
+        let cross = glm::cross(b - a, c - a);
+        let normal = if glm::length(cross) > 0.0 {
+            glm::normalize(cross)
+        } else {
+            cross
+        };
+
+        for &index in triangle {
+            let index = index as usize;
+            positions.extend_from_slice(&mesh.positions[index * 3..index * 3 + 3]);
+            normals.extend_from_slice(&[normal.x, normal.y, normal.z]);
+            if has_uvs {
+                texcoords.extend_from_slice(&mesh.texcoords[index * 2..index * 2 + 2]);
+            }
+        }
+    }
+    mesh.indices = (0..positions.len() as u32 / 3).collect();
+    mesh.positions = positions;
+    mesh.normals = normals;
+    mesh.texcoords = texcoords;
+}
+
 /// Converts vectors of floats to vectors
 /// of vertices. The length of the `positions`
-/// array must be a multiple of three.
-pub fn positions_to_vertices(positions: &Vec<f32>, normals: &Vec<f32>) -> Vec<Vertex> {
+/// array must be a multiple of three, and
+/// `normals` must be as long. `texcoords`
+/// may be empty, in which case they are zero.
+pub fn positions_to_vertices(
+    positions: &Vec<f32>,
+    normals: &Vec<f32>,
+    texcoords: &Vec<f32>,
+) -> Vec<Vertex> {
     if positions.len() % 3 != 0 {
         panic!("Length of position array must be a multiple of three");
     }
+    if normals.len() != positions.len() {
+        panic!("There must be a normal for every position");
+    }
 
     let mut result = Vec::new();
     for index in 0..positions.len() {
         if index % 3 != 0 {
             continue;
         }
-        result.push(Vertex::new(
+        let mut vertex = Vertex::new(
             positions[index],
             positions[index + 1],
             positions[index + 2],
             normals[index],
             normals[index + 1],
             normals[index + 2],
-        ));
+        );
+        let uv_index = index / 3 * 2;
+        if uv_index + 1 < texcoords.len() {
+            vertex = vertex.with_uv(texcoords[uv_index], texcoords[uv_index + 1]);
+        }
+        result.push(vertex);
     }
 
     result
 }
 
-pub fn create_context<B: RenderBackend>(title: &str, dimensions: (u32, u32)) -> B::RenderContext {
-    let mut ctx = B::create_context(title, dimensions);
-    upload_models::<B>(&mut ctx);
-    ctx
+/// Creates a context and uploads the models. Settings the device
+/// doesn't support are reduced when the context is created, and
+/// returned along with it.
+pub fn create_context<B: RenderBackend>(
+    title: &str,
+    dimensions: (u32, u32),
+    settings: &GraphicsSettings,
+    mods: &ModLoader,
+) -> (B::RenderContext, GraphicsSettings) {
+    let mut ctx = B::create_context(title, dimensions, settings);
+    let settings = B::features(&ctx).supported_settings(settings);
+    upload_models::<B>(&mut ctx, mods);
+    (ctx, settings)
 }
@@ -0,0 +1,90 @@
+//! Graphics quality presets.
+//!
+//! A preset configures every adjustable render feature at
+//! once. Settings which only affect how a frame is extracted
+//! (such as view distance) apply immediately. MSAA, vsync and
+//! the depth mode are baked into the context's swapchain,
+//! render pass and pipelines, which are not recreated when the
+//! settings change, so they need a restart to take effect.
+use super::DepthMode;
+
+/// A graphics quality preset.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GraphicsQuality {
+    Low,
+    Medium,
+    High,
+    Ultra,
+    /// User-chosen settings
+    Custom(GraphicsSettings),
+}
+
+/// The individual settings configured by a `GraphicsQuality`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GraphicsSettings {
+    /// Number of samples per pixel; 1 disables MSAA.
+    /// Only read when the context is created.
+    pub msaa_samples: u8,
+    /// Whether to wait for vertical sync when presenting.
+    /// Only read when the context is created.
+    pub vsync: bool,
+    /// Objects further than this from the camera are not drawn
+    pub view_distance: f32,
+    /// How depth is stored, which is baked into the context
+    /// like `msaa_samples` but also used by every projection
+    pub depth_mode: DepthMode,
+    /// Whether to wait for the previous frame to finish on the
+    /// GPU before sampling input, trading throughput for latency.
+    /// This does nothing on the gfx backend, which already waits
+    /// for every frame before `submit()` returns.
+    pub low_latency: bool,
+}
+
+impl GraphicsQuality {
+    /// Returns the settings for this preset.
+    pub fn settings(&self) -> GraphicsSettings {
+        match *self {
+            GraphicsQuality::Low => GraphicsSettings {
+                msaa_samples: 1,
+                vsync: false,
+                view_distance: 256.0,
+                depth_mode: DepthMode::Reversed,
+                low_latency: false,
+            },
+            GraphicsQuality::Medium => GraphicsSettings {
+                msaa_samples: 2,
+                vsync: true,
+                view_distance: 512.0,
+                depth_mode: DepthMode::Reversed,
+                low_latency: false,
+            },
+            GraphicsQuality::High => GraphicsSettings {
+                msaa_samples: 4,
+                vsync: true,
+                view_distance: 1024.0,
+                depth_mode: DepthMode::Reversed,
+                low_latency: false,
+            },
+            GraphicsQuality::Ultra => GraphicsSettings {
+                msaa_samples: 8,
+                vsync: true,
+                view_distance: 2048.0,
+                depth_mode: DepthMode::Reversed,
+                low_latency: false,
+            },
+            GraphicsQuality::Custom(settings) => settings,
+        }
+    }
+}
+
+impl Default for GraphicsQuality {
+    fn default() -> Self {
+        GraphicsQuality::High
+    }
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> Self {
+        GraphicsQuality::default().settings()
+    }
+}
@@ -0,0 +1,82 @@
+//! Engine constants shared with shaders.
+//!
+//! Values like the layers of the overdraw view have to agree
+//! between the engine and its shaders, so they are only defined
+//! here. Every shader gets a `#define` for each of them right
+//! after its `#version` line, whether it is compiled by the
+//! build script, by the `glsl` feature or by the OpenGL
+//! backend. Shaders use the names in `SHADER_CONSTANTS` and
+//! must not define them.
+//!
+//! The build script compiles this file too, so it can only
+//! use `std`.
+
+/// Layers of overdraw which saturate the red, green
+/// and blue channels of the overdraw debug view
+pub const OVERDRAW_RED_LAYERS: u32 = 4;
+pub const OVERDRAW_GREEN_LAYERS: u32 = 16;
+pub const OVERDRAW_BLUE_LAYERS: u32 = 64;
+
+/// Every constant, by the name shaders use
+pub const SHADER_CONSTANTS: &[(&str, u32)] = &[
+    ("OVERDRAW_RED_LAYERS", OVERDRAW_RED_LAYERS),
+    ("OVERDRAW_GREEN_LAYERS", OVERDRAW_GREEN_LAYERS),
+    ("OVERDRAW_BLUE_LAYERS", OVERDRAW_BLUE_LAYERS),
+];
+
+/// Returns a `#define` line for each constant.
+pub fn defines() -> Vec<String> {
+    SHADER_CONSTANTS
+        .iter()
+        .map(|&(name, value)| format!("#define {} {}", name, value))
+        .collect()
+}
+
+/// Returns the index of the line the defines go before:
+/// the one after `#version`, or the first if there is none.
+pub fn insertion_line(source: &str) -> usize {
+    source
+        .lines()
+        .position(|line| line.trim_start().starts_with("#version"))
+        .map(|index| index + 1)
+        .unwrap_or(0)
+}
+
+/// Returns `source` with the constants defined. A `#line`
+/// directive follows the defines, so compile errors still
+/// have the line numbers of `source`.
+pub fn inject(source: &str) -> String {
+    let lines = source.lines().collect::<Vec<_>>();
+    let insertion = insertion_line(source);
+    let mut injected = String::with_capacity(source.len() + 32 * SHADER_CONSTANTS.len());
+    for line in &lines[..insertion] {
+        injected.push_str(line);
+        injected.push('\n');
+    }
+    for define in defines() {
+        injected.push_str(&define);
+        injected.push('\n');
+    }
+    injected.push_str(&format!("#line {}\n", insertion + 1));
+    for line in &lines[insertion..] {
+        injected.push_str(line);
+        injected.push('\n');
+    }
+    injected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defines_after_version() {
+        let injected = inject("// Comment\n#version 450\nvoid main() {}\n");
+        let lines = injected.lines().collect::<Vec<_>>();
+        assert_eq!(&lines[..2], &["// Comment", "#version 450"]);
+        assert_eq!(lines[2], "#define OVERDRAW_RED_LAYERS 4");
+        assert_eq!(lines[lines.len() - 2], "#line 3");
+        assert_eq!(lines[lines.len() - 1], "void main() {}");
+        assert_eq!(lines.len(), 3 + SHADER_CONSTANTS.len() + 1);
+    }
+}
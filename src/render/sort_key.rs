@@ -0,0 +1,131 @@
+//! Keys used to order draws within a frame.
+//!
+//! Draws are sorted by key in ascending order before they
+//! are submitted. A key packs, from most to least significant:
+//!
+//! | Bits     | Field    | Meaning                                    |
+//! |----------|----------|--------------------------------------------|
+//! | 56 .. 64 | priority | `Object::render_priority`; higher is later |
+//! | 51 .. 56 | layer    | `Object::layer`; higher is later           |
+//! | 40 .. 51 | shader   | shader index, to group pipeline changes    |
+//! | 24 .. 40 | model    | model index, to group buffer bindings      |
+//! |  0 .. 24 | depth    | view depth, nearest first                  |
+//!
+//! Shader indices are truncated to 11 bits, model indices to
+//! 16 bits, and depth is quantized over `0..max_depth` into 24
+//! bits. Sorting is stable, so draws with equal keys keep the
+//! order in which their objects are stored in the world.
+use super::Layer;
+
+/// The priority objects are created with
+pub const DEFAULT_PRIORITY: u8 = 128;
+/// A priority which draws after all default-priority objects,
+/// e.g. for first-person weapons
+pub const LATE_PRIORITY: u8 = 192;
+
+const PRIORITY_SHIFT: u32 = 56;
+const LAYER_SHIFT: u32 = 51;
+const SHADER_SHIFT: u32 = 40;
+const SHADER_MASK: u64 = 0x7FF;
+const MODEL_SHIFT: u32 = 24;
+const DEPTH_BITS: u32 = 24;
+const DEPTH_MAX: u32 = (1 << DEPTH_BITS) - 1;
+
+/// A packed draw sort key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SortKey(pub u64);
+
+impl SortKey {
+    /// Packs a key. `depth` is clamped to `0..max_depth`.
+    pub fn new(
+        priority: u8,
+        layer: Layer,
+        shader: usize,
+        model: usize,
+        depth: f32,
+        max_depth: f32,
+    ) -> SortKey {
+        let depth = (depth / max_depth).max(0.0).min(1.0);
+        let depth = (depth * DEPTH_MAX as f32) as u64;
+        SortKey(
+            (priority as u64) << PRIORITY_SHIFT
+                | (layer.index() as u64) << LAYER_SHIFT
+                | (shader as u64 & SHADER_MASK) << SHADER_SHIFT
+                | (model as u64 & 0xFFFF) << MODEL_SHIFT
+                | depth,
+        )
+    }
+
+    pub fn priority(self) -> u8 {
+        (self.0 >> PRIORITY_SHIFT) as u8
+    }
+
+    pub fn layer(self) -> Layer {
+        Layer::new((self.0 >> LAYER_SHIFT) as u8 & 0x1F)
+    }
+
+    pub fn shader(self) -> usize {
+        ((self.0 >> SHADER_SHIFT) & SHADER_MASK) as usize
+    }
+
+    pub fn model(self) -> usize {
+        ((self.0 >> MODEL_SHIFT) & 0xFFFF) as usize
+    }
+
+    /// Returns the quantized depth.
+    pub fn depth(self) -> u32 {
+        (self.0 & DEPTH_MAX as u64) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_round_trip() {
+        let key = SortKey::new(200, Layer::new(31), 3, 7, 512.0, 1024.0);
+        assert_eq!(key.priority(), 200);
+        assert_eq!(key.layer(), Layer::new(31));
+        assert_eq!(key.shader(), 3);
+        assert_eq!(key.model(), 7);
+        assert_eq!(key.depth(), DEPTH_MAX / 2);
+    }
+
+    #[test]
+    fn depth_is_clamped() {
+        let layer = Layer::DEFAULT;
+        assert_eq!(SortKey::new(0, layer, 0, 0, -5.0, 10.0).depth(), 0);
+        assert_eq!(SortKey::new(0, layer, 0, 0, 50.0, 10.0).depth(), DEPTH_MAX);
+    }
+
+    #[test]
+    fn priority_dominates() {
+        let layer = Layer::DEFAULT;
+        let late_near = SortKey::new(LATE_PRIORITY, layer, 0, 0, 0.0, 1.0);
+        let default_far = SortKey::new(DEFAULT_PRIORITY, Layer::new(31), 9, 9, 1.0, 1.0);
+        assert!(default_far < late_near);
+
+        let near = SortKey::new(DEFAULT_PRIORITY, layer, 1, 1, 0.1, 1.0);
+        let far = SortKey::new(DEFAULT_PRIORITY, layer, 1, 1, 0.9, 1.0);
+        assert!(near < far);
+    }
+
+    #[test]
+    fn shader_indices_wrap() {
+        let layer = Layer::DEFAULT;
+        let low = SortKey::new(DEFAULT_PRIORITY, layer, 5, 0, 0.5, 1.0);
+        let high = SortKey::new(DEFAULT_PRIORITY, layer, 5 + 2048, 0, 0.5, 1.0);
+        assert_eq!(high.shader(), 5);
+        assert_eq!(low, high);
+        // Wrapping doesn't spill into the layer
+        assert_eq!(high.layer(), layer);
+    }
+
+    #[test]
+    fn layers_order_within_priority() {
+        let lower = SortKey::new(DEFAULT_PRIORITY, Layer::new(1), 9, 9, 1.0, 1.0);
+        let higher = SortKey::new(DEFAULT_PRIORITY, Layer::new(2), 0, 0, 0.0, 1.0);
+        assert!(lower < higher);
+    }
+}
@@ -0,0 +1,165 @@
+//! Timings of expensive renderer events.
+//!
+//! Creating a swapchain, compiling a pipeline or uploading a
+//! large model can take long enough to cause a hitch. Each is
+//! recorded as a `RenderEvent` with how long it took and what
+//! triggered it, so hitches can be traced back to the engine
+//! action behind them. The most recent events are kept for
+//! querying, and each is logged: at warning level if it took
+//! at least `log_threshold` milliseconds, otherwise at debug
+//! level.
+use profile::millis_since;
+use std::collections::vec_deque::{self, VecDeque};
+use std::time::Instant;
+
+/// Number of events kept
+const HISTORY_LEN: usize = 64;
+/// Default duration in milliseconds from which events are warned about
+const LOG_THRESHOLD: f64 = 8.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    SwapchainCreation,
+    PipelineCompilation,
+    Upload,
+}
+
+impl EventKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            EventKind::SwapchainCreation => "swapchain creation",
+            EventKind::PipelineCompilation => "pipeline compilation",
+            EventKind::Upload => "upload",
+        }
+    }
+}
+
+/// An expensive event and how long it took.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderEvent {
+    pub kind: EventKind,
+    /// What triggered the event
+    pub reason: String,
+    /// Duration in milliseconds
+    pub millis: f64,
+    /// Bytes transferred, for uploads
+    pub bytes: Option<u64>,
+}
+
+/// Records the most recent expensive events.
+pub struct Telemetry {
+    events: VecDeque<RenderEvent>,
+    /// Duration in milliseconds from which events are logged
+    /// as warnings rather than at debug level
+    pub log_threshold: f64,
+}
+
+impl Telemetry {
+    pub fn new() -> Telemetry {
+        Telemetry {
+            events: VecDeque::with_capacity(HISTORY_LEN),
+            log_threshold: LOG_THRESHOLD,
+        }
+    }
+
+    pub fn with_log_threshold(mut self, millis: f64) -> Self {
+        self.log_threshold = millis;
+        self
+    }
+
+    /// Records and logs an event.
+    pub fn record(&mut self, event: RenderEvent) {
+        let bytes = match event.bytes {
+            Some(bytes) => format!(", {:.1} KiB", bytes as f64 / 1024.0),
+            None => String::new(),
+        };
+        if event.millis >= self.log_threshold {
+            warn!(
+                "{} took {:.1} ms{} ({})",
+                event.kind.name(),
+                event.millis,
+                bytes,
+                event.reason
+            );
+        } else {
+            debug!(
+                "{} took {:.1} ms{} ({})",
+                event.kind.name(),
+                event.millis,
+                bytes,
+                event.reason
+            );
+        }
+
+        if self.events.len() == HISTORY_LEN {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// Runs `f`, recording how long it took as an event.
+    pub fn time<R, F: FnOnce() -> R>(&mut self, kind: EventKind, reason: &str, f: F) -> R {
+        let start = Instant::now();
+        let result = f();
+        self.record(RenderEvent {
+            kind,
+            reason: reason.to_string(),
+            millis: millis_since(start),
+            bytes: None,
+        });
+        result
+    }
+
+    /// Returns the recorded events, oldest first.
+    pub fn events(&self) -> vec_deque::Iter<RenderEvent> {
+        self.events.iter()
+    }
+
+    /// Returns the most recent event.
+    pub fn last(&self) -> Option<&RenderEvent> {
+        self.events.back()
+    }
+
+    /// Returns the longest recorded event of `kind`.
+    pub fn slowest(&self, kind: EventKind) -> Option<&RenderEvent> {
+        self.events
+            .iter()
+            .filter(|event| event.kind == kind)
+            .max_by(|a, b| a.millis.partial_cmp(&b.millis).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(kind: EventKind, millis: f64) -> RenderEvent {
+        RenderEvent {
+            kind,
+            reason: "test".to_string(),
+            millis,
+            bytes: None,
+        }
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut telemetry = Telemetry::new();
+        for i in 0..HISTORY_LEN + 1 {
+            telemetry.record(event(EventKind::Upload, i as f64));
+        }
+        telemetry.record(event(EventKind::PipelineCompilation, 2.0));
+
+        assert_eq!(telemetry.events().count(), HISTORY_LEN);
+        assert_eq!(telemetry.events().next().unwrap().millis, 2.0);
+        assert_eq!(
+            telemetry.last().unwrap().kind,
+            EventKind::PipelineCompilation
+        );
+        assert_eq!(
+            telemetry.slowest(EventKind::Upload).unwrap().millis,
+            HISTORY_LEN as f64
+        );
+        assert_eq!(telemetry.slowest(EventKind::SwapchainCreation), None);
+    }
+}
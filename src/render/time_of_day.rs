@@ -0,0 +1,163 @@
+//! The day/night cycle.
+//!
+//! `TimeOfDay` tracks the hour of the in-game day and
+//! derives the lighting for it: the direction and color of
+//! the sun (or the moon at night), the ambient light, and the
+//! color of the sky, which the backends clear the frame to.
+use super::*;
+
+/// Hours in a day
+const DAY_HOURS: f32 = 24.0;
+/// How far the sun's path is tilted away from straight
+/// overhead, as the Z component of its direction
+const SUN_TILT: f32 = 0.3;
+
+/// Light colors at one point of the day.
+#[derive(Clone, Copy, Debug)]
+struct Palette {
+    light: (f32, f32, f32),
+    ambient: (f32, f32, f32),
+    sky: (f32, f32, f32),
+}
+
+const NIGHT: Palette = Palette {
+    light: (0.1, 0.1, 0.25),
+    ambient: (0.0, 0.0, 0.08),
+    sky: (0.01, 0.01, 0.04),
+};
+const DAWN: Palette = Palette {
+    light: (1.0, 0.55, 0.3),
+    ambient: (0.1, 0.05, 0.15),
+    sky: (0.8, 0.4, 0.3),
+};
+const DAY: Palette = Palette {
+    light: (1.0, 1.0, 1.0),
+    ambient: (0.0, 0.0, 0.3),
+    sky: (0.4, 0.6, 0.9),
+};
+
+/// The lighting at a time of day.
+#[derive(Clone, Copy, Debug)]
+pub struct Lighting {
+    /// Direction towards the light in world space
+    pub direction: Vec3,
+    pub light_color: Vec3,
+    pub ambient_color: Vec3,
+    pub sky_color: Vec3,
+    /// Layers of the objects the light reaches
+    pub culling_mask: LayerMask,
+}
+
+/// The current time of the in-game day.
+#[derive(Clone, Debug)]
+pub struct TimeOfDay {
+    /// Hours since midnight, from 0 to 24
+    hours: f32,
+    /// Real seconds for a full day to pass
+    pub day_length: f32,
+    /// Stops time from advancing
+    pub paused: bool,
+    /// Layers of the objects the sun and moon light
+    pub light_mask: LayerMask,
+}
+
+impl TimeOfDay {
+    /// Creates a cycle starting at `hours` past midnight.
+    pub fn new(hours: f32, day_length: f32) -> TimeOfDay {
+        TimeOfDay {
+            hours: hours % DAY_HOURS,
+            day_length,
+            paused: false,
+            light_mask: LayerMask::ALL,
+        }
+    }
+
+    /// Advances the time by `delta` real seconds.
+    pub fn update(&mut self, delta: f32) {
+        if self.paused || self.day_length <= 0.0 {
+            return;
+        }
+        self.hours = (self.hours + delta / self.day_length * DAY_HOURS) % DAY_HOURS;
+    }
+
+    /// Returns the hours since midnight.
+    pub fn hours(&self) -> f32 {
+        self.hours
+    }
+
+    pub fn set_hours(&mut self, hours: f32) {
+        self.hours = hours.max(0.0) % DAY_HOURS;
+    }
+
+    /// Returns the direction towards the sun. It rises
+    /// in the east (+X) at 6:00 and sets at 18:00.
+    pub fn sun_direction(&self) -> Vec3 {
+        let angle = (self.hours - 6.0) / DAY_HOURS * 2.0 * std::f32::consts::PI;
+        normalize(vec3(angle.cos(), angle.sin(), SUN_TILT))
+    }
+
+    /// Returns the lighting for the current time.
+    pub fn lighting(&self) -> Lighting {
+        let sun = self.sun_direction();
+        // Sine of the sun's elevation
+        let height = sun.y;
+
+        let (from, to, t) = if height < -0.2 {
+            (NIGHT, NIGHT, 0.0)
+        } else if height < 0.1 {
+            (NIGHT, DAWN, (height + 0.2) / 0.3)
+        } else if height < 0.5 {
+            (DAWN, DAY, (height - 0.1) / 0.4)
+        } else {
+            (DAY, DAY, 0.0)
+        };
+
+        Lighting {
+            // The moon lights the scene at night, opposite the sun
+            direction: if height < 0.0 { -sun } else { sun },
+            light_color: mix_color(from.light, to.light, t),
+            ambient_color: mix_color(from.ambient, to.ambient, t),
+            sky_color: mix_color(from.sky, to.sky, t),
+            culling_mask: self.light_mask,
+        }
+    }
+}
+
+impl Default for TimeOfDay {
+    /// Noon, with a ten minute day.
+    fn default() -> Self {
+        TimeOfDay::new(12.0, 600.0)
+    }
+}
+
+fn mix_color(from: (f32, f32, f32), to: (f32, f32, f32), t: f32) -> Vec3 {
+    let from = vec3(from.0, from.1, from.2);
+    let to = vec3(to.0, to.1, to.2);
+    from + (to - from) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_wraps() {
+        let mut time = TimeOfDay::new(23.0, 24.0);
+        time.update(2.0);
+        assert!((time.hours() - 1.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn sun_rises_and_sets() {
+        let mut time = TimeOfDay::default();
+        assert!(time.sun_direction().y > 0.9);
+
+        time.set_hours(0.0);
+        assert!(time.sun_direction().y < -0.9);
+        // The moon is up at midnight
+        assert!(time.lighting().direction.y > 0.9);
+
+        time.set_hours(6.0);
+        assert!(time.sun_direction().x > 0.9);
+    }
+}
@@ -0,0 +1,124 @@
+//! Windowed and fullscreen modes.
+//!
+//! Backends switch the window between modes with winit and
+//! recreate whatever is sized to it. `WindowModeState` keeps
+//! the position and size the window had while windowed, so
+//! returning from fullscreen puts it back where it was.
+//!
+//! winit can't change the display's video mode, so exclusive
+//! fullscreen uses its fullscreen API, which lets the platform
+//! skip compositing, while borderless fullscreen is a window
+//! without decorations covering the monitor.
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowMode {
+    Windowed,
+    /// A borderless window covering the monitor
+    Borderless,
+    /// Fullscreen on the window's current monitor
+    Exclusive,
+}
+
+impl WindowMode {
+    /// Returns the mode after this one, for cycling through them.
+    pub fn next(self) -> WindowMode {
+        match self {
+            WindowMode::Windowed => WindowMode::Borderless,
+            WindowMode::Borderless => WindowMode::Exclusive,
+            WindowMode::Exclusive => WindowMode::Windowed,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WindowMode::Windowed => "windowed",
+            WindowMode::Borderless => "borderless fullscreen",
+            WindowMode::Exclusive => "exclusive fullscreen",
+        }
+    }
+}
+
+impl fmt::Display for WindowMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The position and size of a window, in logical pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WindowRect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+/// The current window mode, and where the
+/// window was when it was last windowed.
+#[derive(Clone, Debug)]
+pub struct WindowModeState {
+    mode: WindowMode,
+    windowed: Option<WindowRect>,
+}
+
+impl WindowModeState {
+    /// Windows are created windowed.
+    pub fn new() -> WindowModeState {
+        WindowModeState {
+            mode: WindowMode::Windowed,
+            windowed: None,
+        }
+    }
+
+    pub fn mode(&self) -> WindowMode {
+        self.mode
+    }
+
+    /// Records a switch to `mode` from a window at `current`.
+    /// Returns where to put the window when it returns to
+    /// windowed mode, if its old place is known.
+    pub fn switch(&mut self, mode: WindowMode, current: Option<WindowRect>) -> Option<WindowRect> {
+        let previous = std::mem::replace(&mut self.mode, mode);
+        match (previous, mode) {
+            (WindowMode::Windowed, WindowMode::Windowed) => None,
+            (WindowMode::Windowed, _) => {
+                self.windowed = current;
+                None
+            }
+            (_, WindowMode::Windowed) => self.windowed.take(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restores_windowed_rect() {
+        let rect = WindowRect {
+            x: 40.0,
+            y: 60.0,
+            width: 720.0,
+            height: 480.0,
+        };
+        let fullscreen = WindowRect {
+            x: 0.0,
+            y: 0.0,
+            width: 1920.0,
+            height: 1080.0,
+        };
+        let mut state = WindowModeState::new();
+        assert_eq!(state.switch(WindowMode::Borderless, Some(rect)), None);
+        // Switching between fullscreen modes keeps the windowed rect
+        assert_eq!(state.switch(WindowMode::Exclusive, Some(fullscreen)), None);
+        assert_eq!(
+            state.switch(WindowMode::Windowed, Some(fullscreen)),
+            Some(rect)
+        );
+        assert_eq!(state.mode(), WindowMode::Windowed);
+        assert_eq!(state.switch(WindowMode::Windowed, None), None);
+    }
+}
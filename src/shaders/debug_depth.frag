@@ -0,0 +1,17 @@
+/*
+* Debug view showing the depth buffer,
+* brighter where depth is greater.
+*/
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout (location = 0) out vec4 target;
+
+layout (location = 0) in vec3 v_position;
+layout (location = 1) in vec3 v_normal;
+
+void main() {
+    // Reversed depth falls off as one over the distance,
+    // so it is brightened to make distant values visible
+    target = vec4(vec3(pow(gl_FragCoord.z, 0.2)), 1.0);
+}
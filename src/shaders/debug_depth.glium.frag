@@ -0,0 +1,16 @@
+/*
+* Debug view showing the depth buffer,
+* brighter where depth is greater.
+*/
+#version 330
+
+out vec4 target;
+
+in vec3 v_position;
+in vec3 v_normal;
+
+void main() {
+    // Reversed depth falls off as one over the distance,
+    // so it is brightened to make distant values visible
+    target = vec4(vec3(pow(gl_FragCoord.z, 0.2)), 1.0);
+}
@@ -0,0 +1,15 @@
+/*
+* Debug view showing view space
+* normals as colors.
+*/
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout (location = 0) out vec4 target;
+
+layout (location = 0) in vec3 v_position;
+layout (location = 1) in vec3 v_normal;
+
+void main() {
+    target = vec4(normalize(v_normal) * 0.5 + 0.5, 1.0);
+}
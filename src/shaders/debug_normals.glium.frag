@@ -0,0 +1,14 @@
+/*
+* Debug view showing view space
+* normals as colors.
+*/
+#version 330
+
+out vec4 target;
+
+in vec3 v_position;
+in vec3 v_normal;
+
+void main() {
+    target = vec4(normalize(v_normal) * 0.5 + 0.5, 1.0);
+}
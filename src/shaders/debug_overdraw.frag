@@ -0,0 +1,23 @@
+/*
+* Debug view counting how many times
+* each pixel is shaded. Drawn with
+* additive blending over black.
+*/
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout (location = 0) out vec4 target;
+
+layout (location = 0) in vec3 v_position;
+layout (location = 1) in vec3 v_normal;
+
+void main() {
+    // Each channel saturates after its OVERDRAW_*_LAYERS
+    // layers, which OverdrawStats decodes the counts from
+    target = vec4(
+        1.0 / float(OVERDRAW_RED_LAYERS),
+        1.0 / float(OVERDRAW_GREEN_LAYERS),
+        1.0 / float(OVERDRAW_BLUE_LAYERS),
+        1.0
+    );
+}
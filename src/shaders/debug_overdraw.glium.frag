@@ -0,0 +1,21 @@
+/*
+* Debug view counting how many times
+* each pixel is shaded. Drawn with
+* additive blending over black.
+*/
+#version 330
+
+out vec4 target;
+
+in vec3 v_position;
+in vec3 v_normal;
+
+void main() {
+    // Each channel saturates after its OVERDRAW_*_LAYERS layers
+    target = vec4(
+        1.0 / float(OVERDRAW_RED_LAYERS),
+        1.0 / float(OVERDRAW_GREEN_LAYERS),
+        1.0 / float(OVERDRAW_BLUE_LAYERS),
+        1.0
+    );
+}
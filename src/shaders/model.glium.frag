@@ -9,8 +9,11 @@ out vec4 target;
 in vec3 v_position;
 in vec3 v_normal;
 
-const vec3 light_dir = vec3(0.0, 0.0, 1.0);
-const vec3 ambient_color = vec3(0.0, 0.0, 0.3);
+// Direction towards the light in view space
+uniform vec3 light_dir;
+uniform vec3 light_color;
+uniform vec3 ambient_color;
+
 const vec3 diffuse_color = vec3(0.0, 0.0, 1.0);
 const vec3 specular_color = vec3(1.0, 1.0, 1.0);
 
@@ -30,5 +33,6 @@ void main() {
     vec3 half_direction = normalize(normalize(light_dir) + camera_dir);
     float specular = pow(max(dot(half_direction, normalize(v_normal)), 0.0), 16.0);
 
-    target = vec4(ambient_color + diffuse * diffuse_color + specular * specular_color, 1.0);
+    vec3 lit = (diffuse * diffuse_color + specular * specular_color) * light_color;
+    target = vec4(ambient_color + lit, 1.0);
 }
@@ -15,6 +15,9 @@ layout (location = 1) out vec3 v_normal;
 layout (binding = 0) uniform MatrixBlock {
     mat4 matrix;
     mat4 modelview; // TODO - this is redundant
+    vec4 light_dir;
+    vec4 light_color;
+    vec4 ambient_color;
 };
 
 void main() {
@@ -0,0 +1,183 @@
+//! The engine statistics overlay.
+//!
+//! There is no text rendering yet, so the overlay is
+//! shown in the window title. It is toggled with F3.
+use render::{RenderEvent, RenderStats};
+use std::collections::VecDeque;
+
+/// Number of frames kept for frame time statistics
+const HISTORY_LEN: usize = 120;
+/// Number of frames shown in the frame time graph
+const GRAPH_LEN: usize = 16;
+/// Milliseconds between overlay updates
+const UPDATE_INTERVAL: f64 = 500.0;
+/// Characters used to draw the frame time graph, from lowest to highest
+const GRAPH_CHARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Tracks frame times and formats engine statistics.
+pub struct StatsOverlay {
+    pub enabled: bool,
+    /// Recent frame times in milliseconds, oldest first
+    frame_times: VecDeque<f64>,
+    /// Time of the last overlay update in milliseconds
+    last_update: f64,
+}
+
+impl StatsOverlay {
+    pub fn new() -> StatsOverlay {
+        StatsOverlay {
+            enabled: false,
+            frame_times: VecDeque::with_capacity(HISTORY_LEN),
+            last_update: 0.0,
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+    }
+
+    /// Records the duration of a frame in milliseconds.
+    pub fn record_frame(&mut self, frame_time: f64) {
+        if self.frame_times.len() == HISTORY_LEN {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(frame_time);
+    }
+
+    /// Returns whether the overlay should be redrawn at time `now`,
+    /// in milliseconds, and marks it as updated if so.
+    pub fn should_update(&mut self, now: f64) -> bool {
+        if !self.enabled || now - self.last_update < UPDATE_INTERVAL {
+            return false;
+        }
+        self.last_update = now;
+        true
+    }
+
+    /// Average frame time over the recorded history, in milliseconds.
+    pub fn average_frame_time(&self) -> f64 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+        self.frame_times.iter().sum::<f64>() / self.frame_times.len() as f64
+    }
+
+    /// Longest frame time in the recorded history, in milliseconds.
+    pub fn max_frame_time(&self) -> f64 {
+        self.frame_times.iter().cloned().fold(0.0, f64::max)
+    }
+
+    pub fn fps(&self) -> f64 {
+        let average = self.average_frame_time();
+        if average == 0.0 {
+            0.0
+        } else {
+            1000.0 / average
+        }
+    }
+
+    /// Draws the most recent frame times as a bar graph,
+    /// scaled to the longest frame shown.
+    pub fn graph(&self) -> String {
+        let skip = self.frame_times.len().saturating_sub(GRAPH_LEN);
+        let recent = self.frame_times.iter().skip(skip);
+        let max = recent.clone().cloned().fold(0.0, f64::max);
+        recent
+            .map(|&time| {
+                if max == 0.0 {
+                    return GRAPH_CHARS[0];
+                }
+                let level = (time / max * (GRAPH_CHARS.len() - 1) as f64).round() as usize;
+                GRAPH_CHARS[level]
+            })
+            .collect()
+    }
+
+    /// Formats all statistics into a single line.
+    pub fn summary(
+        &self,
+        render: &RenderStats,
+        last_event: Option<&RenderEvent>,
+        objects: usize,
+        systems: usize,
+    ) -> String {
+        let mut summary = format!(
+            "{:.0} fps ({:.1} ms, max {:.1}) {} | {} draws | {} tris | {} objects | {} systems | {:.1} MiB models",
+            self.fps(),
+            self.average_frame_time(),
+            self.max_frame_time(),
+            self.graph(),
+            render.draw_calls,
+            render.triangles,
+            objects,
+            systems,
+            render.model_bytes as f64 / (1024.0 * 1024.0)
+        );
+        if let Some(overdraw) = render.overdraw {
+            summary += &format!(
+                " | overdraw {:.2} (max {}) | {:.0}% quad occupancy",
+                overdraw.average(),
+                overdraw.max,
+                overdraw.quad_occupancy * 100.0
+            );
+        }
+        if let Some(latency) = render.latency {
+            summary += &format!(" | {:.1} ms CPU latency", latency.input_to_present());
+        }
+        if let Some(event) = last_event {
+            summary += &format!(
+                " | last {} {:.1} ms ({})",
+                event.kind.name(),
+                event.millis,
+                event.reason
+            );
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_time_stats() {
+        let mut overlay = StatsOverlay::new();
+        overlay.record_frame(10.0);
+        overlay.record_frame(30.0);
+
+        assert_eq!(overlay.average_frame_time(), 20.0);
+        assert_eq!(overlay.max_frame_time(), 30.0);
+        assert_eq!(overlay.fps(), 50.0);
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut overlay = StatsOverlay::new();
+        for _ in 0..HISTORY_LEN * 2 {
+            overlay.record_frame(16.0);
+        }
+        assert_eq!(overlay.frame_times.len(), HISTORY_LEN);
+    }
+
+    #[test]
+    fn graph() {
+        let mut overlay = StatsOverlay::new();
+        overlay.record_frame(0.0);
+        overlay.record_frame(10.0);
+        overlay.record_frame(70.0);
+
+        assert_eq!(overlay.graph(), "▁▂█");
+    }
+
+    #[test]
+    fn update_interval() {
+        let mut overlay = StatsOverlay::new();
+        assert!(!overlay.should_update(1000.0));
+
+        overlay.toggle();
+        assert!(overlay.should_update(1000.0));
+        assert!(!overlay.should_update(1100.0));
+        assert!(overlay.should_update(1600.0));
+    }
+}
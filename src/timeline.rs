@@ -0,0 +1,425 @@
+//! Cutscene timelines.
+//!
+//! A timeline holds a camera track, made of shots which cut
+//! from one camera path to the next, and an event track of
+//! named cues. A `TimelinePlayer` plays a timeline back: each
+//! update it moves the camera and returns the cues passed
+//! since the previous update, which the game can use to
+//! trigger whatever the cue stands for.
+//!
+//! There is no audio or skeletal animation yet, so sound
+//! and animation cues are ordinary named events.
+//!
+//! Timelines are loaded from `cutscenes/<name>.txt` in the
+//! assets, which mods can override. Each line is a shot, a
+//! keyframe of the shot above it, or a cue, with times in
+//! milliseconds:
+//!
+//! ```text
+//! # shot <start>
+//! shot 0
+//! # key <time> <position x y z> <target x y z>
+//! key 0 512 512 512 512 0 512
+//! key 4000 64 96 64 256 0 256
+//! # cue <time> <name>
+//! cue 1500 music start
+//! ```
+//!
+//! Starting the game with `--cutscene=<name>` plays a
+//! cutscene over the world.
+use glm::vec3;
+use mods::ModLoader;
+use render::{Camera, CameraPath, Keyframe};
+use std::fmt;
+use std::fs;
+use std::io;
+
+/// The command line argument which plays a cutscene at
+/// startup, followed by its name
+pub const ARG: &str = "--cutscene=";
+/// The directory of the assets cutscenes are loaded from
+pub const CUTSCENE_DIR: &str = "cutscenes";
+
+/// A camera path which starts playing at a given time.
+pub struct Shot {
+    /// Time the shot cuts in, in milliseconds
+    pub start: f64,
+    /// The path followed, with keyframe times relative to `start`
+    pub path: CameraPath,
+}
+
+/// A named event at a point in a timeline.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cue {
+    /// Time of the cue in milliseconds
+    pub time: f64,
+    pub name: String,
+}
+
+/// A sequence of camera shots and cues.
+pub struct Timeline {
+    /// Shots in order of start time
+    shots: Vec<Shot>,
+    /// Cues in order of time
+    cues: Vec<Cue>,
+}
+
+impl Timeline {
+    pub fn new() -> Timeline {
+        Timeline {
+            shots: Vec::new(),
+            cues: Vec::new(),
+        }
+    }
+
+    /// Adds a shot which cuts in at `start` milliseconds.
+    pub fn with_shot(mut self, start: f64, path: CameraPath) -> Self {
+        let index = self
+            .shots
+            .iter()
+            .position(|shot| shot.start > start)
+            .unwrap_or(self.shots.len());
+        self.shots.insert(index, Shot { start, path });
+        self
+    }
+
+    /// Adds a cue at `time` milliseconds.
+    pub fn with_cue(mut self, time: f64, name: &str) -> Self {
+        let index = self
+            .cues
+            .iter()
+            .position(|cue| cue.time > time)
+            .unwrap_or(self.cues.len());
+        self.cues.insert(
+            index,
+            Cue {
+                time,
+                name: name.to_string(),
+            },
+        );
+        self
+    }
+
+    /// Returns the length of the timeline in milliseconds:
+    /// the end of the last shot or the last cue, whichever is later.
+    pub fn duration(&self) -> f64 {
+        let shots = self
+            .shots
+            .iter()
+            .map(|shot| shot.start + shot.path.duration());
+        let cues = self.cues.iter().map(|cue| cue.time);
+        shots.chain(cues).fold(0.0, f64::max)
+    }
+
+    /// Parses a timeline in the format described in the module
+    /// documentation. Blank lines and lines starting with `#`
+    /// are ignored.
+    pub fn parse(source: &str) -> Result<Timeline, TimelineError> {
+        let mut timeline = Timeline::new();
+        // The line, start time and keyframes of the shot being read
+        let mut shot: Option<(usize, f64, Vec<Keyframe>)> = None;
+        for (index, line) in source.lines().enumerate() {
+            let number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let syntax = || TimelineError::Syntax {
+                line: number,
+                text: line.to_string(),
+            };
+
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("shot") => {
+                    let start = parse_numbers(words, 1).ok_or_else(syntax)?[0];
+                    if let Some(shot) = shot.take() {
+                        timeline = timeline.with_parsed_shot(shot)?;
+                    }
+                    shot = Some((number, start, Vec::new()));
+                }
+                Some("key") => {
+                    let values = parse_numbers(words, 7).ok_or_else(syntax)?;
+                    let keyframes = match shot {
+                        Some((_, _, ref mut keyframes)) => keyframes,
+                        None => return Err(TimelineError::KeyOutsideShot(number)),
+                    };
+                    if keyframes.last().map_or(false, |last| last.time > values[0]) {
+                        return Err(TimelineError::Unordered(number));
+                    }
+                    let value = |index: usize| values[index] as f32;
+                    keyframes.push(Keyframe {
+                        time: values[0],
+                        position: vec3(value(1), value(2), value(3)),
+                        target: vec3(value(4), value(5), value(6)),
+                    });
+                }
+                Some("cue") => {
+                    let time = words
+                        .next()
+                        .and_then(|word| word.parse().ok())
+                        .ok_or_else(syntax)?;
+                    let name = words.collect::<Vec<_>>().join(" ");
+                    if name.is_empty() {
+                        return Err(syntax());
+                    }
+                    timeline = timeline.with_cue(time, &name);
+                }
+                _ => return Err(syntax()),
+            }
+        }
+        match shot {
+            Some(shot) => timeline.with_parsed_shot(shot),
+            None => Ok(timeline),
+        }
+    }
+
+    /// Loads the cutscene `name` from the assets.
+    pub fn load(mods: &ModLoader, name: &str) -> Result<Timeline, TimelineError> {
+        let path = mods.resolve(&format!("{}/{}.txt", CUTSCENE_DIR, name));
+        let source = fs::read_to_string(path).map_err(TimelineError::Io)?;
+        Timeline::parse(&source)
+    }
+
+    /// Adds a shot read by `parse()`, which must have keyframes.
+    fn with_parsed_shot(
+        self,
+        (line, start, keyframes): (usize, f64, Vec<Keyframe>),
+    ) -> Result<Timeline, TimelineError> {
+        if keyframes.is_empty() {
+            return Err(TimelineError::EmptyShot(line));
+        }
+        Ok(self.with_shot(start, CameraPath::new(keyframes)))
+    }
+
+    /// Returns the shot playing at `time`, if any has started.
+    fn shot_at(&self, time: f64) -> Option<&Shot> {
+        self.shots.iter().rev().find(|shot| shot.start <= time)
+    }
+}
+
+/// Parses exactly `count` numbers from `words`.
+fn parse_numbers<'a, I: Iterator<Item = &'a str>>(words: I, count: usize) -> Option<Vec<f64>> {
+    let numbers = words
+        .map(|word| word.parse().ok())
+        .collect::<Option<Vec<f64>>>()?;
+    if numbers.len() == count {
+        Some(numbers)
+    } else {
+        None
+    }
+}
+
+/// An error produced when loading a timeline.
+#[derive(Debug)]
+pub enum TimelineError {
+    Io(io::Error),
+    /// A line which isn't a valid shot, keyframe or cue
+    Syntax {
+        line: usize,
+        text: String,
+    },
+    /// A keyframe on this line comes before any shot
+    KeyOutsideShot(usize),
+    /// The shot starting on this line has no keyframes
+    EmptyShot(usize),
+    /// The keyframe on this line is earlier than the one before it
+    Unordered(usize),
+}
+
+impl fmt::Display for TimelineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimelineError::Io(err) => write!(f, "failed to read timeline: {}", err),
+            TimelineError::Syntax { line, text } => {
+                write!(f, "line {}: invalid timeline entry {:?}", line, text)
+            }
+            TimelineError::KeyOutsideShot(line) => {
+                write!(f, "line {}: keyframe before the first shot", line)
+            }
+            TimelineError::EmptyShot(line) => write!(f, "line {}: shot has no keyframes", line),
+            TimelineError::Unordered(line) => {
+                write!(
+                    f,
+                    "line {}: keyframe is earlier than the previous one",
+                    line
+                )
+            }
+        }
+    }
+}
+
+/// Returns the name of the cutscene requested
+/// on the command line, if any.
+pub fn requested() -> Option<String> {
+    ::std::env::args()
+        .find(|arg| arg.starts_with(ARG))
+        .map(|arg| arg[ARG.len()..].to_string())
+}
+
+/// Plays a `Timeline` with play, pause and seek controls.
+pub struct TimelinePlayer {
+    timeline: Timeline,
+    /// Playback position in milliseconds
+    time: f64,
+    playing: bool,
+    /// Whether cues at the very start have fired
+    started: bool,
+}
+
+impl TimelinePlayer {
+    /// Creates a paused player at the start of the timeline.
+    pub fn new(timeline: Timeline) -> TimelinePlayer {
+        TimelinePlayer {
+            timeline,
+            time: 0.0,
+            playing: false,
+            started: false,
+        }
+    }
+
+    pub fn play(&mut self) {
+        self.playing = true;
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Jumps to `time` milliseconds. Cues between the old and
+    /// new position are skipped, while seeking back to the start
+    /// fires cues at the start again.
+    pub fn seek(&mut self, time: f64) {
+        self.time = time.max(0.0).min(self.timeline.duration());
+        if self.time == 0.0 {
+            self.started = false;
+        }
+    }
+
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// Returns whether playback has reached the end.
+    pub fn finished(&self) -> bool {
+        self.time >= self.timeline.duration()
+    }
+
+    /// Advances playback by `delta` milliseconds if playing,
+    /// returning the cues passed. Playback pauses at the end.
+    pub fn update(&mut self, delta: f64) -> Vec<&Cue> {
+        if !self.playing {
+            return Vec::new();
+        }
+        let previous = self.time;
+        self.time = (self.time + delta).min(self.timeline.duration());
+        if self.finished() {
+            self.playing = false;
+        }
+
+        // Cues at exactly zero fire on the first update
+        let start = !self.started;
+        self.started = true;
+        let time = self.time;
+        self.timeline
+            .cues
+            .iter()
+            .filter(|cue| (cue.time > previous || (start && cue.time == 0.0)) && cue.time <= time)
+            .collect()
+    }
+
+    /// Moves `camera` to its position in the current shot.
+    /// Leaves it alone before the first shot starts.
+    pub fn update_camera(&self, camera: &mut Camera) {
+        if let Some(shot) = self.timeline.shot_at(self.time) {
+            let (position, target) = shot.path.sample(self.time - shot.start);
+            camera.position = position;
+            camera.target = target;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glm::vec3;
+    use render::Keyframe;
+
+    fn still(x: f32) -> CameraPath {
+        CameraPath::new(vec![Keyframe {
+            time: 0.0,
+            position: vec3(x, 0.0, 0.0),
+            target: vec3(x, 0.0, 1.0),
+        }])
+    }
+
+    #[test]
+    fn cues_fire_once() {
+        let timeline = Timeline::new()
+            .with_cue(0.0, "start")
+            .with_cue(150.0, "explosion")
+            .with_cue(100.0, "music");
+        let mut player = TimelinePlayer::new(timeline);
+        assert!(player.update(100.0).is_empty());
+
+        player.play();
+        let names = |cues: Vec<&Cue>| cues.iter().map(|cue| cue.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(player.update(0.0)), vec!["start"]);
+        assert!(player.update(0.0).is_empty());
+        assert_eq!(names(player.update(100.0)), vec!["music"]);
+        assert_eq!(names(player.update(100.0)), vec!["explosion"]);
+        assert!(player.finished());
+        assert!(!player.is_playing());
+    }
+
+    #[test]
+    fn camera_cuts() {
+        let timeline = Timeline::new()
+            .with_shot(1000.0, still(2.0))
+            .with_shot(0.0, still(1.0));
+        let mut player = TimelinePlayer::new(timeline);
+        let mut camera = Camera::default();
+
+        player.seek(500.0);
+        player.update_camera(&mut camera);
+        assert_eq!(camera.position.x, 1.0);
+
+        player.seek(1000.0);
+        player.update_camera(&mut camera);
+        assert_eq!(camera.position.x, 2.0);
+    }
+
+    #[test]
+    fn parse() {
+        let timeline = Timeline::parse(
+            "# opening
+             shot 0
+             key 0 1 0 0 1 0 1
+             key 1000 1 0 0 1 0 1
+             shot 500
+             key 0 2 0 0 2 0 1
+             cue 200 door opens",
+        )
+        .unwrap();
+        assert_eq!(timeline.shots.len(), 2);
+        assert_eq!(timeline.duration(), 1000.0);
+        assert_eq!(timeline.cues[0].name, "door opens");
+
+        match Timeline::parse("key 0 1 0 0 1 0 1") {
+            Err(TimelineError::KeyOutsideShot(1)) => (),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        match Timeline::parse("shot 0\nshot 100\nkey 0 1 0 0 1 0 1") {
+            Err(TimelineError::EmptyShot(1)) => (),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        match Timeline::parse("shot 0\nkey 0 1 0 0") {
+            Err(TimelineError::Syntax { line: 2, .. }) => (),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,339 @@
+//! Touch input and gesture recognition.
+//!
+//! Touch events from the window are fed to a
+//! `GestureRecognizer`, which tracks each touch point and
+//! turns them into taps, long presses, pans, pinches and
+//! swipes. Positions are in logical pixels and times in
+//! milliseconds.
+use std::collections::HashMap;
+
+/// Longest press in milliseconds which counts as a tap
+const TAP_TIME: f64 = 250.0;
+/// How long in milliseconds a touch is held for a long press
+const LONG_PRESS_TIME: f64 = 500.0;
+/// Distance in pixels a touch can move and still be a tap or long press
+const SLOP: f64 = 10.0;
+/// Slowest release in pixels per millisecond which counts as a swipe
+const SWIPE_SPEED: f64 = 1.0;
+/// Shortest time in milliseconds swipe velocity is measured
+/// over, so events with the same timestamp don't divide by zero
+const TIME_EPSILON: f64 = 1e-3;
+
+/// The stage of a touch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TouchPhase {
+    Started,
+    Moved,
+    Ended,
+    Cancelled,
+}
+
+#[cfg(not(feature = "gl"))]
+impl From<::winit::TouchPhase> for TouchPhase {
+    fn from(phase: ::winit::TouchPhase) -> TouchPhase {
+        use winit::TouchPhase as Phase;
+        match phase {
+            Phase::Started => TouchPhase::Started,
+            Phase::Moved => TouchPhase::Moved,
+            Phase::Ended => TouchPhase::Ended,
+            Phase::Cancelled => TouchPhase::Cancelled,
+        }
+    }
+}
+
+#[cfg(feature = "gl")]
+impl From<::glium::glutin::TouchPhase> for TouchPhase {
+    fn from(phase: ::glium::glutin::TouchPhase) -> TouchPhase {
+        use glium::glutin::TouchPhase as Phase;
+        match phase {
+            Phase::Started => TouchPhase::Started,
+            Phase::Moved => TouchPhase::Moved,
+            Phase::Ended => TouchPhase::Ended,
+            Phase::Cancelled => TouchPhase::Cancelled,
+        }
+    }
+}
+
+/// A single touch event.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TouchEvent {
+    /// Identifies the finger for as long as it touches the screen
+    pub id: u64,
+    pub phase: TouchPhase,
+    pub position: (f64, f64),
+    /// Time of the event in milliseconds
+    pub time: f64,
+}
+
+/// A recognized gesture.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Gesture {
+    Tap {
+        position: (f64, f64),
+    },
+    LongPress {
+        position: (f64, f64),
+    },
+    /// A single touch moved by `delta` since the last event
+    Pan {
+        delta: (f64, f64),
+    },
+    /// Two touches moved, changing the distance between them
+    /// by `scale`. Values above 1 are zooming in.
+    Pinch {
+        center: (f64, f64),
+        scale: f64,
+    },
+    /// A touch was released while moving quickly, with
+    /// `velocity` in pixels per millisecond
+    Swipe {
+        velocity: (f64, f64),
+    },
+}
+
+/// A finger currently touching the screen.
+struct TouchPoint {
+    start: (f64, f64),
+    start_time: f64,
+    position: (f64, f64),
+    /// Time of the latest event
+    time: f64,
+    /// Set once the touch moves too far to be a tap or long press
+    moved: bool,
+    long_pressed: bool,
+    /// Position and time of the event before the latest,
+    /// for swipe velocity
+    previous: ((f64, f64), f64),
+}
+
+/// Turns touch events into gestures.
+pub struct GestureRecognizer {
+    touches: HashMap<u64, TouchPoint>,
+}
+
+impl GestureRecognizer {
+    pub fn new() -> GestureRecognizer {
+        GestureRecognizer {
+            touches: HashMap::new(),
+        }
+    }
+
+    /// Returns the number of fingers touching the screen.
+    pub fn touch_count(&self) -> usize {
+        self.touches.len()
+    }
+
+    /// Handles a touch event, returning any gestures it completes.
+    pub fn handle(&mut self, event: TouchEvent) -> Vec<Gesture> {
+        let mut gestures = Vec::new();
+        match event.phase {
+            TouchPhase::Started => {
+                self.touches.insert(
+                    event.id,
+                    TouchPoint {
+                        start: event.position,
+                        start_time: event.time,
+                        position: event.position,
+                        time: event.time,
+                        moved: false,
+                        long_pressed: false,
+                        previous: (event.position, event.time),
+                    },
+                );
+            }
+            TouchPhase::Moved => {
+                let pinch = self.pinch_distance();
+                let last = match self.touches.get_mut(&event.id) {
+                    Some(touch) => {
+                        let last = touch.position;
+                        touch.previous = (touch.position, touch.time);
+                        touch.position = event.position;
+                        touch.time = event.time;
+                        if distance(touch.start, event.position) > SLOP {
+                            touch.moved = true;
+                        }
+                        last
+                    }
+                    None => return gestures,
+                };
+
+                match self.touches.len() {
+                    1 if self.touches[&event.id].moved => gestures.push(Gesture::Pan {
+                        delta: (event.position.0 - last.0, event.position.1 - last.1),
+                    }),
+                    2 => {
+                        if let (Some(before), Some(after)) = (pinch, self.pinch_distance()) {
+                            if before > 0.0 {
+                                gestures.push(Gesture::Pinch {
+                                    center: self.center(),
+                                    scale: after / before,
+                                });
+                            }
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            TouchPhase::Ended => {
+                let single = self.touches.len() == 1;
+                if let Some(touch) = self.touches.remove(&event.id) {
+                    if single {
+                        gestures.extend(release(&touch, event));
+                    }
+                }
+            }
+            TouchPhase::Cancelled => {
+                self.touches.remove(&event.id);
+            }
+        }
+        gestures
+    }
+
+    /// Checks held touches for long presses at time `now`.
+    pub fn update(&mut self, now: f64) -> Vec<Gesture> {
+        let single = self.touches.len() == 1;
+        self.touches
+            .values_mut()
+            .filter(|touch| {
+                single
+                    && !touch.moved
+                    && !touch.long_pressed
+                    && now - touch.start_time >= LONG_PRESS_TIME
+            })
+            .map(|touch| {
+                touch.long_pressed = true;
+                Gesture::LongPress {
+                    position: touch.position,
+                }
+            })
+            .collect()
+    }
+
+    /// Distance between the first two touches, if there are two.
+    fn pinch_distance(&self) -> Option<f64> {
+        let mut touches = self.touches.values();
+        match (touches.next(), touches.next()) {
+            (Some(a), Some(b)) => Some(distance(a.position, b.position)),
+            _ => None,
+        }
+    }
+
+    /// The average position of all touches.
+    fn center(&self) -> (f64, f64) {
+        let count = self.touches.len() as f64;
+        let (x, y) = self.touches.values().fold((0.0, 0.0), |sum, touch| {
+            (sum.0 + touch.position.0, sum.1 + touch.position.1)
+        });
+        (x / count, y / count)
+    }
+}
+
+/// Returns the gesture completed by lifting `touch`, if any.
+fn release(touch: &TouchPoint, event: TouchEvent) -> Option<Gesture> {
+    if touch.long_pressed {
+        return None;
+    }
+    if !touch.moved && event.time - touch.start_time <= TAP_TIME {
+        return Some(Gesture::Tap {
+            position: event.position,
+        });
+    }
+
+    let (previous, previous_time) = touch.previous;
+    let elapsed = (event.time - previous_time).max(TIME_EPSILON);
+    let velocity = (
+        (event.position.0 - previous.0) / elapsed,
+        (event.position.1 - previous.1) / elapsed,
+    );
+    if touch.moved && distance((0.0, 0.0), velocity) >= SWIPE_SPEED {
+        Some(Gesture::Swipe { velocity })
+    } else {
+        None
+    }
+}
+
+fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touch(id: u64, phase: TouchPhase, x: f64, y: f64, time: f64) -> TouchEvent {
+        TouchEvent {
+            id,
+            phase,
+            position: (x, y),
+            time,
+        }
+    }
+
+    #[test]
+    fn tap_and_long_press() {
+        let mut recognizer = GestureRecognizer::new();
+        recognizer.handle(touch(0, TouchPhase::Started, 10.0, 10.0, 0.0));
+        let gestures = recognizer.handle(touch(0, TouchPhase::Ended, 12.0, 10.0, 100.0));
+        assert_eq!(
+            gestures,
+            vec![Gesture::Tap {
+                position: (12.0, 10.0)
+            }]
+        );
+
+        recognizer.handle(touch(1, TouchPhase::Started, 10.0, 10.0, 200.0));
+        assert!(recognizer.update(400.0).is_empty());
+        assert_eq!(recognizer.update(800.0).len(), 1);
+        assert!(recognizer.update(900.0).is_empty());
+        assert!(recognizer
+            .handle(touch(1, TouchPhase::Ended, 10.0, 10.0, 1000.0))
+            .is_empty());
+    }
+
+    #[test]
+    fn pan_and_swipe() {
+        let mut recognizer = GestureRecognizer::new();
+        recognizer.handle(touch(0, TouchPhase::Started, 0.0, 0.0, 0.0));
+        assert!(recognizer
+            .handle(touch(0, TouchPhase::Moved, 5.0, 0.0, 10.0))
+            .is_empty());
+        assert_eq!(
+            recognizer.handle(touch(0, TouchPhase::Moved, 50.0, 0.0, 20.0)),
+            vec![Gesture::Pan { delta: (45.0, 0.0) }]
+        );
+        assert_eq!(
+            recognizer.handle(touch(0, TouchPhase::Ended, 70.0, 0.0, 30.0)),
+            vec![Gesture::Swipe {
+                velocity: (3.25, 0.0)
+            }]
+        );
+    }
+
+    #[test]
+    fn fast_swipe() {
+        // Events less than a millisecond apart
+        let mut recognizer = GestureRecognizer::new();
+        recognizer.handle(touch(0, TouchPhase::Started, 0.0, 0.0, 0.0));
+        recognizer.handle(touch(0, TouchPhase::Moved, 30.0, 0.0, 0.5));
+        assert_eq!(
+            recognizer.handle(touch(0, TouchPhase::Ended, 40.0, 0.0, 0.8)),
+            vec![Gesture::Swipe {
+                velocity: (50.0, 0.0)
+            }]
+        );
+    }
+
+    #[test]
+    fn pinch() {
+        let mut recognizer = GestureRecognizer::new();
+        recognizer.handle(touch(0, TouchPhase::Started, 0.0, 0.0, 0.0));
+        recognizer.handle(touch(1, TouchPhase::Started, 100.0, 0.0, 0.0));
+        assert_eq!(
+            recognizer.handle(touch(1, TouchPhase::Moved, 200.0, 0.0, 10.0)),
+            vec![Gesture::Pinch {
+                center: (100.0, 0.0),
+                scale: 2.0
+            }]
+        );
+    }
+}
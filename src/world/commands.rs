@@ -0,0 +1,69 @@
+//! Deferred structural changes to the world.
+//!
+//! Code iterating over the objects in a `World` cannot add
+//! or remove objects without invalidating the iteration. Instead,
+//! it queues those changes into a `Commands` buffer, which the
+//! world applies at the end of each tick.
+use super::*;
+
+/// A single queued change to the world.
+pub enum Command<B: RenderBackend> {
+    /// Adds an object to the world
+    Spawn(Object<B>),
+    /// Removes the object with the given global ID
+    Despawn(u64),
+    /// Moves the object with the given global ID
+    /// to a new location
+    SetLocation(u64, Location),
+}
+
+/// A queue of structural changes which
+/// are applied to the world all at once.
+pub struct Commands<B: RenderBackend> {
+    queue: Vec<Command<B>>,
+}
+
+impl<B: RenderBackend> Commands<B> {
+    /// Creates an empty command buffer.
+    pub fn new() -> Commands<B> {
+        Commands { queue: Vec::new() }
+    }
+
+    /// Queues an object to be added to the world,
+    /// returning the global ID it will have.
+    pub fn spawn(&mut self, object: Object<B>) -> u64 {
+        let id = object.global_id;
+        self.queue.push(Command::Spawn(object));
+        id
+    }
+
+    /// Queues the removal of the object with the given ID.
+    pub fn despawn(&mut self, id: u64) {
+        self.queue.push(Command::Despawn(id));
+    }
+
+    /// Queues a change of location for the object with the given ID.
+    pub fn set_location(&mut self, id: u64, location: Location) {
+        self.queue.push(Command::SetLocation(id, location));
+    }
+
+    /// Moves all commands from `other` to the end of this buffer.
+    /// This is useful for merging buffers filled on separate threads.
+    pub fn append(&mut self, other: &mut Commands<B>) {
+        self.queue.append(&mut other.queue);
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Removes all commands from the buffer in the order
+    /// they were queued.
+    pub fn drain(&mut self) -> std::vec::Drain<Command<B>> {
+        self.queue.drain(..)
+    }
+}
@@ -1,64 +1,171 @@
 //! Stores data of objects and entities in the world.
 use super::*;
-use render::RenderBackend;
+use render::{Layer, RenderBackend};
 use std::collections::HashMap;
 use std::hash::Hash;
 use std::hash::Hasher;
 
+pub use self::commands::{Command, Commands};
+pub use self::schedule::{Schedule, System};
+pub use self::snapshot::{Snapshot, SnapshotHistory};
+
+pub mod commands;
+pub mod schedule;
+pub mod snapshot;
+
 static mut OBJECT_GLOBAL_ID: u64 = 0;
 
+/// Objects are stored contiguously so that hot loops
+/// (such as rendering) iterate over packed memory. A
+/// separate map from global ID to index allows lookups
+/// by ID; removal swaps the last object into the hole.
 pub struct World<B: RenderBackend> {
-    objects: HashMap<u64, Object<B>>,
+    objects: Vec<Object<B>>,
+    /// Maps global IDs to indices into `objects`
+    indices: HashMap<u64, usize>,
+    /// Changes queued during the current tick
+    commands: Commands<B>,
+    /// The number of ticks run so far
+    ticks: u64,
 }
 
 impl<B: RenderBackend> World<B> {
+    /// Adds an object to the world, replacing
+    /// any existing object with the same ID.
     pub fn add_obj(&mut self, object: Object<B>) {
         let id = object.global_id;
-        self.objects.insert(id, object);
+        match self.indices.get(&id) {
+            Some(&index) => self.objects[index] = object,
+            None => {
+                self.indices.insert(id, self.objects.len());
+                self.objects.push(object);
+            }
+        }
     }
 
     pub fn add_objs(&mut self, objects: Vec<Object<B>>) {
+        self.objects.reserve(objects.len());
         for obj in objects {
-            let id = obj.global_id;
-            self.objects.insert(id, obj);
+            self.add_obj(obj);
         }
     }
 
-    pub fn get_objs<'a>(&'a self) -> &'a HashMap<u64, Object<B>> {
+    pub fn get_objs<'a>(&'a self) -> &'a [Object<B>] {
         &self.objects
     }
 
-    pub fn get_objs_mut<'a>(&'a mut self) -> &'a mut HashMap<u64, Object<B>> {
+    pub fn get_objs_mut<'a>(&'a mut self) -> &'a mut [Object<B>] {
         &mut self.objects
     }
 
-    pub fn get_obj(&mut self, id: u64) -> Option<&Object<B>> {
-        self.objects.get(&id)
+    /// Returns the objects along with the command buffer,
+    /// so that changes can be queued while iterating.
+    pub fn get_objs_and_commands_mut<'a>(
+        &'a mut self,
+    ) -> (&'a mut [Object<B>], &'a mut Commands<B>) {
+        (&mut self.objects, &mut self.commands)
+    }
+
+    pub fn commands_mut<'a>(&'a mut self) -> &'a mut Commands<B> {
+        &mut self.commands
+    }
+
+    pub fn get_obj(&self, id: u64) -> Option<&Object<B>> {
+        self.indices.get(&id).map(|&index| &self.objects[index])
+    }
+
+    pub fn get_obj_mut(&mut self, id: u64) -> Option<&mut Object<B>> {
+        match self.indices.get(&id) {
+            Some(&index) => Some(&mut self.objects[index]),
+            None => None,
+        }
+    }
+
+    /// Consumes the world, returning its objects.
+    pub fn into_objs(self) -> Vec<Object<B>> {
+        self.objects
     }
 
     pub fn remove_obj(&mut self, id: u64) -> Option<Object<B>> {
-        self.objects.remove(&id)
+        let index = self.indices.remove(&id)?;
+        let object = self.objects.swap_remove(index);
+        // The last object was moved into the removed slot
+        if index < self.objects.len() {
+            let moved_id = self.objects[index].global_id;
+            self.indices.insert(moved_id, index);
+        }
+        Some(object)
     }
 
     /// Creates a new world with no objects.
     pub fn new() -> World<B> {
         World {
-            objects: HashMap::new(),
+            objects: Vec::new(),
+            indices: HashMap::new(),
+            commands: Commands::new(),
+            ticks: 0,
         }
     }
 
-    pub fn tick(&mut self) {}
+    pub fn tick(&mut self) {
+        // Structural changes are applied once all
+        // other work for the tick has finished
+        self.apply_commands();
+        self.ticks += 1;
+    }
+
+    /// Returns the number of ticks run so far.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Sets the tick counter, as when rolling back to a snapshot.
+    pub fn set_ticks(&mut self, ticks: u64) {
+        self.ticks = ticks;
+    }
+
+    /// Applies all queued commands in the order they were queued.
+    /// Commands referring to objects which no longer exist are ignored.
+    pub fn apply_commands(&mut self) {
+        let mut commands = std::mem::replace(&mut self.commands, Commands::new());
+        for command in commands.drain() {
+            match command {
+                Command::Spawn(object) => self.add_obj(object),
+                Command::Despawn(id) => {
+                    self.remove_obj(id);
+                }
+                Command::SetLocation(id, location) => {
+                    if let Some(object) = self.get_obj_mut(id) {
+                        object.location = location;
+                    }
+                }
+            }
+        }
+    }
 }
 
 /// An object in the world
 pub struct Object<B: RenderBackend> {
     /// The GLOBAL ID for the object. This
     /// is used for things like hashing and equal.
-    pub global_id: u64,
+    /// It is private so that the world's map from
+    /// IDs to indices can't be invalidated.
+    global_id: u64,
     /// The location in world space of the object
     pub location: Location,
     /// The render data associated with this object
     pub render: B::ObjectRender,
+    /// Objects with a higher priority are drawn later.
+    /// See `render::sort_key` for how draws are ordered.
+    pub render_priority: u8,
+    /// Whether the object is a viewmodel (such as a first-person
+    /// weapon). Viewmodels are positioned relative to the camera
+    /// and drawn after the rest of the scene with a cleared depth
+    /// buffer, so they never clip into walls.
+    pub viewmodel: bool,
+    /// The layer the object is on, which cameras, lights and
+    /// passes can exclude. See `render::layer`.
+    pub layer: Layer,
     // The descriptor for the object - this is loaded from
     // JSON files in assets/objects
     // TODO
@@ -90,12 +197,20 @@ impl<B: RenderBackend> Object<B> {
         Object {
             render,
             location,
+            render_priority: render::sort_key::DEFAULT_PRIORITY,
+            viewmodel: false,
+            layer: Layer::DEFAULT,
             global_id: unsafe {
                 OBJECT_GLOBAL_ID += 1;
                 OBJECT_GLOBAL_ID
             },
         }
     }
+
+    /// Returns the object's global ID.
+    pub fn global_id(&self) -> u64 {
+        self.global_id
+    }
 }
 
 /// A three-dimensional location in world space
@@ -0,0 +1,256 @@
+//! Ordering and execution of systems.
+//!
+//! A system is a labeled function which runs once per
+//! tick. Systems can declare that they must run `before`
+//! or `after` other systems by label; the schedule sorts
+//! them once and caches the resulting order until a system
+//! is added.
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt;
+use std::time::Instant;
+
+/// A labeled function run by a `Schedule`.
+pub struct System<T> {
+    label: &'static str,
+    before: Vec<&'static str>,
+    after: Vec<&'static str>,
+    run: Box<dyn FnMut(&mut T)>,
+}
+
+impl<T> System<T> {
+    /// Creates a system with the specified label.
+    /// Labels must be unique within a schedule.
+    pub fn new<F: FnMut(&mut T) + 'static>(label: &'static str, run: F) -> System<T> {
+        System {
+            label,
+            before: Vec::new(),
+            after: Vec::new(),
+            run: Box::new(run),
+        }
+    }
+
+    /// Requires this system to run before the system with the given label.
+    pub fn before(mut self, label: &'static str) -> Self {
+        self.before.push(label);
+        self
+    }
+
+    /// Requires this system to run after the system with the given label.
+    pub fn after(mut self, label: &'static str) -> Self {
+        self.after.push(label);
+        self
+    }
+
+    pub fn label(&self) -> &'static str {
+        self.label
+    }
+}
+
+/// An error produced when sorting a schedule.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ScheduleError {
+    /// A system was ordered relative to a label no system has
+    UnknownLabel(&'static str),
+    /// Two systems were added with the same label
+    DuplicateLabel(&'static str),
+    /// The ordering constraints form a cycle
+    /// containing the system with this label
+    Cycle(&'static str),
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScheduleError::UnknownLabel(label) => write!(f, "no system has label {}", label),
+            ScheduleError::DuplicateLabel(label) => write!(f, "duplicate system label {}", label),
+            ScheduleError::Cycle(label) => write!(f, "ordering cycle involving {}", label),
+        }
+    }
+}
+
+/// A set of systems run in dependency order.
+pub struct Schedule<T> {
+    systems: Vec<System<T>>,
+    /// Cached execution order as indices into `systems`
+    order: Option<Vec<usize>>,
+}
+
+impl<T> Schedule<T> {
+    pub fn new() -> Schedule<T> {
+        Schedule {
+            systems: Vec::new(),
+            order: None,
+        }
+    }
+
+    pub fn add_system(&mut self, system: System<T>) {
+        self.systems.push(system);
+        self.order = None;
+    }
+
+    /// Returns the number of systems in the schedule.
+    pub fn len(&self) -> usize {
+        self.systems.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.systems.is_empty()
+    }
+
+    /// Returns the labels of all systems in the order they will run.
+    pub fn labels(&mut self) -> Result<Vec<&'static str>, ScheduleError> {
+        self.sort()?;
+        let order = self.order.as_ref().unwrap();
+        Ok(order.iter().map(|&i| self.systems[i].label).collect())
+    }
+
+    /// Runs every system once, in order.
+    ///
+    /// Panics if the ordering constraints cannot be satisfied.
+    pub fn run(&mut self, ctx: &mut T) {
+        self.run_with(ctx, |_, _| ());
+    }
+
+    /// Runs every system once, in order, passing the label
+    /// and run time in milliseconds of each system to `on_system`.
+    ///
+    /// Panics if the ordering constraints cannot be satisfied.
+    pub fn run_with<F: FnMut(&'static str, f64)>(&mut self, ctx: &mut T, mut on_system: F) {
+        if let Err(err) = self.sort() {
+            panic!("invalid system schedule: {}", err);
+        }
+        let order = self.order.as_ref().unwrap();
+        for &index in order {
+            let system = &mut self.systems[index];
+            let start = Instant::now();
+            (system.run)(ctx);
+            on_system(system.label, ::profile::millis_since(start));
+        }
+    }
+
+    /// Computes and caches the execution order if it is not already cached.
+    fn sort(&mut self) -> Result<(), ScheduleError> {
+        if self.order.is_some() {
+            return Ok(());
+        }
+
+        let mut indices = HashMap::new();
+        for (index, system) in self.systems.iter().enumerate() {
+            if indices.insert(system.label, index).is_some() {
+                return Err(ScheduleError::DuplicateLabel(system.label));
+            }
+        }
+
+        // Edges go from a system to the systems which must run after it
+        let mut edges = vec![Vec::new(); self.systems.len()];
+        let mut in_degree = vec![0; self.systems.len()];
+        for (index, system) in self.systems.iter().enumerate() {
+            for &label in &system.before {
+                let other = *indices
+                    .get(label)
+                    .ok_or(ScheduleError::UnknownLabel(label))?;
+                edges[index].push(other);
+                in_degree[other] += 1;
+            }
+            for &label in &system.after {
+                let other = *indices
+                    .get(label)
+                    .ok_or(ScheduleError::UnknownLabel(label))?;
+                edges[other].push(index);
+                in_degree[index] += 1;
+            }
+        }
+
+        // Kahn's algorithm, always picking the earliest added system
+        // which is ready so that unconstrained systems keep insertion order
+        let mut ready = BinaryHeap::new();
+        for (index, &degree) in in_degree.iter().enumerate() {
+            if degree == 0 {
+                ready.push(Reverse(index));
+            }
+        }
+
+        let mut order = Vec::with_capacity(self.systems.len());
+        while let Some(Reverse(index)) = ready.pop() {
+            order.push(index);
+            for &next in &edges[index] {
+                in_degree[next] -= 1;
+                if in_degree[next] == 0 {
+                    ready.push(Reverse(next));
+                }
+            }
+        }
+
+        if order.len() != self.systems.len() {
+            let stuck = in_degree.iter().position(|&degree| degree > 0).unwrap();
+            return Err(ScheduleError::Cycle(self.systems[stuck].label));
+        }
+
+        self.order = Some(order);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push(label: &'static str) -> System<Vec<&'static str>> {
+        System::new(label, move |log: &mut Vec<&'static str>| log.push(label))
+    }
+
+    #[test]
+    fn insertion_order() {
+        let mut schedule = Schedule::new();
+        schedule.add_system(push("a"));
+        schedule.add_system(push("b"));
+        schedule.add_system(push("c"));
+
+        let mut log = Vec::new();
+        schedule.run(&mut log);
+        assert_eq!(log, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn before_and_after() {
+        let mut schedule = Schedule::new();
+        schedule.add_system(push("render").after("physics"));
+        schedule.add_system(push("physics"));
+        schedule.add_system(push("input").before("physics"));
+
+        assert_eq!(
+            schedule.labels().unwrap(),
+            vec!["input", "physics", "render"]
+        );
+    }
+
+    #[test]
+    fn cycle() {
+        let mut schedule = Schedule::new();
+        schedule.add_system(push("a").after("b"));
+        schedule.add_system(push("b").after("a"));
+
+        assert_eq!(schedule.labels(), Err(ScheduleError::Cycle("a")));
+    }
+
+    #[test]
+    fn unknown_label() {
+        let mut schedule = Schedule::new();
+        schedule.add_system(push("a").after("missing"));
+
+        assert_eq!(
+            schedule.labels(),
+            Err(ScheduleError::UnknownLabel("missing"))
+        );
+    }
+
+    #[test]
+    fn duplicate_label() {
+        let mut schedule = Schedule::new();
+        schedule.add_system(push("a"));
+        schedule.add_system(push("a"));
+
+        assert_eq!(schedule.labels(), Err(ScheduleError::DuplicateLabel("a")));
+    }
+}
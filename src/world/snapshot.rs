@@ -0,0 +1,202 @@
+//! Snapshots of world state for rollback.
+//!
+//! A snapshot records the location of every object in the
+//! world. Locations are stored in fixed-size chunks behind
+//! reference counts, so consecutive snapshots share every
+//! chunk in which no object moved.
+//!
+//! Only locations are restored by a rollback. Objects spawned
+//! after a snapshot are removed, but objects despawned after it
+//! cannot be brought back since their render data is gone.
+use super::*;
+use std::collections::{HashSet, VecDeque};
+use std::rc::Rc;
+
+/// Number of objects stored in each chunk
+const CHUNK_SIZE: usize = 64;
+
+type Chunk = Vec<(u64, Location)>;
+
+/// The state of the world at a single tick.
+#[derive(Clone)]
+pub struct Snapshot {
+    /// The world tick the snapshot was taken on
+    pub tick: u64,
+    chunks: Vec<Rc<Chunk>>,
+}
+
+impl Snapshot {
+    /// Captures the current state of the world.
+    pub fn take<B: RenderBackend>(world: &World<B>) -> Snapshot {
+        Snapshot::take_with_base(world, None)
+    }
+
+    /// Captures the current state of the world, sharing any
+    /// chunks which are unchanged since `base`.
+    pub fn take_with_base<B: RenderBackend>(world: &World<B>, base: Option<&Snapshot>) -> Snapshot {
+        let chunks = world
+            .get_objs()
+            .chunks(CHUNK_SIZE)
+            .enumerate()
+            .map(|(index, objects)| {
+                let shared = base
+                    .and_then(|base| base.chunks.get(index))
+                    .filter(|chunk| {
+                        chunk.len() == objects.len()
+                            && chunk
+                                .iter()
+                                .zip(objects)
+                                .all(|(&(id, ref location), object)| {
+                                    id == object.global_id && *location == object.location
+                                })
+                    });
+
+                match shared {
+                    Some(chunk) => Rc::clone(chunk),
+                    None => Rc::new(
+                        objects
+                            .iter()
+                            .map(|object| (object.global_id, object.location.clone()))
+                            .collect(),
+                    ),
+                }
+            })
+            .collect();
+
+        Snapshot {
+            tick: world.ticks(),
+            chunks,
+        }
+    }
+
+    /// Restores the world to the state captured in this snapshot.
+    pub fn restore<B: RenderBackend>(&self, world: &mut World<B>) {
+        let mut ids = HashSet::new();
+        for chunk in &self.chunks {
+            for &(id, ref location) in chunk.iter() {
+                ids.insert(id);
+                if let Some(object) = world.get_obj_mut(id) {
+                    object.location = location.clone();
+                }
+            }
+        }
+
+        // Remove objects which did not exist yet
+        let spawned = world
+            .get_objs()
+            .iter()
+            .map(|object| object.global_id)
+            .filter(|id| !ids.contains(id))
+            .collect::<Vec<_>>();
+        for id in spawned {
+            world.remove_obj(id);
+        }
+
+        world.set_ticks(self.tick);
+    }
+
+    /// Returns the number of chunks this snapshot shares with `other`.
+    pub fn shared_chunks(&self, other: &Snapshot) -> usize {
+        self.chunks
+            .iter()
+            .zip(&other.chunks)
+            .filter(|&(a, b)| Rc::ptr_eq(a, b))
+            .count()
+    }
+}
+
+/// A bounded history of snapshots, one per tick.
+pub struct SnapshotHistory {
+    snapshots: VecDeque<Snapshot>,
+    capacity: usize,
+}
+
+impl SnapshotHistory {
+    /// Creates a history which keeps at most `capacity`
+    /// snapshots. A capacity of 0 disables the history.
+    pub fn new(capacity: usize) -> SnapshotHistory {
+        SnapshotHistory {
+            snapshots: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Takes a snapshot of the world, discarding
+    /// the oldest snapshot if the history is full.
+    pub fn record<B: RenderBackend>(&mut self, world: &World<B>) {
+        let snapshot = Snapshot::take_with_base(world, self.snapshots.back());
+        self.push(snapshot);
+    }
+
+    /// Stores a snapshot, discarding the oldest snapshots
+    /// until it fits. Nothing is stored with a capacity of 0.
+    fn push(&mut self, snapshot: Snapshot) {
+        if self.capacity == 0 {
+            return;
+        }
+        while self.snapshots.len() >= self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(snapshot);
+    }
+
+    /// Returns the snapshot taken on the given tick, if it is still stored.
+    pub fn get(&self, tick: u64) -> Option<&Snapshot> {
+        self.snapshots.iter().find(|snapshot| snapshot.tick == tick)
+    }
+
+    /// Rolls the world back to the given tick, discarding all
+    /// later snapshots. Returns false if no snapshot for the tick exists.
+    pub fn rollback<B: RenderBackend>(&mut self, world: &mut World<B>, tick: u64) -> bool {
+        let position = match self.snapshots.iter().position(|s| s.tick == tick) {
+            Some(position) => position,
+            None => return false,
+        };
+        self.snapshots.truncate(position + 1);
+        self.snapshots[position].restore(world);
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(tick: u64) -> Snapshot {
+        Snapshot {
+            tick,
+            chunks: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn eviction() {
+        let mut history = SnapshotHistory::new(3);
+        for tick in 0..5 {
+            history.push(snapshot(tick));
+        }
+        assert_eq!(history.len(), 3);
+        assert!(history.get(1).is_none());
+        assert_eq!(
+            history.snapshots.iter().map(|s| s.tick).collect::<Vec<_>>(),
+            vec![2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn disabled() {
+        let mut history = SnapshotHistory::new(0);
+        for tick in 0..5 {
+            history.push(snapshot(tick));
+        }
+        assert!(history.is_empty());
+    }
+}